@@ -1,8 +1,273 @@
 use crate::bit_width::BitWidth;
+use crate::layout::{RawBits, read_bits, read_bits_lsb, swap_bytes, write_bits, write_bits_lsb};
+use crate::{Endian, Msb};
+use std::net::{Ipv4Addr, Ipv6Addr};
 
-pub trait BitSerde: BitWidth {
-    fn to_bits(&self) -> Vec<u8>;
-    fn from_bits(&self, bytes: &[u8]) -> Self
-    where
-        Self: Sized;
+/// How a value is laid out in bits: the bit orientation within bytes and
+/// the order of the bytes of values wider than one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BitOrder {
+    pub msb: Msb,
+    pub endian: Endian,
+}
+
+impl BitOrder {
+    /// Most significant bit and byte first.
+    pub const NETWORK: BitOrder = BitOrder::new(Msb::Left, Endian::Big);
+
+    pub const fn new(msb: Msb, endian: Endian) -> Self {
+        Self { msb, endian }
+    }
+
+    /// `Msb::Left` naturally puts the most significant byte first and
+    /// `Msb::Right` the least significant, the other byte order is a swap.
+    fn swaps(&self, width: usize) -> bool {
+        let swapped = matches!(
+            (self.msb, self.endian),
+            (Msb::Left, Endian::Little) | (Msb::Right, Endian::Big)
+        );
+        swapped && width > 8
+    }
+
+    /// Reads `width` raw bits. Panics if the value needs a byte swap but
+    /// isn't whole bytes.
+    pub fn read(&self, bytes: &[u8], offset: usize, width: usize) -> u128 {
+        let raw = match self.msb {
+            Msb::Left => read_bits(bytes, offset, width),
+            Msb::Right => read_bits_lsb(bytes, offset, width),
+        };
+        if self.swaps(width) {
+            swap_bytes(raw, width)
+        } else {
+            raw
+        }
+    }
+
+    /// Writes `width` raw bits. Panics if the value needs a byte swap but
+    /// isn't whole bytes.
+    pub fn write(&self, bytes: &mut [u8], offset: usize, width: usize, value: u128) {
+        let value = if self.swaps(width) {
+            swap_bytes(value, width)
+        } else {
+            value
+        };
+        match self.msb {
+            Msb::Left => write_bits(bytes, offset, width, value),
+            Msb::Right => write_bits_lsb(bytes, offset, width, value),
+        }
+    }
+}
+
+/// Values that can be read from and written to any bit offset of a byte
+/// slice. `Ipv4Addr` and the `arbitrary_int` types have inherent `to_bits`
+/// and `from_bits` of their own, call these as `BitSerde::to_bits(&value)`.
+pub trait BitSerde: BitWidth + Sized {
+    /// Reads a value from the `width()` bits at `offset` bits into `bytes`.
+    /// Bits past the end of `bytes` read as zero.
+    fn read_at(bytes: &[u8], offset: usize, order: BitOrder) -> Self;
+
+    /// Writes the value to the `width()` bits at `offset` bits into `bytes`,
+    /// leaving the bits around it alone. Panics if `bytes` is too short.
+    fn write_at(&self, bytes: &mut [u8], offset: usize, order: BitOrder);
+
+    /// The value in network order, right aligned in whole bytes.
+    fn to_bits(&self) -> Vec<u8> {
+        let size = Self::width().div_ceil(8);
+        let mut bytes = vec![0; size];
+        self.write_at(&mut bytes, size * 8 - Self::width(), BitOrder::NETWORK);
+        bytes
+    }
+
+    /// The inverse of `to_bits`.
+    fn from_bits(bytes: &[u8]) -> Self {
+        let size = Self::width().div_ceil(8);
+        Self::read_at(bytes, size * 8 - Self::width(), BitOrder::NETWORK)
+    }
+}
+
+impl<T: RawBits> BitSerde for T {
+    fn read_at(bytes: &[u8], offset: usize, order: BitOrder) -> Self {
+        T::from_raw(order.read(bytes, offset, T::width()))
+    }
+
+    fn write_at(&self, bytes: &mut [u8], offset: usize, order: BitOrder) {
+        order.write(bytes, offset, T::width(), self.to_raw());
+    }
+}
+
+/// Byte strings are numbers with the first byte most significant, so
+/// `Endian::Little` reverses them.
+impl<const N: usize> BitSerde for [u8; N] {
+    fn read_at(bytes: &[u8], offset: usize, order: BitOrder) -> Self {
+        let mut value = [0; N];
+        for (index, byte) in value.iter_mut().enumerate() {
+            let position = match order.endian {
+                Endian::Big => index,
+                Endian::Little => N - 1 - index,
+            };
+            *byte = order.read(bytes, offset + position * 8, 8) as u8;
+        }
+        value
+    }
+
+    fn write_at(&self, bytes: &mut [u8], offset: usize, order: BitOrder) {
+        for (index, byte) in self.iter().enumerate() {
+            let position = match order.endian {
+                Endian::Big => index,
+                Endian::Little => N - 1 - index,
+            };
+            order.write(bytes, offset + position * 8, 8, *byte as u128);
+        }
+    }
+}
+
+impl BitSerde for Ipv4Addr {
+    fn read_at(bytes: &[u8], offset: usize, order: BitOrder) -> Self {
+        <[u8; 4]>::read_at(bytes, offset, order).into()
+    }
+
+    fn write_at(&self, bytes: &mut [u8], offset: usize, order: BitOrder) {
+        self.octets().write_at(bytes, offset, order);
+    }
+}
+
+impl BitSerde for Ipv6Addr {
+    fn read_at(bytes: &[u8], offset: usize, order: BitOrder) -> Self {
+        <[u8; 16]>::read_at(bytes, offset, order).into()
+    }
+
+    fn write_at(&self, bytes: &mut [u8], offset: usize, order: BitOrder) {
+        self.octets().write_at(bytes, offset, order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arbitrary_int::prelude::*;
+    use std::fmt::Debug;
+
+    const ORDERS: [BitOrder; 4] = [
+        BitOrder::new(Msb::Left, Endian::Big),
+        BitOrder::new(Msb::Left, Endian::Little),
+        BitOrder::new(Msb::Right, Endian::Big),
+        BitOrder::new(Msb::Right, Endian::Little),
+    ];
+
+    /// Writes `value` at every alignment and order over a patterned
+    /// background, reads it back, and checks that writing back what was
+    /// there restores the background.
+    fn round_trip<T: BitSerde + PartialEq + Debug>(value: &T) {
+        let width = T::width();
+        for order in ORDERS {
+            if order.swaps(width) && !width.is_multiple_of(8) {
+                continue;
+            }
+            for offset in 0..9 {
+                let len = (offset + width).div_ceil(8) + 1;
+                let background: Vec<u8> = (0..len)
+                    .map(|i| 0xa5 ^ (i as u8).wrapping_mul(0x3b))
+                    .collect();
+                let mut bytes = background.clone();
+                let original = T::read_at(&bytes, offset, order);
+                value.write_at(&mut bytes, offset, order);
+                assert!(T::read_at(&bytes, offset, order) == *value, "{:?}", value);
+                original.write_at(&mut bytes, offset, order);
+                assert!(bytes == background, "{:?} at {} {:?}", value, offset, order);
+            }
+        }
+        assert!(<T as BitSerde>::from_bits(&BitSerde::to_bits(value)) == *value);
+    }
+
+    fn exhaustive<T: BitSerde + RawBits + PartialEq + Debug>() {
+        for raw in 0..1u128 << T::width() {
+            let value = T::from_raw(raw);
+            assert!(value.to_raw() == raw);
+            round_trip(&value);
+        }
+    }
+
+    /// Edge values and a spread of others, for types too wide to cover.
+    fn sampled<T: BitSerde + RawBits + PartialEq + Debug>() {
+        let width = T::width();
+        let mask = u128::MAX >> (128 - width);
+        let mut raws = vec![0, 1, mask, mask - 1, mask >> 1, (mask >> 1) + 1];
+        raws.extend([
+            0x5555_5555_5555_5555_5555_5555_5555_5555u128 & mask,
+            !0x5555 & mask,
+        ]);
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..64 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            raws.push(((state as u128) << 64 | state.rotate_left(29) as u128) & mask);
+        }
+        for raw in raws {
+            let value = T::from_raw(raw);
+            assert!(value.to_raw() == raw);
+            round_trip(&value);
+        }
+    }
+
+    macro_rules! for_types {
+        ($check:ident: $($type_name:ty),* $(,)?) => {
+            $( $check::<$type_name>(); )*
+        };
+    }
+
+    #[test]
+    fn test_exhaustive() {
+        for_types!(exhaustive: bool, u1, u2, u3, u4, u5, u6, u7, u8, u9, u10, u11, u12, u16);
+        for_types!(exhaustive: i1, i2, i3, i4, i5, i6, i7, i8, i9, i10, i11, i12, i16);
+    }
+
+    #[test]
+    fn test_sampled() {
+        for_types!(sampled: u13, u20, u24, u31, u32, u33, u48, u63, u64, u65, u100, u127, u128);
+        for_types!(sampled: i13, i20, i24, i31, i32, i33, i48, i63, i64, i65, i100, i127, i128);
+        round_trip(&[0xde, 0xad, 0xbe, 0xef, 0x00, 0x01]);
+        round_trip(&[0x5a; 20]);
+        round_trip(&Ipv4Addr::new(192, 0, 2, 1));
+        round_trip(&"2001:db8::1".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn test_layouts() {
+        let encode = |value: u16, order: BitOrder| {
+            let mut bytes = [0; 2];
+            value.write_at(&mut bytes, 0, order);
+            bytes
+        };
+        assert!(encode(0x1234, ORDERS[0]) == [0x12, 0x34]);
+        assert!(encode(0x1234, ORDERS[1]) == [0x34, 0x12]);
+        assert!(encode(0x1234, ORDERS[2]) == [0x12, 0x34]);
+        assert!(encode(0x1234, ORDERS[3]) == [0x34, 0x12]);
+
+        let mut bytes = [0; 2];
+        u4::new(5).write_at(&mut bytes, 0, ORDERS[0]);
+        u4::new(5).write_at(&mut bytes, 8, ORDERS[3]);
+        assert!(bytes == [0x50, 0x05]);
+        u12::new(0xabc).write_at(&mut bytes, 4, ORDERS[3]);
+        assert!(bytes == [0xc0, 0xab]);
+        assert!(u12::read_at(&[0x0a, 0xbc], 4, ORDERS[0]) == u12::new(0xabc));
+
+        let addr = Ipv4Addr::new(192, 0, 2, 1);
+        assert!(BitSerde::to_bits(&addr) == [192, 0, 2, 1]);
+        let mut bytes = [0; 5];
+        addr.write_at(&mut bytes, 4, ORDERS[1]);
+        assert!(bytes == [0x00, 0x10, 0x20, 0x0c, 0x00]);
+
+        assert!(BitSerde::to_bits(&u4::new(5)) == [0x05]);
+        assert!(BitSerde::to_bits(&i4::new(-1)) == [0x0f]);
+        assert!(<u13 as BitSerde>::from_bits(&[0x1f, 0xff]) == u13::MAX);
+        assert!(<i13 as BitSerde>::from_bits(&[0x1f, 0xff]) == i13::new(-1));
+        assert!(u32::read_at(&[0xab], 0, BitOrder::NETWORK) == 0xab00_0000);
+    }
+
+    #[test]
+    #[should_panic(expected = "whole bytes")]
+    fn test_partial_bytes_swap() {
+        u12::new(1).write_at(&mut [0; 2], 0, ORDERS[1]);
+    }
 }
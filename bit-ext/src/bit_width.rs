@@ -1,4 +1,5 @@
 use arbitrary_int::prelude::*;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 pub trait BitWidth {
     fn width() -> usize;
@@ -279,4 +280,15 @@ impl_bit_width!(u127, 127, 16);
 impl_bit_width!(u128, 128, 16);
 
 impl_bit_width!(bool, 1, 8);
-// impl_bit_width!()
+impl_bit_width!(Ipv4Addr, 32, 4);
+impl_bit_width!(Ipv6Addr, 128, 16);
+
+impl<const N: usize> BitWidth for [u8; N] {
+    fn width() -> usize {
+        N * 8
+    }
+
+    fn size() -> usize {
+        N
+    }
+}
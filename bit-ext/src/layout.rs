@@ -0,0 +1,219 @@
+use crate::bit_width::BitWidth;
+use arbitrary_int::prelude::*;
+
+/// Activates an optional field, given the header bytes.
+pub type ActivateCallback = fn(&[u8]) -> bool;
+
+/// Determines the number of repeats of a field, given the header bytes.
+pub type RepeatCallback = fn(&[u8]) -> usize;
+
+/// Skips `S` bits of a layout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PadBits<const S: usize>;
+
+/// Skips `S` bytes of a layout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PadBytes<const S: usize>;
+
+impl<const S: usize> BitWidth for PadBits<S> {
+    fn width() -> usize {
+        S
+    }
+
+    fn size() -> usize {
+        S.div_ceil(8)
+    }
+}
+
+impl<const S: usize> BitWidth for PadBytes<S> {
+    fn width() -> usize {
+        S * 8
+    }
+
+    fn size() -> usize {
+        S
+    }
+}
+
+/// Reads `width` bits starting `offset` bits into `bytes`, most significant
+/// bit first. Bits past the end of `bytes` read as zero.
+pub fn read_bits(bytes: &[u8], offset: usize, width: usize) -> u128 {
+    assert!(width <= 128, "can't read more than 128 bits");
+    let end = offset + width;
+    let mut value = 0u128;
+    let mut bit = offset;
+    while bit < end {
+        let used = bit % 8;
+        let take = (8 - used).min(end - bit);
+        let byte = bytes.get(bit / 8).copied().unwrap_or(0);
+        let chunk = (byte >> (8 - used - take)) & low_mask(take);
+        value = (value << take) | chunk as u128;
+        bit += take;
+    }
+    value
+}
+
+/// Writes the low `width` bits of `value` starting `offset` bits into
+/// `bytes`, most significant bit first, leaving the bits around it alone.
+pub fn write_bits(bytes: &mut [u8], offset: usize, width: usize, value: u128) {
+    assert!(width <= 128, "can't write more than 128 bits");
+    let end = offset + width;
+    let mut bit = offset;
+    while bit < end {
+        let used = bit % 8;
+        let take = (8 - used).min(end - bit);
+        let shift = 8 - used - take;
+        let chunk = (value >> (end - bit - take)) as u8 & low_mask(take);
+        let byte = &mut bytes[bit / 8];
+        *byte = (*byte & !(low_mask(take) << shift)) | (chunk << shift);
+        bit += take;
+    }
+}
+
+/// Reads `width` bits starting `offset` bits into `bytes`, counting bits
+/// from the least significant end of each byte. The bit at `offset` becomes
+/// the value's least significant bit. Bits past the end of `bytes` read as
+/// zero.
+pub fn read_bits_lsb(bytes: &[u8], offset: usize, width: usize) -> u128 {
+    assert!(width <= 128, "can't read more than 128 bits");
+    let mut value = 0u128;
+    let mut done = 0;
+    while done < width {
+        let bit = offset + done;
+        let used = bit % 8;
+        let take = (8 - used).min(width - done);
+        let byte = bytes.get(bit / 8).copied().unwrap_or(0);
+        let chunk = (byte >> used) & low_mask(take);
+        value |= (chunk as u128) << done;
+        done += take;
+    }
+    value
+}
+
+/// Writes the low `width` bits of `value` starting `offset` bits into
+/// `bytes`, counting bits from the least significant end of each byte.
+pub fn write_bits_lsb(bytes: &mut [u8], offset: usize, width: usize, value: u128) {
+    assert!(width <= 128, "can't write more than 128 bits");
+    let mut done = 0;
+    while done < width {
+        let bit = offset + done;
+        let used = bit % 8;
+        let take = (8 - used).min(width - done);
+        let chunk = (value >> done) as u8 & low_mask(take);
+        let byte = &mut bytes[bit / 8];
+        *byte = (*byte & !(low_mask(take) << used)) | (chunk << used);
+        done += take;
+    }
+}
+
+/// Reverses the byte order of a `width` bit value, for little endian fields.
+pub fn swap_bytes(value: u128, width: usize) -> u128 {
+    assert!(
+        width.is_multiple_of(8),
+        "little endian fields must be whole bytes"
+    );
+    if width == 0 {
+        return 0;
+    }
+    value.swap_bytes() >> (128 - width)
+}
+
+fn low_mask(bits: usize) -> u8 {
+    ((1u16 << bits) - 1) as u8
+}
+
+/// Converts a field value to and from the raw bits it occupies.
+pub trait RawBits: BitWidth + Sized {
+    /// Builds a value from its low `width()` bits. Signed types are sign
+    /// extended.
+    fn from_raw(raw: u128) -> Self;
+
+    /// The value's bits, zero extended.
+    fn to_raw(&self) -> u128;
+}
+
+macro_rules! impl_raw_bits {
+    ($($type_name:ident),* $(,)?) => {
+        $(
+            impl RawBits for $type_name {
+                fn from_raw(raw: u128) -> Self {
+                    <$type_name as Integer>::masked_new(raw)
+                }
+
+                fn to_raw(&self) -> u128 {
+                    self.to_unsigned().as_u128()
+                }
+            }
+        )*
+    };
+}
+
+impl_raw_bits!(
+    u1, u2, u3, u4, u5, u6, u7, u8, u9, u10, u11, u12, u13, u14, u15, u16, u17, u18, u19, u20, u21,
+    u22, u23, u24, u25, u26, u27, u28, u29, u30, u31, u32, u33, u34, u35, u36, u37, u38, u39, u40,
+    u41, u42, u43, u44, u45, u46, u47, u48, u49, u50, u51, u52, u53, u54, u55, u56, u57, u58, u59,
+    u60, u61, u62, u63, u64, u65, u66, u67, u68, u69, u70, u71, u72, u73, u74, u75, u76, u77, u78,
+    u79, u80, u81, u82, u83, u84, u85, u86, u87, u88, u89, u90, u91, u92, u93, u94, u95, u96, u97,
+    u98, u99, u100, u101, u102, u103, u104, u105, u106, u107, u108, u109, u110, u111, u112, u113,
+    u114, u115, u116, u117, u118, u119, u120, u121, u122, u123, u124, u125, u126, u127, u128
+);
+
+impl_raw_bits!(
+    i1, i2, i3, i4, i5, i6, i7, i8, i9, i10, i11, i12, i13, i14, i15, i16, i17, i18, i19, i20, i21,
+    i22, i23, i24, i25, i26, i27, i28, i29, i30, i31, i32, i33, i34, i35, i36, i37, i38, i39, i40,
+    i41, i42, i43, i44, i45, i46, i47, i48, i49, i50, i51, i52, i53, i54, i55, i56, i57, i58, i59,
+    i60, i61, i62, i63, i64, i65, i66, i67, i68, i69, i70, i71, i72, i73, i74, i75, i76, i77, i78,
+    i79, i80, i81, i82, i83, i84, i85, i86, i87, i88, i89, i90, i91, i92, i93, i94, i95, i96, i97,
+    i98, i99, i100, i101, i102, i103, i104, i105, i106, i107, i108, i109, i110, i111, i112, i113,
+    i114, i115, i116, i117, i118, i119, i120, i121, i122, i123, i124, i125, i126, i127, i128
+);
+
+impl RawBits for bool {
+    fn from_raw(raw: u128) -> Self {
+        raw & 1 != 0
+    }
+
+    fn to_raw(&self) -> u128 {
+        *self as u128
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_write() {
+        let bytes = [0x45, 0x3c, 0x00, 0x2d];
+        assert!(read_bits(&bytes, 0, 4) == 4);
+        assert!(read_bits(&bytes, 4, 4) == 5);
+        assert!(read_bits(&bytes, 4, 12) == 0x53c);
+        assert!(read_bits(&bytes, 16, 16) == 45);
+        assert!(read_bits(&bytes, 30, 6) == 0b01_0000);
+
+        let mut out = [0xffu8; 4];
+        write_bits(&mut out, 3, 13, 0);
+        assert!(out == [0xe0, 0x00, 0xff, 0xff]);
+        write_bits(&mut out, 4, 12, 0x53c);
+        assert!(out == [0xe5, 0x3c, 0xff, 0xff]);
+        write_bits(&mut out, 0, 32, 0x0102_0304);
+        assert!(out == [1, 2, 3, 4]);
+
+        assert!(read_bits_lsb(&bytes, 0, 4) == 5);
+        assert!(read_bits_lsb(&bytes, 4, 8) == 0xc4);
+        write_bits_lsb(&mut out, 4, 12, 0xabc);
+        assert!(out == [0xc1, 0xab, 3, 4]);
+    }
+
+    #[test]
+    fn test_raw_bits() {
+        assert!(u4::from_raw(0x1f) == u4::new(0xf));
+        assert!(i4::from_raw(0xf) == i4::new(-1));
+        assert!(i4::new(-2).to_raw() == 0xe);
+        assert!((-1i8).to_raw() == 0xff);
+        assert!(u13::new(0x1abc).to_raw() == 0x1abc);
+        assert!(bool::from_raw(1));
+        assert!(swap_bytes(0x1234, 16) == 0x3412);
+        assert!(swap_bytes(0x01_0203, 24) == 0x03_0201);
+    }
+}
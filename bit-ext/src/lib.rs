@@ -1,13 +1,28 @@
 mod bit_serde;
 mod bit_width;
+mod layout;
 
-/// Most significant bit orientation
+/// Most significant bit orientation. With `Left`, bit 0 of a byte is its
+/// most significant bit, as in network protocols. With `Right`, bit 0 is the
+/// least significant bit and values fill bits from the bottom up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Msb {
+    #[default]
     Left,
     Right,
 }
 
+/// Byte order of values wider than a byte.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
 pub mod prelude {
     pub use crate::bit_serde::*;
     pub use crate::bit_width::*;
+    pub use crate::layout::*;
+    pub use crate::{Endian, Msb};
 }
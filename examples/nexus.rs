@@ -1,40 +1,39 @@
 use chrono::DateTime;
 use nexus::ethernet::Ethernet;
 use nexus::ip::Ip;
+use nexus::pcap::PcapReader;
 use nexus::pdu::{Pdu, deserialize};
 use nexus::utils::printable_ascii;
-use pcap::Capture;
 
 fn main() {
-    let mut cap = Capture::from_file("./data/test.pcapng").unwrap();
-    let mut index = 0;
+    let bytes = std::fs::read("./data/test.pcapng").unwrap();
+    let reader = PcapReader::new(&bytes).unwrap();
 
     let mut pkt_vec: Vec<Box<dyn Pdu<'_>>> = Vec::new();
 
-    while let Ok(packet) = cap.next_packet() {
-        index += 1;
+    for (index, record) in reader.enumerate() {
+        let Ok(mut record) = record else {
+            break;
+        };
         println!(
             "{} {:?} {}",
-            index,
+            index + 1,
             DateTime::from_timestamp(
-                packet.header.ts.tv_sec,
-                (packet.header.ts.tv_usec * 1_000) as u32
+                record.timestamp.as_secs() as i64,
+                record.timestamp.subsec_nanos()
             )
             .unwrap(),
-            printable_ascii(packet.data)
+            printable_ascii(record.data)
         );
 
-        let Ok(mut eth_pdu) = Ethernet::from_bytes(&packet.data) else {
-            continue;
-        };
-
+        let eth_pdu = &mut record.pdu;
         pkt_vec.push(eth_pdu.clone());
         let Some(eth_pdu4) = eth_pdu.downcast_mut::<Ethernet>() else {
             continue;
         };
         println!("{}", eth_pdu4.ether_type());
 
-        if let Some(mut eth_pdu3) = deserialize::<Ethernet>(&packet.data) {
+        if let Some(mut eth_pdu3) = deserialize::<Ethernet>(record.data) {
             println!("Old3 ether type {}", eth_pdu3.ether_type());
             eth_pdu3.set_ether_type(1);
             println!("New3 ether type {}", eth_pdu3.ether_type());
@@ -1,168 +1,481 @@
 use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
 use quote::{format_ident, quote};
 use syn::{Data, DeriveInput, Fields, parse_macro_input};
 
 pub(crate) mod types;
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Endian {
+    Big,
+    Little,
+}
+
+/// Options of a `#[field(...)]` attribute.
 struct FieldMeta {
+    /// Keeps the field's bits in the layout without generating accessors.
     skip: bool,
+    /// Bits of padding after the field.
     pad_right: usize,
+    /// Bits of padding before the field.
     pad_left: usize,
-    repeat: Option<fn(&[u8]) -> usize>,
-    enable: Option<fn(&[u8]) -> bool>,
-    endian: String,
+    /// A `fn(&[u8]) -> usize` giving the number of repeats.
+    repeat: Option<syn::Path>,
+    /// A `fn(&[u8]) -> bool` deciding whether the field is present.
+    enable: Option<syn::Path>,
+    endian: Endian,
 }
 
-#[proc_macro_derive(Protocol, attributes(field, header))]
-pub fn derive_protocol(input_stream: TokenStream) -> TokenStream {
-    // let tokens = input_stream.clone();
-    let input: DeriveInput = parse_macro_input!(input_stream as DeriveInput);
+impl Default for FieldMeta {
+    fn default() -> Self {
+        Self {
+            skip: false,
+            pad_right: 0,
+            pad_left: 0,
+            repeat: None,
+            enable: None,
+            endian: Endian::Big,
+        }
+    }
+}
 
-    let mut marked_fields: Vec<(syn::Ident, syn::Type)> = Vec::new();
+impl FieldMeta {
+    fn parse(attr: &syn::Attribute) -> syn::Result<Self> {
+        let mut meta = FieldMeta::default();
+        let syn::Meta::List(_) = &attr.meta else {
+            return Ok(meta);
+        };
+        let mut seen: Vec<String> = Vec::new();
+        attr.parse_nested_meta(|option| {
+            let Some(key) = option.path.get_ident().map(ToString::to_string) else {
+                return Err(option.error("expected a field option"));
+            };
+            if seen.contains(&key) {
+                return Err(option.error(format!("duplicate field option `{}`", key)));
+            }
+            seen.push(key.clone());
+            match key.as_str() {
+                "skip" | "hidden" => {
+                    meta.skip = if option.input.peek(syn::Token![=]) {
+                        option.value()?.parse::<syn::LitBool>()?.value
+                    } else {
+                        true
+                    };
+                }
+                "pad_left" => {
+                    meta.pad_left = option.value()?.parse::<syn::LitInt>()?.base10_parse()?
+                }
+                "pad_right" => {
+                    meta.pad_right = option.value()?.parse::<syn::LitInt>()?.base10_parse()?
+                }
+                "repeat" => meta.repeat = Some(option.value()?.parse()?),
+                "enable" | "activate" => {
+                    if meta.enable.is_some() {
+                        return Err(option.error("`enable` and `activate` are the same option"));
+                    }
+                    meta.enable = Some(option.value()?.parse()?);
+                }
+                "endian" => {
+                    let endian: syn::LitStr = option.value()?.parse()?;
+                    meta.endian = match endian.value().as_str() {
+                        "big" => Endian::Big,
+                        "little" => Endian::Little,
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                endian,
+                                "endian must be \"big\" or \"little\"",
+                            ));
+                        }
+                    };
+                }
+                _ => return Err(option.error(format!("unknown field option `{}`", key))),
+            }
+            Ok(())
+        })?;
+        if meta.repeat.is_some() && meta.enable.is_some() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "a field can't be both repeated and optional",
+            ));
+        }
+        Ok(meta)
+    }
+}
 
-    let ident = &input.ident;
-    let generics = &input.generics;
-    let where_clause = &generics.where_clause;
+/// One entry of the layout: a marked field or padding.
+struct LayoutItem {
+    name: syn::Ident,
+    ty: syn::Type,
+    meta: FieldMeta,
+    pad: bool,
+}
 
-    let data = match &input.data {
-        Data::Struct(data) => data,
+/// The width of types the macro can size on its own, for layout checks.
+fn known_width(ty: &syn::Type) -> Option<usize> {
+    let path = match ty {
+        syn::Type::Path(path) => path,
+        syn::Type::Array(array) => {
+            let syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(len),
+                ..
+            }) = &array.len
+            else {
+                return None;
+            };
+            return (known_width(&array.elem)? == 8)
+                .then_some(len.base10_parse::<usize>().ok()? * 8);
+        }
+        _ => return None,
+    };
+    let segment = path.path.segments.last()?;
+    let name = segment.ident.to_string();
+    match name.as_str() {
+        "bool" => Some(1),
+        "Ipv4Addr" => Some(32),
+        "Ipv6Addr" => Some(128),
+        "PadBits" | "PadBytes" => {
+            let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+                return None;
+            };
+            let Some(syn::GenericArgument::Const(syn::Expr::Lit(lit))) = args.args.first() else {
+                return None;
+            };
+            let syn::Lit::Int(size) = &lit.lit else {
+                return None;
+            };
+            let size: usize = size.base10_parse().ok()?;
+            Some(if name == "PadBits" { size } else { size * 8 })
+        }
         _ => {
-            return syn::Error::new_spanned(input.ident, "Protocol only supports structs")
-                .to_compile_error()
-                .into();
+            let bits: usize = name.strip_prefix(['u', 'i'])?.parse().ok()?;
+            (1..=128).contains(&bits).then_some(bits)
         }
+    }
+}
+
+fn is_pad(ty: &syn::Type) -> bool {
+    let syn::Type::Path(path) = ty else {
+        return false;
     };
+    path.path
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "PadBits" || segment.ident == "PadBytes")
+}
 
-    match &data.fields {
-        Fields::Named(fields) => {
-            for field in &fields.named {
-                for attr in &field.attrs {
-                    if attr.path().is_ident("field") {
-                        match &attr.meta {
-                            syn::Meta::List(_list) => {
-                                // parse list.tokens
-                            }
-                            syn::Meta::Path(_) => {
-                                // #[field] (no args)
-                            }
-                            _ => {}
-                        }
-                    }
-                    if attr.path().is_ident("header") {
-                        continue;
-                    }
-                }
+fn collect_layout(input: &DeriveInput) -> syn::Result<Vec<LayoutItem>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Protocol only supports structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &data.fields,
+            "Protocol requires named fields",
+        ));
+    };
 
-                let has_attr = field.attrs.iter().any(|a| a.path().is_ident("field"));
+    let has_header = fields.named.iter().any(|field| {
+        field
+            .ident
+            .as_ref()
+            .is_some_and(|ident| ident == "__header")
+    });
+    if !has_header {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Protocol needs a `__header: Cow<[u8]>` field, add #[proto_base]",
+        ));
+    }
 
-                if has_attr {
-                    let ident = field.ident.clone().unwrap();
-                    let ty = field.ty.clone();
-                    marked_fields.push((ident, ty));
-                }
+    let mut errors: Option<syn::Error> = None;
+    let mut push_error = |error: syn::Error| match &mut errors {
+        Some(errors) => errors.combine(error),
+        None => errors = Some(error),
+    };
+
+    let mut layout = Vec::new();
+    for field in &fields.named {
+        let attr = field
+            .attrs
+            .iter()
+            .find(|attr| attr.path().is_ident("field"));
+        let pad = is_pad(&field.ty);
+        if attr.is_none() && !pad {
+            continue;
+        }
+        let meta = match attr.map(FieldMeta::parse).transpose() {
+            Ok(meta) => meta.unwrap_or_default(),
+            Err(error) => {
+                push_error(error);
+                continue;
             }
+        };
+        let width = known_width(&field.ty);
+        if pad && width.is_none() {
+            push_error(syn::Error::new_spanned(
+                &field.ty,
+                "padding needs a literal size",
+            ));
         }
-        _ => {
-            return syn::Error::new_spanned(&data.fields, "Protocol requires named fields")
-                .to_compile_error()
-                .into();
+        if width == Some(0) {
+            push_error(syn::Error::new_spanned(
+                &field.ty,
+                "fields must be at least one bit wide",
+            ));
+        }
+        if meta.endian == Endian::Little
+            && let Some(width) = width
+            && !width.is_multiple_of(8)
+        {
+            push_error(syn::Error::new_spanned(
+                &field.ty,
+                format!("little endian fields must be whole bytes, this one is {width} bits"),
+            ));
         }
+        layout.push(LayoutItem {
+            name: field.ident.clone().unwrap(),
+            ty: field.ty.clone(),
+            meta,
+            pad,
+        });
     }
 
-    let mut fields_to_sum: Vec<syn::Type> = vec![];
-    match input.clone().data {
-        Data::Struct(mut data) => {
-            fields_to_sum = data
-                .fields
-                .iter_mut()
-                .filter_map(|f| {
-                    let has_field_attr = f.attrs.iter().any(|attr| attr.path().is_ident("field"));
-                    if has_field_attr {
-                        Some(f.ty.clone())
-                    } else {
-                        None
-                    }
-                })
-                .collect();
+    // A layout whose every width is known must add up to whole bytes.
+    let fixed = layout
+        .iter()
+        .all(|item| item.meta.repeat.is_none() && item.meta.enable.is_none());
+    let widths: Option<Vec<usize>> = layout
+        .iter()
+        .map(|item| {
+            known_width(&item.ty).map(|width| item.meta.pad_left + width + item.meta.pad_right)
+        })
+        .collect();
+    if fixed && let Some(widths) = widths {
+        let total: usize = widths.iter().sum();
+        if !total.is_multiple_of(8) {
+            push_error(syn::Error::new_spanned(
+                &input.ident,
+                format!("the layout is {total} bits, which isn't a whole number of bytes"),
+            ));
         }
-        _ => (),
+    }
+
+    match errors {
+        Some(errors) => Err(errors),
+        None => Ok(layout),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let layout = collect_layout(&input)?;
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let width_of = |ty: &syn::Type| quote!(<#ty as ::bit_ext::prelude::BitWidth>::width());
+    let count_of = |meta: &FieldMeta| match (&meta.repeat, &meta.enable) {
+        (Some(repeat), _) => quote!((#repeat)(&self.__header)),
+        (_, Some(enable)) => quote!(usize::from((#enable)(&self.__header))),
+        _ => quote!(1),
     };
 
-    let field_names = marked_fields.iter().map(|(ident, _)| ident.to_string());
+    let field_names = layout
+        .iter()
+        .filter(|item| !item.pad)
+        .map(|item| item.name.to_string());
+    let fixed_widths = layout
+        .iter()
+        .filter(|item| item.meta.repeat.is_none() && item.meta.enable.is_none())
+        .map(|item| {
+            let width = width_of(&item.ty);
+            let pad = item.meta.pad_left + item.meta.pad_right;
+            quote!(#pad + #width)
+        });
+    let spans: Vec<TokenStream2> = layout
+        .iter()
+        .map(|item| {
+            let width = width_of(&item.ty);
+            let count = count_of(&item.meta);
+            let pad = item.meta.pad_left + item.meta.pad_right;
+            quote!(#pad + #width * #count)
+        })
+        .collect();
 
-    let base_impl_expanded = quote! {
-        impl #generics #ident #generics #where_clause {
+    let base_impl = quote! {
+        #[automatically_derived]
+        impl #impl_generics #ident #ty_generics #where_clause {
             pub fn marked_fields(&self) -> &'static [&'static str] {
                 &[#(#field_names),*]
             }
 
+            /// Bits taken by the fields that are always present once.
             pub fn total_width() -> usize {
-                0 #( + #fields_to_sum::width() )*
+                0 #( + #fixed_widths )*
+            }
+
+            /// Bits taken by the header as laid out by its current contents.
+            pub fn header_width(&self) -> usize {
+                0 #( + #spans )*
             }
         }
     };
 
-    let mut gen_methods: Vec<syn::ItemImpl> = Vec::new();
-    let mut summed_fields: Vec<syn::Type> = Vec::new();
-    for (name, ty) in marked_fields.iter() {
-        let field_set_fn_name = format_ident!("set_{}", name);
-        let field_with_fn_name = format_ident!("with_{}", name);
-        let field_get_fn_name = format_ident!("{}", name);
-        let field_meta_fn_name = format_ident! {"__{}_metadata", name};
+    let mut gen_methods = Vec::new();
+    for (index, item) in layout.iter().enumerate() {
+        if item.pad {
+            continue;
+        }
+        let LayoutItem { name, ty, meta, .. } = item;
+        let prior = &spans[..index];
+        let pad_left = meta.pad_left;
+        let width = width_of(ty);
+        let count = count_of(meta);
+        let meta_fn = format_ident!("__{}_metadata", name);
 
-        let bit_offset_low_stmt: syn::Stmt = syn::parse_quote! {
-            let bit_offset_low = 0 #( + #summed_fields::width() )*;
-        };
+        gen_methods.push(quote! {
+            #[automatically_derived]
+            impl #impl_generics #ident #ty_generics #where_clause {
+                /// Bit and byte ranges of the field in the header, as
+                /// `(bit_low, bit_high, byte_low, byte_high)`.
+                pub fn #meta_fn(&self) -> (usize, usize, usize, usize) {
+                    let bit_offset_low = 0 #( + #prior )* + #pad_left;
+                    let bit_offset_high = bit_offset_low + #width * #count;
+                    (
+                        bit_offset_low,
+                        bit_offset_high,
+                        bit_offset_low / 8,
+                        bit_offset_high.div_ceil(8),
+                    )
+                }
+            }
+        });
+        if meta.skip {
+            continue;
+        }
 
-        let bit_offset_high_stmt: syn::Stmt = syn::parse_quote! {
-            let bit_offset_high = bit_offset_low + #ty::width();
+        let read_fn = format_ident!("__read_{}", name);
+        let write_fn = format_ident!("__write_{}", name);
+        let set_fn = format_ident!("set_{}", name);
+        let with_fn = format_ident!("with_{}", name);
+        let endian = match meta.endian {
+            Endian::Big => quote!(::bit_ext::prelude::Endian::Big),
+            Endian::Little => quote!(::bit_ext::prelude::Endian::Little),
         };
+        let order =
+            quote!(::bit_ext::prelude::BitOrder::new(::bit_ext::prelude::Msb::Left, #endian));
+        let name_str = name.to_string();
 
-        let byte_offset_low_stmt: syn::Stmt = syn::parse_quote! {
-            let byte_offset_low = bit_offset_low / 8;
-        };
+        let accessors = if meta.repeat.is_some() {
+            quote! {
+                pub fn #name(&self) -> Vec<#ty> {
+                    let (low, high, _, _) = self.#meta_fn();
+                    (low..high).step_by(#width).map(|bit| self.#read_fn(bit)).collect()
+                }
 
-        let byte_offset_high_stmt: syn::Stmt = syn::parse_quote! {
-            let byte_offset_high = bit_offset_high.div_ceil(8);
-        };
+                /// Panics unless there is one value per repeat.
+                pub fn #set_fn(&mut self, values: &[#ty]) {
+                    let (low, high, _, _) = self.#meta_fn();
+                    assert!(
+                        values.len() * #width == high - low,
+                        "wrong number of values for `{}`",
+                        #name_str
+                    );
+                    for (bit, value) in (low..high).step_by(#width).zip(values) {
+                        self.#write_fn(bit, value);
+                    }
+                }
+
+                pub fn #with_fn(&mut self, values: &[#ty]) -> &mut Self {
+                    self.#set_fn(values);
+                    self
+                }
+            }
+        } else if meta.enable.is_some() {
+            quote! {
+                pub fn #name(&self) -> Option<#ty> {
+                    let (low, high, _, _) = self.#meta_fn();
+                    (high > low).then(|| self.#read_fn(low))
+                }
 
-        // let byte_boundary_panic_stmt: syn::Stmt = syn::parse_quote! {
-        //     #byte_offset_low_stmt
-        //     #byte_offset_high_stmt
-        //     if (byte_offset_high - byte_offset_low) > #ty::width() {
-        //         panic!("Byte slice sizes greater than type width is not currently supported");
-        //     }
-        // };
+                /// Panics if the field is disabled.
+                pub fn #set_fn(&mut self, value: #ty) {
+                    let (low, high, _, _) = self.#meta_fn();
+                    assert!(high > low, "`{}` is disabled", #name_str);
+                    self.#write_fn(low, &value);
+                }
 
-        // let bit_mask: syn::Stmt = syn::parse_quote! {};
+                pub fn #with_fn(&mut self, value: #ty) -> &mut Self {
+                    self.#set_fn(value);
+                    self
+                }
+            }
+        } else {
+            quote! {
+                pub fn #name(&self) -> #ty {
+                    let (low, _, _, _) = self.#meta_fn();
+                    self.#read_fn(low)
+                }
 
-        let field_get: syn::ItemImpl = syn::parse_quote! {
-            impl #generics #ident #generics #where_clause {
-                pub fn #field_get_fn_name(&self) -> #ty {
-                    // 0 #( + #summed_fields::width() )*
-                    self.#name
+                pub fn #set_fn(&mut self, value: #ty) {
+                    let (low, _, _, _) = self.#meta_fn();
+                    self.#write_fn(low, &value);
                 }
 
-                pub fn #field_meta_fn_name(&self) -> (usize, usize, usize, usize) {
-                    #bit_offset_low_stmt
-                    #bit_offset_high_stmt
-                    #byte_offset_low_stmt
-                    #byte_offset_high_stmt
-                    (bit_offset_low, bit_offset_high, byte_offset_low, byte_offset_high)
+                pub fn #with_fn(&mut self, value: #ty) -> &mut Self {
+                    self.#set_fn(value);
+                    self
                 }
             }
         };
 
-        summed_fields.push(ty.clone());
-        gen_methods.push(field_get);
-    }
+        gen_methods.push(quote! {
+            #[automatically_derived]
+            impl #impl_generics #ident #ty_generics #where_clause {
+                #accessors
 
-    quote! {
-        // #struct_expanded
-        #base_impl_expanded
-        # ( #gen_methods )*
+                fn #read_fn(&self, bit: usize) -> #ty {
+                    <#ty as ::bit_ext::prelude::BitSerde>::read_at(&self.__header, bit, #order)
+                }
+
+                /// Grows the header if the field runs past its end.
+                fn #write_fn(&mut self, bit: usize, value: &#ty) {
+                    let header = self.__header.to_mut();
+                    let end = (bit + #width).div_ceil(8);
+                    if header.len() < end {
+                        header.resize(end, 0);
+                    }
+                    ::bit_ext::prelude::BitSerde::write_at(value, header, bit, #order);
+                }
+            }
+        });
     }
-    .into()
+
+    Ok(quote! {
+        #base_impl
+        #( #gen_methods )*
+    })
+}
+
+/// Generates accessors over `__header` for fields marked `#[field]`, laid
+/// out in declaration order. Getters read missing bytes as zero and setters
+/// grow the header, so a default struct reads as all zeros. `PadBits<N>`
+/// and `PadBytes<N>` fields reserve space without accessors. Options:
+///
+/// - `skip` (or `hidden = true`): keep the bits, generate no accessors
+/// - `pad_left = N`, `pad_right = N`: bits of padding around the field
+/// - `repeat = f`: `f(&header) -> usize` repeats, accessed as a `Vec`
+/// - `enable = f` (or `activate = f`): `f(&header) -> bool` decides if the
+///   field is present, accessed as an `Option`
+/// - `endian = "little"`: byte swap a whole-byte field
+#[proc_macro_derive(Protocol, attributes(field, header))]
+pub fn derive_protocol(input_stream: TokenStream) -> TokenStream {
+    let input: DeriveInput = parse_macro_input!(input_stream as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
 }
 
 #[proc_macro_attribute]
@@ -197,3 +510,91 @@ pub fn protocol(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
     quote!(#struct_kind).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: DeriveInput) -> String {
+        expand(input).unwrap_err().to_string()
+    }
+
+    fn errors(input: DeriveInput) -> Vec<String> {
+        let error = expand(input).unwrap_err();
+        error.into_iter().map(|error| error.to_string()).collect()
+    }
+
+    #[test]
+    fn test_layout_errors() {
+        let odd = syn::parse_quote! {
+            struct Odd<'a> {
+                #[field]
+                version: u4,
+                #[field]
+                flag: u1,
+                __header: Cow<'a, [u8]>,
+            }
+        };
+        assert!(error(odd) == "the layout is 5 bits, which isn't a whole number of bytes");
+
+        let headless = syn::parse_quote! {
+            struct Headless {
+                #[field]
+                version: u8,
+            }
+        };
+        assert!(error(headless).contains("add #[proto_base]"));
+
+        let little = syn::parse_quote! {
+            struct Little<'a> {
+                #[field(endian = "little")]
+                value: u12,
+                pad: PadBits<4>,
+                __header: Cow<'a, [u8]>,
+            }
+        };
+        assert!(error(little) == "little endian fields must be whole bytes, this one is 12 bits");
+
+        let options = syn::parse_quote! {
+            struct Options<'a> {
+                #[field(color = 3)]
+                value: u8,
+                #[field(repeat = count, enable = present)]
+                other: u8,
+                #[field(endian = "middle")]
+                third: u8,
+                __header: Cow<'a, [u8]>,
+            }
+        };
+        assert!(
+            errors(options)
+                == [
+                    "unknown field option `color`",
+                    "a field can't be both repeated and optional",
+                    "endian must be \"big\" or \"little\"",
+                ]
+        );
+    }
+
+    #[test]
+    fn test_dynamic_layout() {
+        // Repeated fields make the width depend on the header, so it isn't
+        // checked.
+        let dynamic = syn::parse_quote! {
+            struct Dynamic<'a> {
+                #[field]
+                count: u4,
+                #[field(repeat = count_of)]
+                items: u3,
+                __header: Cow<'a, [u8]>,
+            }
+        };
+        assert!(expand(dynamic).is_ok());
+        assert!(known_width(&syn::parse_quote!(PadBytes<3>)) == Some(24));
+        assert!(known_width(&syn::parse_quote!(arbitrary_int::u13)) == Some(13));
+        assert!(known_width(&syn::parse_quote!(Ipv4Addr)) == Some(32));
+        assert!(known_width(&syn::parse_quote!([u8; 6])) == Some(48));
+        assert!(known_width(&syn::parse_quote!([u16; 2])).is_none());
+        assert!(known_width(&syn::parse_quote!(Vec<u8>)).is_none());
+    }
+}
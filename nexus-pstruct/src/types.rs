@@ -1,28 +1,18 @@
 #![allow(dead_code)]
 
+use bit_ext::prelude::{ActivateCallback, RepeatCallback};
+
 pub enum Alignment {
     Left,
     Right,
 }
 
-/// Activates a field
-pub type ActivateCallback = fn(&[u8]) -> bool;
-
-/// Determine number of repeats of a field
-pub type RepeatCallback = fn(&[u8]) -> usize;
-
 /// Constructs a byte array
 pub struct BytesField<const S: usize, const O: usize> {}
 
-/// Used to skip irrelevant bytes
-pub struct PadBytes<const S: usize> {}
-
 /// Constructs a bits field
 pub struct BitsField<const S: usize> {}
 
-/// Used to skip irrelevant bits
-pub struct PadBits<const S: usize> {}
-
 /// All Protocol fields must implement this
 pub trait ProtoField {
     fn from_bytes(bytes: &[u8]);
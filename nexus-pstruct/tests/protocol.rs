@@ -54,7 +54,6 @@ fn integ() {
     #![allow(dead_code)]
 
     use arbitrary_int::prelude::*;
-    use bit_ext::prelude::*;
     use nexus_pstruct::{Protocol, proto_base};
     use std::borrow::Cow;
 
@@ -116,3 +115,263 @@ fn integ() {
     println!("{:?}", p.__ihl_metadata());
     println!("Ipv4::total_width() = {}", Ipv4::total_width());
 }
+
+#[test]
+fn accessors() {
+    #![allow(dead_code)]
+
+    use arbitrary_int::prelude::*;
+    use nexus_pstruct::{Protocol, proto_base};
+    use std::borrow::Cow;
+
+    #[proto_base]
+    #[derive(Protocol, Default)]
+    struct Ipv4<'a> {
+        #[field]
+        version: u4,
+        #[field]
+        ihl: u4,
+        #[field]
+        tos: u8,
+        #[field]
+        total_len: u16,
+        #[field]
+        identification: u16,
+        #[field(skip)]
+        reserved: u1,
+        #[field]
+        dont_frag: bool,
+        #[field]
+        more_frags: bool,
+        #[field]
+        frag_offset: u13,
+        #[field]
+        ttl: u8,
+        #[field]
+        protocol: u8,
+        #[field]
+        checksum: u16,
+        #[field]
+        src_addr: u32,
+        #[field]
+        dst_addr: u32,
+    }
+
+    let bytes: [u8; 20] = [
+        0x45, 0x3c, 0x00, 0x2d, 0x1c, 0x46, 0x40, 0x00, 0x40, 0x06, 0x32, 0x4e, 0xc0, 0x00, 0x02,
+        0x01, 0xc6, 0x33, 0x64, 0x02,
+    ];
+    let mut ip = Ipv4 {
+        __header: Cow::Borrowed(&bytes),
+        ..Default::default()
+    };
+    assert!(Ipv4::total_width() == 160);
+    assert!(ip.header_width() == 160);
+    assert!(ip.version() == u4::new(4));
+    assert!(ip.ihl() == u4::new(5));
+    assert!(ip.tos() == 0x3c);
+    assert!(ip.total_len() == 45);
+    assert!(ip.identification() == 0x1c46);
+    assert!(ip.dont_frag());
+    assert!(!ip.more_frags());
+    assert!(ip.frag_offset() == u13::new(0));
+    assert!(ip.ttl() == 64);
+    assert!(ip.protocol() == 6);
+    assert!(ip.checksum() == 0x324e);
+    assert!(ip.src_addr() == 0xc000_0201);
+    assert!(ip.dst_addr() == 0xc633_6402);
+    assert!(ip.__frag_offset_metadata() == (51, 64, 6, 8));
+
+    ip.with_more_frags(true).with_frag_offset(u13::new(0x123));
+    assert!(matches!(ip.__header, Cow::Owned(_)));
+    assert!(ip.__header[6..8] == [0x61, 0x23]);
+    assert!(ip.dont_frag());
+    ip.set_ttl(1);
+    assert!(ip.__header[8] == 1);
+    assert!(ip.__header[..6] == bytes[..6]);
+
+    // A default struct reads as zeros and grows as fields are set.
+    let mut ip = Ipv4::default();
+    assert!(ip.ttl() == 0);
+    ip.with_version(u4::new(4)).with_ihl(u4::new(5));
+    assert!(*ip.__header == [0x45]);
+    ip.set_dst_addr(0x0a00_0001);
+    assert!(ip.__header.len() == 20);
+    assert!(ip.__header[16..] == [10, 0, 0, 1]);
+}
+
+#[test]
+fn padding_and_endian() {
+    #![allow(dead_code)]
+
+    use arbitrary_int::prelude::*;
+    use bit_ext::prelude::{PadBits, PadBytes};
+    use nexus_pstruct::{Protocol, proto_base};
+    use std::borrow::Cow;
+
+    #[proto_base]
+    #[derive(Protocol, Default)]
+    struct Record<'a> {
+        #[field]
+        kind: u8,
+        reserved: PadBits<4>,
+        #[field]
+        flags: u4,
+        #[field(endian = "little")]
+        length: u16,
+        #[field(pad_left = 3, pad_right = 4)]
+        urgent: bool,
+        spare: PadBytes<1>,
+        #[field]
+        delta: i8,
+    }
+
+    let bytes = [0x07, 0xfa, 0x34, 0x12, 0x10, 0xff, 0xfe];
+    let mut record = Record {
+        __header: Cow::Borrowed(&bytes),
+        ..Default::default()
+    };
+    assert!(Record::total_width() == 56);
+    assert!(record.marked_fields() == ["kind", "flags", "length", "urgent", "delta"]);
+    assert!(record.kind() == 7);
+    assert!(record.flags() == u4::new(0xa));
+    assert!(record.length() == 0x1234);
+    assert!(record.urgent());
+    assert!(record.delta() == -2);
+    assert!(record.__delta_metadata() == (48, 56, 6, 7));
+
+    record.with_length(0xbeef).with_urgent(false).with_delta(5);
+    assert!(*record.__header == [0x07, 0xfa, 0xef, 0xbe, 0x00, 0xff, 0x05]);
+}
+
+#[test]
+fn addresses() {
+    #![allow(dead_code)]
+
+    use arbitrary_int::prelude::*;
+    use nexus_pstruct::{Protocol, proto_base};
+    use std::borrow::Cow;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[proto_base]
+    #[derive(Protocol)]
+    struct Binding<'a> {
+        #[field]
+        flags: u4,
+        #[field]
+        scope: u4,
+        #[field]
+        mac: [u8; 6],
+        #[field(endian = "little")]
+        cookie: [u8; 2],
+        #[field]
+        v4: Ipv4Addr,
+        #[field]
+        v6: Ipv6Addr,
+    }
+
+    let mut binding = Binding {
+        __header: Cow::Owned(Vec::new()),
+        __parent: None,
+        __child: None,
+        flags: u4::new(0),
+        scope: u4::new(0),
+        mac: [0; 6],
+        cookie: [0; 2],
+        v4: Ipv4Addr::UNSPECIFIED,
+        v6: Ipv6Addr::UNSPECIFIED,
+    };
+    assert!(Binding::total_width() == 8 + 48 + 16 + 32 + 128);
+    binding
+        .with_flags(u4::new(1))
+        .with_scope(u4::new(2))
+        .with_mac([0, 1, 2, 3, 4, 5])
+        .with_cookie([0xab, 0xcd])
+        .with_v4(Ipv4Addr::new(192, 0, 2, 1))
+        .with_v6("2001:db8::1".parse().unwrap());
+    assert!(binding.__header[..13] == [0x12, 0, 1, 2, 3, 4, 5, 0xcd, 0xab, 192, 0, 2, 1]);
+    assert!(binding.__header[13..15] == [0x20, 0x01]);
+    assert!(binding.__header[28] == 1);
+    assert!(binding.mac() == [0, 1, 2, 3, 4, 5]);
+    assert!(binding.cookie() == [0xab, 0xcd]);
+    assert!(binding.v4() == Ipv4Addr::new(192, 0, 2, 1));
+    assert!(binding.v6() == "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+    assert!(binding.__v4_metadata() == (72, 104, 9, 13));
+}
+
+#[test]
+fn optional_and_repeated() {
+    #![allow(dead_code)]
+
+    use arbitrary_int::prelude::*;
+    use nexus_pstruct::{Protocol, proto_base};
+    use std::borrow::Cow;
+
+    fn has_extra(header: &[u8]) -> bool {
+        header.first().is_some_and(|byte| byte & 0x80 != 0)
+    }
+
+    fn item_count(header: &[u8]) -> usize {
+        header.first().map_or(0, |byte| (byte & 0x7f) as usize)
+    }
+
+    #[proto_base]
+    #[derive(Protocol, Default)]
+    struct Items<'a> {
+        #[field]
+        extra_present: bool,
+        #[field]
+        count: u7,
+        #[field(activate = has_extra)]
+        extra: u16,
+        #[field(repeat = item_count)]
+        items: u4,
+    }
+
+    let bytes = [0x83, 0xab, 0xcd, 0x12, 0x30];
+    let mut items = Items {
+        __header: Cow::Borrowed(&bytes),
+        ..Default::default()
+    };
+    assert!(Items::total_width() == 8);
+    assert!(items.header_width() == 36);
+    assert!(items.extra() == Some(0xabcd));
+    assert!(items.items() == [u4::new(1), u4::new(2), u4::new(3)]);
+    items.set_items(&[u4::new(4), u4::new(5), u4::new(6)]);
+    assert!(items.__header[3..] == [0x45, 0x60]);
+
+    let bytes = [0x02, 0x9a];
+    let mut items = Items {
+        __header: Cow::Borrowed(&bytes),
+        ..Default::default()
+    };
+    assert!(items.extra().is_none());
+    assert!(items.__items_metadata() == (8, 16, 1, 2));
+    assert!(items.items() == [u4::new(9), u4::new(0xa)]);
+    items.set_count(u7::new(3));
+    assert!(items.items().len() == 3);
+}
+
+#[test]
+#[should_panic(expected = "`extra` is disabled")]
+fn disabled_field() {
+    #![allow(dead_code)]
+
+    use nexus_pstruct::{Protocol, proto_base};
+    use std::borrow::Cow;
+
+    fn never(_header: &[u8]) -> bool {
+        false
+    }
+
+    #[proto_base]
+    #[derive(Protocol, Default)]
+    struct Optional<'a> {
+        #[field]
+        kind: u8,
+        #[field(enable = never)]
+        extra: u16,
+    }
+
+    Optional::default().set_extra(1);
+}
@@ -24,10 +24,7 @@ impl<'a> dyn Tid<'a> + 'a {
         }
     }
 
-    pub fn downcast<T: Tid<'a> + 'a>(self: Box<Self>) -> Option<Box<T>>
-    where
-        T: 'a,
-    {
+    pub fn downcast<T: Tid<'a> + 'a>(self: Box<Self>) -> Option<Box<T>> {
         if self.self_id() == T::id() {
             let raw = Box::into_raw(self);
             unsafe { Some(Box::from_raw(raw as *mut T)) }
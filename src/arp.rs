@@ -0,0 +1,501 @@
+use crate::ethernet::{ETHER_DISSECTION_TABLE, ETHER_TYPE_NAMES, EtherType};
+use crate::mac_address::MacAddress;
+use crate::prelude::*;
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const ARP_HW_TYPE_OFFSET: usize = 0;
+const ARP_PROTO_TYPE_OFFSET: usize = 2;
+const ARP_HW_LEN_OFFSET: usize = 4;
+const ARP_PROTO_LEN_OFFSET: usize = 5;
+const ARP_OPCODE_OFFSET: usize = 6;
+const ARP_ADDR_OFFSET: usize = 8;
+const ARP_FIXED_LEN: usize = 8;
+
+const ETHERNET_ADDR_LEN: usize = 6;
+const IPV4_ADDR_LEN: usize = 4;
+const IPV6_ADDR_LEN: usize = 16;
+
+pub const HW_TYPE_ETHERNET: u16 = 1;
+pub const PROTO_TYPE_IPV4: u16 = 0x0800;
+
+pub const ARP_REQUEST: u16 = 1;
+pub const ARP_REPLY: u16 = 2;
+pub const RARP_REQUEST: u16 = 3;
+pub const RARP_REPLY: u16 = 4;
+
+/// Total ARP packet length for the given hardware and protocol address lengths.
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc826>
+fn get_arp_len(hw_len: usize, proto_len: usize) -> usize {
+    ARP_FIXED_LEN + 2 * (hw_len + proto_len)
+}
+
+/// The length fields are a single byte, longer addresses can't be encoded.
+fn addr_len(addr: &[u8]) -> u8 {
+    u8::try_from(addr.len()).expect("ARP addresses are at most 255 bytes")
+}
+
+fn format_addr(addr: &[u8]) -> String {
+    match addr.len() {
+        ETHERNET_ADDR_LEN => MacAddress::from_bytes(addr).to_string(),
+        IPV4_ADDR_LEN => Ipv4Addr::from(<[u8; IPV4_ADDR_LEN]>::try_from(addr).unwrap()).to_string(),
+        IPV6_ADDR_LEN => Ipv6Addr::from(<[u8; IPV6_ADDR_LEN]>::try_from(addr).unwrap()).to_string(),
+        _ => addr
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(":"),
+    }
+}
+
+#[pdu_type]
+pub struct Arp<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Arp<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(Arp);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("arp", "header length", bytes, 0, ARP_FIXED_LEN)?;
+
+        let arp_len = get_arp_len(
+            bytes[ARP_HW_LEN_OFFSET] as usize,
+            bytes[ARP_PROTO_LEN_OFFSET] as usize,
+        );
+        ParseError::check_len("arp", "length", bytes, 0, arp_len)?;
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..arp_len]),
+            parent: None,
+            child: None,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "arp": {
+                "arp.hw_type": self.hw_type(),
+                "arp.proto_type": self.proto_type(),
+                "arp.hw_len": self.hw_len(),
+                "arp.proto_len": self.proto_len(),
+                "arp.opcode": self.opcode(),
+                "arp.sender_hw_addr": format_addr(self.sender_hw_bytes()),
+                "arp.sender_proto_addr": format_addr(self.sender_proto_bytes()),
+                "arp.target_hw_addr": format_addr(self.target_hw_bytes()),
+                "arp.target_proto_addr": format_addr(self.target_proto_bytes()),
+            }
+        }))
+    }
+}
+
+impl<'a> Arp<'a> {
+    /// Creates an Ethernet/IPv4 ARP packet with every address zeroed.
+    pub fn new() -> Self {
+        let mut header = vec![0; get_arp_len(ETHERNET_ADDR_LEN, IPV4_ADDR_LEN)];
+        header[ARP_HW_TYPE_OFFSET..ARP_PROTO_TYPE_OFFSET]
+            .copy_from_slice(&HW_TYPE_ETHERNET.to_be_bytes());
+        header[ARP_PROTO_TYPE_OFFSET..ARP_HW_LEN_OFFSET]
+            .copy_from_slice(&PROTO_TYPE_IPV4.to_be_bytes());
+        header[ARP_HW_LEN_OFFSET] = ETHERNET_ADDR_LEN as u8;
+        header[ARP_PROTO_LEN_OFFSET] = IPV4_ADDR_LEN as u8;
+
+        Self {
+            header: Cow::Owned(header),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn hw_type(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[ARP_HW_TYPE_OFFSET..ARP_PROTO_TYPE_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_hw_type(&mut self, hw_type: u16) {
+        self.header.to_mut()[ARP_HW_TYPE_OFFSET..ARP_PROTO_TYPE_OFFSET]
+            .copy_from_slice(&hw_type.to_be_bytes());
+    }
+
+    pub fn with_hw_type(&mut self, hw_type: u16) -> &mut Self {
+        self.set_hw_type(hw_type);
+        self
+    }
+
+    pub fn proto_type(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[ARP_PROTO_TYPE_OFFSET..ARP_HW_LEN_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_proto_type(&mut self, proto_type: u16) {
+        self.header.to_mut()[ARP_PROTO_TYPE_OFFSET..ARP_HW_LEN_OFFSET]
+            .copy_from_slice(&proto_type.to_be_bytes());
+    }
+
+    pub fn with_proto_type(&mut self, proto_type: u16) -> &mut Self {
+        self.set_proto_type(proto_type);
+        self
+    }
+
+    pub fn hw_len(&self) -> u8 {
+        self.header[ARP_HW_LEN_OFFSET]
+    }
+
+    /// Changing the hardware address length resizes the packet. Existing
+    /// hardware addresses are truncated or zero-padded to the new length.
+    pub fn set_hw_len(&mut self, hw_len: u8) {
+        self.resize_addrs(hw_len, self.proto_len());
+    }
+
+    pub fn with_hw_len(&mut self, hw_len: u8) -> &mut Self {
+        self.set_hw_len(hw_len);
+        self
+    }
+
+    pub fn proto_len(&self) -> u8 {
+        self.header[ARP_PROTO_LEN_OFFSET]
+    }
+
+    /// Changing the protocol address length resizes the packet. Existing
+    /// protocol addresses are truncated or zero-padded to the new length.
+    pub fn set_proto_len(&mut self, proto_len: u8) {
+        self.resize_addrs(self.hw_len(), proto_len);
+    }
+
+    pub fn with_proto_len(&mut self, proto_len: u8) -> &mut Self {
+        self.set_proto_len(proto_len);
+        self
+    }
+
+    pub fn opcode(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[ARP_OPCODE_OFFSET..ARP_ADDR_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_opcode(&mut self, opcode: u16) {
+        self.header.to_mut()[ARP_OPCODE_OFFSET..ARP_ADDR_OFFSET]
+            .copy_from_slice(&opcode.to_be_bytes());
+    }
+
+    pub fn with_opcode(&mut self, opcode: u16) -> &mut Self {
+        self.set_opcode(opcode);
+        self
+    }
+
+    fn sender_hw_offset(&self) -> usize {
+        ARP_ADDR_OFFSET
+    }
+
+    fn sender_proto_offset(&self) -> usize {
+        self.sender_hw_offset() + self.hw_len() as usize
+    }
+
+    fn target_hw_offset(&self) -> usize {
+        self.sender_proto_offset() + self.proto_len() as usize
+    }
+
+    fn target_proto_offset(&self) -> usize {
+        self.target_hw_offset() + self.hw_len() as usize
+    }
+
+    fn resize_addrs(&mut self, hw_len: u8, proto_len: u8) {
+        let mut addrs = [
+            self.sender_hw_bytes().to_vec(),
+            self.sender_proto_bytes().to_vec(),
+            self.target_hw_bytes().to_vec(),
+            self.target_proto_bytes().to_vec(),
+        ];
+        addrs[0].resize(hw_len as usize, 0);
+        addrs[1].resize(proto_len as usize, 0);
+        addrs[2].resize(hw_len as usize, 0);
+        addrs[3].resize(proto_len as usize, 0);
+
+        let header = self.header.to_mut();
+        header.truncate(ARP_ADDR_OFFSET);
+        header[ARP_HW_LEN_OFFSET] = hw_len;
+        header[ARP_PROTO_LEN_OFFSET] = proto_len;
+        for addr in addrs {
+            header.extend_from_slice(&addr);
+        }
+    }
+
+    fn set_addr_bytes(&mut self, offset: fn(&Self) -> usize, len: usize, addr: &[u8]) {
+        let start = offset(self);
+        self.header.to_mut()[start..start + len].copy_from_slice(addr);
+    }
+
+    pub fn sender_hw_bytes(&self) -> &[u8] {
+        &self.header[self.sender_hw_offset()..self.sender_proto_offset()]
+    }
+
+    /// Resizes the hardware address length if `addr` does not match it.
+    /// Panics if `addr` is longer than 255 bytes.
+    pub fn set_sender_hw_bytes(&mut self, addr: &[u8]) {
+        if addr.len() != self.hw_len() as usize {
+            self.set_hw_len(addr_len(addr));
+        }
+        self.set_addr_bytes(Self::sender_hw_offset, addr.len(), addr);
+    }
+
+    pub fn with_sender_hw_bytes(&mut self, addr: &[u8]) -> &mut Self {
+        self.set_sender_hw_bytes(addr);
+        self
+    }
+
+    /// Returns `None` unless the hardware address length is 6.
+    pub fn sender_hw_addr(&self) -> Option<MacAddress<'_>> {
+        let addr = self.sender_hw_bytes();
+        (addr.len() == ETHERNET_ADDR_LEN).then(|| MacAddress::from_bytes(addr))
+    }
+
+    pub fn set_sender_hw_addr(&mut self, sender_hw_addr: MacAddress) {
+        self.set_sender_hw_bytes(&sender_hw_addr.to_bytes());
+    }
+
+    pub fn with_sender_hw_addr(&mut self, sender_hw_addr: MacAddress) -> &mut Self {
+        self.set_sender_hw_addr(sender_hw_addr);
+        self
+    }
+
+    pub fn sender_proto_bytes(&self) -> &[u8] {
+        &self.header[self.sender_proto_offset()..self.target_hw_offset()]
+    }
+
+    /// Resizes the protocol address length if `addr` does not match it.
+    /// Panics if `addr` is longer than 255 bytes.
+    pub fn set_sender_proto_bytes(&mut self, addr: &[u8]) {
+        if addr.len() != self.proto_len() as usize {
+            self.set_proto_len(addr_len(addr));
+        }
+        self.set_addr_bytes(Self::sender_proto_offset, addr.len(), addr);
+    }
+
+    pub fn with_sender_proto_bytes(&mut self, addr: &[u8]) -> &mut Self {
+        self.set_sender_proto_bytes(addr);
+        self
+    }
+
+    /// Returns `None` unless the protocol address length is 4.
+    pub fn sender_proto_addr(&self) -> Option<Ipv4Addr> {
+        let addr = self.sender_proto_bytes();
+        (addr.len() == IPV4_ADDR_LEN).then(|| Ipv4Addr::from_bits(parse_bytes(addr, Endian::Big)))
+    }
+
+    pub fn set_sender_proto_addr(&mut self, sender_proto_addr: Ipv4Addr) {
+        self.set_sender_proto_bytes(&sender_proto_addr.to_bits().to_be_bytes());
+    }
+
+    pub fn with_sender_proto_addr(&mut self, sender_proto_addr: Ipv4Addr) -> &mut Self {
+        self.set_sender_proto_addr(sender_proto_addr);
+        self
+    }
+
+    pub fn target_hw_bytes(&self) -> &[u8] {
+        &self.header[self.target_hw_offset()..self.target_proto_offset()]
+    }
+
+    /// Resizes the hardware address length if `addr` does not match it.
+    /// Panics if `addr` is longer than 255 bytes.
+    pub fn set_target_hw_bytes(&mut self, addr: &[u8]) {
+        if addr.len() != self.hw_len() as usize {
+            self.set_hw_len(addr_len(addr));
+        }
+        self.set_addr_bytes(Self::target_hw_offset, addr.len(), addr);
+    }
+
+    pub fn with_target_hw_bytes(&mut self, addr: &[u8]) -> &mut Self {
+        self.set_target_hw_bytes(addr);
+        self
+    }
+
+    /// Returns `None` unless the hardware address length is 6.
+    pub fn target_hw_addr(&self) -> Option<MacAddress<'_>> {
+        let addr = self.target_hw_bytes();
+        (addr.len() == ETHERNET_ADDR_LEN).then(|| MacAddress::from_bytes(addr))
+    }
+
+    pub fn set_target_hw_addr(&mut self, target_hw_addr: MacAddress) {
+        self.set_target_hw_bytes(&target_hw_addr.to_bytes());
+    }
+
+    pub fn with_target_hw_addr(&mut self, target_hw_addr: MacAddress) -> &mut Self {
+        self.set_target_hw_addr(target_hw_addr);
+        self
+    }
+
+    pub fn target_proto_bytes(&self) -> &[u8] {
+        let start = self.target_proto_offset();
+        &self.header[start..start + self.proto_len() as usize]
+    }
+
+    /// Resizes the protocol address length if `addr` does not match it.
+    /// Panics if `addr` is longer than 255 bytes.
+    pub fn set_target_proto_bytes(&mut self, addr: &[u8]) {
+        if addr.len() != self.proto_len() as usize {
+            self.set_proto_len(addr_len(addr));
+        }
+        self.set_addr_bytes(Self::target_proto_offset, addr.len(), addr);
+    }
+
+    pub fn with_target_proto_bytes(&mut self, addr: &[u8]) -> &mut Self {
+        self.set_target_proto_bytes(addr);
+        self
+    }
+
+    /// Returns `None` unless the protocol address length is 4.
+    pub fn target_proto_addr(&self) -> Option<Ipv4Addr> {
+        let addr = self.target_proto_bytes();
+        (addr.len() == IPV4_ADDR_LEN).then(|| Ipv4Addr::from_bits(parse_bytes(addr, Endian::Big)))
+    }
+
+    pub fn set_target_proto_addr(&mut self, target_proto_addr: Ipv4Addr) {
+        self.set_target_proto_bytes(&target_proto_addr.to_bits().to_be_bytes());
+    }
+
+    pub fn with_target_proto_addr(&mut self, target_proto_addr: Ipv4Addr) -> &mut Self {
+        self.set_target_proto_addr(target_proto_addr);
+        self
+    }
+}
+
+register_pdu!(EtherType(0x0806), Arp, ETHER_DISSECTION_TABLE);
+
+/// ARP hardware types, also used by Linux cooked captures.
+pub const ARP_HW_TYPE_NAMES: ValueStrings = &[
+    (1, "Ethernet"),
+    (6, "IEEE 802"),
+    (772, "Loopback"),
+    (776, "IPv6 over IPv6"),
+    (778, "GRE over IP"),
+    (801, "IEEE 802.11"),
+];
+
+const ARP_OPCODE_NAMES: ValueStrings = &[
+    (ARP_REQUEST as u64, "request"),
+    (ARP_REPLY as u64, "reply"),
+    (RARP_REQUEST as u64, "reverse request"),
+    (RARP_REPLY as u64, "reverse reply"),
+];
+
+register_fields!(Arp, "arp", {
+    "arp.hw_type" => hw_type {
+        name: "Hardware Type",
+        bits: (ARP_HW_TYPE_OFFSET * 8, 16),
+        values: ARP_HW_TYPE_NAMES,
+    },
+    "arp.proto_type" => proto_type {
+        name: "Protocol Type",
+        bits: (ARP_PROTO_TYPE_OFFSET * 8, 16),
+        base: Hex,
+        values: ETHER_TYPE_NAMES,
+    },
+    "arp.hw_len" => hw_len { name: "Hardware Size", bits: (ARP_HW_LEN_OFFSET * 8, 8) },
+    "arp.proto_len" => proto_len { name: "Protocol Size", bits: (ARP_PROTO_LEN_OFFSET * 8, 8) },
+    "arp.opcode" => opcode {
+        name: "Opcode",
+        bits: (ARP_OPCODE_OFFSET * 8, 16),
+        values: ARP_OPCODE_NAMES,
+    },
+    "arp.sender_hw_addr" => sender_hw_bytes { name: "Sender Hardware Address" },
+    "arp.sender_proto_addr" => sender_proto_bytes { name: "Sender Protocol Address" },
+    "arp.target_hw_addr" => target_hw_bytes { name: "Target Hardware Address" },
+    "arp.target_proto_addr" => target_proto_bytes { name: "Target Protocol Address" },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethernet::Ethernet;
+    use crate::pdu::deserialize;
+
+    const ETH_ARP_REQUEST: [u8; 60] = [
+        // Ethernet header
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // Dst: broadcast
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, // Src: 00:11:22:33:44:55
+        0x08, 0x06, // EtherType = ARP
+        // ARP
+        0x00, 0x01, // Hardware type = Ethernet
+        0x08, 0x00, // Protocol type = IPv4
+        0x06, // Hardware length
+        0x04, // Protocol length
+        0x00, 0x01, // Opcode = request
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, // Sender MAC
+        0xc0, 0xa8, 0x00, 0x01, // Sender IP: 192.168.0.1
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Target MAC
+        0xc0, 0xa8, 0x00, 0x02, // Target IP: 192.168.0.2
+        // Ethernet padding
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_from_ethernet() {
+        let eth = Ethernet::from_bytes(&ETH_ARP_REQUEST).unwrap();
+        let arp = eth.find::<Arp>().unwrap();
+        assert!(arp.opcode() == ARP_REQUEST);
+        assert!(arp.to_bytes().len() == 28);
+        assert!(arp.sender_hw_addr().unwrap().to_string() == "00:11:22:33:44:55");
+        assert!(arp.sender_proto_addr() == Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert!(arp.target_proto_addr() == Some(Ipv4Addr::new(192, 168, 0, 2)));
+    }
+
+    #[test]
+    fn test_not_enough_data() {
+        assert!(Arp::from_bytes(&ETH_ARP_REQUEST[14..30]).is_err());
+    }
+
+    #[test]
+    fn test_build_reply() {
+        let mac = [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff];
+        let mut arp = Arp::new();
+        arp.with_opcode(ARP_REPLY)
+            .with_sender_hw_addr(MacAddress::from_bytes(&mac))
+            .with_sender_proto_addr(Ipv4Addr::new(10, 0, 0, 1))
+            .with_target_proto_addr(Ipv4Addr::new(10, 0, 0, 2));
+
+        let bytes = arp.to_bytes();
+        let parsed = deserialize::<Arp>(&bytes).unwrap();
+        assert!(parsed.opcode() == ARP_REPLY);
+        assert!(parsed.sender_hw_bytes() == mac);
+        assert!(parsed.target_hw_bytes() == [0; 6]);
+        assert!(parsed.sender_proto_addr() == Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert!(parsed.target_proto_addr() == Some(Ipv4Addr::new(10, 0, 0, 2)));
+    }
+
+    #[test]
+    fn test_non_ethernet_lengths() {
+        let mut arp = Arp::new();
+        arp.with_sender_proto_addr(Ipv4Addr::new(10, 0, 0, 1))
+            .with_sender_hw_bytes(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])
+            .with_target_proto_bytes(&Ipv6Addr::LOCALHOST.octets());
+
+        assert!(arp.hw_len() == 8);
+        assert!(arp.proto_len() == 16);
+        assert!(arp.to_bytes().len() == get_arp_len(8, 16));
+        assert!(arp.sender_hw_addr().is_none());
+        assert!(arp.sender_proto_addr().is_none());
+        assert!(arp.sender_proto_bytes()[..4] == [10, 0, 0, 1]);
+        assert!(arp.target_proto_bytes() == Ipv6Addr::LOCALHOST.octets());
+
+        let json = arp.to_json().unwrap();
+        assert!(json["arp"]["arp.target_proto_addr"] == "::1");
+        assert!(json["arp"]["arp.sender_hw_addr"] == "01:02:03:04:05:06:07:08");
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 255 bytes")]
+    fn test_oversized_addr() {
+        Arp::new().set_sender_hw_bytes(&[0; 256]);
+    }
+}
@@ -0,0 +1,550 @@
+use crate::icmp::{ICMP_CHECKSUM_OFFSET, Icmp};
+use crate::icmpv6::{ICMPV6, ICMPV6_CHECKSUM_OFFSET, Icmpv6};
+use crate::ip::{IPV4_CHECKSUM_OFFSET, Ip};
+use crate::ip6::Ipv6;
+use crate::packet::Packet;
+use crate::pdu::Pdu;
+use crate::tcp::{TCP_CHECKSUM_OFFSET, Tcp};
+use crate::udp::{UDP_CHECKSUM_OFFSET, Udp};
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// Adds `bytes` to a running one's complement sum. An odd trailing byte is
+/// padded with zero, so only the final chunk of a message may be odd sized.
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc1071>
+pub fn checksum_add(mut sum: u32, bytes: &[u8]) -> u32 {
+    let mut chunks = bytes.chunks_exact(2);
+    for word in &mut chunks {
+        sum = sum.wrapping_add(u16::from_be_bytes([word[0], word[1]]) as u32);
+    }
+    if let [last] = chunks.remainder() {
+        sum = sum.wrapping_add((*last as u32) << 8);
+    }
+    fold(sum)
+}
+
+fn fold(mut sum: u32) -> u32 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum
+}
+
+/// Folds and complements a running sum into the value stored on the wire.
+pub fn checksum_finish(sum: u32) -> u16 {
+    !(fold(sum) as u16)
+}
+
+/// The Internet checksum of `bytes`.
+pub fn internet_checksum(bytes: &[u8]) -> u16 {
+    checksum_finish(checksum_add(0, bytes))
+}
+
+/// Sum of the IPv4 pseudo-header used by TCP, UDP and other upper layers.
+pub fn ipv4_pseudo_header_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: u16) -> u32 {
+    let mut sum = checksum_add(0, &src.octets());
+    sum = checksum_add(sum, &dst.octets());
+    sum = checksum_add(sum, &[0, protocol]);
+    checksum_add(sum, &len.to_be_bytes())
+}
+
+/// Sum of the IPv6 pseudo-header.
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc8200#section-8.1>
+pub fn ipv6_pseudo_header_sum(src: Ipv6Addr, dst: Ipv6Addr, next_header: u8, len: u32) -> u32 {
+    let mut sum = checksum_add(0, &src.octets());
+    sum = checksum_add(sum, &dst.octets());
+    sum = checksum_add(sum, &len.to_be_bytes());
+    checksum_add(sum, &[0, 0, 0, next_header])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    Good,
+    Bad {
+        stored: u16,
+        computed: u16,
+    },
+    /// A zero UDP checksum over IPv4 means the sender didn't compute one.
+    Disabled,
+    /// The data needed to compute the checksum is missing, e.g. a truncated
+    /// capture or a transport layer without an IP layer above it.
+    Unverified,
+}
+
+/// Checksum status of one layer, named after its JSON key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerChecksum {
+    pub layer: &'static str,
+    pub status: ChecksumStatus,
+}
+
+enum PseudoHeader {
+    V4(Ipv4Addr, Ipv4Addr),
+    V6(Ipv6Addr, Ipv6Addr),
+}
+
+impl PseudoHeader {
+    fn sum(&self, protocol: u8, len: usize) -> u32 {
+        match self {
+            PseudoHeader::V4(src, dst) => ipv4_pseudo_header_sum(*src, *dst, protocol, len as u16),
+            PseudoHeader::V6(src, dst) => ipv6_pseudo_header_sum(*src, *dst, protocol, len as u32),
+        }
+    }
+}
+
+struct Computed {
+    idx: usize,
+    layer: &'static str,
+    stored: u16,
+    computed: Option<u16>,
+    disabled: bool,
+}
+
+impl Computed {
+    fn status(&self) -> ChecksumStatus {
+        match self.computed {
+            _ if self.disabled => ChecksumStatus::Disabled,
+            None => ChecksumStatus::Unverified,
+            Some(computed) if computed == self.stored => ChecksumStatus::Good,
+            Some(computed) => ChecksumStatus::Bad {
+                stored: self.stored,
+                computed,
+            },
+        }
+    }
+}
+
+/// Sums `bytes` with the checksum field at `offset` treated as zero.
+fn sum_without_checksum(sum: u32, bytes: &[u8], offset: usize) -> u32 {
+    let sum = checksum_add(sum, &bytes[..offset]);
+    checksum_add(sum, &bytes[offset + 2..])
+}
+
+/// Computes the checksum of every layer that carries one. Transport layers
+/// cover every later layer. Layers quoted by ICMP errors are not checked.
+fn compute_checksums<'a>(layers: &[&(dyn Pdu<'a> + 'a)]) -> Vec<Computed> {
+    let mut res = Vec::new();
+    let mut pseudo = None;
+    // Upper layer length announced by the IP layer, to spot truncation.
+    let mut upper_len = None;
+
+    let payload =
+        |idx: usize| -> Vec<u8> { layers[idx..].iter().flat_map(|l| l.to_bytes()).collect() };
+    let truncated =
+        |len: usize, upper_len: Option<usize>| upper_len.is_some_and(|upper| len < upper);
+
+    for (idx, layer) in layers.iter().enumerate() {
+        if let Some(ip) = layer.downcast_ref::<Ip>() {
+            let header = ip.to_bytes();
+            res.push(Computed {
+                idx,
+                layer: "ip",
+                stored: ip.checksum(),
+                computed: Some(checksum_finish(sum_without_checksum(
+                    0,
+                    &header,
+                    IPV4_CHECKSUM_OFFSET,
+                ))),
+                disabled: false,
+            });
+            pseudo = Some(PseudoHeader::V4(ip.src_addr(), ip.dst_addr()));
+            upper_len = (ip.total_len() as usize).checked_sub(header.len());
+        } else if let Some(ipv6) = layer.downcast_ref::<Ipv6>() {
+            pseudo = Some(PseudoHeader::V6(ipv6.src_addr(), ipv6.dst_addr()));
+            let ext_len: usize = ipv6.ext_headers().iter().map(|ext| ext.len()).sum();
+            upper_len = match ipv6.payload_len() {
+                0 => None,
+                len => (len as usize).checked_sub(ext_len),
+            };
+        } else if let Some(tcp) = layer.downcast_ref::<Tcp>() {
+            let bytes = payload(idx);
+            let computed = match &pseudo {
+                Some(pseudo) if !truncated(bytes.len(), upper_len) => {
+                    let sum = pseudo.sum(IPPROTO_TCP, bytes.len());
+                    Some(checksum_finish(sum_without_checksum(
+                        sum,
+                        &bytes,
+                        TCP_CHECKSUM_OFFSET,
+                    )))
+                }
+                _ => None,
+            };
+            res.push(Computed {
+                idx,
+                layer: "tcp",
+                stored: tcp.checksum(),
+                computed,
+                disabled: false,
+            });
+        } else if let Some(udp) = layer.downcast_ref::<Udp>() {
+            let bytes = payload(idx);
+            let computed = match &pseudo {
+                Some(pseudo) if !truncated(bytes.len(), upper_len) => {
+                    let sum = pseudo.sum(IPPROTO_UDP, bytes.len());
+                    // Zero means no checksum, so a computed zero is sent
+                    // as all ones.
+                    match checksum_finish(sum_without_checksum(sum, &bytes, UDP_CHECKSUM_OFFSET)) {
+                        0 => Some(0xFFFF),
+                        checksum => Some(checksum),
+                    }
+                }
+                _ => None,
+            };
+            res.push(Computed {
+                idx,
+                layer: "udp",
+                stored: udp.checksum(),
+                computed,
+                disabled: udp.checksum() == 0 && matches!(pseudo, Some(PseudoHeader::V4(..))),
+            });
+        } else if let Some(icmp) = layer.downcast_ref::<Icmp>() {
+            let bytes = payload(idx);
+            let computed = (!truncated(bytes.len(), upper_len))
+                .then(|| checksum_finish(sum_without_checksum(0, &bytes, ICMP_CHECKSUM_OFFSET)));
+            res.push(Computed {
+                idx,
+                layer: "icmp",
+                stored: icmp.checksum(),
+                computed,
+                disabled: false,
+            });
+            break;
+        } else if let Some(icmpv6) = layer.downcast_ref::<Icmpv6>() {
+            let bytes = payload(idx);
+            let computed = match &pseudo {
+                Some(pseudo @ PseudoHeader::V6(..)) if !truncated(bytes.len(), upper_len) => {
+                    let sum = pseudo.sum(ICMPV6, bytes.len());
+                    Some(checksum_finish(sum_without_checksum(
+                        sum,
+                        &bytes,
+                        ICMPV6_CHECKSUM_OFFSET,
+                    )))
+                }
+                _ => None,
+            };
+            res.push(Computed {
+                idx,
+                layer: "icmpv6",
+                stored: icmpv6.checksum(),
+                computed,
+                disabled: false,
+            });
+            break;
+        }
+    }
+    res
+}
+
+fn set_checksum<'a>(layer: &mut (dyn Pdu<'a> + 'a), checksum: u16) {
+    if let Some(ip) = layer.downcast_mut::<Ip>() {
+        ip.set_checksum(checksum);
+    } else if let Some(tcp) = layer.downcast_mut::<Tcp>() {
+        tcp.set_checksum(checksum);
+    } else if let Some(udp) = layer.downcast_mut::<Udp>() {
+        udp.set_checksum(Some(checksum));
+    } else if let Some(icmp) = layer.downcast_mut::<Icmp>() {
+        icmp.set_checksum(checksum);
+    } else if let Some(icmpv6) = layer.downcast_mut::<Icmpv6>() {
+        icmpv6.set_checksum(checksum);
+    }
+}
+
+pub(crate) fn push_layers<'r, 'a>(
+    pdu: &'r (dyn Pdu<'a> + 'a),
+    layers: &mut Vec<&'r (dyn Pdu<'a> + 'a)>,
+) {
+    layers.push(pdu);
+    if let Some(child) = pdu.child_pdu() {
+        push_layers(child.as_ref(), layers);
+    }
+}
+
+/// Visits `pdu` and its children in order, numbering them from `idx`.
+pub(crate) fn for_each_layer_mut<'a>(
+    pdu: &mut (dyn Pdu<'a> + 'a),
+    idx: &mut usize,
+    f: &mut impl FnMut(usize, &mut (dyn Pdu<'a> + 'a)),
+) {
+    f(*idx, &mut *pdu);
+    *idx += 1;
+    if let Some(child) = pdu.child_pdu_mut() {
+        for_each_layer_mut(child.as_mut(), idx, f);
+    }
+}
+
+fn statuses(computed: &[Computed]) -> Vec<LayerChecksum> {
+    computed
+        .iter()
+        .map(|c| LayerChecksum {
+            layer: c.layer,
+            status: c.status(),
+        })
+        .collect()
+}
+
+/// The innermost checksum before layer `before` that `select` picks, by
+/// layer index. Unverifiable checksums are left alone. Outer checksums
+/// cover the inner ones, so they are stored innermost first and the rest
+/// are recomputed after each one.
+fn next_fix(
+    computed: &[Computed],
+    before: usize,
+    select: impl Fn(&Computed) -> bool,
+) -> Option<(usize, u16)> {
+    computed
+        .iter()
+        .rev()
+        .filter(|c| c.idx < before && select(c))
+        .find_map(|c| c.computed.map(|checksum| (c.idx, checksum)))
+}
+
+impl<'a> dyn Pdu<'a> + 'a {
+    /// Checks the checksum of this Pdu and its children.
+    pub fn verify_checksums(&self) -> Vec<LayerChecksum> {
+        let mut layers = Vec::new();
+        push_layers(self, &mut layers);
+        statuses(&compute_checksums(&layers))
+    }
+
+    /// Recomputes every checksum that can be verified and stores it.
+    /// Disabled checksums are left alone.
+    pub fn fix_checksums(&mut self) {
+        let mut before = usize::MAX;
+        loop {
+            let mut layers = Vec::new();
+            push_layers(self, &mut layers);
+            let Some((fix, checksum)) =
+                next_fix(&compute_checksums(&layers), before, |c| !c.disabled)
+            else {
+                break;
+            };
+
+            let mut idx = 0;
+            for_each_layer_mut(self, &mut idx, &mut |idx, layer| {
+                if idx == fix {
+                    set_checksum(layer, checksum);
+                }
+            });
+            before = fix;
+        }
+    }
+}
+
+impl<'a> Packet<'a> {
+    fn layers(&self) -> Vec<&(dyn Pdu<'a> + 'a)> {
+        let mut layers = Vec::new();
+        for pdu in &self.pdu_chain {
+            push_layers(pdu.as_ref(), &mut layers);
+        }
+        layers
+    }
+
+    /// Stores the computed checksums `select` picks, innermost first.
+    fn store_checksums(&mut self, select: impl Fn(&Computed) -> bool) {
+        let mut before = usize::MAX;
+        while let Some((fix, checksum)) =
+            next_fix(&compute_checksums(&self.layers()), before, &select)
+        {
+            let mut idx = 0;
+            for pdu in &mut self.pdu_chain {
+                for_each_layer_mut(pdu.as_mut(), &mut idx, &mut |idx, layer| {
+                    if idx == fix {
+                        set_checksum(layer, checksum);
+                    }
+                });
+            }
+            before = fix;
+        }
+    }
+
+    /// Checks the checksum of every layer in the packet.
+    pub fn verify_checksums(&self) -> Vec<LayerChecksum> {
+        statuses(&compute_checksums(&self.layers()))
+    }
+
+    /// Recomputes every checksum that can be verified and stores it.
+    /// Disabled checksums are left alone.
+    pub fn fix_checksums(&mut self) {
+        self.store_checksums(|c| !c.disabled);
+    }
+
+    /// Stores the checksums that are still zero, or every one of them if
+    /// `overwrite` is set. Unlike `fix_checksums`, a zero UDP checksum
+    /// counts as missing rather than disabled.
+    pub(crate) fn fill_checksums(&mut self, overwrite: bool) {
+        self.store_checksums(|c| overwrite || c.stored == 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethernet::Ethernet;
+    use crate::vxlan::Vxlan;
+
+    const IPV4_TCP_HELLO: [u8; 45] = [
+        0x45, 0x3c, 0x00, 0x2D, 0x1C, 0x46, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 0xC0, 0x00, 0x02,
+        0x01, 0xC6, 0x33, 0x64, 0x02, // IPv4, checksum zeroed
+        0x30, 0x39, 0x00, 0x50, 0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0x00, 0x00, 0x50, 0x18, 0xFF,
+        0xFF, 0x00, 0x00, 0x00, 0x00, // TCP, checksum zeroed
+        0x68, 0x65, 0x6C, 0x6C, 0x6F,
+    ];
+
+    const IPV4_UDP_HELLO: [u8; 33] = [
+        0x45, 0x00, 0x00, 0x21, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 0xC0, 0x00, 0x02,
+        0x01, 0xC6, 0x33, 0x64, 0x02, // IPv4, checksum zeroed
+        0x30, 0x39, 0x30, 0x3a, 0x00, 0x0d, 0x00, 0x00, // UDP, checksum disabled
+        0x68, 0x65, 0x6C, 0x6C, 0x6F,
+    ];
+
+    fn layer_statuses<'a>(pdu: &(dyn Pdu<'a> + 'a)) -> Vec<(&'static str, ChecksumStatus)> {
+        pdu.verify_checksums()
+            .into_iter()
+            .map(|layer| (layer.layer, layer.status))
+            .collect()
+    }
+
+    #[test]
+    fn test_rfc1071_example() {
+        let bytes = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert!(checksum_add(0, &bytes) == 0xddf2);
+        assert!(internet_checksum(&bytes) == !0xddf2);
+    }
+
+    #[test]
+    fn test_odd_length() {
+        assert!(internet_checksum(&[0x01]) == !0x0100);
+        assert!(internet_checksum(&[]) == 0xFFFF);
+    }
+
+    #[test]
+    fn test_ipv4_header() {
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert!(internet_checksum(&header) == 0);
+    }
+
+    #[test]
+    fn test_fix_ipv4_tcp() {
+        let mut ip = Ip::from_bytes(&IPV4_TCP_HELLO).unwrap();
+        let before = layer_statuses(ip.as_ref());
+        assert!(
+            before[0].0 == "ip" && matches!(before[0].1, ChecksumStatus::Bad { stored: 0, .. })
+        );
+        assert!(
+            before[1].0 == "tcp" && matches!(before[1].1, ChecksumStatus::Bad { stored: 0, .. })
+        );
+
+        ip.fix_checksums();
+        assert!(
+            layer_statuses(ip.as_ref())
+                == [("ip", ChecksumStatus::Good), ("tcp", ChecksumStatus::Good)]
+        );
+        assert!(ip.find::<Ip>().unwrap().checksum() == 0x3212);
+        assert!(internet_checksum(&ip.to_bytes()) == 0);
+    }
+
+    #[test]
+    fn test_udp_disabled_and_truncated() {
+        let mut ip = Ip::from_bytes(&IPV4_UDP_HELLO).unwrap();
+        ip.fix_checksums();
+        assert!(
+            layer_statuses(ip.as_ref())
+                == [
+                    ("ip", ChecksumStatus::Good),
+                    ("udp", ChecksumStatus::Disabled)
+                ]
+        );
+        assert!(ip.find::<Udp>().unwrap().checksum() == 0);
+
+        // Snapped captures can't have their transport checksum verified.
+        let ip = Ip::from_bytes(&IPV4_TCP_HELLO[..42]).unwrap();
+        assert!(layer_statuses(ip.as_ref())[1] == ("tcp", ChecksumStatus::Unverified));
+    }
+
+    #[test]
+    fn test_icmp() {
+        let mut bytes = IPV4_UDP_HELLO[..20].to_vec();
+        bytes[9] = 1;
+        bytes.extend_from_slice(&[
+            0x08, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02, b'p', b'i', b'n', b'g',
+        ]);
+        bytes[3] = bytes.len() as u8;
+
+        let mut ip = Ip::from_bytes(&bytes).unwrap();
+        ip.fix_checksums();
+        let icmp = ip.find::<Icmp>().unwrap();
+        assert!(icmp.checksum() == 0x192c);
+        assert!(layer_statuses(ip.as_ref())[1] == ("icmp", ChecksumStatus::Good));
+    }
+
+    #[test]
+    fn test_ipv6_udp_and_icmpv6() {
+        let mut bytes = vec![0x60, 0, 0, 0, 0x00, 0x0d, 17, 64];
+        bytes.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&IPV4_UDP_HELLO[20..]);
+
+        let mut ipv6 = Ipv6::from_bytes(&bytes).unwrap();
+        assert!(matches!(
+            layer_statuses(ipv6.as_ref())[0],
+            ("udp", ChecksumStatus::Bad { .. })
+        ));
+        ipv6.fix_checksums();
+        assert!(layer_statuses(ipv6.as_ref()) == [("udp", ChecksumStatus::Good)]);
+
+        let mut bytes = bytes[..40].to_vec();
+        bytes[6] = ICMPV6;
+        bytes.extend_from_slice(&[0x80, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x01]);
+        bytes[5] = 8;
+        let mut ipv6 = Ipv6::from_bytes(&bytes).unwrap();
+        ipv6.fix_checksums();
+        let src = "2001:db8::1".parse().unwrap();
+        let dst = "2001:db8::2".parse().unwrap();
+        assert!(ipv6.find::<Icmpv6>().unwrap().verify_checksum(src, dst));
+    }
+
+    #[test]
+    fn test_fix_tunnel_inner_first() {
+        let mut bytes = vec![0; 12];
+        bytes.extend_from_slice(&[0x08, 0x00]); // Ethernet
+        bytes.extend_from_slice(&[0x45, 0x00, 0x00, 95, 0x00, 0x01, 0x00, 0x00, 0x40, 17]);
+        bytes.extend_from_slice(&[0x00, 0x00, 192, 0, 2, 1, 198, 51, 100, 2]); // IPv4
+        bytes.extend_from_slice(&[0x30, 0x39, 0x12, 0xb5, 0x00, 75, 0x12, 0x34]); // UDP 4789
+        bytes.extend_from_slice(&[0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2a, 0x00]); // VXLAN
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(&[0x08, 0x00]); // Ethernet
+        bytes.extend_from_slice(&IPV4_TCP_HELLO);
+
+        let all_good = |statuses: Vec<LayerChecksum>| {
+            statuses.len() == 4 && statuses.iter().all(|c| c.status == ChecksumStatus::Good)
+        };
+
+        let mut eth = Ethernet::from_bytes(&bytes).unwrap();
+        assert!(eth.find::<Vxlan>().is_some());
+        assert!(!all_good(eth.verify_checksums()));
+        eth.fix_checksums();
+        assert!(all_good(eth.verify_checksums()));
+
+        let mut packet = Packet::new(vec![Ethernet::from_bytes(&bytes).unwrap()]);
+        packet.fix_checksums();
+        assert!(all_good(packet.verify_checksums()));
+    }
+
+    #[test]
+    fn test_packet() {
+        let mut packet = Packet::new(vec![Ip::from_bytes(&IPV4_TCP_HELLO).unwrap()]);
+        packet.fix_checksums();
+        assert!(
+            packet
+                .verify_checksums()
+                .iter()
+                .all(|layer| layer.status == ChecksumStatus::Good)
+        );
+    }
+}
@@ -0,0 +1,944 @@
+use crate::checksum::internet_checksum;
+use crate::error::ReassemblyError;
+use crate::ip::{IPV4_FRAG_UNIT, Ip};
+use crate::ip6::Ipv6;
+use crate::ip6_ext::FRAGMENT;
+use crate::prelude::*;
+
+use std::hash::Hash;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+pub const IPV4_MAX_DATAGRAM_LEN: usize = 65535;
+/// Largest payload without a jumbogram option.
+pub const IPV6_MAX_PAYLOAD_LEN: usize = 65535;
+/// IPv6 fragment offsets count 8 byte units too.
+const IPV6_FRAG_UNIT: usize = 8;
+
+/// Linux's ipfrag_time.
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Linux's ipfrag_high_thresh.
+pub const DEFAULT_REASSEMBLY_MEMORY: usize = 4 * 1024 * 1024;
+
+/// Which data is kept where fragments overlap. Hosts disagree, so an IDS
+/// has to pick the behavior of the host it protects.
+/// Refer to <https://www.sans.org/white-papers/1618/>
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// Data that arrived first wins.
+    First,
+    /// Data that arrived last wins.
+    Last,
+    /// Data that arrived first wins unless the new fragment starts before
+    /// the old one.
+    Bsd,
+    /// Like `Bsd`, and a new fragment at the same offset that ends later
+    /// also wins.
+    #[default]
+    Linux,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Piece {
+    offset: usize,
+    end: usize,
+}
+
+impl OverlapPolicy {
+    fn new_wins(&self, old: &Piece, new: &Piece) -> bool {
+        match self {
+            OverlapPolicy::First => false,
+            OverlapPolicy::Last => true,
+            OverlapPolicy::Bsd => new.offset < old.offset,
+            OverlapPolicy::Linux => {
+                new.offset < old.offset || (new.offset == old.offset && new.end > old.end)
+            }
+        }
+    }
+}
+
+/// Bytes at `offset..end` of the payload, taken from the fragment `from`.
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    offset: usize,
+    end: usize,
+    from: Piece,
+}
+
+/// The payload of one datagram as its fragments arrive. Sorted runs that
+/// don't overlap remember the fragment each byte came from, so overlaps can
+/// be resolved per byte.
+pub(crate) struct FragmentBuffer {
+    data: Vec<u8>,
+    runs: Vec<Run>,
+    filled: usize,
+    total_len: Option<usize>,
+}
+
+impl FragmentBuffer {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            runs: Vec::new(),
+            filled: 0,
+            total_len: None,
+        }
+    }
+
+    /// Adds the data of a fragment at `offset`. `last` marks the fragment
+    /// that fixes the payload length. Without a policy, a fragment that
+    /// overlaps another is `Inconsistent` unless it's an exact duplicate.
+    pub(crate) fn insert(
+        &mut self,
+        offset: usize,
+        data: &[u8],
+        last: bool,
+        policy: Option<OverlapPolicy>,
+    ) -> Result<(), ReassemblyError> {
+        let piece = Piece {
+            offset,
+            end: offset + data.len(),
+        };
+        if last {
+            if self.total_len.is_some_and(|len| len != piece.end) || self.data.len() > piece.end {
+                return Err(ReassemblyError::Inconsistent);
+            }
+            self.total_len = Some(piece.end);
+        } else if self.total_len.is_some_and(|len| piece.end > len) {
+            return Err(ReassemblyError::Inconsistent);
+        }
+
+        // Without a policy nothing overlaps, so any policy will do below.
+        let policy = match policy {
+            Some(policy) => policy,
+            None => match self
+                .runs
+                .iter()
+                .find(|run| run.offset < piece.end && piece.offset < run.end)
+            {
+                Some(run) if run.from == piece => return Ok(()),
+                Some(_) => return Err(ReassemblyError::Inconsistent),
+                None => OverlapPolicy::First,
+            },
+        };
+
+        if piece.end > self.data.len() {
+            self.data.resize(piece.end, 0);
+        }
+
+        // Walks the runs in order, splitting those the fragment overlaps and
+        // filling the holes between them. `pos` is where the fragment's data
+        // that hasn't been placed yet starts.
+        let mut runs = Vec::with_capacity(self.runs.len() + 2);
+        let mut pos = piece.offset;
+        for run in std::mem::take(&mut self.runs) {
+            if run.end <= piece.offset || run.offset >= piece.end {
+                if run.offset >= piece.end && pos < piece.end {
+                    self.fill(&mut runs, piece, data, pos, piece.end);
+                    pos = piece.end;
+                }
+                runs.push(run);
+                continue;
+            }
+
+            if run.offset > pos {
+                self.fill(&mut runs, piece, data, pos, run.offset);
+            }
+            let start = run.offset.max(piece.offset);
+            let end = run.end.min(piece.end);
+            if run.offset < start {
+                runs.push(Run { end: start, ..run });
+            }
+            if policy.new_wins(&run.from, &piece) {
+                self.data[start..end].copy_from_slice(&data[start - offset..end - offset]);
+                runs.push(Run {
+                    offset: start,
+                    end,
+                    from: piece,
+                });
+            } else {
+                runs.push(Run {
+                    offset: start,
+                    end,
+                    ..run
+                });
+            }
+            if end < run.end {
+                runs.push(Run { offset: end, ..run });
+            }
+            pos = end;
+        }
+        if pos < piece.end {
+            self.fill(&mut runs, piece, data, pos, piece.end);
+        }
+
+        // Runs split from the same fragment join up again, so duplicates
+        // don't add any.
+        runs.dedup_by(|next, prev| {
+            let joins = prev.from == next.from && prev.end == next.offset;
+            if joins {
+                prev.end = next.end;
+            }
+            joins
+        });
+        self.runs = runs;
+        Ok(())
+    }
+
+    /// Copies the fragment's data into the hole at `start..end`.
+    fn fill(&mut self, runs: &mut Vec<Run>, piece: Piece, data: &[u8], start: usize, end: usize) {
+        self.data[start..end].copy_from_slice(&data[start - piece.offset..end - piece.offset]);
+        self.filled += end - start;
+        runs.push(Run {
+            offset: start,
+            end,
+            from: piece,
+        });
+    }
+
+    pub(crate) fn is_complete(&self) -> bool {
+        self.total_len.is_some_and(|len| self.filled == len)
+    }
+
+    /// Bytes used, counting holes in the payload and the runs.
+    pub(crate) fn memory(&self) -> usize {
+        self.data.len() + self.runs.len() * size_of::<Run>()
+    }
+
+    pub(crate) fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Identifies the fragments of one datagram, and names what is kept of the
+/// first fragment to rebuild it.
+pub trait FragmentKey: Copy + Hash + Eq {
+    type Header;
+    /// Whether overlapping fragments are resolved with the `OverlapPolicy`.
+    /// Otherwise they make the datagram `Inconsistent`.
+    const OVERLAPS_ALLOWED: bool;
+}
+
+/// Fragments of one datagram share the addresses, protocol and ID.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Ipv4FragmentKey {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub id: u16,
+}
+
+impl Ipv4FragmentKey {
+    pub fn of(ip: &Ip) -> Self {
+        Self {
+            src: ip.src_addr(),
+            dst: ip.dst_addr(),
+            protocol: ip.protocol(),
+            id: ip.id(),
+        }
+    }
+}
+
+impl FragmentKey for Ipv4FragmentKey {
+    /// The first fragment carries every option.
+    type Header = Box<Ip<'static>>;
+    const OVERLAPS_ALLOWED: bool = true;
+}
+
+/// IPv6 fragments share the addresses and the fragment header's ID.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct Ipv6FragmentKey {
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+    pub id: u32,
+}
+
+impl FragmentKey for Ipv6FragmentKey {
+    /// The unfragmentable part and fragment header of the first fragment.
+    type Header = Box<Ipv6<'static>>;
+    /// RFC 5722 drops the datagram instead.
+    const OVERLAPS_ALLOWED: bool = false;
+}
+
+/// The first fragment's header and the reassembled payload.
+type Reassembled<K> = (<K as FragmentKey>::Header, Vec<u8>);
+
+struct Pending<K: FragmentKey> {
+    header: Option<K::Header>,
+    buffer: FragmentBuffer,
+    first_seen: Duration,
+}
+
+/// Collects fragments and returns each datagram once all of its data has
+/// arrived. Timestamps are capture times, e.g. `Record::timestamp`.
+pub struct Reassembler<K: FragmentKey> {
+    policy: OverlapPolicy,
+    timeout: Duration,
+    memory_limit: usize,
+    memory: usize,
+    pending: HashMap<K, Pending<K>>,
+}
+
+pub type Ipv4Reassembler = Reassembler<Ipv4FragmentKey>;
+pub type Ipv6Reassembler = Reassembler<Ipv6FragmentKey>;
+
+impl<K: FragmentKey> Reassembler<K> {
+    pub fn new() -> Self {
+        Self {
+            policy: OverlapPolicy::default(),
+            timeout: DEFAULT_REASSEMBLY_TIMEOUT,
+            memory_limit: DEFAULT_REASSEMBLY_MEMORY,
+            memory: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// How IPv4 overlaps are resolved. IPv6 datagrams with overlapping
+    /// fragments are dropped as `Inconsistent`.
+    pub fn policy(&self) -> OverlapPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: OverlapPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn with_policy(&mut self, policy: OverlapPolicy) -> &mut Self {
+        self.set_policy(policy);
+        self
+    }
+
+    /// How long after its first fragment a datagram is dropped.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Cap on memory across all datagrams: buffered payload bytes, holes
+    /// included, plus a few words per run of bytes from one fragment. The
+    /// oldest datagrams are dropped to stay under it.
+    pub fn memory_limit(&self) -> usize {
+        self.memory_limit
+    }
+
+    pub fn set_memory_limit(&mut self, memory_limit: usize) {
+        self.memory_limit = memory_limit;
+    }
+
+    pub fn with_memory_limit(&mut self, memory_limit: usize) -> &mut Self {
+        self.set_memory_limit(memory_limit);
+        self
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.memory
+    }
+
+    /// Number of incomplete datagrams.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drops datagrams whose timeout has passed at `now` and returns how
+    /// many were dropped.
+    pub fn expire(&mut self, now: Duration) -> usize {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        let mut freed = 0;
+        self.pending.retain(|_, pending| {
+            let keep = now.saturating_sub(pending.first_seen) < timeout;
+            if !keep {
+                freed += pending.buffer.memory();
+            }
+            keep
+        });
+        self.memory -= freed;
+        before - self.pending.len()
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Pending<K>> {
+        let pending = self.pending.remove(key)?;
+        self.memory -= pending.buffer.memory();
+        Some(pending)
+    }
+
+    /// Buffers one fragment's data. `header` is only called for the first
+    /// fragment. Returns the first fragment's header and the payload once
+    /// the datagram is complete.
+    fn add(
+        &mut self,
+        ts: Duration,
+        key: K,
+        offset: usize,
+        data: &[u8],
+        last: bool,
+        header: impl FnOnce() -> Option<K::Header>,
+    ) -> Result<Option<Reassembled<K>>, ReassemblyError> {
+        let policy = K::OVERLAPS_ALLOWED.then_some(self.policy);
+        let pending = self.pending.entry(key).or_insert_with(|| Pending {
+            header: None,
+            buffer: FragmentBuffer::new(),
+            first_seen: ts,
+        });
+        let before = pending.buffer.memory();
+        let inserted = pending.buffer.insert(offset, data, last, policy);
+        self.memory = self.memory - before + pending.buffer.memory();
+        if let Err(err) = inserted {
+            self.remove(&key);
+            return Err(err);
+        }
+        if offset == 0 && pending.header.is_none() {
+            pending.header = header();
+        }
+
+        self.enforce_memory_limit(&key)?;
+
+        let pending = &self.pending[&key];
+        if !pending.buffer.is_complete() || pending.header.is_none() {
+            return Ok(None);
+        }
+        Ok(self.remove(&key).and_then(|pending| {
+            let header = pending.header?;
+            Some((header, pending.buffer.into_data()))
+        }))
+    }
+
+    /// Drops the oldest other datagrams until the cap is met, then `key`
+    /// itself if that isn't enough.
+    fn enforce_memory_limit(&mut self, key: &K) -> Result<(), ReassemblyError> {
+        while self.memory > self.memory_limit {
+            let oldest = self
+                .pending
+                .iter()
+                .filter(|(other, _)| *other != key)
+                .min_by_key(|(_, pending)| pending.first_seen)
+                .map(|(other, _)| *other);
+            match oldest {
+                Some(oldest) => {
+                    self.remove(&oldest);
+                }
+                None => {
+                    self.remove(key);
+                    return Err(ReassemblyError::MemoryLimit);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: FragmentKey> Default for Reassembler<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reassembler<Ipv4FragmentKey> {
+    /// Adds `ip` and returns the whole datagram, header included, once it
+    /// is complete. Datagrams that aren't fragmented are returned as they
+    /// are, so every result can go through `Ip::from_bytes`.
+    pub fn process(&mut self, ts: Duration, ip: &Ip) -> Result<Option<Vec<u8>>, ReassemblyError> {
+        self.expire(ts);
+
+        let payload = ip
+            .child_pdu()
+            .as_ref()
+            .map(|child| child.chain_to_bytes())
+            .unwrap_or_default();
+        if !ip.is_fragment() {
+            let mut datagram = ip.to_bytes();
+            datagram.extend_from_slice(&payload);
+            return Ok(Some(datagram));
+        }
+
+        let key = Ipv4FragmentKey::of(ip);
+        let offset = ip.frag_offset() as usize * IPV4_FRAG_UNIT;
+        let last = !ip.mf();
+        if !last && !payload.len().is_multiple_of(IPV4_FRAG_UNIT) {
+            self.remove(&key);
+            return Err(ReassemblyError::Inconsistent);
+        }
+        if ip.to_bytes().len() + offset + payload.len() > IPV4_MAX_DATAGRAM_LEN {
+            self.remove(&key);
+            return Err(ReassemblyError::TooLarge);
+        }
+
+        let header = || Pdu::clone(ip).downcast::<Ip>();
+        let Some((mut header, payload)) = self.add(ts, key, offset, &payload, last, header)? else {
+            return Ok(None);
+        };
+
+        let header_len = header.to_bytes().len();
+        header.set_total_len((header_len + payload.len()) as u16);
+        header.set_mf(0);
+        header.set_frag_offset(0);
+        header.set_checksum(0);
+        header.set_checksum(internet_checksum(&header.to_bytes()));
+
+        let mut datagram = header.to_bytes();
+        datagram.extend_from_slice(&payload);
+        Ok(Some(datagram))
+    }
+}
+
+impl Reassembler<Ipv6FragmentKey> {
+    /// Adds `ipv6` and returns the whole datagram once it is complete: the
+    /// unfragmentable part of the first fragment followed by the payload,
+    /// with the fragment header removed. Atomic fragments are returned
+    /// right away and packets without a fragment header as they are, so
+    /// every result can go through `Ipv6::from_bytes`.
+    pub fn process(
+        &mut self,
+        ts: Duration,
+        ipv6: &Ipv6,
+    ) -> Result<Option<Vec<u8>>, ReassemblyError> {
+        self.expire(ts);
+
+        let payload = ipv6
+            .child_pdu()
+            .as_ref()
+            .map(|child| child.chain_to_bytes())
+            .unwrap_or_default();
+        let Some(frag) = ipv6.ext_header(FRAGMENT) else {
+            let mut datagram = ipv6.to_bytes();
+            datagram.extend_from_slice(&payload);
+            return Ok(Some(datagram));
+        };
+
+        let header = || Pdu::clone(ipv6).downcast::<Ipv6>();
+        if !frag.is_fragmented() {
+            return Ok(header().map(|header| rebuild_ipv6(*header, &payload)));
+        }
+
+        let key = Ipv6FragmentKey {
+            src: ipv6.src_addr(),
+            dst: ipv6.dst_addr(),
+            id: frag.identification(),
+        };
+        let offset = frag.frag_offset() as usize * IPV6_FRAG_UNIT;
+        let last = !frag.more_fragments();
+        if !last && !payload.len().is_multiple_of(IPV6_FRAG_UNIT) {
+            self.remove(&key);
+            return Err(ReassemblyError::Inconsistent);
+        }
+        let unfragmentable: usize = ipv6
+            .ext_headers()
+            .iter()
+            .take_while(|ext| ext.kind() != FRAGMENT)
+            .map(|ext| ext.len())
+            .sum();
+        if unfragmentable + offset + payload.len() > IPV6_MAX_PAYLOAD_LEN {
+            self.remove(&key);
+            return Err(ReassemblyError::TooLarge);
+        }
+
+        Ok(self
+            .add(ts, key, offset, &payload, last, header)?
+            .map(|(header, payload)| rebuild_ipv6(*header, &payload)))
+    }
+}
+
+/// Drops the fragment header of `header` and appends `payload`. The header
+/// before it takes over its next header field. Headers after it, which only
+/// atomic fragments have parsed, are kept.
+fn rebuild_ipv6(mut header: Ipv6<'static>, payload: &[u8]) -> Vec<u8> {
+    let mut ext_headers: Vec<_> = header
+        .ext_headers()
+        .iter()
+        .map(|ext| ext.to_owned())
+        .collect();
+    if let Some(pos) = ext_headers.iter().position(|ext| ext.kind() == FRAGMENT) {
+        let next_header = ext_headers.remove(pos).next_header();
+        match pos.checked_sub(1) {
+            Some(prev) => ext_headers[prev].set_next_header(next_header),
+            None => header.set_next_header(next_header),
+        }
+    }
+
+    let ext_len: usize = ext_headers.iter().map(|ext| ext.len()).sum();
+    header.set_ext_headers(ext_headers);
+    header.set_payload_len((ext_len + payload.len()) as u16);
+
+    let mut datagram = header.to_bytes();
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip6_ext::{DEST_OPTS, HOP_BY_HOP, Ipv6ExtHeader};
+    use crate::pdu::deserialize;
+    use crate::udp::Udp;
+
+    const RUN: usize = size_of::<Run>();
+
+    const IPV4_UDP: [u8; 28] = [
+        0x45, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0x00, 0x02,
+        0x01, 0xc6, 0x33, 0x64, 0x02, // IPv4
+        0x30, 0x39, 0x00, 0x35, 0x00, 0x00, 0x00, 0x00, // UDP
+    ];
+
+    /// A UDP datagram with a 40 byte payload of 0..40.
+    fn datagram() -> Vec<u8> {
+        let mut bytes = IPV4_UDP.to_vec();
+        bytes.extend(0..40u8);
+        let total_len = bytes.len() as u16;
+        bytes[2..4].copy_from_slice(&total_len.to_be_bytes());
+        bytes[24..26].copy_from_slice(&48u16.to_be_bytes());
+        bytes
+    }
+
+    /// A fragment of `datagram` carrying `data` at `offset`.
+    fn fragment(datagram: &[u8], offset: usize, data: &[u8], mf: bool) -> Vec<u8> {
+        let mut ip = deserialize::<Ip>(&datagram[..20]).unwrap();
+        ip.with_frag_offset((offset / 8) as u16)
+            .with_mf(mf as u8)
+            .with_total_len((20 + data.len()) as u16);
+        let mut bytes = ip.to_bytes();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn feed(
+        reassembler: &mut Ipv4Reassembler,
+        ts: u64,
+        bytes: &[u8],
+    ) -> Result<Option<Vec<u8>>, ReassemblyError> {
+        let pdu = Ip::from_bytes(bytes).unwrap();
+        reassembler.process(Duration::from_secs(ts), pdu.downcast_ref::<Ip>().unwrap())
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let original = datagram();
+        let payload = &original[20..];
+        let mut reassembler = Ipv4Reassembler::new();
+
+        let tail = fragment(&original, 32, &payload[32..], false);
+        let middle = fragment(&original, 16, &payload[16..32], true);
+        let head = fragment(&original, 0, &payload[..16], true);
+        assert!(feed(&mut reassembler, 0, &tail).unwrap().is_none());
+        assert!(feed(&mut reassembler, 0, &head).unwrap().is_none());
+        assert!(reassembler.pending() == 1);
+        assert!(reassembler.memory_used() == 48 + 2 * RUN);
+
+        let bytes = feed(&mut reassembler, 1, &middle).unwrap().unwrap();
+        assert!(reassembler.pending() == 0);
+        assert!(reassembler.memory_used() == 0);
+        assert!(bytes[20..] == original[20..]);
+
+        let ip = deserialize::<Ip>(&bytes).unwrap();
+        assert!(!ip.is_fragment());
+        assert!(ip.total_len() as usize == original.len());
+        assert!(internet_checksum(&ip.to_bytes()) == 0);
+        let pdu = Ip::from_bytes(&bytes).unwrap();
+        assert!(pdu.find::<Udp>().unwrap().dst_port() == 53);
+    }
+
+    #[test]
+    fn test_not_fragmented() {
+        let original = datagram();
+        let mut reassembler = Ipv4Reassembler::new();
+        assert!(feed(&mut reassembler, 0, &original).unwrap() == Some(original));
+        assert!(reassembler.pending() == 0);
+    }
+
+    fn overlapped(policy: OverlapPolicy) -> Vec<u8> {
+        let original = datagram();
+        let mut reassembler = Ipv4Reassembler::new();
+        reassembler.set_policy(policy);
+
+        // 0..16 is 'A', then 8..24 is 'B' and 8..32 is 'C', then the rest.
+        feed(
+            &mut reassembler,
+            0,
+            &fragment(&original, 0, &[b'A'; 16], true),
+        )
+        .unwrap();
+        feed(
+            &mut reassembler,
+            0,
+            &fragment(&original, 8, &[b'B'; 16], true),
+        )
+        .unwrap();
+        feed(
+            &mut reassembler,
+            0,
+            &fragment(&original, 8, &[b'C'; 24], true),
+        )
+        .unwrap();
+        let bytes = feed(
+            &mut reassembler,
+            0,
+            &fragment(&original, 32, &[b'D'; 16], false),
+        )
+        .unwrap()
+        .unwrap();
+        bytes[20..52].to_vec()
+    }
+
+    #[test]
+    fn test_overlap_policies() {
+        let expect = |parts: &[(u8, usize)]| -> Vec<u8> {
+            parts.iter().flat_map(|&(byte, n)| vec![byte; n]).collect()
+        };
+
+        assert!(overlapped(OverlapPolicy::First) == expect(&[(b'A', 16), (b'B', 8), (b'C', 8)]));
+        assert!(overlapped(OverlapPolicy::Last) == expect(&[(b'A', 8), (b'C', 24)]));
+        assert!(overlapped(OverlapPolicy::Bsd) == expect(&[(b'A', 16), (b'B', 8), (b'C', 8)]));
+        assert!(overlapped(OverlapPolicy::Linux) == expect(&[(b'A', 16), (b'C', 16)]));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let original = datagram();
+        let mut reassembler = Ipv4Reassembler::new();
+        reassembler.set_timeout(Duration::from_secs(5));
+
+        feed(
+            &mut reassembler,
+            0,
+            &fragment(&original, 0, &original[20..36], true),
+        )
+        .unwrap();
+        assert!(reassembler.expire(Duration::from_secs(4)) == 0);
+        let tail = fragment(&original, 16, &original[36..], false);
+        assert!(feed(&mut reassembler, 5, &tail).unwrap().is_none());
+        assert!(reassembler.pending() == 1);
+        assert!(reassembler.memory_used() == 48 + RUN);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let original = datagram();
+        let mut other = original.clone();
+        other[5] = 0x35;
+        let mut reassembler = Ipv4Reassembler::new();
+        reassembler.set_memory_limit(40 + RUN);
+
+        feed(
+            &mut reassembler,
+            0,
+            &fragment(&original, 0, &original[20..36], true),
+        )
+        .unwrap();
+        feed(
+            &mut reassembler,
+            1,
+            &fragment(&other, 8, &other[28..52], true),
+        )
+        .unwrap();
+        assert!(reassembler.pending() == 1);
+        assert!(reassembler.memory_used() == 32 + RUN);
+
+        let tail = fragment(&original, 40, &original[60..], false);
+        assert!(feed(&mut reassembler, 2, &tail) == Err(ReassemblyError::MemoryLimit));
+        assert!(reassembler.pending() == 0);
+        assert!(reassembler.memory_used() == 0);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let original = datagram();
+        let mut reassembler = Ipv4Reassembler::new();
+        reassembler.set_policy(OverlapPolicy::Last);
+
+        let head = fragment(&original, 0, &original[20..36], true);
+        let middle = fragment(&original, 8, &original[28..44], true);
+        for _ in 0..100 {
+            feed(&mut reassembler, 0, &head).unwrap();
+            feed(&mut reassembler, 0, &middle).unwrap();
+        }
+        assert!(reassembler.memory_used() == 24 + 2 * RUN);
+
+        let tail = fragment(&original, 24, &original[44..], false);
+        let bytes = feed(&mut reassembler, 0, &tail).unwrap().unwrap();
+        assert!(bytes[20..] == original[20..]);
+    }
+
+    #[test]
+    fn test_inconsistent() {
+        let original = datagram();
+        let mut reassembler = Ipv4Reassembler::new();
+        let odd = fragment(&original, 0, &original[20..30], true);
+        assert!(feed(&mut reassembler, 0, &odd) == Err(ReassemblyError::Inconsistent));
+
+        feed(
+            &mut reassembler,
+            0,
+            &fragment(&original, 16, &original[36..], false),
+        )
+        .unwrap();
+        let past_end = fragment(&original, 48, &original[20..28], true);
+        assert!(feed(&mut reassembler, 0, &past_end) == Err(ReassemblyError::Inconsistent));
+        assert!(reassembler.pending() == 0);
+    }
+
+    /// A UDP datagram with a 40 byte payload, after a hop-by-hop header.
+    fn ipv6_datagram() -> (Ipv6<'static>, Vec<u8>) {
+        let mut payload = IPV4_UDP[20..].to_vec();
+        payload.extend(0..40u8);
+        let mut hop_by_hop = Ipv6ExtHeader::new(HOP_BY_HOP, 8);
+        hop_by_hop.set_next_header(17);
+        let mut ipv6 = Ipv6::new();
+        ipv6.with_src_addr("2001:db8::1".parse().unwrap())
+            .with_dst_addr("2001:db8::2".parse().unwrap())
+            .with_next_header(HOP_BY_HOP)
+            .with_ext_header(hop_by_hop)
+            .with_payload_len((8 + payload.len()) as u16);
+        (ipv6, payload)
+    }
+
+    /// A fragment of `payload` carrying `data` at `offset`, its fragment
+    /// header after the hop-by-hop header.
+    fn ipv6_fragment(ipv6: &Ipv6, offset: usize, data: &[u8], more: bool) -> Vec<u8> {
+        let mut frag = Ipv6ExtHeader::new(FRAGMENT, 8);
+        frag.with_next_header(17)
+            .with_frag_offset((offset / 8) as u16)
+            .with_more_fragments(more)
+            .with_identification(0xdeadbeef);
+        let mut hop_by_hop = ipv6.ext_headers()[0].to_owned();
+        hop_by_hop.set_next_header(FRAGMENT);
+
+        let mut bytes = Ipv6::new()
+            .with_src_addr(ipv6.src_addr())
+            .with_dst_addr(ipv6.dst_addr())
+            .with_next_header(HOP_BY_HOP)
+            .with_ext_headers(vec![hop_by_hop, frag])
+            .with_payload_len((16 + data.len()) as u16)
+            .to_bytes();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn feed_ipv6(
+        reassembler: &mut Ipv6Reassembler,
+        ts: u64,
+        bytes: &[u8],
+    ) -> Result<Option<Vec<u8>>, ReassemblyError> {
+        let pdu = Ipv6::from_bytes(bytes).unwrap();
+        reassembler.process(Duration::from_secs(ts), pdu.downcast_ref::<Ipv6>().unwrap())
+    }
+
+    #[test]
+    fn test_ipv6_out_of_order() {
+        let (ipv6, payload) = ipv6_datagram();
+        let mut reassembler = Ipv6Reassembler::new();
+
+        let tail = ipv6_fragment(&ipv6, 32, &payload[32..], false);
+        let head = ipv6_fragment(&ipv6, 0, &payload[..32], true);
+        assert!(Ipv6::from_bytes(&tail).unwrap().find::<Udp>().is_none());
+        assert!(feed_ipv6(&mut reassembler, 0, &tail).unwrap().is_none());
+        assert!(reassembler.pending() == 1);
+
+        let bytes = feed_ipv6(&mut reassembler, 1, &head).unwrap().unwrap();
+        assert!(reassembler.pending() == 0);
+        assert!(reassembler.memory_used() == 0);
+        let mut expected = ipv6.to_bytes();
+        expected.extend_from_slice(&payload);
+        assert!(bytes == expected);
+
+        let pdu = Ipv6::from_bytes(&bytes).unwrap();
+        let reassembled = pdu.downcast_ref::<Ipv6>().unwrap();
+        assert!(reassembled.ext_header(FRAGMENT).is_none());
+        assert!(reassembled.upper_layer_protocol() == 17);
+        assert!(pdu.find::<Udp>().unwrap().dst_port() == 53);
+    }
+
+    #[test]
+    fn test_ipv6_atomic_fragment() {
+        let (ipv6, payload) = ipv6_datagram();
+        let mut reassembler = Ipv6Reassembler::new();
+        let atomic = ipv6_fragment(&ipv6, 0, &payload, false);
+
+        let bytes = feed_ipv6(&mut reassembler, 0, &atomic).unwrap().unwrap();
+        assert!(reassembler.pending() == 0);
+        assert!(bytes[..ipv6.to_bytes().len()] == ipv6.to_bytes());
+        assert!(Ipv6::from_bytes(&bytes).unwrap().find::<Udp>().is_some());
+
+        let mut plain = ipv6.to_bytes();
+        plain.extend_from_slice(&payload);
+        assert!(feed_ipv6(&mut reassembler, 0, &plain).unwrap() == Some(plain));
+    }
+
+    #[test]
+    fn test_ipv6_atomic_fragment_keeps_later_headers() {
+        let (ipv6, payload) = ipv6_datagram();
+        let mut frag = Ipv6ExtHeader::new(FRAGMENT, 8);
+        frag.set_next_header(DEST_OPTS);
+        let mut dest_opts = Ipv6ExtHeader::new(DEST_OPTS, 8);
+        dest_opts.set_next_header(17);
+        let mut atomic = Ipv6::new()
+            .with_src_addr(ipv6.src_addr())
+            .with_dst_addr(ipv6.dst_addr())
+            .with_next_header(FRAGMENT)
+            .with_ext_headers(vec![frag, dest_opts.to_owned()])
+            .with_payload_len((16 + payload.len()) as u16)
+            .to_bytes();
+        atomic.extend_from_slice(&payload);
+
+        let mut reassembler = Ipv6Reassembler::new();
+        let bytes = feed_ipv6(&mut reassembler, 0, &atomic).unwrap().unwrap();
+        let mut expected = Ipv6::new()
+            .with_src_addr(ipv6.src_addr())
+            .with_dst_addr(ipv6.dst_addr())
+            .with_next_header(DEST_OPTS)
+            .with_ext_headers(vec![dest_opts])
+            .with_payload_len((8 + payload.len()) as u16)
+            .to_bytes();
+        expected.extend_from_slice(&payload);
+        assert!(bytes == expected);
+        assert!(Ipv6::from_bytes(&bytes).unwrap().find::<Udp>().is_some());
+    }
+
+    #[test]
+    fn test_ipv6_overlap() {
+        let (ipv6, payload) = ipv6_datagram();
+        let mut reassembler = Ipv6Reassembler::new();
+        reassembler.set_policy(OverlapPolicy::Last);
+        let head = ipv6_fragment(&ipv6, 0, &payload[..32], true);
+        assert!(feed_ipv6(&mut reassembler, 0, &head).unwrap().is_none());
+        assert!(feed_ipv6(&mut reassembler, 0, &head).unwrap().is_none());
+        assert!(reassembler.memory_used() == 32 + RUN);
+
+        let overlap = ipv6_fragment(&ipv6, 24, &payload[24..], false);
+        assert!(feed_ipv6(&mut reassembler, 0, &overlap) == Err(ReassemblyError::Inconsistent));
+        assert!(reassembler.pending() == 0);
+    }
+
+    #[test]
+    fn test_ipv6_timeout_and_memory_limit() {
+        let (ipv6, payload) = ipv6_datagram();
+        let mut reassembler = Ipv6Reassembler::new();
+        let head = ipv6_fragment(&ipv6, 0, &payload[..32], true);
+        let tail = ipv6_fragment(&ipv6, 32, &payload[32..], false);
+
+        assert!(feed_ipv6(&mut reassembler, 0, &head).unwrap().is_none());
+        assert!(reassembler.memory_used() == 32 + RUN);
+        assert!(feed_ipv6(&mut reassembler, 31, &tail).unwrap().is_none());
+        assert!(reassembler.pending() == 1);
+        assert!(reassembler.memory_used() == 48 + RUN);
+
+        reassembler.set_memory_limit(24);
+        assert!(feed_ipv6(&mut reassembler, 32, &tail) == Err(ReassemblyError::MemoryLimit));
+        assert!(reassembler.pending() == 0);
+        assert!(reassembler.memory_used() == 0);
+    }
+
+    #[test]
+    fn test_ipv6_unaligned_fragment() {
+        let (ipv6, payload) = ipv6_datagram();
+        let mut reassembler = Ipv6Reassembler::new();
+        let head = ipv6_fragment(&ipv6, 0, &payload[..30], true);
+        assert!(feed_ipv6(&mut reassembler, 0, &head) == Err(ReassemblyError::Inconsistent));
+    }
+}
@@ -0,0 +1,1520 @@
+use crate::prelude::*;
+use crate::tcp::{TCP_DISSECTION_TABLE, TcpType};
+use crate::udp::{UDP_DISSECTION_TABLE, UdpType};
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const DNS_ID_OFFSET: usize = 0;
+const DNS_FLAGS_OFFSET: usize = 2;
+const DNS_QDCOUNT_OFFSET: usize = 4;
+const DNS_ANCOUNT_OFFSET: usize = 6;
+const DNS_NSCOUNT_OFFSET: usize = 8;
+const DNS_ARCOUNT_OFFSET: usize = 10;
+const DNS_HEADER_LEN: usize = 12;
+
+const DNS_TCP_LENGTH_OFFSET: usize = 0;
+const DNS_TCP_HEADER_LEN: usize = 2;
+
+const DNS_QR_MASK: u16 = 0x8000;
+const DNS_OPCODE_MASK: u16 = 0x7800;
+const DNS_OPCODE_SHIFT: u16 = 11;
+const DNS_AA_MASK: u16 = 0x0400;
+const DNS_TC_MASK: u16 = 0x0200;
+const DNS_RD_MASK: u16 = 0x0100;
+const DNS_RA_MASK: u16 = 0x0080;
+const DNS_Z_MASK: u16 = 0x0040;
+const DNS_AD_MASK: u16 = 0x0020;
+const DNS_CD_MASK: u16 = 0x0010;
+const DNS_RCODE_MASK: u16 = 0x000F;
+
+const DNS_MAX_LABEL_LEN: usize = 63;
+const DNS_MAX_NAME_LEN: usize = 255;
+const DNS_POINTER_MASK: u8 = 0xC0;
+const DNS_MAX_POINTER: usize = 0x3FFF;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_OPT: u16 = 41;
+pub const TYPE_ANY: u16 = 255;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_CH: u16 = 3;
+pub const CLASS_ANY: u16 = 255;
+
+pub const OPCODE_QUERY: u8 = 0;
+pub const OPCODE_IQUERY: u8 = 1;
+pub const OPCODE_STATUS: u8 = 2;
+pub const OPCODE_NOTIFY: u8 = 4;
+pub const OPCODE_UPDATE: u8 = 5;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_FORMERR: u8 = 1;
+pub const RCODE_SERVFAIL: u8 = 2;
+pub const RCODE_NXDOMAIN: u8 = 3;
+pub const RCODE_NOTIMP: u8 = 4;
+pub const RCODE_REFUSED: u8 = 5;
+
+const DNS_OPCODE_NAMES: ValueStrings = &[
+    (OPCODE_QUERY as u64, "Standard query"),
+    (OPCODE_IQUERY as u64, "Inverse query"),
+    (OPCODE_STATUS as u64, "Server status request"),
+    (OPCODE_NOTIFY as u64, "Zone change notification"),
+    (OPCODE_UPDATE as u64, "Dynamic update"),
+];
+
+const DNS_RCODE_NAMES: ValueStrings = &[
+    (RCODE_NOERROR as u64, "No error"),
+    (RCODE_FORMERR as u64, "Format error"),
+    (RCODE_SERVFAIL as u64, "Server failure"),
+    (RCODE_NXDOMAIN as u64, "No such name"),
+    (RCODE_NOTIMP as u64, "Not implemented"),
+    (RCODE_REFUSED as u64, "Refused"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+impl DnsQuestion {
+    /// Fails if `name` has a label over 63 bytes or is over 255 bytes in
+    /// wire format.
+    pub fn new(name: &str, qtype: u16, qclass: u16) -> Result<Self, ParseError> {
+        check_name(name)?;
+        Ok(Self {
+            name: trim_root(name).to_string(),
+            qtype,
+            qclass,
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "dns.qry.name": self.name,
+            "dns.qry.type": self.qtype,
+            "dns.qry.class": self.qclass,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+/// Decoded resource record data. Types without a dedicated variant are kept
+/// as raw bytes in `Unknown`. Names are in presentation format, see
+/// `escape_label`. TXT strings longer than 255 bytes are split when written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsRData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Ptr(String),
+    Mx {
+        preference: u16,
+        exchange: String,
+    },
+    Soa {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    Txt(Vec<Vec<u8>>),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Opt(Vec<EdnsOption>),
+    Unknown(Vec<u8>),
+}
+
+impl DnsRData {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            DnsRData::A(addr) => json!(addr.to_string()),
+            DnsRData::Aaaa(addr) => json!(addr.to_string()),
+            DnsRData::Cname(name) | DnsRData::Ns(name) | DnsRData::Ptr(name) => json!(name),
+            DnsRData::Mx {
+                preference,
+                exchange,
+            } => json!({
+                "dns.mx.preference": preference,
+                "dns.mx.exchange": exchange,
+            }),
+            DnsRData::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => json!({
+                "dns.soa.mname": mname,
+                "dns.soa.rname": rname,
+                "dns.soa.serial": serial,
+                "dns.soa.refresh": refresh,
+                "dns.soa.retry": retry,
+                "dns.soa.expire": expire,
+                "dns.soa.minimum": minimum,
+            }),
+            DnsRData::Txt(strings) => json!(
+                strings
+                    .iter()
+                    .map(|s| printable_ascii(s))
+                    .collect::<Vec<String>>()
+            ),
+            DnsRData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => json!({
+                "dns.srv.priority": priority,
+                "dns.srv.weight": weight,
+                "dns.srv.port": port,
+                "dns.srv.target": target,
+            }),
+            DnsRData::Opt(opts) => json!(
+                opts.iter()
+                    .map(|opt| json!({
+                        "dns.opt.code": opt.code,
+                        "dns.opt.data": printable_ascii(&opt.data),
+                    }))
+                    .collect::<Vec<serde_json::Value>>()
+            ),
+            DnsRData::Unknown(data) => json!(printable_ascii(data)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+    pub name: String,
+    pub rtype: u16,
+    pub rclass: u16,
+    pub ttl: u32,
+    pub data: DnsRData,
+}
+
+impl DnsRecord {
+    /// Fails if `name` or a name in `data` has a label over 63 bytes or is
+    /// over 255 bytes in wire format.
+    pub fn new(
+        name: &str,
+        rtype: u16,
+        rclass: u16,
+        ttl: u32,
+        data: DnsRData,
+    ) -> Result<Self, ParseError> {
+        let record = Self {
+            name: trim_root(name).to_string(),
+            rtype,
+            rclass,
+            ttl,
+            data,
+        };
+        record.check_names()?;
+        Ok(record)
+    }
+
+    /// Builds an EDNS0 OPT pseudo-record advertising `udp_payload_size`.
+    pub fn edns(udp_payload_size: u16, dnssec_ok: bool, options: Vec<EdnsOption>) -> Self {
+        let ttl = if dnssec_ok { 0x8000 } else { 0 };
+        Self {
+            name: String::new(),
+            rtype: TYPE_OPT,
+            rclass: udp_payload_size,
+            ttl,
+            data: DnsRData::Opt(options),
+        }
+    }
+
+    fn check_names(&self) -> Result<(), ParseError> {
+        check_name(&self.name)?;
+        match &self.data {
+            DnsRData::Cname(name) | DnsRData::Ns(name) | DnsRData::Ptr(name) => check_name(name),
+            DnsRData::Mx { exchange, .. } => check_name(exchange),
+            DnsRData::Soa { mname, rname, .. } => {
+                check_name(mname)?;
+                check_name(rname)
+            }
+            DnsRData::Srv { target, .. } => check_name(target),
+            _ => Ok(()),
+        }
+    }
+
+    /// Only meaningful for OPT records, where the class carries the payload size.
+    pub fn udp_payload_size(&self) -> u16 {
+        self.rclass
+    }
+
+    /// Only meaningful for OPT records. Upper 8 bits of the extended RCODE.
+    pub fn ext_rcode(&self) -> u8 {
+        (self.ttl >> 24) as u8
+    }
+
+    /// Only meaningful for OPT records.
+    pub fn edns_version(&self) -> u8 {
+        (self.ttl >> 16) as u8
+    }
+
+    /// Only meaningful for OPT records.
+    pub fn dnssec_ok(&self) -> bool {
+        (self.ttl & 0x8000) != 0
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "dns.resp.name": self.name,
+            "dns.resp.type": self.rtype,
+            "dns.resp.class": self.rclass,
+            "dns.resp.ttl": self.ttl,
+            "dns.resp.data": self.data.to_json(),
+        })
+    }
+}
+
+/// Drops the trailing dot of a fully qualified name, unless it is escaped.
+fn trim_root(name: &str) -> &str {
+    match name.strip_suffix('.') {
+        Some(trimmed) if trimmed.bytes().rev().take_while(|&b| b == b'\\').count() % 2 == 0 => {
+            trimmed
+        }
+        _ => name,
+    }
+}
+
+/// Appends a label in presentation format, the way dig and Wireshark show
+/// it: dots and backslashes are escaped with a backslash, and bytes that
+/// aren't printable ASCII as `\DDD` in decimal.
+fn escape_label(label: &[u8], name: &mut String) {
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                name.push('\\');
+                name.push(byte as char);
+            }
+            0x21..=0x7e => name.push(byte as char),
+            _ => name.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+}
+
+/// Splits a name in presentation format into raw labels, undoing
+/// `escape_label`.
+fn parse_labels(name: &str) -> Vec<Vec<u8>> {
+    let name = trim_root(name).as_bytes();
+    if name.is_empty() {
+        return Vec::new();
+    }
+
+    let mut labels = vec![Vec::new()];
+    let mut pos = 0;
+    while pos < name.len() {
+        let label = labels.last_mut().unwrap();
+        match name[pos] {
+            b'\\' => {
+                let digits = name
+                    .get(pos + 1..pos + 4)
+                    .filter(|d| d.iter().all(u8::is_ascii_digit));
+                match digits.and_then(|d| std::str::from_utf8(d).ok()?.parse::<u8>().ok()) {
+                    Some(byte) => {
+                        label.push(byte);
+                        pos += 4;
+                    }
+                    None => {
+                        label.extend(name.get(pos + 1));
+                        pos += 2;
+                    }
+                }
+            }
+            b'.' => {
+                labels.push(Vec::new());
+                pos += 1;
+            }
+            byte => {
+                label.push(byte);
+                pos += 1;
+            }
+        }
+    }
+    labels
+}
+
+/// Checks that a name in presentation format can be written. Offsets are
+/// within the name in wire format.
+fn check_name(name: &str) -> Result<(), ParseError> {
+    let mut name_len = 0;
+    for label in parse_labels(name) {
+        if label.len() > DNS_MAX_LABEL_LEN {
+            return Err(ParseError::InvalidHeader {
+                protocol: "dns",
+                field: "label length",
+                offset: name_len,
+                value: label.len() as u64,
+                reason: "exceeds the maximum of 63",
+            });
+        }
+        name_len += label.len() + 1;
+    }
+    // The root label ends every name.
+    name_len += 1;
+    if name_len > DNS_MAX_NAME_LEN {
+        return Err(ParseError::InvalidHeader {
+            protocol: "dns",
+            field: "name length",
+            offset: 0,
+            value: name_len as u64,
+            reason: "exceeds the maximum of 255",
+        });
+    }
+    Ok(())
+}
+
+/// Reads a possibly compressed name starting at `offset` within `msg`. Returns
+/// the name and the offset just past it in the original (uncompressed) stream.
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc1035#section-4.1.4>
+fn read_name(msg: &[u8], offset: usize) -> Result<(String, usize), ParseError> {
+    let mut name = String::new();
+    let mut pos = offset;
+    let mut end = None;
+    // The root label ends every name.
+    let mut name_len = 1;
+
+    loop {
+        ParseError::check_len("dns", "label length", msg, pos, 1)?;
+        let len = msg[pos];
+
+        if len & DNS_POINTER_MASK == DNS_POINTER_MASK {
+            ParseError::check_len("dns", "compression pointer", msg, pos, 2)?;
+            let target = (((len & !DNS_POINTER_MASK) as usize) << 8) | msg[pos + 1] as usize;
+            // Pointers must move strictly backwards, which rules out loops.
+            if target >= pos {
+                return Err(ParseError::InvalidHeader {
+                    protocol: "dns",
+                    field: "compression pointer",
+                    offset: pos,
+                    value: target as u64,
+                    reason: "doesn't point backwards",
+                });
+            }
+            end.get_or_insert(pos + 2);
+            pos = target;
+            continue;
+        }
+
+        if len & DNS_POINTER_MASK != 0 {
+            return Err(ParseError::InvalidHeader {
+                protocol: "dns",
+                field: "label length",
+                offset: pos,
+                value: len as u64,
+                reason: "uses a reserved label type",
+            });
+        }
+
+        if len == 0 {
+            let end = *end.get_or_insert(pos + 1);
+            return Ok((name, end));
+        }
+
+        let len = len as usize;
+        name_len += len + 1;
+        if name_len > DNS_MAX_NAME_LEN {
+            return Err(ParseError::InvalidHeader {
+                protocol: "dns",
+                field: "name length",
+                offset,
+                value: name_len as u64,
+                reason: "exceeds the maximum of 255",
+            });
+        }
+
+        ParseError::check_len("dns", "label length", msg, pos + 1, len)?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        escape_label(&msg[pos + 1..pos + 1 + len], &mut name);
+        pos += 1 + len;
+    }
+}
+
+fn read_u16(msg: &[u8], offset: usize, field: &'static str) -> Result<u16, ParseError> {
+    ParseError::check_len("dns", field, msg, offset, 2)?;
+    Ok(parse_bytes::<u16>(&msg[offset..offset + 2], Endian::Big))
+}
+
+fn read_u32(msg: &[u8], offset: usize, field: &'static str) -> Result<u32, ParseError> {
+    ParseError::check_len("dns", field, msg, offset, 4)?;
+    Ok(parse_bytes::<u32>(&msg[offset..offset + 4], Endian::Big))
+}
+
+fn read_question(msg: &[u8], offset: usize) -> Result<(DnsQuestion, usize), ParseError> {
+    let (name, pos) = read_name(msg, offset)?;
+    let question = DnsQuestion {
+        name,
+        qtype: read_u16(msg, pos, "qtype")?,
+        qclass: read_u16(msg, pos + 2, "qclass")?,
+    };
+    Ok((question, pos + 4))
+}
+
+fn read_rdata(msg: &[u8], rtype: u16, start: usize, end: usize) -> Result<DnsRData, ParseError> {
+    let rdata = &msg[start..end];
+    // Reads within the record data can't run into the next record.
+    let rdata_u16 = |pos: usize, field| read_u16(&msg[..end], start + pos, field);
+    let name_at = |offset: usize| -> Result<(String, usize), ParseError> {
+        let (name, pos) = read_name(msg, offset)?;
+        if pos > end {
+            return Err(ParseError::InvalidHeader {
+                protocol: "dns",
+                field: "name length",
+                offset,
+                value: (pos - offset) as u64,
+                reason: "runs past the record data",
+            });
+        }
+        Ok((name, pos))
+    };
+    let rdlength = |reason| ParseError::InvalidHeader {
+        protocol: "dns",
+        field: "rdlength",
+        offset: start - 2,
+        value: rdata.len() as u64,
+        reason,
+    };
+
+    let data = match rtype {
+        TYPE_A => {
+            let addr: [u8; 4] = rdata
+                .try_into()
+                .map_err(|_| rdlength("isn't 4 for an A record"))?;
+            DnsRData::A(Ipv4Addr::from(addr))
+        }
+        TYPE_AAAA => {
+            let addr: [u8; 16] = rdata
+                .try_into()
+                .map_err(|_| rdlength("isn't 16 for an AAAA record"))?;
+            DnsRData::Aaaa(Ipv6Addr::from(addr))
+        }
+        TYPE_CNAME => DnsRData::Cname(name_at(start)?.0),
+        TYPE_NS => DnsRData::Ns(name_at(start)?.0),
+        TYPE_PTR => DnsRData::Ptr(name_at(start)?.0),
+        TYPE_MX => DnsRData::Mx {
+            preference: rdata_u16(0, "preference")?,
+            exchange: name_at(start + 2)?.0,
+        },
+        TYPE_SOA => {
+            let (mname, pos) = name_at(start)?;
+            let (rname, pos) = name_at(pos)?;
+            let fixed = &msg[..end];
+            DnsRData::Soa {
+                mname,
+                rname,
+                serial: read_u32(fixed, pos, "serial")?,
+                refresh: read_u32(fixed, pos + 4, "refresh")?,
+                retry: read_u32(fixed, pos + 8, "retry")?,
+                expire: read_u32(fixed, pos + 12, "expire")?,
+                minimum: read_u32(fixed, pos + 16, "minimum")?,
+            }
+        }
+        TYPE_TXT => {
+            let mut strings = Vec::new();
+            let mut pos = 0;
+            while pos < rdata.len() {
+                let len = rdata[pos] as usize;
+                ParseError::check_len("dns", "txt length", &msg[..end], start + pos + 1, len)?;
+                strings.push(rdata[pos + 1..pos + 1 + len].to_vec());
+                pos += 1 + len;
+            }
+            DnsRData::Txt(strings)
+        }
+        TYPE_SRV => DnsRData::Srv {
+            priority: rdata_u16(0, "priority")?,
+            weight: rdata_u16(2, "weight")?,
+            port: rdata_u16(4, "port")?,
+            target: name_at(start + 6)?.0,
+        },
+        TYPE_OPT => {
+            let mut opts = Vec::new();
+            let mut pos = 0;
+            while pos < rdata.len() {
+                let code = rdata_u16(pos, "option code")?;
+                let len = rdata_u16(pos + 2, "option length")? as usize;
+                ParseError::check_len("dns", "option length", &msg[..end], start + pos + 4, len)?;
+                opts.push(EdnsOption {
+                    code,
+                    data: rdata[pos + 4..pos + 4 + len].to_vec(),
+                });
+                pos += 4 + len;
+            }
+            DnsRData::Opt(opts)
+        }
+        _ => DnsRData::Unknown(rdata.to_vec()),
+    };
+
+    Ok(data)
+}
+
+fn read_record(msg: &[u8], offset: usize) -> Result<(DnsRecord, usize), ParseError> {
+    let (name, pos) = read_name(msg, offset)?;
+    let rtype = read_u16(msg, pos, "type")?;
+    let rclass = read_u16(msg, pos + 2, "class")?;
+    let ttl = read_u32(msg, pos + 4, "ttl")?;
+    let rdlength = read_u16(msg, pos + 8, "rdlength")? as usize;
+    let rdata_start = pos + 10;
+    let rdata_end = rdata_start + rdlength;
+    ParseError::check_len("dns", "rdlength", msg, rdata_start, rdlength)?;
+
+    let record = DnsRecord {
+        name,
+        rtype,
+        rclass,
+        ttl,
+        data: read_rdata(msg, rtype, rdata_start, rdata_end)?,
+    };
+    Ok((record, rdata_end))
+}
+
+fn read_records(msg: &[u8], offset: &mut usize, count: u16) -> Result<Vec<DnsRecord>, ParseError> {
+    let mut records = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (record, pos) = read_record(msg, *offset)?;
+        records.push(record);
+        *offset = pos;
+    }
+    Ok(records)
+}
+
+/// Serializes names with message compression. Suffixes already written are
+/// replaced with a pointer to their first occurrence.
+struct DnsWriter {
+    buf: Vec<u8>,
+    names: HashMap<Vec<Vec<u8>>, usize>,
+}
+
+impl DnsWriter {
+    fn write_name(&mut self, name: &str, compress: bool) {
+        let labels = parse_labels(name);
+        for idx in 0..labels.len() {
+            // Matching is exact, so a pointer never changes the case of
+            // what it replaces.
+            let suffix = labels[idx..].to_vec();
+            if compress && let Some(&target) = self.names.get(&suffix) {
+                self.buf
+                    .extend_from_slice(&(0xC000 | target as u16).to_be_bytes());
+                return;
+            }
+            if self.buf.len() <= DNS_MAX_POINTER {
+                self.names.entry(suffix).or_insert(self.buf.len());
+            }
+
+            let label = &labels[idx];
+            self.buf.push(label.len() as u8);
+            self.buf.extend_from_slice(label);
+        }
+        self.buf.push(0);
+    }
+
+    fn write_question(&mut self, question: &DnsQuestion) {
+        self.write_name(&question.name, true);
+        self.buf.extend_from_slice(&question.qtype.to_be_bytes());
+        self.buf.extend_from_slice(&question.qclass.to_be_bytes());
+    }
+
+    fn write_record(&mut self, record: &DnsRecord) {
+        self.write_name(&record.name, true);
+        self.buf.extend_from_slice(&record.rtype.to_be_bytes());
+        self.buf.extend_from_slice(&record.rclass.to_be_bytes());
+        self.buf.extend_from_slice(&record.ttl.to_be_bytes());
+
+        let rdlength_offset = self.buf.len();
+        self.buf.extend_from_slice(&[0, 0]);
+
+        match &record.data {
+            DnsRData::A(addr) => self.buf.extend_from_slice(&addr.octets()),
+            DnsRData::Aaaa(addr) => self.buf.extend_from_slice(&addr.octets()),
+            DnsRData::Cname(name) | DnsRData::Ns(name) | DnsRData::Ptr(name) => {
+                self.write_name(name, true)
+            }
+            DnsRData::Mx {
+                preference,
+                exchange,
+            } => {
+                self.buf.extend_from_slice(&preference.to_be_bytes());
+                self.write_name(exchange, true);
+            }
+            DnsRData::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                self.write_name(mname, true);
+                self.write_name(rname, true);
+                for value in [serial, refresh, retry, expire, minimum] {
+                    self.buf.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            DnsRData::Txt(strings) => {
+                for string in strings {
+                    // Character strings are at most 255 bytes, longer ones
+                    // carry on in the next string.
+                    for chunk in string.chunks(u8::MAX as usize) {
+                        self.buf.push(chunk.len() as u8);
+                        self.buf.extend_from_slice(chunk);
+                    }
+                    if string.is_empty() {
+                        self.buf.push(0);
+                    }
+                }
+            }
+            DnsRData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                self.buf.extend_from_slice(&priority.to_be_bytes());
+                self.buf.extend_from_slice(&weight.to_be_bytes());
+                self.buf.extend_from_slice(&port.to_be_bytes());
+                // RFC 2782 forbids compressing the SRV target
+                self.write_name(target, false);
+            }
+            DnsRData::Opt(opts) => {
+                for opt in opts {
+                    self.buf.extend_from_slice(&opt.code.to_be_bytes());
+                    self.buf
+                        .extend_from_slice(&(opt.data.len() as u16).to_be_bytes());
+                    self.buf.extend_from_slice(&opt.data);
+                }
+            }
+            DnsRData::Unknown(data) => self.buf.extend_from_slice(data),
+        }
+
+        let rdlength = (self.buf.len() - rdlength_offset - 2) as u16;
+        self.buf[rdlength_offset..rdlength_offset + 2].copy_from_slice(&rdlength.to_be_bytes());
+    }
+}
+
+#[pdu_type]
+pub struct Dns<'a> {
+    questions: Vec<DnsQuestion>,
+    answers: Vec<DnsRecord>,
+    authorities: Vec<DnsRecord>,
+    additionals: Vec<DnsRecord>,
+}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Dns<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = DnsWriter {
+            buf: self.header.to_vec(),
+            names: HashMap::new(),
+        };
+        for question in &self.questions {
+            writer.write_question(question);
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            writer.write_record(record);
+        }
+        writer.buf
+    }
+
+    fn clone(&self) -> Box<dyn Pdu<'static> + 'static> {
+        Box::new(Dns {
+            header: Cow::Owned(self.header.to_vec()),
+            questions: self.questions.clone(),
+            answers: self.answers.clone(),
+            authorities: self.authorities.clone(),
+            additionals: self.additionals.clone(),
+            parent: None,
+            child: None,
+        })
+    }
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("dns", "header length", bytes, 0, DNS_HEADER_LEN)?;
+
+        let mut offset = DNS_HEADER_LEN;
+        let qdcount = read_u16(bytes, DNS_QDCOUNT_OFFSET, "qdcount")?;
+        let mut questions = Vec::with_capacity(qdcount as usize);
+        for _ in 0..qdcount {
+            let (question, pos) = read_question(bytes, offset)?;
+            questions.push(question);
+            offset = pos;
+        }
+
+        let ancount = read_u16(bytes, DNS_ANCOUNT_OFFSET, "ancount")?;
+        let answers = read_records(bytes, &mut offset, ancount)?;
+        let nscount = read_u16(bytes, DNS_NSCOUNT_OFFSET, "nscount")?;
+        let authorities = read_records(bytes, &mut offset, nscount)?;
+        let arcount = read_u16(bytes, DNS_ARCOUNT_OFFSET, "arcount")?;
+        let additionals = read_records(bytes, &mut offset, arcount)?;
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..DNS_HEADER_LEN]),
+            questions,
+            answers,
+            authorities,
+            additionals,
+            parent: None,
+            child: None,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        let records_json = |records: &[DnsRecord]| -> Vec<serde_json::Value> {
+            records.iter().map(|r| r.to_json()).collect()
+        };
+
+        Ok(json!({
+            "dns": {
+                "dns.id": self.id(),
+                "dns.flags": self.flags(),
+                "dns.qr": self.qr(),
+                "dns.opcode": self.opcode(),
+                "dns.aa": self.aa(),
+                "dns.tc": self.tc(),
+                "dns.rd": self.rd(),
+                "dns.ra": self.ra(),
+                "dns.ad": self.ad(),
+                "dns.cd": self.cd(),
+                "dns.rcode": self.rcode(),
+                "dns.qdcount": self.qdcount(),
+                "dns.ancount": self.ancount(),
+                "dns.nscount": self.nscount(),
+                "dns.arcount": self.arcount(),
+                "dns.questions": self
+                    .questions
+                    .iter()
+                    .map(|q| q.to_json())
+                    .collect::<Vec<serde_json::Value>>(),
+                "dns.answers": records_json(&self.answers),
+                "dns.authorities": records_json(&self.authorities),
+                "dns.additionals": records_json(&self.additionals),
+            }
+        }))
+    }
+}
+
+impl<'a> Dns<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; DNS_HEADER_LEN]),
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            parent: None,
+            child: None,
+        }
+    }
+
+    /// Creates a recursive query for a single question.
+    pub fn query(id: u16, name: &str, qtype: u16) -> Result<Self, ParseError> {
+        let mut dns = Self::new();
+        dns.with_id(id)
+            .with_rd(true)
+            .with_question(DnsQuestion::new(name, qtype, CLASS_IN)?)?;
+        Ok(dns)
+    }
+
+    /// Creates an empty response echoing the id, opcode, RD bit and questions
+    /// of this message.
+    pub fn response(&self) -> Dns<'static> {
+        let mut dns = Dns::new();
+        dns.with_id(self.id())
+            .with_qr(true)
+            .with_opcode(self.opcode())
+            .with_rd(self.rd());
+        dns.questions = self.questions.clone();
+        dns.set_u16(DNS_QDCOUNT_OFFSET, dns.questions.len() as u16);
+        dns
+    }
+
+    fn get_u16(&self, offset: usize) -> u16 {
+        parse_bytes::<u16>(&self.header[offset..offset + 2], Endian::Big)
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) {
+        self.header.to_mut()[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    fn set_flag(&mut self, mask: u16, value: bool) {
+        let flags = self.flags() & !mask;
+        self.set_flags(if value { flags | mask } else { flags });
+    }
+
+    pub fn id(&self) -> u16 {
+        self.get_u16(DNS_ID_OFFSET)
+    }
+
+    pub fn set_id(&mut self, id: u16) {
+        self.set_u16(DNS_ID_OFFSET, id);
+    }
+
+    pub fn with_id(&mut self, id: u16) -> &mut Self {
+        self.set_id(id);
+        self
+    }
+
+    pub fn flags(&self) -> u16 {
+        self.get_u16(DNS_FLAGS_OFFSET)
+    }
+
+    pub fn set_flags(&mut self, flags: u16) {
+        self.set_u16(DNS_FLAGS_OFFSET, flags);
+    }
+
+    pub fn with_flags(&mut self, flags: u16) -> &mut Self {
+        self.set_flags(flags);
+        self
+    }
+
+    pub fn qr(&self) -> bool {
+        (self.flags() & DNS_QR_MASK) != 0
+    }
+
+    pub fn set_qr(&mut self, qr: bool) {
+        self.set_flag(DNS_QR_MASK, qr);
+    }
+
+    pub fn with_qr(&mut self, qr: bool) -> &mut Self {
+        self.set_qr(qr);
+        self
+    }
+
+    pub fn opcode(&self) -> u8 {
+        ((self.flags() & DNS_OPCODE_MASK) >> DNS_OPCODE_SHIFT) as u8
+    }
+
+    pub fn set_opcode(&mut self, opcode: u8) {
+        let flags = self.flags() & !DNS_OPCODE_MASK;
+        self.set_flags(flags | (((opcode as u16) << DNS_OPCODE_SHIFT) & DNS_OPCODE_MASK));
+    }
+
+    pub fn with_opcode(&mut self, opcode: u8) -> &mut Self {
+        self.set_opcode(opcode);
+        self
+    }
+
+    pub fn aa(&self) -> bool {
+        (self.flags() & DNS_AA_MASK) != 0
+    }
+
+    pub fn set_aa(&mut self, aa: bool) {
+        self.set_flag(DNS_AA_MASK, aa);
+    }
+
+    pub fn with_aa(&mut self, aa: bool) -> &mut Self {
+        self.set_aa(aa);
+        self
+    }
+
+    pub fn tc(&self) -> bool {
+        (self.flags() & DNS_TC_MASK) != 0
+    }
+
+    pub fn set_tc(&mut self, tc: bool) {
+        self.set_flag(DNS_TC_MASK, tc);
+    }
+
+    pub fn with_tc(&mut self, tc: bool) -> &mut Self {
+        self.set_tc(tc);
+        self
+    }
+
+    pub fn rd(&self) -> bool {
+        (self.flags() & DNS_RD_MASK) != 0
+    }
+
+    pub fn set_rd(&mut self, rd: bool) {
+        self.set_flag(DNS_RD_MASK, rd);
+    }
+
+    pub fn with_rd(&mut self, rd: bool) -> &mut Self {
+        self.set_rd(rd);
+        self
+    }
+
+    pub fn ra(&self) -> bool {
+        (self.flags() & DNS_RA_MASK) != 0
+    }
+
+    pub fn set_ra(&mut self, ra: bool) {
+        self.set_flag(DNS_RA_MASK, ra);
+    }
+
+    pub fn with_ra(&mut self, ra: bool) -> &mut Self {
+        self.set_ra(ra);
+        self
+    }
+
+    pub fn z(&self) -> bool {
+        (self.flags() & DNS_Z_MASK) != 0
+    }
+
+    pub fn ad(&self) -> bool {
+        (self.flags() & DNS_AD_MASK) != 0
+    }
+
+    pub fn set_ad(&mut self, ad: bool) {
+        self.set_flag(DNS_AD_MASK, ad);
+    }
+
+    pub fn with_ad(&mut self, ad: bool) -> &mut Self {
+        self.set_ad(ad);
+        self
+    }
+
+    pub fn cd(&self) -> bool {
+        (self.flags() & DNS_CD_MASK) != 0
+    }
+
+    pub fn set_cd(&mut self, cd: bool) {
+        self.set_flag(DNS_CD_MASK, cd);
+    }
+
+    pub fn with_cd(&mut self, cd: bool) -> &mut Self {
+        self.set_cd(cd);
+        self
+    }
+
+    pub fn rcode(&self) -> u8 {
+        (self.flags() & DNS_RCODE_MASK) as u8
+    }
+
+    pub fn set_rcode(&mut self, rcode: u8) {
+        let flags = self.flags() & !DNS_RCODE_MASK;
+        self.set_flags(flags | (rcode as u16 & DNS_RCODE_MASK));
+    }
+
+    pub fn with_rcode(&mut self, rcode: u8) -> &mut Self {
+        self.set_rcode(rcode);
+        self
+    }
+
+    pub fn qdcount(&self) -> u16 {
+        self.get_u16(DNS_QDCOUNT_OFFSET)
+    }
+
+    pub fn ancount(&self) -> u16 {
+        self.get_u16(DNS_ANCOUNT_OFFSET)
+    }
+
+    pub fn nscount(&self) -> u16 {
+        self.get_u16(DNS_NSCOUNT_OFFSET)
+    }
+
+    pub fn arcount(&self) -> u16 {
+        self.get_u16(DNS_ARCOUNT_OFFSET)
+    }
+
+    pub fn questions(&self) -> &[DnsQuestion] {
+        &self.questions
+    }
+
+    /// Fails if the name can't be written, see `DnsQuestion::new`.
+    pub fn add_question(&mut self, question: DnsQuestion) -> Result<(), ParseError> {
+        check_name(&question.name)?;
+        self.questions.push(question);
+        self.set_u16(DNS_QDCOUNT_OFFSET, self.questions.len() as u16);
+        Ok(())
+    }
+
+    pub fn with_question(&mut self, question: DnsQuestion) -> Result<&mut Self, ParseError> {
+        self.add_question(question)?;
+        Ok(self)
+    }
+
+    pub fn answers(&self) -> &[DnsRecord] {
+        &self.answers
+    }
+
+    /// Fails if a name can't be written, see `DnsRecord::new`.
+    pub fn add_answer(&mut self, record: DnsRecord) -> Result<(), ParseError> {
+        record.check_names()?;
+        self.answers.push(record);
+        self.set_u16(DNS_ANCOUNT_OFFSET, self.answers.len() as u16);
+        Ok(())
+    }
+
+    pub fn with_answer(&mut self, record: DnsRecord) -> Result<&mut Self, ParseError> {
+        self.add_answer(record)?;
+        Ok(self)
+    }
+
+    pub fn authorities(&self) -> &[DnsRecord] {
+        &self.authorities
+    }
+
+    pub fn add_authority(&mut self, record: DnsRecord) -> Result<(), ParseError> {
+        record.check_names()?;
+        self.authorities.push(record);
+        self.set_u16(DNS_NSCOUNT_OFFSET, self.authorities.len() as u16);
+        Ok(())
+    }
+
+    pub fn with_authority(&mut self, record: DnsRecord) -> Result<&mut Self, ParseError> {
+        self.add_authority(record)?;
+        Ok(self)
+    }
+
+    pub fn additionals(&self) -> &[DnsRecord] {
+        &self.additionals
+    }
+
+    pub fn add_additional(&mut self, record: DnsRecord) -> Result<(), ParseError> {
+        record.check_names()?;
+        self.additionals.push(record);
+        self.set_u16(DNS_ARCOUNT_OFFSET, self.additionals.len() as u16);
+        Ok(())
+    }
+
+    pub fn with_additional(&mut self, record: DnsRecord) -> Result<&mut Self, ParseError> {
+        self.add_additional(record)?;
+        Ok(self)
+    }
+
+    /// Returns the EDNS0 OPT pseudo-record from the additional section, if any.
+    pub fn edns(&self) -> Option<&DnsRecord> {
+        self.additionals.iter().find(|r| r.rtype == TYPE_OPT)
+    }
+}
+
+/// DNS over TCP prefixes every message with a two byte length.
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc1035#section-4.2.2>
+#[pdu_type]
+pub struct DnsTcp<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for DnsTcp<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(DnsTcp);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("dns_tcp", "header length", bytes, 0, DNS_TCP_HEADER_LEN)?;
+        let length = parse_bytes::<u16>(
+            &bytes[DNS_TCP_LENGTH_OFFSET..DNS_TCP_HEADER_LEN],
+            Endian::Big,
+        ) as usize;
+        ParseError::check_len("dns_tcp", "length", bytes, DNS_TCP_HEADER_LEN, length)?;
+        let msg = &bytes[DNS_TCP_HEADER_LEN..DNS_TCP_HEADER_LEN + length];
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..DNS_TCP_HEADER_LEN]),
+            parent: None,
+            child: Some(
+                Dns::from_bytes(msg).map_err(|err| err.within("dns_tcp", DNS_TCP_HEADER_LEN))?,
+            ),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "dns_tcp": {
+                "dns_tcp.length": self.length(),
+                "dns_tcp.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> DnsTcp<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; DNS_TCP_HEADER_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn length(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[DNS_TCP_LENGTH_OFFSET..DNS_TCP_HEADER_LEN],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_length(&mut self, length: u16) {
+        self.header.to_mut()[DNS_TCP_LENGTH_OFFSET..DNS_TCP_HEADER_LEN]
+            .copy_from_slice(&length.to_be_bytes());
+    }
+
+    pub fn with_length(&mut self, length: u16) -> &mut Self {
+        self.set_length(length);
+        self
+    }
+}
+
+register_pdu!(UdpType(53), Dns, UDP_DISSECTION_TABLE);
+register_pdu!(TcpType(53), DnsTcp, TCP_DISSECTION_TABLE);
+
+register_fields!(Dns, "dns", {
+    "dns.id" => id { name: "Transaction ID", bits: (DNS_ID_OFFSET * 8, 16), base: Hex },
+    "dns.flags" => flags { name: "Flags", bits: (DNS_FLAGS_OFFSET * 8, 16), base: Hex },
+    "dns.qr" => qr { name: "Response", bits: (DNS_FLAGS_OFFSET * 8, 1) },
+    "dns.opcode" => opcode {
+        name: "Opcode",
+        bits: (DNS_FLAGS_OFFSET * 8 + 1, 4),
+        values: DNS_OPCODE_NAMES,
+    },
+    "dns.aa" => aa { name: "Authoritative", bits: (DNS_FLAGS_OFFSET * 8 + 5, 1) },
+    "dns.tc" => tc { name: "Truncated", bits: (DNS_FLAGS_OFFSET * 8 + 6, 1) },
+    "dns.rd" => rd { name: "Recursion Desired", bits: (DNS_FLAGS_OFFSET * 8 + 7, 1) },
+    "dns.ra" => ra { name: "Recursion Available", bits: (DNS_FLAGS_OFFSET * 8 + 8, 1) },
+    "dns.ad" => ad { name: "Authentic Data", bits: (DNS_FLAGS_OFFSET * 8 + 10, 1) },
+    "dns.cd" => cd { name: "Checking Disabled", bits: (DNS_FLAGS_OFFSET * 8 + 11, 1) },
+    "dns.rcode" => rcode {
+        name: "Reply Code",
+        bits: (DNS_FLAGS_OFFSET * 8 + 12, 4),
+        values: DNS_RCODE_NAMES,
+    },
+    "dns.qdcount" => qdcount { name: "Questions", bits: (DNS_QDCOUNT_OFFSET * 8, 16) },
+    "dns.ancount" => ancount { name: "Answer RRs", bits: (DNS_ANCOUNT_OFFSET * 8, 16) },
+    "dns.nscount" => nscount { name: "Authority RRs", bits: (DNS_NSCOUNT_OFFSET * 8, 16) },
+    "dns.arcount" => arcount { name: "Additional RRs", bits: (DNS_ARCOUNT_OFFSET * 8, 16) },
+});
+register_fields!(DnsTcp, "dns_tcp", {
+    "dns_tcp.length" => length { name: "Length", bits: (DNS_TCP_LENGTH_OFFSET * 8, 16) },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdu::deserialize;
+
+    const DNS_QUERY: [u8; 29] = [
+        0x12, 0x34, // ID
+        0x01, 0x00, // Flags: RD
+        0x00, 0x01, // QDCOUNT
+        0x00, 0x00, // ANCOUNT
+        0x00, 0x00, // NSCOUNT
+        0x00, 0x00, // ARCOUNT
+        0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', // "example"
+        0x03, b'c', b'o', b'm', // "com"
+        0x00, // root
+        0x00, 0x01, // QTYPE = A
+        0x00, 0x01, // QCLASS = IN
+    ];
+
+    const DNS_RESPONSE: [u8; 90] = [
+        0x12, 0x34, // ID
+        0x81, 0x80, // Flags: QR, RD, RA
+        0x00, 0x01, // QDCOUNT
+        0x00, 0x02, // ANCOUNT
+        0x00, 0x00, // NSCOUNT
+        0x00, 0x01, // ARCOUNT
+        // Question: www.example.com A IN
+        0x03, b'w', b'w', b'w', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o',
+        b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+        // Answer 1: www.example.com CNAME web.example.com
+        0xc0, 0x0c, // pointer to www.example.com
+        0x00, 0x05, 0x00, 0x01, // CNAME IN
+        0x00, 0x00, 0x0e, 0x10, // TTL = 3600
+        0x00, 0x06, // RDLENGTH
+        0x03, b'w', b'e', b'b', 0xc0, 0x10, // web + pointer to example.com
+        // Answer 2: web.example.com A 93.184.216.34
+        0xc0, 0x2d, // pointer to web.example.com
+        0x00, 0x01, 0x00, 0x01, // A IN
+        0x00, 0x00, 0x00, 0x3c, // TTL = 60
+        0x00, 0x04, // RDLENGTH
+        0x5d, 0xb8, 0xd8, 0x22, // 93.184.216.34
+        // Additional: OPT, payload 1232, DO bit, one cookie option
+        0x00, // root
+        0x00, 0x29, // OPT
+        0x04, 0xd0, // UDP payload size
+        0x00, 0x00, 0x80, 0x00, // ext rcode, version, DO
+        0x00, 0x0c, // RDLENGTH
+        0x00, 0x0a, 0x00, 0x08, // COOKIE, length 8
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+    ];
+
+    #[test]
+    fn test_query() {
+        let dns = deserialize::<Dns>(&DNS_QUERY).unwrap();
+        assert!(dns.id() == 0x1234);
+        assert!(!dns.qr());
+        assert!(dns.rd());
+        assert!(dns.questions() == [DnsQuestion::new("example.com", TYPE_A, CLASS_IN).unwrap()]);
+        assert!(dns.to_bytes() == DNS_QUERY);
+    }
+
+    #[test]
+    fn test_response() {
+        let dns = deserialize::<Dns>(&DNS_RESPONSE).unwrap();
+        assert!(dns.qr() && dns.ra() && dns.rcode() == RCODE_NOERROR);
+        assert!(dns.answers().len() == 2);
+        assert!(dns.answers()[0].data == DnsRData::Cname("web.example.com".to_string()));
+        assert!(dns.answers()[1].name == "web.example.com");
+        assert!(dns.answers()[1].data == DnsRData::A(Ipv4Addr::new(93, 184, 216, 34)));
+
+        let edns = dns.edns().unwrap();
+        assert!(edns.udp_payload_size() == 1232);
+        assert!(edns.dnssec_ok());
+        assert!(edns.edns_version() == 0);
+
+        assert!(dns.to_bytes() == DNS_RESPONSE);
+    }
+
+    #[test]
+    fn test_pointer_loop() {
+        let mut msg = DNS_QUERY.to_vec();
+        // Replace the question name with a pointer to itself
+        msg.truncate(DNS_HEADER_LEN);
+        msg.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+        let err = Dns::from_bytes(&msg).err().unwrap();
+        assert!(
+            err.to_string() == "dns: compression pointer 12 doesn't point backwards at offset 12"
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(Dns::from_bytes(&DNS_RESPONSE[..60]).is_err());
+        assert!(Dns::from_bytes(&DNS_QUERY[..8]).is_err());
+    }
+
+    #[test]
+    fn test_build_round_trip() {
+        let query = Dns::query(0xbeef, "example.org.", TYPE_MX).unwrap();
+        let mut response = query.response();
+        response
+            .with_ra(true)
+            .with_answer(
+                DnsRecord::new(
+                    "example.org",
+                    TYPE_MX,
+                    CLASS_IN,
+                    300,
+                    DnsRData::Mx {
+                        preference: 10,
+                        exchange: "mail.example.org".to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+            .with_answer(
+                DnsRecord::new(
+                    "example.org",
+                    TYPE_TXT,
+                    CLASS_IN,
+                    300,
+                    DnsRData::Txt(vec![b"v=spf1 -all".to_vec()]),
+                )
+                .unwrap(),
+            )
+            .unwrap()
+            .with_authority(
+                DnsRecord::new(
+                    "example.org",
+                    TYPE_SOA,
+                    CLASS_IN,
+                    300,
+                    DnsRData::Soa {
+                        mname: "ns1.example.org".to_string(),
+                        rname: "hostmaster.example.org".to_string(),
+                        serial: 2024010101,
+                        refresh: 7200,
+                        retry: 3600,
+                        expire: 1209600,
+                        minimum: 300,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+            .with_additional(
+                DnsRecord::new(
+                    "_sip._udp.example.org",
+                    TYPE_SRV,
+                    CLASS_IN,
+                    300,
+                    DnsRData::Srv {
+                        priority: 1,
+                        weight: 2,
+                        port: 5060,
+                        target: "sip.example.org".to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+            .with_additional(
+                DnsRecord::new(
+                    "mail.example.org",
+                    TYPE_AAAA,
+                    CLASS_IN,
+                    300,
+                    DnsRData::Aaaa(Ipv6Addr::LOCALHOST),
+                )
+                .unwrap(),
+            )
+            .unwrap()
+            .with_additional(DnsRecord::edns(4096, false, Vec::new()))
+            .unwrap();
+
+        let bytes = response.to_bytes();
+        let parsed = deserialize::<Dns>(&bytes).unwrap();
+        assert!(parsed.id() == 0xbeef);
+        assert!(parsed.qr() && parsed.rd() && parsed.ra());
+        assert!(parsed.ancount() == 2 && parsed.nscount() == 1 && parsed.arcount() == 3);
+        assert!(parsed.questions() == response.questions());
+        assert!(parsed.answers() == response.answers());
+        assert!(parsed.authorities() == response.authorities());
+        assert!(parsed.additionals() == response.additionals());
+        assert!(parsed.to_bytes() == bytes);
+    }
+
+    #[test]
+    fn test_binary_labels() {
+        let mut msg = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        msg.push(63);
+        msg.extend_from_slice(&[0xff; 63]);
+        msg.extend_from_slice(&[5, b'a', b'.', b'b', b'\\', b' ', 0]);
+        msg.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+
+        let dns = deserialize::<Dns>(&msg).unwrap();
+        let name = &dns.questions()[0].name;
+        assert!(name.starts_with("\\255\\255") && name.ends_with(".a\\.b\\\\\\032"));
+        assert!(dns.to_bytes() == msg);
+
+        let question = DnsQuestion::new("trailing\\.", TYPE_A, CLASS_IN).unwrap();
+        assert!(question.name == "trailing\\.");
+        assert!(parse_labels("trailing\\.") == [b"trailing.".to_vec()]);
+        assert!(parse_labels("a.b.") == [b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn test_name_limits() {
+        let err = DnsQuestion::new(&"a".repeat(64), TYPE_A, CLASS_IN)
+            .err()
+            .unwrap();
+        assert!(err.to_string() == "dns: label length 64 exceeds the maximum of 63 at offset 0");
+
+        let label = "a".repeat(63);
+        let longest = format!("{0}.{0}.{0}.{1}", label, "a".repeat(61));
+        let bytes = Dns::query(1, &longest, TYPE_A).unwrap().to_bytes();
+        assert!(deserialize::<Dns>(&bytes).unwrap().questions()[0].name == longest);
+        let too_long = format!("{0}.{0}.{0}.{1}", label, "a".repeat(62));
+        let err = DnsQuestion::new(&too_long, TYPE_A, CLASS_IN).err().unwrap();
+        assert!(err.to_string() == "dns: name length 256 exceeds the maximum of 255 at offset 0");
+
+        let cname = DnsRData::Cname(format!("{}.example.org", "a".repeat(64)));
+        assert!(DnsRecord::new("example.org", TYPE_CNAME, CLASS_IN, 300, cname).is_err());
+
+        // Names can be changed after they're checked, so adding checks again.
+        let mut record = DnsRecord::new(
+            "example.org",
+            TYPE_A,
+            CLASS_IN,
+            300,
+            DnsRData::A(Ipv4Addr::LOCALHOST),
+        )
+        .unwrap();
+        record.name = "a".repeat(64);
+        let mut dns = Dns::new();
+        assert!(dns.add_answer(record).is_err());
+        assert!(dns.ancount() == 0);
+    }
+
+    #[test]
+    fn test_compression_keeps_case() {
+        let mut response = Dns::query(1, "Example.ORG", TYPE_A).unwrap().response();
+        response
+            .with_answer(
+                DnsRecord::new(
+                    "example.org",
+                    TYPE_CNAME,
+                    CLASS_IN,
+                    300,
+                    DnsRData::Cname("www.EXAMPLE.org".to_string()),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        let bytes = response.to_bytes();
+        let parsed = deserialize::<Dns>(&bytes).unwrap();
+        assert!(parsed.questions()[0].name == "Example.ORG");
+        assert!(parsed.answers()[0].name == "example.org");
+        assert!(parsed.answers()[0].data == DnsRData::Cname("www.EXAMPLE.org".to_string()));
+    }
+
+    #[test]
+    fn test_long_txt() {
+        let mut response = Dns::query(1, "example.org", TYPE_TXT).unwrap().response();
+        response
+            .with_answer(
+                DnsRecord::new(
+                    "example.org",
+                    TYPE_TXT,
+                    CLASS_IN,
+                    300,
+                    DnsRData::Txt(vec![vec![b'x'; 300], Vec::new()]),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        let bytes = response.to_bytes();
+        let parsed = deserialize::<Dns>(&bytes).unwrap();
+        assert!(
+            parsed.answers()[0].data
+                == DnsRData::Txt(vec![vec![b'x'; 255], vec![b'x'; 45], Vec::new()])
+        );
+    }
+
+    #[test]
+    fn test_tcp_length_prefix() {
+        let mut msg = (DNS_QUERY.len() as u16).to_be_bytes().to_vec();
+        msg.extend_from_slice(&DNS_QUERY);
+        let dns_tcp = DnsTcp::from_bytes(&msg).unwrap();
+        let dns = dns_tcp.find::<Dns>().unwrap();
+        assert!(dns.id() == 0x1234);
+        assert!(DnsTcp::from_bytes(&msg[..20]).is_err());
+
+        // Errors in the message are reported at their offset in the stream.
+        let mut bad = msg.clone();
+        bad[DNS_TCP_HEADER_LEN + DNS_HEADER_LEN] = 0xc0;
+        let err = DnsTcp::from_bytes(&bad).err().unwrap();
+        assert!(err.protocol() == "dns_tcp");
+        assert!(err.root().offset() == DNS_TCP_HEADER_LEN + DNS_HEADER_LEN);
+        assert!(
+            err.to_string()
+                .starts_with("dns_tcp: dns: compression pointer")
+        );
+    }
+}
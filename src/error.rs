@@ -1,30 +1,281 @@
 use std::error::Error;
 use std::fmt;
 
-#[derive(Debug)]
+/// Why a Pdu couldn't be parsed. Offsets are in bytes from the start of the
+/// slice given to the outermost parser that reported the error, see
+/// `ParseError::within`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseError {
-    NotEnoughData,
-    InvalidHeader,
-    UnsupportedProtocol,
+    /// `field` needs `expected` bytes from `offset`, but only `actual` remain.
+    NotEnoughData {
+        protocol: &'static str,
+        field: &'static str,
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// `field` at `offset` holds a value the protocol doesn't allow, `reason`
+    /// says why, e.g. "is shorter than the header".
+    InvalidHeader {
+        protocol: &'static str,
+        field: &'static str,
+        offset: usize,
+        value: u64,
+        reason: &'static str,
+    },
+    /// `field` at `offset` selects something that isn't decoded.
+    UnsupportedProtocol {
+        protocol: &'static str,
+        field: &'static str,
+        offset: usize,
+        value: u64,
+    },
+    /// A nested layer or structure starting at `offset` failed to parse.
+    Layer {
+        protocol: &'static str,
+        offset: usize,
+        source: Box<ParseError>,
+    },
 }
 
 #[derive(Debug)]
 pub enum AllocError {
     InsufficientSpace,
+    /// The existing contents couldn't be parsed, so they can't be rewritten
+    /// without losing them.
+    Malformed(ParseError),
+}
+
+#[derive(Debug)]
+pub enum PcapError {
+    /// The file starts with neither a pcap nor a pcapng magic number.
+    UnknownFormat,
+    /// A header or block runs past the end of the file.
+    Truncated,
+    /// A block length or field is inconsistent.
+    InvalidBlock,
+    /// A packet refers to an interface that was never described.
+    UnknownInterface(u32),
+    /// The output format can't represent what was asked for.
+    Unsupported(&'static str),
+    Io(std::io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReassemblyError {
+    /// A fragment disagrees with the datagram length or isn't a multiple
+    /// of the fragment unit. The datagram is dropped.
+    Inconsistent,
+    /// The datagram would be larger than the protocol allows.
+    TooLarge,
+    /// The datagram alone would exceed the memory cap.
+    MemoryLimit,
+}
+
+/// A display filter that doesn't parse or type check. `offset` is the byte
+/// offset in the filter text the problem was found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    /// Fails with `NotEnoughData` unless `len` bytes of `field` are
+    /// available from `offset` in `bytes`.
+    pub fn check_len(
+        protocol: &'static str,
+        field: &'static str,
+        bytes: &[u8],
+        offset: usize,
+        len: usize,
+    ) -> Result<(), ParseError> {
+        let actual = bytes.len().saturating_sub(offset);
+        if actual < len {
+            Err(ParseError::NotEnoughData {
+                protocol,
+                field,
+                offset,
+                expected: len,
+                actual,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wraps the error of a structure found at `offset` in a `protocol`
+    /// layer, moving its offsets to be relative to the layer. An error the
+    /// same protocol reported is only moved, so it isn't named twice.
+    pub fn within(self, protocol: &'static str, offset: usize) -> Self {
+        if self.protocol() == protocol {
+            return self.shifted(offset);
+        }
+        ParseError::Layer {
+            protocol,
+            offset,
+            source: Box::new(self.shifted(offset)),
+        }
+    }
+
+    /// Moves the offsets of an error found `by` bytes into the slice.
+    pub(crate) fn shifted(mut self, by: usize) -> Self {
+        match &mut self {
+            ParseError::NotEnoughData { offset, .. }
+            | ParseError::InvalidHeader { offset, .. }
+            | ParseError::UnsupportedProtocol { offset, .. } => *offset += by,
+            ParseError::Layer { offset, source, .. } => {
+                *offset += by;
+                **source = source.as_ref().clone().shifted(by);
+            }
+        }
+        self
+    }
+
+    /// The layer that reported the error.
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            ParseError::NotEnoughData { protocol, .. }
+            | ParseError::InvalidHeader { protocol, .. }
+            | ParseError::UnsupportedProtocol { protocol, .. }
+            | ParseError::Layer { protocol, .. } => protocol,
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        match self {
+            ParseError::NotEnoughData { offset, .. }
+            | ParseError::InvalidHeader { offset, .. }
+            | ParseError::UnsupportedProtocol { offset, .. }
+            | ParseError::Layer { offset, .. } => *offset,
+        }
+    }
+
+    /// The innermost error of a chain of layers.
+    pub fn root(&self) -> &ParseError {
+        match self {
+            ParseError::Layer { source, .. } => source.root(),
+            _ => self,
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "pdu parsing failed")
+        match self {
+            ParseError::NotEnoughData {
+                protocol,
+                field,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: {} {} exceeds remaining {} bytes at offset {}",
+                protocol, field, expected, actual, offset
+            ),
+            ParseError::InvalidHeader {
+                protocol,
+                field,
+                offset,
+                value,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "{}: {} {} {} at offset {}",
+                    protocol, field, value, reason, offset
+                )
+            }
+            ParseError::UnsupportedProtocol {
+                protocol,
+                field,
+                offset,
+                value,
+            } => {
+                write!(
+                    f,
+                    "{}: unsupported {} {:#x} at offset {}",
+                    protocol, field, value, offset
+                )
+            }
+            ParseError::Layer {
+                protocol, source, ..
+            } => write!(f, "{}: {}", protocol, source),
+        }
     }
 }
 
-impl Error for ParseError {}
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Layer { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for AllocError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "insufficient space")
+        match self {
+            AllocError::InsufficientSpace => write!(f, "insufficient space"),
+            AllocError::Malformed(err) => write!(f, "malformed contents: {}", err),
+        }
     }
 }
 
-impl Error for AllocError {}
+impl Error for AllocError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AllocError::Malformed(err) => Some(err),
+            AllocError::InsufficientSpace => None,
+        }
+    }
+}
+
+impl fmt::Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PcapError::UnknownFormat => write!(f, "not a pcap or pcapng file"),
+            PcapError::Truncated => write!(f, "capture file is truncated"),
+            PcapError::InvalidBlock => write!(f, "invalid capture block"),
+            PcapError::UnknownInterface(id) => write!(f, "unknown interface {}", id),
+            PcapError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            PcapError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for PcapError {}
+
+impl fmt::Display for ReassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReassemblyError::Inconsistent => write!(f, "inconsistent fragment"),
+            ReassemblyError::TooLarge => write!(f, "reassembled datagram is too large"),
+            ReassemblyError::MemoryLimit => write!(f, "reassembly memory limit exceeded"),
+        }
+    }
+}
+
+impl Error for ReassemblyError {}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl Error for FilterError {}
+
+impl From<ParseError> for AllocError {
+    fn from(err: ParseError) -> Self {
+        AllocError::Malformed(err)
+    }
+}
+
+impl From<std::io::Error> for PcapError {
+    fn from(err: std::io::Error) -> Self {
+        PcapError::Io(err)
+    }
+}
@@ -1,4 +1,5 @@
 use crate::mac_address::MacAddress;
+use crate::pcap::{LINKTYPE_DISSECTION_TABLE, LINKTYPE_ETHERNET, LinkType};
 use crate::prelude::*;
 
 const ETH_DST_OFFSET: usize = 0;
@@ -6,10 +7,10 @@ const ETH_SRC_OFFSET: usize = 6;
 const ETH_TYPE_OFFSET: usize = 12;
 const ETH_HEADER_LEN: usize = 14;
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct EtherType(pub u16);
 
-fn get_ether_type<'a>(bytes: &'a [u8]) -> u16 {
+fn get_ether_type(bytes: &[u8]) -> u16 {
     parse_bytes::<u16>(
         &bytes[ETH_TYPE_OFFSET..ETH_HEADER_LEN],
         crate::utils::Endian::Big,
@@ -18,6 +19,40 @@ fn get_ether_type<'a>(bytes: &'a [u8]) -> u16 {
 
 pub static ETHER_DISSECTION_TABLE: DissectionTable<EtherType> = create_table();
 
+/// Transparent Ethernet Bridging, used by GRE and Geneve to carry frames.
+pub const ETHER_TYPE_TEB: u16 = 0x6558;
+
+register_pdu!(EtherType(ETHER_TYPE_TEB), Ethernet, ETHER_DISSECTION_TABLE);
+
+register_pdu!(
+    LinkType(LINKTYPE_ETHERNET),
+    Ethernet,
+    LINKTYPE_DISSECTION_TABLE
+);
+
+/// EtherType names, shared by every field that holds one.
+pub const ETHER_TYPE_NAMES: ValueStrings = &[
+    (0x0800, "IPv4"),
+    (0x0806, "ARP"),
+    (0x6558, "Transparent Ethernet Bridging"),
+    (0x8100, "802.1Q Virtual LAN"),
+    (0x86DD, "IPv6"),
+    (0x8847, "MPLS unicast"),
+    (0x8848, "MPLS multicast"),
+    (0x88A8, "802.1ad Provider Bridging"),
+];
+
+register_fields!(Ethernet, "eth", {
+    "eth.src_addr" => src_addr { name: "Source", bits: (ETH_SRC_OFFSET * 8, 48) },
+    "eth.dst_addr" => dst_addr { name: "Destination", bits: (ETH_DST_OFFSET * 8, 48) },
+    "eth.type" => ether_type {
+        name: "Type",
+        bits: (ETH_TYPE_OFFSET * 8, 16),
+        base: Hex,
+        values: ETHER_TYPE_NAMES,
+    },
+});
+
 #[pdu_type]
 pub struct Ethernet<'a> {}
 
@@ -38,17 +73,13 @@ impl<'a> Pdu<'a> for Ethernet<'a> {
     }
 
     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
-        if bytes.len() < ETH_HEADER_LEN {
-            return Err(ParseError::NotEnoughData);
-        }
+        ParseError::check_len("eth", "header length", bytes, 0, ETH_HEADER_LEN)?;
 
-        let Some(inner) = build_from_table(
+        let inner = build_from_table(
             &ETHER_DISSECTION_TABLE,
             EtherType(get_ether_type(bytes)),
             &bytes[ETH_HEADER_LEN..],
-        ) else {
-            return Err(ParseError::UnsupportedProtocol);
-        };
+        );
 
         Ok(Box::new(Self {
             header: Cow::Borrowed(&bytes[..ETH_HEADER_LEN]),
@@ -85,7 +116,7 @@ impl<'a> Ethernet<'a> {
 
     pub fn set_dst_addr(&mut self, dst_addr: MacAddress) {
         dst_addr
-            .into_buff(&mut self.header.to_mut()[..ETH_DST_OFFSET])
+            .into_buff(&mut self.header.to_mut()[ETH_DST_OFFSET..ETH_SRC_OFFSET])
             .expect("Failed to set destination MAC address");
     }
 
@@ -100,7 +131,7 @@ impl<'a> Ethernet<'a> {
 
     pub fn set_src_addr(&mut self, src_addr: MacAddress) {
         src_addr
-            .into_buff(&mut self.header.to_mut()[ETH_DST_OFFSET..ETH_SRC_OFFSET])
+            .into_buff(&mut self.header.to_mut()[ETH_SRC_OFFSET..ETH_TYPE_OFFSET])
             .expect("Failed to set source MAC address");
     }
 
@@ -122,3 +153,49 @@ impl<'a> Ethernet<'a> {
         get_ether_type(&self.header)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::Ip;
+
+    #[test]
+    fn test_set_addrs() {
+        let mut eth = Ethernet::new();
+        eth.with_dst_addr(MacAddress::from_bytes(&[0xff; 6]))
+            .with_src_addr(MacAddress::from_bytes(&[
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+            ]))
+            .with_ether_type(0x0800);
+        assert!(
+            eth.to_bytes()
+                == [
+                    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x08,
+                    0x00
+                ]
+        );
+        assert!(eth.src_addr().to_bytes() == [0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+    }
+
+    #[test]
+    fn test_nested_error() {
+        let mut bytes = vec![0xff; 12];
+        bytes.extend_from_slice(&[0x08, 0x00]);
+        bytes.extend_from_slice(&[0x45, 0x00, 0x00, 52, 0x00, 0x01, 0x40, 0x00, 0x40, 6, 0, 0]);
+        bytes.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 2]);
+        let mut tcp = vec![0; 32];
+        tcp[12] = 0xf0;
+        bytes.extend_from_slice(&tcp);
+
+        // The layers around the bad one are kept.
+        let eth = Ethernet::from_bytes(&bytes).unwrap();
+        assert!(eth.find::<Ip>().is_some());
+        assert!(eth.find::<Raw>().unwrap().error().unwrap().protocol() == "tcp");
+        let err = eth.parse_error().unwrap();
+        assert!(
+            err.to_string()
+                == "eth: ip: tcp: data offset 60 exceeds remaining 32 bytes at offset 34"
+        );
+        assert!(err.root().protocol() == "tcp");
+    }
+}
@@ -0,0 +1,571 @@
+use crate::mac_address::MacAddress;
+use crate::prelude::*;
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// The kind of value a field holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    Ipv4,
+    Ipv6,
+    Mac,
+    Bytes,
+    Str,
+}
+
+impl FieldType {
+    /// The largest value of an integer type.
+    pub fn max_value(&self) -> Option<u64> {
+        match self {
+            FieldType::U8 => Some(u8::MAX as u64),
+            FieldType::U16 => Some(u16::MAX as u64),
+            FieldType::U32 => Some(u32::MAX as u64),
+            FieldType::U64 => Some(u64::MAX),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FieldType::Bool => "bool",
+            FieldType::U8 => "u8",
+            FieldType::U16 => "u16",
+            FieldType::U32 => "u32",
+            FieldType::U64 => "u64",
+            FieldType::Ipv4 => "IPv4 address",
+            FieldType::Ipv6 => "IPv6 address",
+            FieldType::Mac => "MAC address",
+            FieldType::Bytes => "bytes",
+            FieldType::Str => "string",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A field read out of a Pdu. Values of the same type are ordered.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FieldValue {
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    Mac([u8; 6]),
+    Bytes(Vec<u8>),
+    Str(String),
+}
+
+impl FieldValue {
+    pub fn field_type(&self) -> FieldType {
+        match self {
+            FieldValue::Bool(_) => FieldType::Bool,
+            FieldValue::U8(_) => FieldType::U8,
+            FieldValue::U16(_) => FieldType::U16,
+            FieldValue::U32(_) => FieldType::U32,
+            FieldValue::U64(_) => FieldType::U64,
+            FieldValue::Ipv4(_) => FieldType::Ipv4,
+            FieldValue::Ipv6(_) => FieldType::Ipv6,
+            FieldValue::Mac(_) => FieldType::Mac,
+            FieldValue::Bytes(_) => FieldType::Bytes,
+            FieldValue::Str(_) => FieldType::Str,
+        }
+    }
+
+    /// The value of an integer or bool.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            FieldValue::Bool(value) => Some(*value as u64),
+            FieldValue::U8(value) => Some(*value as u64),
+            FieldValue::U16(value) => Some(*value as u64),
+            FieldValue::U32(value) => Some(*value as u64),
+            FieldValue::U64(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value in network byte order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            FieldValue::Bool(value) => vec![*value as u8],
+            FieldValue::U8(value) => value.to_be_bytes().to_vec(),
+            FieldValue::U16(value) => value.to_be_bytes().to_vec(),
+            FieldValue::U32(value) => value.to_be_bytes().to_vec(),
+            FieldValue::U64(value) => value.to_be_bytes().to_vec(),
+            FieldValue::Ipv4(addr) => addr.octets().to_vec(),
+            FieldValue::Ipv6(addr) => addr.octets().to_vec(),
+            FieldValue::Mac(addr) => addr.to_vec(),
+            FieldValue::Bytes(bytes) => bytes.clone(),
+            FieldValue::Str(value) => value.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldValue::Bool(value) => write!(f, "{}", value),
+            FieldValue::Ipv4(addr) => write!(f, "{}", addr),
+            FieldValue::Ipv6(addr) => write!(f, "{}", addr),
+            FieldValue::Mac(addr) => write!(f, "{}", MacAddress::from_bytes(addr)),
+            FieldValue::Bytes(bytes) => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "{}", hex.join(":"))
+            }
+            FieldValue::Str(value) => write!(f, "{:?}", value),
+            _ => write!(f, "{}", self.as_u64().unwrap_or_default()),
+        }
+    }
+}
+
+/// Accessor results that can be turned into a `FieldValue`.
+pub trait IntoFieldValue {
+    const TYPE: FieldType;
+
+    fn into_field_value(self) -> Option<FieldValue>;
+}
+
+macro_rules! impl_into_field_value {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl IntoFieldValue for $ty {
+                const TYPE: FieldType = FieldType::$variant;
+
+                fn into_field_value(self) -> Option<FieldValue> {
+                    Some(FieldValue::$variant(self.into()))
+                }
+            }
+        )*
+    };
+}
+
+impl_into_field_value!(
+    bool => Bool,
+    u8 => U8,
+    u16 => U16,
+    u32 => U32,
+    u64 => U64,
+    Ipv4Addr => Ipv4,
+    Ipv6Addr => Ipv6,
+    [u8; 6] => Mac,
+    Vec<u8> => Bytes,
+    &[u8] => Bytes,
+    String => Str,
+);
+
+impl IntoFieldValue for MacAddress<'_> {
+    const TYPE: FieldType = FieldType::Mac;
+
+    fn into_field_value(self) -> Option<FieldValue> {
+        Some(FieldValue::Mac(self.to_bytes()))
+    }
+}
+
+/// Optional fields, like the GRE key, are absent when `None`.
+impl<T: IntoFieldValue> IntoFieldValue for Option<T> {
+    const TYPE: FieldType = T::TYPE;
+
+    fn into_field_value(self) -> Option<FieldValue> {
+        self.and_then(T::into_field_value)
+    }
+}
+
+/// Reads a field from a Pdu of the type it was registered for.
+pub type FieldGetter = for<'p, 'a> fn(&'p (dyn Pdu<'a> + 'a)) -> Option<FieldValue>;
+
+/// How an integer field is shown to people.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldBase {
+    /// Not a number, or shown the way `FieldValue` displays it.
+    None,
+    Dec,
+    Hex,
+    Oct,
+    /// Decimal with the hex value in parentheses.
+    DecHex,
+    /// Hex with the decimal value in parentheses.
+    HexDec,
+}
+
+impl FieldBase {
+    /// The base a field gets unless it asks for another.
+    pub fn default_for(kind: FieldType) -> Self {
+        match kind {
+            FieldType::U8 | FieldType::U16 | FieldType::U32 | FieldType::U64 => FieldBase::Dec,
+            _ => FieldBase::None,
+        }
+    }
+}
+
+/// Names for well-known values of a field, e.g. `(6, "TCP")` for
+/// `ip.protocol`.
+pub type ValueStrings = &'static [(u64, &'static str)];
+
+/// A named field of one Pdu type, e.g. `ip.ttl`. Names match the keys
+/// emitted by `to_json`.
+#[derive(Clone, Copy)]
+pub struct FieldDescriptor {
+    pub name: &'static str,
+    pub display_name: &'static str,
+    pub protocol: TypeId,
+    pub kind: FieldType,
+    /// Bit offset and width within the header, counted from the most
+    /// significant bit of its first byte. `None` for fields without a fixed
+    /// place, like GRE's optional words. The accessor may scale what it
+    /// reads from there, as `tcp.data_offset` does.
+    pub bits: Option<(usize, usize)>,
+    pub base: FieldBase,
+    pub values: ValueStrings,
+    pub get: FieldGetter,
+}
+
+impl FieldDescriptor {
+    /// The name of a well-known value.
+    pub fn value_name(&self, value: &FieldValue) -> Option<&'static str> {
+        let value = value.as_u64()?;
+        self.values
+            .iter()
+            .find(|(known, _)| *known == value)
+            .map(|(_, name)| *name)
+    }
+
+    /// The value in the field's base, followed by its name if it has one,
+    /// e.g. `0x0800 (IPv4)`.
+    pub fn format(&self, value: &FieldValue) -> String {
+        let text = match (value, value.as_u64()) {
+            (FieldValue::Bool(_), _) | (_, None) => value.to_string(),
+            (_, Some(number)) => {
+                let digits = self.bits.map_or_else(
+                    || value.to_bytes().len() * 2,
+                    |(_, width)| width.div_ceil(4),
+                );
+                match self.base {
+                    FieldBase::None | FieldBase::Dec => number.to_string(),
+                    FieldBase::Hex => format!("{:#0width$x}", number, width = digits + 2),
+                    FieldBase::Oct => format!("{:#o}", number),
+                    FieldBase::DecHex => {
+                        format!("{} ({:#0width$x})", number, number, width = digits + 2)
+                    }
+                    FieldBase::HexDec => {
+                        format!("{:#0width$x} ({})", number, number, width = digits + 2)
+                    }
+                }
+            }
+        };
+        match self.value_name(value) {
+            Some(name) => format!("{} ({})", text, name),
+            None => text,
+        }
+    }
+
+    /// The field's bits in `header`, for fields with a fixed place of at
+    /// most 64 bits.
+    pub fn raw_bits(&self, header: &[u8]) -> Option<u64> {
+        let (offset, width) = self.bits?;
+        if width == 0 || width > 64 || offset + width > header.len() * 8 {
+            return None;
+        }
+        let mut value = 0u64;
+        for bit in offset..offset + width {
+            let set = header[bit / 8] >> (7 - bit % 8) & 1;
+            value = value << 1 | set as u64;
+        }
+        Some(value)
+    }
+}
+
+impl fmt::Debug for FieldDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FieldDescriptor")
+            .field("name", &self.name)
+            .field("display_name", &self.display_name)
+            .field("kind", &self.kind)
+            .field("bits", &self.bits)
+            .field("base", &self.base)
+            .finish()
+    }
+}
+
+/// Protocol and field names. A name can cover several Pdu types, like
+/// `sll` for both Linux cooked capture versions.
+#[derive(Default)]
+pub struct FieldRegistry {
+    protocols: HashMap<&'static str, Vec<TypeId>>,
+    names: HashMap<TypeId, &'static str>,
+    fields: HashMap<&'static str, Vec<FieldDescriptor>>,
+    by_type: HashMap<TypeId, Vec<FieldDescriptor>>,
+}
+
+impl FieldRegistry {
+    pub fn add_protocol(&mut self, name: &'static str, type_id: TypeId) {
+        self.protocols.entry(name).or_default().push(type_id);
+        self.names.entry(type_id).or_insert(name);
+    }
+
+    pub fn add_field(&mut self, field: FieldDescriptor) {
+        self.fields.entry(field.name).or_default().push(field);
+        self.by_type.entry(field.protocol).or_default().push(field);
+    }
+
+    pub fn protocol(&self, name: &str) -> Option<&[TypeId]> {
+        self.protocols.get(name).map(Vec::as_slice)
+    }
+
+    /// The name a Pdu type was registered under.
+    pub fn protocol_name(&self, type_id: TypeId) -> Option<&'static str> {
+        self.names.get(&type_id).copied()
+    }
+
+    pub fn field(&self, name: &str) -> Option<&[FieldDescriptor]> {
+        self.fields.get(name).map(Vec::as_slice)
+    }
+
+    /// The fields of a Pdu type, in the order they were registered.
+    pub fn fields_of(&self, type_id: TypeId) -> &[FieldDescriptor] {
+        self.by_type.get(&type_id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FieldDescriptor> {
+        self.by_type.values().flatten()
+    }
+}
+
+pub static FIELD_REGISTRY: LazyLock<RwLock<FieldRegistry>> =
+    LazyLock::new(|| RwLock::new(FieldRegistry::default()));
+
+pub fn lookup_protocol(name: &str) -> Option<Vec<TypeId>> {
+    let Ok(registry) = FIELD_REGISTRY.read() else {
+        panic!("Failed to secure field registry.")
+    };
+    registry.protocol(name).map(<[TypeId]>::to_vec)
+}
+
+pub fn protocol_name(type_id: TypeId) -> Option<&'static str> {
+    let Ok(registry) = FIELD_REGISTRY.read() else {
+        panic!("Failed to secure field registry.")
+    };
+    registry.protocol_name(type_id)
+}
+
+pub fn lookup_field(name: &str) -> Option<Vec<FieldDescriptor>> {
+    let Ok(registry) = FIELD_REGISTRY.read() else {
+        panic!("Failed to secure field registry.")
+    };
+    registry.field(name).map(<[FieldDescriptor]>::to_vec)
+}
+
+pub fn fields_of(type_id: TypeId) -> Vec<FieldDescriptor> {
+    let Ok(registry) = FIELD_REGISTRY.read() else {
+        panic!("Failed to secure field registry.")
+    };
+    registry.fields_of(type_id).to_vec()
+}
+
+/// Every registered field, sorted by name.
+pub fn all_fields() -> Vec<FieldDescriptor> {
+    let Ok(registry) = FIELD_REGISTRY.read() else {
+        panic!("Failed to secure field registry.")
+    };
+    let mut fields: Vec<FieldDescriptor> = registry.iter().copied().collect();
+    fields.sort_by_key(|field| field.name);
+    fields
+}
+
+/// The type of the field an accessor reads, for `register_fields!`.
+pub fn field_type_of<'p, P, T: IntoFieldValue>(_getter: fn(&'p P) -> T) -> FieldType {
+    T::TYPE
+}
+
+/// Registers a Pdu type under a protocol name, along with descriptors for
+/// fields read by its accessors. Each field takes a display name and
+/// optionally its `bits: (offset, width)` in the header, a `base` and a
+/// table of `values`:
+///
+/// ```ignore
+/// register_fields!(Ip, "ip", {
+///     "ip.ttl" => ttl { name: "Time to Live", bits: (64, 8) },
+///     "ip.protocol" => protocol { name: "Protocol", bits: (72, 8), values: IP_PROTOCOL_NAMES },
+/// });
+/// ```
+#[macro_export]
+macro_rules! register_fields {
+    (@opt) => { None };
+    (@opt $($value:tt)+) => { Some($($value)+) };
+    (
+        $pdu:ident, $protocol:literal, {
+            $(
+                $name:literal => $getter:ident {
+                    name: $display:literal
+                    $(, bits: ($offset:expr, $width:expr))?
+                    $(, base: $base:ident)?
+                    $(, values: $values:ident)?
+                    $(,)?
+                }
+            ),* $(,)?
+        }
+    ) => {
+        const _: () = {
+            paste! {
+                #[ctor]
+                fn [<__nexus_register_fields_ $pdu:lower>]() {
+                    let Ok(mut registry) = $crate::field::FIELD_REGISTRY.write() else {
+                        panic!("Failed to secure field registry.")
+                    };
+                    registry.add_protocol($protocol, <$pdu as Tid>::id());
+                    $(
+                        let kind = $crate::field::field_type_of($pdu::$getter);
+                        let base: Option<$crate::field::FieldBase> =
+                            $crate::register_fields!(@opt $($crate::field::FieldBase::$base)?);
+                        let values: Option<$crate::field::ValueStrings> =
+                            $crate::register_fields!(@opt $($values)?);
+                        registry.add_field($crate::field::FieldDescriptor {
+                            name: $name,
+                            display_name: $display,
+                            protocol: <$pdu as Tid>::id(),
+                            kind,
+                            bits: $crate::register_fields!(@opt $(($offset, $width))?),
+                            base: base.unwrap_or_else(|| $crate::field::FieldBase::default_for(kind)),
+                            values: values.unwrap_or(&[]),
+                            get: |pdu| {
+                                pdu.downcast_ref::<$pdu>().and_then(|pdu| {
+                                    $crate::field::IntoFieldValue::into_field_value(pdu.$getter())
+                                })
+                            },
+                        });
+                    )*
+                }
+            }
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethernet::Ethernet;
+    use crate::ip::Ip;
+    use crate::ip6::Ipv6;
+    use crate::tcp::Tcp;
+
+    const ETH_VLAN_IPV4_TCP: [u8; 58] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x81, 0x00, 0xb0,
+        0x64, 0x08, 0x00, // VLAN: PCP 5, DEI, VID 100
+        0x45, 0x10, 0x00, 0x28, 0x1c, 0x46, 0x40, 0x00, 0x40, 0x06, 0xab, 0xcd, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7, // IPv4
+        0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x50, 0x12, 0x72,
+        0x10, 0xbe, 0xef, 0x00, 0x07, // TCP SYN-ACK
+    ];
+
+    const IPV6_UDP_DNS: [u8; 60] = [
+        0x6a, 0xbc, 0xde, 0xf1, 0x00, 0x14, 0x11, 0x40, // Traffic class 0xab, flow 0xcdef1
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, // 2001:db8::1
+        0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02, // 2001:db8::2
+        0xd4, 0x31, 0x00, 0x35, 0x00, 0x14, 0x12, 0x34, // UDP
+        0xbe, 0xef, 0x85, 0xa3, 0, 0, 0, 0, 0, 0, 0, 0, // DNS response, no records
+    ];
+
+    #[test]
+    fn test_lookup() {
+        let ttl = lookup_field("ip.ttl").unwrap();
+        assert!(ttl.len() == 1);
+        assert!(ttl[0].kind == FieldType::U8);
+        assert!(ttl[0].protocol == <Ip as Tid>::id());
+        assert!(lookup_protocol("tcp").unwrap() == [<Tcp as Tid>::id()]);
+        assert!(lookup_protocol("sll").unwrap().len() == 2);
+        assert!(lookup_field("ip.nope").is_none());
+
+        let ip = Ip::new();
+        let pdu: &dyn Pdu = &ip;
+        assert!((ttl[0].get)(pdu) == Some(FieldValue::U8(ip.ttl())));
+        assert!((lookup_field("tcp.sport").unwrap()[0].get)(pdu).is_none());
+    }
+
+    #[test]
+    fn test_descriptors() {
+        let protocol = lookup_field("ip.protocol").unwrap()[0];
+        assert!(protocol.display_name == "Protocol");
+        assert!(protocol.bits == Some((72, 8)));
+        assert!(protocol.base == FieldBase::Dec);
+        assert!(protocol.format(&FieldValue::U8(6)) == "6 (TCP)");
+        assert!(protocol.format(&FieldValue::U8(200)) == "200");
+
+        let ether_type = lookup_field("eth.type").unwrap()[0];
+        assert!(ether_type.format(&FieldValue::U16(0x86dd)) == "0x86dd (IPv6)");
+        let flags = lookup_field("tcp.flags").unwrap()[0];
+        assert!(flags.format(&FieldValue::U8(0x12)) == "0x12");
+        let id = lookup_field("ip.id").unwrap()[0];
+        assert!(id.format(&FieldValue::U16(0x1c46)) == "0x1c46 (7238)");
+        let src = lookup_field("ip.src").unwrap()[0];
+        assert!(src.base == FieldBase::None);
+        assert!(src.format(&FieldValue::Ipv4(Ipv4Addr::new(10, 0, 0, 1))) == "10.0.0.1");
+        assert!(lookup_field("gre.key").unwrap()[0].bits.is_none());
+
+        let names: Vec<&str> = fields_of(<Tcp as Tid>::id())
+            .iter()
+            .map(|field| field.name)
+            .collect();
+        assert!(names[..3] == ["tcp.sport", "tcp.dport", "tcp.seq_number"]);
+        let all = all_fields();
+        assert!(all.is_sorted_by_key(|field| field.name));
+        assert!(all.iter().any(|field| field.name == "vxlan.vni"));
+    }
+
+    /// Every field with a fixed place reads the same value as its accessor.
+    #[test]
+    fn test_layouts() {
+        let packets: [&dyn Fn() -> Box<dyn Pdu<'static>>; 2] = [
+            &|| Ethernet::from_bytes(&ETH_VLAN_IPV4_TCP).unwrap(),
+            &|| Ipv6::from_bytes(&IPV6_UDP_DNS).unwrap(),
+        ];
+        let mut checked = 0;
+        for packet in packets {
+            let packet = packet();
+            let mut layer = Some(&*packet);
+            while let Some(pdu) = layer {
+                let header = pdu.to_bytes();
+                for (field, value) in pdu.fields() {
+                    let Some((offset, width)) = field.bits else {
+                        continue;
+                    };
+                    match (value.as_u64(), field.raw_bits(&header)) {
+                        (Some(value), Some(raw)) => {
+                            let expected = match field.name {
+                                "tcp.data_offset" => raw * 4,
+                                _ => raw,
+                            };
+                            assert!(value == expected, "{}", field.name);
+                        }
+                        _ => {
+                            let bytes = &header[offset / 8..(offset + width) / 8];
+                            assert!(value.to_bytes() == bytes, "{}", field.name);
+                        }
+                    }
+                    checked += 1;
+                }
+                layer = pdu.child_pdu().as_deref();
+            }
+        }
+        assert!(checked == 55);
+    }
+
+    #[test]
+    fn test_values() {
+        assert!(FieldValue::U16(0x1234).to_bytes() == [0x12, 0x34]);
+        assert!(FieldValue::U8(7) < FieldValue::U8(9));
+        assert!(
+            FieldValue::Mac([0, 0x11, 0x22, 0x33, 0x44, 0x55]).to_string() == "00:11:22:33:44:55"
+        );
+        assert!(Some(5u32).into_field_value() == Some(FieldValue::U32(5)));
+        assert!(None::<u32>.into_field_value().is_none());
+        assert!(<Option<u32>>::TYPE == FieldType::U32);
+    }
+}
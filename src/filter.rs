@@ -0,0 +1,1093 @@
+use crate::error::FilterError;
+use crate::field::{FieldDescriptor, FieldType, FieldValue, lookup_field, lookup_protocol};
+use crate::prelude::*;
+
+use regex::bytes::Regex;
+use std::cmp::Ordering;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// A display filter, e.g. `ip.src == 10.0.0.0/8 && tcp.dport in {80 443}`.
+///
+/// Tests name a protocol or one of the fields registered with
+/// `register_fields!`, which are the keys `to_json` emits. A bare name tests
+/// for presence. Fields can be compared with `==`, `!=`, `<`, `<=`, `>`,
+/// `>=` (or `eq`, `ne`, `lt`, `le`, `gt`, `ge`), checked against a set with
+/// `in {80 443 8000..8080}`, searched with `contains` or matched against a
+/// regex with `matches`. A slice like `eth.src_addr[0:3]` or `tcp[13]`
+/// turns a field or protocol into bytes. Tests combine with `&&`, `||` and
+/// `!` (or `and`, `or`, `not`), and hold if any instance of the field in
+/// the packet passes, e.g. the outer or inner `ip.src` of a tunnel.
+#[derive(Debug)]
+pub struct Filter {
+    text: String,
+    root: Node,
+}
+
+impl Filter {
+    /// Parses and type checks `text`.
+    pub fn new(text: &str) -> Result<Self, FilterError> {
+        let tokens = tokenize(text)?;
+        let expr = Parser::new(text, tokens).parse()?;
+        let root = check(&expr)?;
+        Ok(Self {
+            text: text.to_string(),
+            root,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Whether the Pdu tree rooted at `pdu` passes the filter.
+    pub fn matches<'a>(&self, pdu: &(dyn Pdu<'a> + 'a)) -> bool {
+        self.root.eval(pdu)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Filter::new(text)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+fn error(offset: usize, message: impl Into<String>) -> FilterError {
+    FilterError {
+        offset,
+        message: message.into(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn holds(&self, ordering: Ordering) -> bool {
+        match self {
+            CmpOp::Eq => ordering == Ordering::Equal,
+            CmpOp::Ne => ordering != Ordering::Equal,
+            CmpOp::Lt => ordering == Ordering::Less,
+            CmpOp::Le => ordering != Ordering::Greater,
+            CmpOp::Gt => ordering == Ordering::Greater,
+            CmpOp::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Comma,
+    And,
+    Or,
+    Not,
+    Cmp(CmpOp),
+    In,
+    Contains,
+    Matches,
+    /// A name, number, address or byte string.
+    Word(String),
+    /// A quoted string, escapes resolved.
+    Str(Vec<u8>),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            TokenKind::LParen => "(",
+            TokenKind::RParen => ")",
+            TokenKind::LBrace => "{",
+            TokenKind::RBrace => "}",
+            TokenKind::LBracket => "[",
+            TokenKind::RBracket => "]",
+            TokenKind::Comma => ",",
+            TokenKind::And => "&&",
+            TokenKind::Or => "||",
+            TokenKind::Not => "!",
+            TokenKind::Cmp(CmpOp::Eq) => "==",
+            TokenKind::Cmp(CmpOp::Ne) => "!=",
+            TokenKind::Cmp(CmpOp::Lt) => "<",
+            TokenKind::Cmp(CmpOp::Le) => "<=",
+            TokenKind::Cmp(CmpOp::Gt) => ">",
+            TokenKind::Cmp(CmpOp::Ge) => ">=",
+            TokenKind::In => "in",
+            TokenKind::Contains => "contains",
+            TokenKind::Matches => "matches",
+            TokenKind::Word(word) => word,
+            TokenKind::Str(_) => return write!(f, "a string"),
+        };
+        write!(f, "'{}'", symbol)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    offset: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | ':' | '/' | '-')
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(offset, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let next = text[offset + c.len_utf8()..].chars().next();
+        let (kind, len) = match (c, next) {
+            ('(', _) => (TokenKind::LParen, 1),
+            (')', _) => (TokenKind::RParen, 1),
+            ('{', _) => (TokenKind::LBrace, 1),
+            ('}', _) => (TokenKind::RBrace, 1),
+            ('[', _) => (TokenKind::LBracket, 1),
+            (']', _) => (TokenKind::RBracket, 1),
+            (',', _) => (TokenKind::Comma, 1),
+            ('&', Some('&')) => (TokenKind::And, 2),
+            ('|', Some('|')) => (TokenKind::Or, 2),
+            ('=', Some('=')) => (TokenKind::Cmp(CmpOp::Eq), 2),
+            ('!', Some('=')) => (TokenKind::Cmp(CmpOp::Ne), 2),
+            ('<', Some('=')) => (TokenKind::Cmp(CmpOp::Le), 2),
+            ('>', Some('=')) => (TokenKind::Cmp(CmpOp::Ge), 2),
+            ('!', _) => (TokenKind::Not, 1),
+            ('<', _) => (TokenKind::Cmp(CmpOp::Lt), 1),
+            ('>', _) => (TokenKind::Cmp(CmpOp::Gt), 1),
+            ('"', _) => {
+                chars.next();
+                let value = lex_string(text, offset, &mut chars)?;
+                tokens.push(Token {
+                    kind: TokenKind::Str(value),
+                    offset,
+                });
+                continue;
+            }
+            _ if is_word_char(c) => {
+                let mut end = offset;
+                while let Some(&(idx, c)) = chars.peek()
+                    && is_word_char(c)
+                {
+                    end = idx + c.len_utf8();
+                    chars.next();
+                }
+                let word = &text[offset..end];
+                let kind = match word {
+                    "and" => TokenKind::And,
+                    "or" => TokenKind::Or,
+                    "not" => TokenKind::Not,
+                    "eq" => TokenKind::Cmp(CmpOp::Eq),
+                    "ne" => TokenKind::Cmp(CmpOp::Ne),
+                    "lt" => TokenKind::Cmp(CmpOp::Lt),
+                    "le" => TokenKind::Cmp(CmpOp::Le),
+                    "gt" => TokenKind::Cmp(CmpOp::Gt),
+                    "ge" => TokenKind::Cmp(CmpOp::Ge),
+                    "in" => TokenKind::In,
+                    "contains" => TokenKind::Contains,
+                    "matches" => TokenKind::Matches,
+                    _ => TokenKind::Word(word.to_string()),
+                };
+                tokens.push(Token { kind, offset });
+                continue;
+            }
+            _ => return Err(error(offset, format!("unexpected character '{}'", c))),
+        };
+        for _ in 0..len {
+            chars.next();
+        }
+        tokens.push(Token { kind, offset });
+    }
+    Ok(tokens)
+}
+
+/// Reads a string up to its closing quote, resolving `\"`, `\\`, `\n`,
+/// `\r`, `\t` and `\xHH`.
+fn lex_string(
+    text: &str,
+    start: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<Vec<u8>, FilterError> {
+    let mut value = Vec::new();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '"' => return Ok(value),
+            '\\' => {
+                let Some((_, escape)) = chars.next() else {
+                    break;
+                };
+                match escape {
+                    'n' => value.push(b'\n'),
+                    'r' => value.push(b'\r'),
+                    't' => value.push(b'\t'),
+                    '"' | '\\' => value.push(escape as u8),
+                    'x' => {
+                        let hex = text.get(offset + 2..offset + 4);
+                        let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        else {
+                            return Err(error(offset, "expected two hex digits after '\\x'"));
+                        };
+                        value.push(byte);
+                        chars.next();
+                        chars.next();
+                    }
+                    _ => return Err(error(offset, format!("unknown escape '\\{}'", escape))),
+                }
+            }
+            _ => {
+                let mut buf = [0; 4];
+                value.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    Err(error(start, "unterminated string"))
+}
+
+/// A byte range of a field or protocol. Negative starts count from the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slice {
+    start: i64,
+    /// Exclusive, relative to the same end as `start`. `None` runs to the
+    /// end of the value.
+    end: Option<i64>,
+}
+
+impl Slice {
+    /// Parses `i:len`, `i-j`, `i`, `i:` and `:len`. Ends that don't fit an
+    /// `i64` are rejected.
+    fn parse(spec: &str) -> Option<Self> {
+        let number = |text: &str| text.parse::<i64>().ok();
+        if let Some((start, len)) = spec.split_once(':') {
+            let start = if start.is_empty() { 0 } else { number(start)? };
+            let end = match len {
+                "" => None,
+                len => Some(start.checked_add(number(len).filter(|len| *len > 0)?)?),
+            };
+            return Some(Self { start, end });
+        }
+        // A leading '-' is a negative start, not a range.
+        let split = spec
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| *c == '-')
+            .map(|(idx, _)| idx);
+        if let Some(idx) = split {
+            let (start, end) = (number(&spec[..idx])?, number(&spec[idx + 1..])?);
+            if end < start || (start < 0) != (end < 0) {
+                return None;
+            }
+            return Some(Self {
+                start,
+                end: Some(end.checked_add(1)?),
+            });
+        }
+        let start = number(spec)?;
+        Some(Self {
+            start,
+            end: Some(start.checked_add(1)?),
+        })
+    }
+
+    fn apply(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        let len = bytes.len() as i64;
+        let resolve = |idx: i64| if idx < 0 { len + idx } else { idx };
+        let start = resolve(self.start);
+        let end = self.end.map_or(len, |end| {
+            if self.start < 0 && end == 0 {
+                len
+            } else {
+                resolve(end)
+            }
+        });
+        if start < 0 || end > len || start >= end {
+            return None;
+        }
+        Some(bytes[start as usize..end as usize].to_vec())
+    }
+}
+
+/// A literal as written. Its meaning depends on the field it's tested
+/// against.
+#[derive(Debug, Clone)]
+enum Literal {
+    Word(String),
+    Str(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    literal: Literal,
+    offset: usize,
+}
+
+#[derive(Debug, Clone)]
+enum TestOp {
+    Cmp(CmpOp, Operand),
+    In(Vec<Operand>),
+    Contains(Operand),
+    Matches(Operand),
+}
+
+#[derive(Debug, Clone)]
+struct Test {
+    name: String,
+    offset: usize,
+    slice: Option<Slice>,
+    op: Option<(TestOp, usize)>,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Test(Test),
+}
+
+/// Recursive descent over `or`, `and`, `not` and tests, loosest first.
+struct Parser<'t> {
+    text: &'t str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'t> Parser<'t> {
+    fn new(text: &'t str, tokens: Vec<Token>) -> Self {
+        Self {
+            text,
+            tokens,
+            pos: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr, FilterError> {
+        if self.tokens.is_empty() {
+            return Err(error(0, "empty filter"));
+        }
+        let expr = self.parse_or()?;
+        match self.peek() {
+            None => Ok(expr),
+            Some(token) => Err(error(
+                token.offset,
+                format!("unexpected {} after a complete test", token.kind),
+            )),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek().is_some_and(|token| token.kind == *kind) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Offset for errors at the current position.
+    fn offset(&self) -> usize {
+        self.peek().map_or(self.text.len(), |token| token.offset)
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<(), FilterError> {
+        if self.eat(&kind) {
+            return Ok(());
+        }
+        let found = match self.peek() {
+            Some(token) => format!("{}", token.kind),
+            None => "the end".to_string(),
+        };
+        Err(error(
+            self.offset(),
+            format!("expected {}, found {}", what, found),
+        ))
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_and()?;
+        while self.eat(&TokenKind::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_not()?;
+        while self.eat(&TokenKind::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, FilterError> {
+        if self.eat(&TokenKind::Not) {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        if self.eat(&TokenKind::LParen) {
+            let expr = self.parse_or()?;
+            self.expect(TokenKind::RParen, "')'")?;
+            return Ok(expr);
+        }
+        self.parse_test()
+    }
+
+    fn parse_test(&mut self) -> Result<Expr, FilterError> {
+        let offset = self.offset();
+        let name = match self.next().map(|token| token.kind) {
+            Some(TokenKind::Word(name)) => name,
+            Some(kind) => {
+                return Err(error(
+                    offset,
+                    format!("expected a field or protocol, found {}", kind),
+                ));
+            }
+            None => return Err(error(offset, "expected a field or protocol, found the end")),
+        };
+
+        let slice = if self.eat(&TokenKind::LBracket) {
+            let spec_offset = self.offset();
+            let spec = match self.next().map(|token| token.kind) {
+                Some(TokenKind::Word(spec)) => spec,
+                _ => return Err(error(spec_offset, "expected a slice like [0:2]")),
+            };
+            let Some(slice) = Slice::parse(&spec) else {
+                return Err(error(spec_offset, format!("invalid slice '{}'", spec)));
+            };
+            self.expect(TokenKind::RBracket, "']'")?;
+            Some(slice)
+        } else {
+            None
+        };
+
+        let op_offset = self.offset();
+        let op = match self.peek().map(|token| token.kind.clone()) {
+            Some(TokenKind::Cmp(op)) => {
+                self.pos += 1;
+                Some(TestOp::Cmp(op, self.parse_operand()?))
+            }
+            Some(TokenKind::Contains) => {
+                self.pos += 1;
+                Some(TestOp::Contains(self.parse_operand()?))
+            }
+            Some(TokenKind::Matches) => {
+                self.pos += 1;
+                Some(TestOp::Matches(self.parse_operand()?))
+            }
+            Some(TokenKind::In) => {
+                self.pos += 1;
+                Some(TestOp::In(self.parse_set()?))
+            }
+            _ => None,
+        };
+
+        Ok(Expr::Test(Test {
+            name,
+            offset,
+            slice,
+            op: op.map(|op| (op, op_offset)),
+        }))
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, FilterError> {
+        let offset = self.offset();
+        let literal = match self.next().map(|token| token.kind) {
+            Some(TokenKind::Word(word)) => Literal::Word(word),
+            Some(TokenKind::Str(value)) => Literal::Str(value),
+            Some(kind) => return Err(error(offset, format!("expected a value, found {}", kind))),
+            None => return Err(error(offset, "expected a value, found the end")),
+        };
+        Ok(Operand { literal, offset })
+    }
+
+    /// `{a b c}` or `{a, b, c}`.
+    fn parse_set(&mut self) -> Result<Vec<Operand>, FilterError> {
+        self.expect(TokenKind::LBrace, "'{'")?;
+        let mut items = Vec::new();
+        while !self.eat(&TokenKind::RBrace) {
+            items.push(self.parse_operand()?);
+            self.eat(&TokenKind::Comma);
+        }
+        if items.is_empty() {
+            return Err(error(self.offset(), "empty set"));
+        }
+        Ok(items)
+    }
+}
+
+/// What a test reads from each layer.
+#[derive(Debug)]
+enum Target {
+    Fields(Vec<FieldDescriptor>),
+    /// A protocol's bytes, header and payload.
+    Protocol(Vec<TypeId>),
+}
+
+/// A literal checked against the type it's compared with.
+#[derive(Debug, Clone)]
+enum Value {
+    Exact(FieldValue),
+    Ipv4Net(Ipv4Addr, u8),
+    Ipv6Net(Ipv6Addr, u8),
+    Range(u64, u64),
+}
+
+impl Value {
+    fn cmp(&self, op: CmpOp, value: &FieldValue) -> bool {
+        match (self, value) {
+            (Value::Exact(expected), value) => value
+                .partial_cmp(expected)
+                .is_some_and(|ordering| op.holds(ordering)),
+            (Value::Ipv4Net(net, prefix), FieldValue::Ipv4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                let inside = u32::from(*addr) & mask == u32::from(*net) & mask;
+                inside == (op == CmpOp::Eq)
+            }
+            (Value::Ipv6Net(net, prefix), FieldValue::Ipv6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                let inside = u128::from(*addr) & mask == u128::from(*net) & mask;
+                inside == (op == CmpOp::Eq)
+            }
+            (Value::Range(low, high), value) => value
+                .as_u64()
+                .is_some_and(|value| (*low..=*high).contains(&value)),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Op {
+    Present,
+    Cmp(CmpOp, Value),
+    In(Vec<Value>),
+    Contains(Vec<u8>),
+    Matches(Regex),
+}
+
+impl Op {
+    fn test(&self, value: &FieldValue) -> bool {
+        match self {
+            Op::Present => true,
+            Op::Cmp(op, expected) => expected.cmp(*op, value),
+            Op::In(set) => set.iter().any(|expected| expected.cmp(CmpOp::Eq, value)),
+            Op::Contains(needle) => {
+                let haystack = value.to_bytes();
+                needle.is_empty()
+                    || haystack
+                        .windows(needle.len())
+                        .any(|window| window == needle.as_slice())
+            }
+            Op::Matches(regex) => regex.is_match(&value.to_bytes()),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Test {
+        target: Target,
+        slice: Option<Slice>,
+        op: Op,
+    },
+}
+
+impl Node {
+    fn eval<'a>(&self, pdu: &(dyn Pdu<'a> + 'a)) -> bool {
+        match self {
+            Node::And(left, right) => left.eval(pdu) && right.eval(pdu),
+            Node::Or(left, right) => left.eval(pdu) || right.eval(pdu),
+            Node::Not(inner) => !inner.eval(pdu),
+            Node::Test { target, slice, op } => {
+                let mut layer = Some(pdu);
+                while let Some(pdu) = layer {
+                    if pdu
+                        .parts()
+                        .into_iter()
+                        .any(|part| test_layer(part, target, slice, op))
+                    {
+                        return true;
+                    }
+                    layer = pdu.child_pdu().as_deref();
+                }
+                false
+            }
+        }
+    }
+}
+
+fn test_layer<'a>(
+    pdu: &(dyn Pdu<'a> + 'a),
+    target: &Target,
+    slice: &Option<Slice>,
+    op: &Op,
+) -> bool {
+    let id = pdu.self_id();
+    let value = match target {
+        Target::Fields(fields) => fields
+            .iter()
+            .filter(|field| field.protocol == id)
+            .find_map(|field| (field.get)(pdu)),
+        Target::Protocol(types) if types.contains(&id) => match (op, slice) {
+            (Op::Present, None) => return true,
+            _ => Some(FieldValue::Bytes(pdu.chain_to_bytes())),
+        },
+        Target::Protocol(_) => None,
+    };
+    let value = match (value, slice) {
+        (Some(value), Some(slice)) => slice.apply(&value.to_bytes()).map(FieldValue::Bytes),
+        (value, _) => value,
+    };
+    value.is_some_and(|value| op.test(&value))
+}
+
+fn check(expr: &Expr) -> Result<Node, FilterError> {
+    Ok(match expr {
+        Expr::And(left, right) => Node::And(Box::new(check(left)?), Box::new(check(right)?)),
+        Expr::Or(left, right) => Node::Or(Box::new(check(left)?), Box::new(check(right)?)),
+        Expr::Not(inner) => Node::Not(Box::new(check(inner)?)),
+        Expr::Test(test) => check_test(test)?,
+    })
+}
+
+fn check_test(test: &Test) -> Result<Node, FilterError> {
+    let (target, kind) = if let Some(fields) = lookup_field(&test.name) {
+        let kind = fields[0].kind;
+        (Target::Fields(fields), kind)
+    } else if let Some(types) = lookup_protocol(&test.name) {
+        (Target::Protocol(types), FieldType::Bytes)
+    } else {
+        return Err(error(
+            test.offset,
+            format!("'{}' is neither a field nor a protocol", test.name),
+        ));
+    };
+    if test.slice.is_some() && kind == FieldType::Bool {
+        return Err(error(
+            test.offset,
+            format!("{} is a bool and can't be sliced", test.name),
+        ));
+    }
+    // Slices are bytes whatever the field holds.
+    let kind = match test.slice {
+        Some(_) => FieldType::Bytes,
+        None => kind,
+    };
+
+    let name = &test.name;
+    let op = match &test.op {
+        None => Op::Present,
+        Some((TestOp::Cmp(op, operand), op_offset)) => {
+            let value = parse_value(name, kind, operand)?;
+            let ordered = *op != CmpOp::Eq && *op != CmpOp::Ne;
+            if ordered && kind == FieldType::Bool {
+                return Err(error(
+                    *op_offset,
+                    format!("{} is a bool and can't be ordered", name),
+                ));
+            }
+            if ordered && !matches!(value, Value::Exact(_)) {
+                return Err(error(
+                    operand.offset,
+                    "a network can only be tested with ==, != or in",
+                ));
+            }
+            Op::Cmp(*op, value)
+        }
+        Some((TestOp::In(set), _)) => Op::In(
+            set.iter()
+                .map(|operand| parse_set_value(name, kind, operand))
+                .collect::<Result<_, _>>()?,
+        ),
+        Some((TestOp::Contains(operand), op_offset)) => {
+            if kind != FieldType::Bytes && kind != FieldType::Str {
+                return Err(error(
+                    *op_offset,
+                    format!(
+                        "'contains' needs bytes or a string, but {} is a {}",
+                        name, kind
+                    ),
+                ));
+            }
+            Op::Contains(parse_bytes_literal(operand)?)
+        }
+        Some((TestOp::Matches(operand), op_offset)) => {
+            if kind != FieldType::Bytes && kind != FieldType::Str {
+                return Err(error(
+                    *op_offset,
+                    format!(
+                        "'matches' needs bytes or a string, but {} is a {}",
+                        name, kind
+                    ),
+                ));
+            }
+            let Literal::Str(pattern) = &operand.literal else {
+                return Err(error(operand.offset, "'matches' needs a quoted regex"));
+            };
+            let pattern = String::from_utf8_lossy(pattern);
+            let regex = Regex::new(&pattern)
+                .map_err(|err| error(operand.offset, format!("invalid regex: {}", err)))?;
+            Op::Matches(regex)
+        }
+    };
+
+    Ok(Node::Test {
+        target,
+        slice: test.slice,
+        op,
+    })
+}
+
+fn parse_set_value(name: &str, kind: FieldType, operand: &Operand) -> Result<Value, FilterError> {
+    if let Literal::Word(word) = &operand.literal
+        && kind.max_value().is_some()
+        && let Some((low, high)) = word.split_once("..")
+    {
+        let bound = |text: &str| parse_uint(name, kind, text, operand.offset);
+        let (low, high) = (bound(low)?, bound(high)?);
+        if low > high {
+            return Err(error(operand.offset, format!("empty range '{}'", word)));
+        }
+        return Ok(Value::Range(low, high));
+    }
+    parse_value(name, kind, operand)
+}
+
+fn parse_uint(name: &str, kind: FieldType, text: &str, offset: usize) -> Result<u64, FilterError> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse::<u64>(),
+    };
+    let Ok(value) = parsed else {
+        return Err(error(
+            offset,
+            format!("'{}' isn't a valid {} for {}", text, kind, name),
+        ));
+    };
+    if kind.max_value().is_some_and(|max| value > max) {
+        return Err(error(
+            offset,
+            format!("{} is too large for {}, a {}", value, name, kind),
+        ));
+    }
+    Ok(value)
+}
+
+/// Hex bytes separated by ':', '-' or '.', e.g. `00:1b:21`, or a string.
+fn parse_bytes_literal(operand: &Operand) -> Result<Vec<u8>, FilterError> {
+    match &operand.literal {
+        Literal::Str(value) => Ok(value.clone()),
+        Literal::Word(word) => word
+            .split([':', '-', '.'])
+            .map(|byte| match byte.len() {
+                1 | 2 => u8::from_str_radix(byte, 16).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| {
+                error(
+                    operand.offset,
+                    format!("'{}' isn't a byte string like 00:1b:21", word),
+                )
+            }),
+    }
+}
+
+fn parse_value(name: &str, kind: FieldType, operand: &Operand) -> Result<Value, FilterError> {
+    let offset = operand.offset;
+    let word = match (&operand.literal, kind) {
+        (Literal::Str(value), FieldType::Str) => {
+            return Ok(Value::Exact(FieldValue::Str(
+                String::from_utf8_lossy(value).into_owned(),
+            )));
+        }
+        (Literal::Str(value), FieldType::Bytes) => {
+            return Ok(Value::Exact(FieldValue::Bytes(value.clone())));
+        }
+        (Literal::Str(_), _) => {
+            return Err(error(
+                offset,
+                format!("{} is a {}, not a string", name, kind),
+            ));
+        }
+        (Literal::Word(_), FieldType::Str) => {
+            return Err(error(
+                offset,
+                format!("{} is a string, so the value must be quoted", name),
+            ));
+        }
+        (Literal::Word(word), _) => word.as_str(),
+    };
+    let invalid = || {
+        error(
+            offset,
+            format!("'{}' isn't a valid {} for {}", word, kind, name),
+        )
+    };
+
+    let value = match kind {
+        FieldType::Bool => match word {
+            "true" | "1" => FieldValue::Bool(true),
+            "false" | "0" => FieldValue::Bool(false),
+            _ => return Err(invalid()),
+        },
+        FieldType::U8 => FieldValue::U8(parse_uint(name, kind, word, offset)? as u8),
+        FieldType::U16 => FieldValue::U16(parse_uint(name, kind, word, offset)? as u16),
+        FieldType::U32 => FieldValue::U32(parse_uint(name, kind, word, offset)? as u32),
+        FieldType::U64 => FieldValue::U64(parse_uint(name, kind, word, offset)?),
+        FieldType::Ipv4 => {
+            if let Some((addr, prefix)) = word.split_once('/') {
+                let addr = addr.parse().map_err(|_| invalid())?;
+                let prefix = prefix
+                    .parse()
+                    .ok()
+                    .filter(|p| *p <= 32)
+                    .ok_or_else(invalid)?;
+                return Ok(Value::Ipv4Net(addr, prefix));
+            }
+            FieldValue::Ipv4(word.parse().map_err(|_| invalid())?)
+        }
+        FieldType::Ipv6 => {
+            if let Some((addr, prefix)) = word.split_once('/') {
+                let addr = addr.parse().map_err(|_| invalid())?;
+                let prefix = prefix
+                    .parse()
+                    .ok()
+                    .filter(|p| *p <= 128)
+                    .ok_or_else(invalid)?;
+                return Ok(Value::Ipv6Net(addr, prefix));
+            }
+            FieldValue::Ipv6(word.parse().map_err(|_| invalid())?)
+        }
+        FieldType::Mac => {
+            let bytes = parse_bytes_literal(operand)?;
+            FieldValue::Mac(bytes.try_into().map_err(|_| invalid())?)
+        }
+        FieldType::Bytes => FieldValue::Bytes(parse_bytes_literal(operand)?),
+        FieldType::Str => unreachable!(),
+    };
+    Ok(Value::Exact(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethernet::Ethernet;
+    use crate::ip6::Ipv6;
+
+    /// Ethernet, IPv4 10.1.2.3 -> 192.0.2.80, TCP 40000 -> 443 with an HTTP
+    /// request.
+    fn packet() -> Vec<u8> {
+        let payload = b"GET /index.html HTTP/1.1\r\n";
+        let mut bytes = vec![
+            0x00, 0x1b, 0x21, 0x0a, 0x0b, 0x0c, // dst
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, // src
+            0x08, 0x00, // IPv4
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 1, 2, 3,
+            192, 0, 2, 80, // IPv4
+            0x9c, 0x40, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x50, 0x18,
+            0xff, 0xff, 0x00, 0x00, 0x00, 0x00, // TCP, PSH ACK
+        ];
+        bytes.extend_from_slice(payload);
+        let total_len = (bytes.len() - 14) as u16;
+        bytes[16..18].copy_from_slice(&total_len.to_be_bytes());
+        bytes
+    }
+
+    fn eval(text: &str) -> bool {
+        let bytes = packet();
+        let pdu = Ethernet::from_bytes(&bytes).unwrap();
+        Filter::new(text).unwrap().matches(&*pdu)
+    }
+
+    fn error_of(text: &str) -> FilterError {
+        Filter::new(text).unwrap_err()
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert!(eval("ip.src == 10.1.2.3"));
+        assert!(eval(
+            "ip.src == 10.0.0.0/8 && tcp.dport in {80 443} && !udp"
+        ));
+        assert!(!eval("ip.src != 10.0.0.0/8"));
+        assert!(eval("ip.ttl ge 64 and ip.ttl < 0x41"));
+        assert!(eval("tcp.flags == 0x18"));
+        assert!(eval("eth.src_addr == 00:11:22:33:44:55"));
+        assert!(eval("eth.dst_addr != 00-11-22-33-44-55"));
+        assert!(!eval("tcp.sport in {1..1024, 8080}"));
+        assert!(eval("tcp.sport in {1..1024, 32768..60999}"));
+        assert!(!eval("ipv6.dst == 2001:db8::/32"));
+    }
+
+    #[test]
+    fn test_presence_and_logic() {
+        assert!(eval("eth && ip && tcp"));
+        assert!(!eval("udp"));
+        assert!(eval("udp || tcp"));
+        assert!(eval("not udp and (arp or tcp.dport == 443)"));
+        assert!(!eval("!(tcp && ip)"));
+        assert!(eval("tcp.dport"));
+        assert!(!eval("udp.dport"));
+        // && binds tighter than ||.
+        assert!(eval("tcp || udp && arp"));
+        assert!(!eval("(tcp || udp) && arp"));
+    }
+
+    #[test]
+    fn test_contains_matches_slices() {
+        assert!(eval("tcp contains \"GET\""));
+        assert!(eval("raw contains 48:54:54:50"));
+        assert!(!eval("tcp contains \"POST\""));
+        assert!(eval("raw matches \"^(GET|HEAD) /[a-z.]+ HTTP/1\\\\.[01]\""));
+        assert!(!eval("raw matches \"^POST\""));
+        assert!(eval("eth.src_addr[0:3] == 00:11:22"));
+        assert!(eval("eth.dst_addr[-3:] == 0a:0b:0c"));
+        assert!(eval("ip.src[0] == 0a"));
+        assert!(eval("ip.dst[1-2] == 00.02"));
+        assert!(eval("tcp[13] == 18"));
+        assert!(eval("tcp.dport[:1] == 01"));
+        assert!(!eval("ip.src[4] == 00"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = error_of("ip.src == 10.0.0.1 && tcp.dprt == 80");
+        assert!(err.offset == 22);
+        assert!(err.message == "'tcp.dprt' is neither a field nor a protocol");
+
+        let err = error_of("ip.ttl == 300");
+        assert!(err.offset == 10);
+        assert!(err.to_string() == "300 is too large for ip.ttl, a u8 at offset 10");
+
+        assert!(error_of("(tcp").message == "expected ')', found the end");
+        assert!(error_of("tcp udp").message == "unexpected 'udp' after a complete test");
+        assert!(error_of("ip.src == 10.0.0.0/8x").offset == 10);
+        assert!(error_of("ip.src > 10.0.0.0/8").message.contains("network"));
+        assert!(error_of("dns.qr < 1").message.contains("can't be ordered"));
+        assert!(
+            error_of("ip.ttl contains 40")
+                .message
+                .contains("'contains'")
+        );
+        assert!(
+            error_of("raw matches \"(\"")
+                .message
+                .starts_with("invalid regex")
+        );
+        assert!(error_of("raw matches GET").message.contains("quoted"));
+        assert!(error_of("tcp[1-0] == 00").message == "invalid slice '1-0'");
+        for spec in [
+            "9223372036854775807:1",
+            "9223372036854775807",
+            "0-9223372036854775807",
+        ] {
+            let err = error_of(&format!("ip[{}]", spec));
+            assert!(err.offset == 3);
+            assert!(err.message == format!("invalid slice '{}'", spec));
+        }
+        assert!(error_of("tcp contains \"abc").message == "unterminated string");
+        assert!(error_of("ip.src == 1 $").message == "unexpected character '$'");
+        assert!(error_of("tcp.sport in {}").message == "empty set");
+        assert!(error_of("").message == "empty filter");
+    }
+
+    #[test]
+    fn test_tunnel_layers() {
+        // Any instance of a field can match, so both source addresses of an
+        // IPv4 in GRE packet do.
+        let mut bytes = packet();
+        let inner = bytes[14..].to_vec();
+        let mut outer = bytes[14..34].to_vec();
+        outer[9] = 47;
+        outer[12..16].copy_from_slice(&[172, 16, 0, 1]);
+        outer.extend_from_slice(&[0x00, 0x00, 0x08, 0x00]);
+        let total_len = (outer.len() + inner.len()) as u16;
+        outer[2..4].copy_from_slice(&total_len.to_be_bytes());
+        bytes.truncate(14);
+        bytes.extend_from_slice(&outer);
+        bytes.extend_from_slice(&inner);
+
+        let pdu = Ethernet::from_bytes(&bytes).unwrap();
+        for text in [
+            "ip.src == 172.16.0.1",
+            "ip.src == 10.1.2.3",
+            "tcp.dport == 443",
+        ] {
+            assert!(Filter::new(text).unwrap().matches(&*pdu));
+        }
+        assert!(!Filter::new("ip.src == 10.9.9.9").unwrap().matches(&*pdu));
+    }
+
+    #[test]
+    fn test_message_and_option_fields() {
+        // IPv6 carrying an ICMPv6 Packet Too Big with an MTU of 1280.
+        let mut bytes = vec![0x60, 0, 0, 0, 0x00, 0x08, 58, 64];
+        bytes.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        bytes.extend_from_slice(&[2, 0, 0, 0, 0x00, 0x00, 0x05, 0x00]);
+        let pdu = Ipv6::from_bytes(&bytes).unwrap();
+        assert!(
+            Filter::new("icmpv6.packet_too_big.mtu == 1280")
+                .unwrap()
+                .matches(&*pdu)
+        );
+        assert!(
+            !Filter::new("icmpv6.packet_too_big.mtu > 1280")
+                .unwrap()
+                .matches(&*pdu)
+        );
+
+        // The IPv4 header of `packet` with a stream ID option.
+        let mut bytes = packet();
+        bytes[14] = 0x46;
+        bytes.splice(34..34, [0x88, 0x04, 0x12, 0x34]);
+        let total_len = (bytes.len() - 14) as u16;
+        bytes[16..18].copy_from_slice(&total_len.to_be_bytes());
+        let pdu = Ethernet::from_bytes(&bytes).unwrap();
+        assert!(
+            Filter::new("ip.opt.number == 8 && ip.opt.len == 4")
+                .unwrap()
+                .matches(&*pdu)
+        );
+        assert!(
+            !Filter::new("ip.opt.copied == false")
+                .unwrap()
+                .matches(&*pdu)
+        );
+        assert!(pdu.get_field("ip.opt.type") == Some(FieldValue::U8(0x88)));
+    }
+}
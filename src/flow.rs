@@ -0,0 +1,594 @@
+use crate::ip::Ip;
+use crate::ip6::Ipv6;
+use crate::prelude::*;
+use crate::tcp::{TCP_ACK, TCP_FIN, TCP_RST, TCP_SYN, Tcp};
+use crate::udp::Udp;
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Cisco's default inactive timeout.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Cisco's default active timeout.
+pub const DEFAULT_ACTIVE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+const IPV6_HEADER_LEN: usize = 40;
+
+/// Addresses, protocol and ports of a packet. Ports are 0 unless the layer
+/// above IP is `Tcp` or `Udp`.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FlowKey {
+    pub protocol: u8,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+}
+
+impl FlowKey {
+    /// The key of a packet, taken from its outermost `Ip` or `Ipv6` layer.
+    pub fn of<'a>(pdu: &(dyn Pdu<'a> + 'a)) -> Option<Self> {
+        locate(pdu).map(|located| located.key)
+    }
+
+    pub fn reversed(&self) -> Self {
+        Self {
+            protocol: self.protocol,
+            src: self.dst,
+            dst: self.src,
+        }
+    }
+
+    /// The same key for both directions of a flow.
+    pub fn canonical(&self) -> Self {
+        if self.src <= self.dst {
+            *self
+        } else {
+            self.reversed()
+        }
+    }
+}
+
+/// Which way a packet goes, relative to the host that opened the flow.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Direction {
+    Forward = 0,
+    Reverse = 1,
+}
+
+impl Direction {
+    pub fn reversed(&self) -> Self {
+        match self {
+            Direction::Forward => Direction::Reverse,
+            Direction::Reverse => Direction::Forward,
+        }
+    }
+}
+
+/// The network layer of a packet and the layer above it.
+pub(crate) struct Located<'p, 'a> {
+    pub key: FlowKey,
+    /// Length of the IP datagram.
+    pub len: usize,
+    pub transport: Option<&'p (dyn Pdu<'a> + 'a)>,
+}
+
+pub(crate) fn locate<'p, 'a>(pdu: &'p (dyn Pdu<'a> + 'a)) -> Option<Located<'p, 'a>> {
+    let mut layer = Some(pdu);
+    while let Some(pdu) = layer {
+        let network = if let Some(ip) = pdu.downcast_ref::<Ip>() {
+            Some((
+                IpAddr::V4(ip.src_addr()),
+                IpAddr::V4(ip.dst_addr()),
+                ip.protocol(),
+                ip.total_len() as usize,
+            ))
+        } else {
+            pdu.downcast_ref::<Ipv6>().map(|ipv6| {
+                (
+                    IpAddr::V6(ipv6.src_addr()),
+                    IpAddr::V6(ipv6.dst_addr()),
+                    ipv6.upper_layer_protocol(),
+                    IPV6_HEADER_LEN + ipv6.payload_len() as usize,
+                )
+            })
+        };
+        if let Some((src, dst, protocol, len)) = network {
+            let transport = pdu.child_pdu().as_deref();
+            let (sport, dport) = match transport {
+                Some(layer) => {
+                    if let Some(tcp) = layer.downcast_ref::<Tcp>() {
+                        (tcp.src_port(), tcp.dst_port())
+                    } else if let Some(udp) = layer.downcast_ref::<Udp>() {
+                        (udp.src_port(), udp.dst_port())
+                    } else {
+                        (0, 0)
+                    }
+                }
+                None => (0, 0),
+            };
+            return Some(Located {
+                key: FlowKey {
+                    protocol,
+                    src: SocketAddr::new(src, sport),
+                    dst: SocketAddr::new(dst, dport),
+                },
+                len,
+                transport,
+            });
+        }
+        layer = pdu.child_pdu().as_deref();
+    }
+    None
+}
+
+/// Where a TCP connection stands, judged from the flags seen so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    /// The opener sent a SYN.
+    SynSent,
+    /// The other side answered with a SYN ACK.
+    SynReceived,
+    /// The handshake completed, or the connection was picked up midstream.
+    Established,
+    /// One side sent a FIN.
+    Closing,
+    /// Both sides sent a FIN. The last ACK isn't waited for.
+    Closed,
+    /// Either side sent a RST.
+    Reset,
+}
+
+impl TcpState {
+    /// The state after the first segment seen of a connection.
+    pub fn initial(flags: u8) -> Self {
+        if flags & TCP_RST != 0 {
+            TcpState::Reset
+        } else if flags & TCP_SYN != 0 {
+            if flags & TCP_ACK != 0 {
+                TcpState::SynReceived
+            } else {
+                TcpState::SynSent
+            }
+        } else {
+            TcpState::Established
+        }
+    }
+
+    /// The state after a segment with `flags` goes in `dir`. `seen` holds
+    /// every flag seen in each direction, these included.
+    pub fn advance(self, dir: Direction, flags: u8, seen: [u8; 2]) -> Self {
+        let fins = seen.iter().filter(|flags| *flags & TCP_FIN != 0).count();
+        match self {
+            TcpState::Reset | TcpState::Closed => self,
+            _ if flags & TCP_RST != 0 => TcpState::Reset,
+            _ if fins == 2 => TcpState::Closed,
+            _ if fins == 1 => TcpState::Closing,
+            TcpState::SynSent
+                if dir == Direction::Reverse
+                    && flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK =>
+            {
+                TcpState::SynReceived
+            }
+            TcpState::SynReceived
+                if dir == Direction::Forward && flags & (TCP_SYN | TCP_ACK) == TCP_ACK =>
+            {
+                TcpState::Established
+            }
+            state => state,
+        }
+    }
+}
+
+/// The opener of a TCP connection is the side that sent the SYN, even when
+/// the SYN ACK is the first segment seen.
+pub(crate) fn opener_key(key: FlowKey, flags: u8) -> FlowKey {
+    if key.protocol == 6 && flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK {
+        key.reversed()
+    } else {
+        key
+    }
+}
+
+/// Statistics of the packets going both ways between two endpoints.
+#[derive(Debug, Clone)]
+pub struct Flow {
+    key: FlowKey,
+    first_seen: Duration,
+    last_seen: Duration,
+    packets: [u64; 2],
+    bytes: [u64; 2],
+    tcp_flags: [u8; 2],
+    tcp_state: Option<TcpState>,
+}
+
+impl Flow {
+    fn new(ts: Duration, key: FlowKey) -> Self {
+        Self {
+            key,
+            first_seen: ts,
+            last_seen: ts,
+            packets: [0; 2],
+            bytes: [0; 2],
+            tcp_flags: [0; 2],
+            tcp_state: None,
+        }
+    }
+
+    /// Starts counting again at `ts`, for a flow cut by the active timeout.
+    fn restart(&mut self, ts: Duration) {
+        *self = Self {
+            tcp_state: self.tcp_state,
+            ..Self::new(ts, self.key)
+        };
+    }
+
+    /// The key as sent by the host that opened the flow.
+    pub fn key(&self) -> FlowKey {
+        self.key
+    }
+
+    pub fn direction_of(&self, key: &FlowKey) -> Direction {
+        if *key == self.key {
+            Direction::Forward
+        } else {
+            Direction::Reverse
+        }
+    }
+
+    pub fn first_seen(&self) -> Duration {
+        self.first_seen
+    }
+
+    pub fn last_seen(&self) -> Duration {
+        self.last_seen
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.last_seen - self.first_seen
+    }
+
+    pub fn packets(&self, dir: Direction) -> u64 {
+        self.packets[dir as usize]
+    }
+
+    /// IP bytes, headers included.
+    pub fn bytes(&self, dir: Direction) -> u64 {
+        self.bytes[dir as usize]
+    }
+
+    /// Every TCP flag seen going in `dir`.
+    pub fn tcp_flags(&self, dir: Direction) -> u8 {
+        self.tcp_flags[dir as usize]
+    }
+
+    /// `None` unless the flow is TCP.
+    pub fn tcp_state(&self) -> Option<TcpState> {
+        self.tcp_state
+    }
+
+    fn update(&mut self, ts: Duration, dir: Direction, len: usize, tcp_flags: Option<u8>) {
+        let idx = dir as usize;
+        self.last_seen = self.last_seen.max(ts);
+        self.packets[idx] += 1;
+        self.bytes[idx] += len as u64;
+        if let Some(flags) = tcp_flags {
+            self.tcp_flags[idx] |= flags;
+            self.tcp_state = Some(match self.tcp_state {
+                Some(state) => state.advance(dir, flags, self.tcp_flags),
+                None => TcpState::initial(flags),
+            });
+        }
+    }
+}
+
+/// Why a flow left the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryReason {
+    /// No packet arrived within the idle timeout.
+    Idle,
+    /// The flow lasted longer than the active timeout. It stays in the table
+    /// and counts again from zero.
+    Active,
+    /// `FlowTable::flush` was called.
+    Flush,
+}
+
+pub type ExpiryCallback = Box<dyn FnMut(&Flow, ExpiryReason)>;
+
+/// Groups packets into bidirectional flows, the way NetFlow exporters do.
+/// Timestamps are capture times, e.g. `Record::timestamp`.
+pub struct FlowTable {
+    idle_timeout: Duration,
+    active_timeout: Duration,
+    flows: HashMap<FlowKey, Flow>,
+    on_expire: Option<ExpiryCallback>,
+}
+
+fn notify(on_expire: &mut Option<ExpiryCallback>, flow: &Flow, reason: ExpiryReason) {
+    if let Some(on_expire) = on_expire {
+        on_expire(flow, reason);
+    }
+}
+
+impl FlowTable {
+    pub fn new() -> Self {
+        Self {
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            active_timeout: DEFAULT_ACTIVE_TIMEOUT,
+            flows: HashMap::new(),
+            on_expire: None,
+        }
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    pub fn with_idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.set_idle_timeout(idle_timeout);
+        self
+    }
+
+    pub fn active_timeout(&self) -> Duration {
+        self.active_timeout
+    }
+
+    pub fn set_active_timeout(&mut self, active_timeout: Duration) {
+        self.active_timeout = active_timeout;
+    }
+
+    pub fn with_active_timeout(&mut self, active_timeout: Duration) -> &mut Self {
+        self.set_active_timeout(active_timeout);
+        self
+    }
+
+    /// Called with every flow that expires, before it is dropped or
+    /// restarted.
+    pub fn set_on_expire(&mut self, on_expire: impl FnMut(&Flow, ExpiryReason) + 'static) {
+        self.on_expire = Some(Box::new(on_expire));
+    }
+
+    pub fn with_on_expire(
+        &mut self,
+        on_expire: impl FnMut(&Flow, ExpiryReason) + 'static,
+    ) -> &mut Self {
+        self.set_on_expire(on_expire);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    /// The flow `key` belongs to, in either direction.
+    pub fn get(&self, key: &FlowKey) -> Option<&Flow> {
+        self.flows.get(&key.canonical())
+    }
+
+    pub fn flows(&self) -> impl Iterator<Item = &Flow> {
+        self.flows.values()
+    }
+
+    /// Counts a packet towards its flow and returns the flow and the
+    /// packet's direction. Packets without an IP layer are ignored. Only
+    /// this packet's flow is checked for timeouts, the rest wait for
+    /// `expire`.
+    pub fn process<'a>(
+        &mut self,
+        ts: Duration,
+        pdu: &(dyn Pdu<'a> + 'a),
+    ) -> Option<(&Flow, Direction)> {
+        let located = locate(pdu)?;
+        let tcp_flags = located
+            .transport
+            .and_then(|layer| layer.downcast_ref::<Tcp>())
+            .map(|tcp| tcp.flags());
+        let canonical = located.key.canonical();
+
+        if let Some(flow) = self.flows.get_mut(&canonical) {
+            if ts.saturating_sub(flow.last_seen) >= self.idle_timeout {
+                notify(&mut self.on_expire, flow, ExpiryReason::Idle);
+                self.flows.remove(&canonical);
+            } else if ts.saturating_sub(flow.first_seen) >= self.active_timeout {
+                notify(&mut self.on_expire, flow, ExpiryReason::Active);
+                flow.restart(ts);
+            }
+        }
+
+        let flow = self.flows.entry(canonical).or_insert_with(|| {
+            let key = opener_key(located.key, tcp_flags.unwrap_or_default());
+            Flow::new(ts, key)
+        });
+        let dir = flow.direction_of(&located.key);
+        flow.update(ts, dir, located.len, tcp_flags);
+        Some((flow, dir))
+    }
+
+    /// Reports and drops flows idle at `now`, and reports and restarts flows
+    /// past the active timeout. Returns how many flows were reported.
+    pub fn expire(&mut self, now: Duration) -> usize {
+        let (idle_timeout, active_timeout) = (self.idle_timeout, self.active_timeout);
+        let on_expire = &mut self.on_expire;
+        let mut expired = 0;
+        self.flows.retain(|_, flow| {
+            if now.saturating_sub(flow.last_seen) >= idle_timeout {
+                notify(on_expire, flow, ExpiryReason::Idle);
+                expired += 1;
+                return false;
+            }
+            if now.saturating_sub(flow.first_seen) >= active_timeout {
+                notify(on_expire, flow, ExpiryReason::Active);
+                expired += 1;
+                flow.restart(now);
+            }
+            true
+        });
+        expired
+    }
+
+    /// Reports and drops every flow, e.g. at the end of a capture.
+    pub fn flush(&mut self) -> usize {
+        let on_expire = &mut self.on_expire;
+        let flushed = self.flows.len();
+        for (_, flow) in self.flows.drain() {
+            notify(on_expire, &flow, ExpiryReason::Flush);
+        }
+        flushed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::TCP_PSH;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const CLIENT: [u8; 4] = [192, 0, 2, 1];
+    const SERVER: [u8; 4] = [198, 51, 100, 2];
+
+    /// An IPv4 packet from `src` to `dst` carrying `transport`.
+    fn ipv4(src: [u8; 4], dst: [u8; 4], protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let total_len = (20 + transport.len()) as u16;
+        let mut bytes = vec![0x45, 0x00];
+        bytes.extend_from_slice(&total_len.to_be_bytes());
+        bytes.extend_from_slice(&[0x00, 0x01, 0x40, 0x00, 0x40, protocol, 0x00, 0x00]);
+        bytes.extend_from_slice(&src);
+        bytes.extend_from_slice(&dst);
+        bytes.extend_from_slice(transport);
+        bytes
+    }
+
+    fn tcp(client_to_server: bool, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let (src, dst, sport, dport) = match client_to_server {
+            true => (CLIENT, SERVER, 40000u16, 9999u16),
+            false => (SERVER, CLIENT, 9999, 40000),
+        };
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&sport.to_be_bytes());
+        segment.extend_from_slice(&dport.to_be_bytes());
+        segment.extend_from_slice(&[0; 8]);
+        segment.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        segment.extend_from_slice(payload);
+        ipv4(src, dst, 6, &segment)
+    }
+
+    fn feed(table: &mut FlowTable, ts: u64, bytes: &[u8]) -> Direction {
+        let pdu = Ip::from_bytes(bytes).unwrap();
+        table.process(Duration::from_secs(ts), &*pdu).unwrap().1
+    }
+
+    #[test]
+    fn test_key() {
+        let bytes = tcp(true, TCP_SYN, &[]);
+        let pdu = Ip::from_bytes(&bytes).unwrap();
+        let key = FlowKey::of(&*pdu).unwrap();
+        assert!(key.protocol == 6);
+        assert!(key.src == "192.0.2.1:40000".parse().unwrap());
+        assert!(key.dst == "198.51.100.2:9999".parse().unwrap());
+        assert!(key.canonical() == key.reversed().canonical());
+
+        let udp = [0x00, 0x35, 0x30, 0x39, 0x00, 0x08, 0x00, 0x00];
+        let bytes = ipv4(SERVER, CLIENT, 17, &udp);
+        let key = FlowKey::of(&*Ip::from_bytes(&bytes).unwrap()).unwrap();
+        assert!(key.src.port() == 53 && key.dst.port() == 12345);
+
+        let bytes = ipv4(SERVER, CLIENT, 1, &[8, 0, 0, 0, 0, 0, 0, 0]);
+        let key = FlowKey::of(&*Ip::from_bytes(&bytes).unwrap()).unwrap();
+        assert!(key.protocol == 1 && key.src.port() == 0);
+    }
+
+    #[test]
+    fn test_tcp_flow() {
+        let mut table = FlowTable::new();
+        // The SYN ACK comes first, so the opener is the other side.
+        assert!(feed(&mut table, 0, &tcp(false, TCP_SYN | TCP_ACK, &[])) == Direction::Reverse);
+        assert!(feed(&mut table, 0, &tcp(true, TCP_ACK, &[])) == Direction::Forward);
+        feed(&mut table, 1, &tcp(true, TCP_PSH | TCP_ACK, b"hello"));
+        feed(&mut table, 2, &tcp(false, TCP_FIN | TCP_ACK, &[]));
+        assert!(table.len() == 1);
+
+        let key = FlowKey::of(&*Ip::from_bytes(&tcp(false, 0, &[])).unwrap()).unwrap();
+        let flow = table.get(&key).unwrap();
+        assert!(flow.key() == key.reversed());
+        assert!(flow.packets(Direction::Forward) == 2);
+        assert!(flow.packets(Direction::Reverse) == 2);
+        assert!(flow.bytes(Direction::Forward) == 40 + 45);
+        assert!(flow.tcp_flags(Direction::Reverse) == TCP_SYN | TCP_ACK | TCP_FIN);
+        assert!(flow.tcp_state() == Some(TcpState::Closing));
+        assert!(flow.duration() == Duration::from_secs(2));
+
+        feed(&mut table, 3, &tcp(true, TCP_FIN | TCP_ACK, &[]));
+        assert!(table.get(&key).unwrap().tcp_state() == Some(TcpState::Closed));
+    }
+
+    #[test]
+    fn test_tcp_state() {
+        let mut state = TcpState::initial(TCP_SYN);
+        assert!(state == TcpState::SynSent);
+        state = state.advance(
+            Direction::Reverse,
+            TCP_SYN | TCP_ACK,
+            [TCP_SYN, TCP_SYN | TCP_ACK],
+        );
+        assert!(state == TcpState::SynReceived);
+        state = state.advance(Direction::Forward, TCP_ACK, [TCP_SYN | TCP_ACK; 2]);
+        assert!(state == TcpState::Established);
+        state = state.advance(Direction::Reverse, TCP_RST, [TCP_SYN | TCP_ACK, TCP_RST]);
+        assert!(state == TcpState::Reset);
+        assert!(TcpState::initial(TCP_ACK) == TcpState::Established);
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let expired = Rc::new(RefCell::new(Vec::new()));
+        let mut table = FlowTable::new();
+        let log = expired.clone();
+        table
+            .with_idle_timeout(Duration::from_secs(10))
+            .with_active_timeout(Duration::from_secs(60))
+            .with_on_expire(move |flow, reason| {
+                log.borrow_mut()
+                    .push((reason, flow.packets(Direction::Forward)))
+            });
+
+        for ts in (0..=60).step_by(5) {
+            feed(&mut table, ts, &tcp(true, TCP_ACK, b"x"));
+        }
+        assert!(*expired.borrow() == [(ExpiryReason::Active, 12)]);
+        let flow = table.flows().next().unwrap();
+        assert!(flow.first_seen() == Duration::from_secs(60));
+        assert!(flow.packets(Direction::Forward) == 1);
+
+        feed(
+            &mut table,
+            62,
+            &ipv4(CLIENT, SERVER, 1, &[8, 0, 0, 0, 0, 0, 0, 0]),
+        );
+        assert!(table.len() == 2);
+        assert!(table.expire(Duration::from_secs(71)) == 1);
+        assert!(table.len() == 1);
+        assert!(expired.borrow()[1] == (ExpiryReason::Idle, 1));
+
+        // An idle flow is replaced by a new one when its next packet comes.
+        feed(
+            &mut table,
+            90,
+            &ipv4(CLIENT, SERVER, 1, &[8, 0, 0, 0, 0, 0, 0, 0]),
+        );
+        assert!(expired.borrow()[2] == (ExpiryReason::Idle, 1));
+        assert!(table.flows().next().unwrap().first_seen() == Duration::from_secs(90));
+
+        assert!(table.flush() == 1);
+        assert!(table.is_empty());
+        assert!(expired.borrow()[3] == (ExpiryReason::Flush, 1));
+    }
+}
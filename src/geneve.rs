@@ -0,0 +1,358 @@
+use crate::error::AllocError;
+use crate::ethernet::{ETHER_DISSECTION_TABLE, ETHER_TYPE_NAMES, EtherType};
+use crate::prelude::*;
+use crate::udp::{UDP_DISSECTION_TABLE, UdpType};
+
+const GENEVE_VER_OPT_LEN_OFFSET: usize = 0;
+const GENEVE_FLAGS_OFFSET: usize = 1;
+const GENEVE_PROTO_OFFSET: usize = 2;
+const GENEVE_VNI_OFFSET: usize = 4;
+const GENEVE_HEADER_LEN: usize = 8;
+
+const GENEVE_OPT_LEN_MASK: u8 = 0x3F;
+const GENEVE_BYTE_MULTIPLE: usize = 4;
+/// The 6 bit option length field counts 4 byte words.
+pub const GENEVE_MAX_OPT_LEN: usize = GENEVE_OPT_LEN_MASK as usize * GENEVE_BYTE_MULTIPLE;
+
+/// Control packet, with no user payload.
+pub const GENEVE_FLAG_O: u8 = 0x80;
+/// At least one critical option is present.
+pub const GENEVE_FLAG_C: u8 = 0x40;
+
+const GENEVE_OPT_CLASS_OFFSET: usize = 0;
+const GENEVE_OPT_TYPE_OFFSET: usize = 2;
+const GENEVE_OPT_LEN_OFFSET: usize = 3;
+const GENEVE_OPT_HEADER_LEN: usize = 4;
+const GENEVE_OPT_DATA_LEN_MASK: u8 = 0x1F;
+const GENEVE_OPT_CRITICAL: u8 = 0x80;
+
+pub const GENEVE_PORT: u16 = 6081;
+
+fn get_geneve_opt_len(bytes: &[u8]) -> usize {
+    (bytes[GENEVE_VER_OPT_LEN_OFFSET] & GENEVE_OPT_LEN_MASK) as usize * GENEVE_BYTE_MULTIPLE
+}
+
+/// A Geneve option TLV.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneveOption {
+    pub class: u16,
+    pub kind: u8,
+    /// Zero padded to a multiple of 4 bytes when serialized.
+    pub data: Vec<u8>,
+}
+
+/// Parses every option in `bytes`, which must be exactly the option area.
+pub fn parse_geneve_options(bytes: &[u8]) -> Result<Vec<GeneveOption>, ParseError> {
+    let mut opts = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let opt = &bytes[offset..];
+        ParseError::check_len(
+            "geneve option",
+            "header length",
+            bytes,
+            offset,
+            GENEVE_OPT_HEADER_LEN,
+        )?;
+        let data_len =
+            (opt[GENEVE_OPT_LEN_OFFSET] & GENEVE_OPT_DATA_LEN_MASK) as usize * GENEVE_BYTE_MULTIPLE;
+        let opt_len = GENEVE_OPT_HEADER_LEN + data_len;
+        ParseError::check_len("geneve option", "length", bytes, offset, opt_len)?;
+
+        opts.push(GeneveOption {
+            class: parse_bytes::<u16>(
+                &opt[GENEVE_OPT_CLASS_OFFSET..GENEVE_OPT_TYPE_OFFSET],
+                Endian::Big,
+            ),
+            kind: opt[GENEVE_OPT_TYPE_OFFSET],
+            data: opt[GENEVE_OPT_HEADER_LEN..opt_len].to_vec(),
+        });
+        offset += opt_len;
+    }
+    Ok(opts)
+}
+
+pub fn geneve_options_to_bytes(opts: &[GeneveOption]) -> Vec<u8> {
+    opts.iter().flat_map(|opt| opt.to_bytes()).collect()
+}
+
+impl GeneveOption {
+    pub fn new(class: u16, kind: u8, data: Vec<u8>) -> Self {
+        Self { class, kind, data }
+    }
+
+    /// Tunnel endpoints must drop packets with critical options they don't
+    /// understand.
+    pub fn critical(&self) -> bool {
+        self.kind & GENEVE_OPT_CRITICAL != 0
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data_len = self.data.len().next_multiple_of(GENEVE_BYTE_MULTIPLE);
+        let mut res = Vec::with_capacity(GENEVE_OPT_HEADER_LEN + data_len);
+        res.extend_from_slice(&self.class.to_be_bytes());
+        res.push(self.kind);
+        res.push((data_len / GENEVE_BYTE_MULTIPLE) as u8 & GENEVE_OPT_DATA_LEN_MASK);
+        res.extend_from_slice(&self.data);
+        res.resize(GENEVE_OPT_HEADER_LEN + data_len, 0);
+        res
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "geneve.option.class": self.class,
+            "geneve.option.type": self.kind,
+            "geneve.option.critical": self.critical(),
+            "geneve.option.data": printable_ascii(&self.data),
+        })
+    }
+}
+
+/// Generic Network Virtualization Encapsulation. The protocol type is an
+/// EtherType, so the payload is dissected through `ETHER_DISSECTION_TABLE`.
+/// Refer to <https://www.rfc-editor.org/rfc/rfc8926>
+#[pdu_type]
+pub struct Geneve<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Geneve<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(Geneve);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("geneve", "header length", bytes, 0, GENEVE_HEADER_LEN)?;
+
+        let header_len = GENEVE_HEADER_LEN + get_geneve_opt_len(bytes);
+        ParseError::check_len("geneve", "header length", bytes, 0, header_len)?;
+        parse_geneve_options(&bytes[GENEVE_HEADER_LEN..header_len])
+            .map_err(|err| err.within("geneve", GENEVE_HEADER_LEN))?;
+
+        let proto = parse_bytes::<u16>(&bytes[GENEVE_PROTO_OFFSET..GENEVE_VNI_OFFSET], Endian::Big);
+        let child = if bytes.len() == header_len {
+            None
+        } else {
+            Some(build_from_table(
+                &ETHER_DISSECTION_TABLE,
+                EtherType(proto),
+                &bytes[header_len..],
+            ))
+        };
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..header_len]),
+            parent: None,
+            child,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        let opts_json: Vec<serde_json::Value> = self
+            .options()
+            .unwrap_or_default()
+            .iter()
+            .map(|opt| opt.to_json())
+            .collect();
+
+        Ok(json!({
+            "geneve": {
+                "geneve.version": self.version(),
+                "geneve.flags": self.flags(),
+                "geneve.proto_type": self.protocol(),
+                "geneve.vni": self.vni(),
+                "geneve.options": opts_json,
+                "geneve.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> Geneve<'a> {
+    pub fn new(vni: u32) -> Self {
+        let mut geneve = Self {
+            header: Cow::Owned(vec![0; GENEVE_HEADER_LEN]),
+            parent: None,
+            child: None,
+        };
+        geneve.set_vni(vni);
+        geneve
+    }
+
+    pub fn version(&self) -> u8 {
+        self.header[GENEVE_VER_OPT_LEN_OFFSET] >> 6
+    }
+
+    pub fn set_version(&mut self, version: u8) {
+        let byte = &mut self.header.to_mut()[GENEVE_VER_OPT_LEN_OFFSET];
+        *byte = (*byte & GENEVE_OPT_LEN_MASK) | (version << 6);
+    }
+
+    pub fn with_version(&mut self, version: u8) -> &mut Self {
+        self.set_version(version);
+        self
+    }
+
+    /// Option area length in 4 byte words.
+    pub fn opt_len(&self) -> u8 {
+        self.header[GENEVE_VER_OPT_LEN_OFFSET] & GENEVE_OPT_LEN_MASK
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.header[GENEVE_FLAGS_OFFSET]
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.header.to_mut()[GENEVE_FLAGS_OFFSET] = flags;
+    }
+
+    pub fn with_flags(&mut self, flags: u8) -> &mut Self {
+        self.set_flags(flags);
+        self
+    }
+
+    /// EtherType of the payload.
+    pub fn protocol(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[GENEVE_PROTO_OFFSET..GENEVE_VNI_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_protocol(&mut self, protocol: u16) {
+        self.header.to_mut()[GENEVE_PROTO_OFFSET..GENEVE_VNI_OFFSET]
+            .copy_from_slice(&protocol.to_be_bytes());
+    }
+
+    pub fn with_protocol(&mut self, protocol: u16) -> &mut Self {
+        self.set_protocol(protocol);
+        self
+    }
+
+    /// 24 bit virtual network identifier.
+    pub fn vni(&self) -> u32 {
+        parse_bytes::<u32>(
+            &self.header[GENEVE_VNI_OFFSET..GENEVE_HEADER_LEN],
+            Endian::Big,
+        ) >> 8
+    }
+
+    pub fn set_vni(&mut self, vni: u32) {
+        self.header.to_mut()[GENEVE_VNI_OFFSET..GENEVE_HEADER_LEN - 1]
+            .copy_from_slice(&vni.to_be_bytes()[1..]);
+    }
+
+    pub fn with_vni(&mut self, vni: u32) -> &mut Self {
+        self.set_vni(vni);
+        self
+    }
+
+    pub fn options_bytes(&self) -> &[u8] {
+        &self.header[GENEVE_HEADER_LEN..]
+    }
+
+    pub fn options(&self) -> Result<Vec<GeneveOption>, ParseError> {
+        parse_geneve_options(self.options_bytes())
+            .map_err(|err| err.within("geneve", GENEVE_HEADER_LEN))
+    }
+
+    /// Rewrites the option area and updates the option length and the
+    /// critical flag to match.
+    pub fn set_options(&mut self, options: &[GeneveOption]) -> Result<(), AllocError> {
+        let opt_bytes = geneve_options_to_bytes(options);
+        if opt_bytes.len() > GENEVE_MAX_OPT_LEN {
+            return Err(AllocError::InsufficientSpace);
+        }
+
+        let critical = options.iter().any(|opt| opt.critical());
+        let header = self.header.to_mut();
+        header.truncate(GENEVE_HEADER_LEN);
+        header.extend_from_slice(&opt_bytes);
+        header[GENEVE_VER_OPT_LEN_OFFSET] = (header[GENEVE_VER_OPT_LEN_OFFSET]
+            & !GENEVE_OPT_LEN_MASK)
+            | (opt_bytes.len() / GENEVE_BYTE_MULTIPLE) as u8;
+        header[GENEVE_FLAGS_OFFSET] = if critical {
+            header[GENEVE_FLAGS_OFFSET] | GENEVE_FLAG_C
+        } else {
+            header[GENEVE_FLAGS_OFFSET] & !GENEVE_FLAG_C
+        };
+        Ok(())
+    }
+
+    pub fn with_options(&mut self, options: &[GeneveOption]) -> &mut Self {
+        self.set_options(options)
+            .expect("Failed to set Geneve options");
+        self
+    }
+}
+
+register_pdu!(UdpType(GENEVE_PORT), Geneve, UDP_DISSECTION_TABLE);
+
+register_fields!(Geneve, "geneve", {
+    "geneve.version" => version { name: "Version", bits: (GENEVE_VER_OPT_LEN_OFFSET * 8, 2) },
+    "geneve.flags" => flags { name: "Flags", bits: (GENEVE_FLAGS_OFFSET * 8, 8), base: Hex },
+    "geneve.proto_type" => protocol {
+        name: "Protocol Type",
+        bits: (GENEVE_PROTO_OFFSET * 8, 16),
+        base: Hex,
+        values: ETHER_TYPE_NAMES,
+    },
+    "geneve.vni" => vni { name: "Virtual Network Identifier", bits: (GENEVE_VNI_OFFSET * 8, 24) },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip6::Ipv6;
+
+    #[test]
+    fn test_geneve_options() {
+        let mut bytes = vec![0x03, 0x40, 0x86, 0xdd, 0x00, 0x00, 0x64, 0x00];
+        bytes.extend_from_slice(&[0x01, 0x02, 0x80, 0x01, 0xde, 0xad, 0xbe, 0xef]);
+        bytes.extend_from_slice(&[0xff, 0xff, 0x05, 0x00]);
+        bytes.extend_from_slice(&[0x60, 0, 0, 0, 0, 0, 59, 64]);
+        bytes.extend_from_slice(&[0; 32]);
+
+        let pdu = Geneve::from_bytes(&bytes).unwrap();
+        let geneve = pdu.downcast_ref::<Geneve>().unwrap();
+        assert!(geneve.version() == 0);
+        assert!(geneve.opt_len() == 3);
+        assert!(geneve.flags() == GENEVE_FLAG_C);
+        assert!(geneve.vni() == 100);
+        let opts = geneve.options().unwrap();
+        assert!(
+            opts == vec![
+                GeneveOption::new(0x0102, 0x80, vec![0xde, 0xad, 0xbe, 0xef]),
+                GeneveOption::new(0xffff, 0x05, vec![]),
+            ]
+        );
+        assert!(opts[0].critical());
+        assert!(pdu.find::<Ipv6>().is_some());
+
+        let mut built = Geneve::new(100);
+        built.with_protocol(0x86dd).with_options(&opts);
+        assert!(built.to_bytes() == bytes[..20]);
+
+        // The option length runs past the packet.
+        assert!(Geneve::from_bytes(&bytes[..16]).is_err());
+    }
+
+    #[test]
+    fn test_set_options() {
+        let mut geneve = Geneve::new(1);
+        geneve.with_options(&[GeneveOption::new(1, 0x80, vec![1, 2, 3])]);
+        assert!(geneve.opt_len() == 2);
+        assert!(geneve.flags() & GENEVE_FLAG_C != 0);
+        assert!(geneve.options_bytes()[4..] == [1, 2, 3, 0]);
+
+        geneve.with_options(&[]);
+        assert!(geneve.opt_len() == 0);
+        assert!(geneve.flags() == 0);
+
+        let big = GeneveOption::new(1, 1, vec![0; GENEVE_MAX_OPT_LEN]);
+        assert!(geneve.set_options(&[big]).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+use crate::ethernet::{ETHER_DISSECTION_TABLE, ETHER_TYPE_NAMES, EtherType};
+use crate::ip::{IPV4_DISSECTION_TABLE, Ipv4Type};
+use crate::ip6::{IPV6_DISSECTION_TABLE, Ipv6Type};
+use crate::prelude::*;
+
+const GRE_FLAGS_OFFSET: usize = 0;
+const GRE_PROTO_OFFSET: usize = 2;
+const GRE_MIN_HEADER_LEN: usize = 4;
+const GRE_FIELD_LEN: usize = 4;
+
+pub const GRE_CHECKSUM: u16 = 0x8000;
+pub const GRE_ROUTING: u16 = 0x4000;
+pub const GRE_KEY: u16 = 0x2000;
+pub const GRE_SEQUENCE: u16 = 0x1000;
+/// Acknowledgment number present, only used by version 1 (PPTP).
+pub const GRE_ACK: u16 = 0x0080;
+const GRE_VERSION_MASK: u16 = 0x0007;
+
+/// The optional fields in the order they follow the fixed header.
+const GRE_FIELDS: [u16; 4] = [GRE_CHECKSUM, GRE_KEY, GRE_SEQUENCE, GRE_ACK];
+
+pub const IP_PROTO_GRE: u8 = 47;
+
+fn get_gre_flags(bytes: &[u8]) -> u16 {
+    parse_bytes::<u16>(&bytes[GRE_FLAGS_OFFSET..GRE_PROTO_OFFSET], Endian::Big)
+}
+
+fn get_gre_proto(bytes: &[u8]) -> u16 {
+    parse_bytes::<u16>(&bytes[GRE_PROTO_OFFSET..GRE_MIN_HEADER_LEN], Endian::Big)
+}
+
+fn get_gre_header_len(flags: u16) -> usize {
+    GRE_MIN_HEADER_LEN
+        + GRE_FIELDS
+            .iter()
+            .filter(|&&field| flags & field != 0)
+            .count()
+            * GRE_FIELD_LEN
+}
+
+/// Generic Routing Encapsulation. The protocol type is an EtherType, so the
+/// payload is dissected through `ETHER_DISSECTION_TABLE`.
+/// Refer to <https://www.rfc-editor.org/rfc/rfc2784> and
+/// <https://www.rfc-editor.org/rfc/rfc2890>
+#[pdu_type]
+pub struct Gre<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Gre<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(Gre);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("gre", "header length", bytes, 0, GRE_MIN_HEADER_LEN)?;
+
+        // Source routing was deprecated by RFC 2784 and is not decoded.
+        let flags = get_gre_flags(bytes);
+        if flags & GRE_ROUTING != 0 {
+            return Err(ParseError::UnsupportedProtocol {
+                protocol: "gre",
+                field: "flags",
+                offset: GRE_FLAGS_OFFSET,
+                value: flags as u64,
+            });
+        }
+
+        let header_len = get_gre_header_len(flags);
+        ParseError::check_len("gre", "header length", bytes, 0, header_len)?;
+
+        let child = if bytes.len() == header_len {
+            None
+        } else {
+            Some(build_from_table(
+                &ETHER_DISSECTION_TABLE,
+                EtherType(get_gre_proto(bytes)),
+                &bytes[header_len..],
+            ))
+        };
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..header_len]),
+            parent: None,
+            child,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "gre": {
+                "gre.flags_and_version": self.flags(),
+                "gre.proto": self.protocol(),
+                "gre.checksum": self.checksum(),
+                "gre.key": self.key(),
+                "gre.sequence_number": self.sequence(),
+                "gre.ack_number": self.ack(),
+                "gre.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> Gre<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; GRE_MIN_HEADER_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    /// Flags and version, as they appear in the first two bytes.
+    pub fn flags(&self) -> u16 {
+        get_gre_flags(&self.header)
+    }
+
+    /// Sets the flag bits that don't change the header layout. Use the
+    /// optional field setters to add or remove fields.
+    pub fn set_flags(&mut self, flags: u16) {
+        let layout = GRE_FIELDS.iter().fold(GRE_VERSION_MASK, |acc, f| acc | f);
+        let flags = (self.flags() & layout) | (flags & !layout);
+        self.header.to_mut()[GRE_FLAGS_OFFSET..GRE_PROTO_OFFSET]
+            .copy_from_slice(&flags.to_be_bytes());
+    }
+
+    pub fn with_flags(&mut self, flags: u16) -> &mut Self {
+        self.set_flags(flags);
+        self
+    }
+
+    pub fn version(&self) -> u8 {
+        (self.flags() & GRE_VERSION_MASK) as u8
+    }
+
+    pub fn set_version(&mut self, version: u8) {
+        let flags = (self.flags() & !GRE_VERSION_MASK) | (version as u16 & GRE_VERSION_MASK);
+        self.header.to_mut()[GRE_FLAGS_OFFSET..GRE_PROTO_OFFSET]
+            .copy_from_slice(&flags.to_be_bytes());
+    }
+
+    pub fn with_version(&mut self, version: u8) -> &mut Self {
+        self.set_version(version);
+        self
+    }
+
+    /// EtherType of the payload.
+    pub fn protocol(&self) -> u16 {
+        get_gre_proto(&self.header)
+    }
+
+    pub fn set_protocol(&mut self, protocol: u16) {
+        self.header.to_mut()[GRE_PROTO_OFFSET..GRE_MIN_HEADER_LEN]
+            .copy_from_slice(&protocol.to_be_bytes());
+    }
+
+    pub fn with_protocol(&mut self, protocol: u16) -> &mut Self {
+        self.set_protocol(protocol);
+        self
+    }
+
+    /// Offset of an optional field, whether or not it is present.
+    fn field_offset(&self, field: u16) -> usize {
+        let flags = self.flags();
+        GRE_MIN_HEADER_LEN
+            + GRE_FIELDS
+                .iter()
+                .take_while(|&&f| f != field)
+                .filter(|&&f| flags & f != 0)
+                .count()
+                * GRE_FIELD_LEN
+    }
+
+    fn field(&self, field: u16) -> Option<u32> {
+        if self.flags() & field == 0 {
+            return None;
+        }
+        let offset = self.field_offset(field);
+        Some(parse_bytes::<u32>(
+            &self.header[offset..offset + GRE_FIELD_LEN],
+            Endian::Big,
+        ))
+    }
+
+    /// Writes, inserts or removes an optional field and its flag.
+    fn set_field(&mut self, field: u16, value: Option<u32>) {
+        let offset = self.field_offset(field);
+        let present = self.flags() & field != 0;
+        let flags = match value {
+            Some(_) => self.flags() | field,
+            None => self.flags() & !field,
+        };
+
+        let header = self.header.to_mut();
+        match (present, value) {
+            (true, Some(value)) => {
+                header[offset..offset + GRE_FIELD_LEN].copy_from_slice(&value.to_be_bytes())
+            }
+            (false, Some(value)) => {
+                header.splice(offset..offset, value.to_be_bytes());
+            }
+            (true, None) => {
+                header.drain(offset..offset + GRE_FIELD_LEN);
+            }
+            (false, None) => {}
+        }
+        header[GRE_FLAGS_OFFSET..GRE_PROTO_OFFSET].copy_from_slice(&flags.to_be_bytes());
+    }
+
+    /// Checksum over the GRE header and payload, if present.
+    pub fn checksum(&self) -> Option<u16> {
+        self.field(GRE_CHECKSUM).map(|field| (field >> 16) as u16)
+    }
+
+    pub fn set_checksum(&mut self, checksum: Option<u16>) {
+        self.set_field(
+            GRE_CHECKSUM,
+            checksum.map(|checksum| (checksum as u32) << 16),
+        );
+    }
+
+    pub fn with_checksum(&mut self, checksum: Option<u16>) -> &mut Self {
+        self.set_checksum(checksum);
+        self
+    }
+
+    /// In version 1 the key holds the payload length and call ID.
+    pub fn key(&self) -> Option<u32> {
+        self.field(GRE_KEY)
+    }
+
+    pub fn set_key(&mut self, key: Option<u32>) {
+        self.set_field(GRE_KEY, key);
+    }
+
+    pub fn with_key(&mut self, key: Option<u32>) -> &mut Self {
+        self.set_key(key);
+        self
+    }
+
+    pub fn sequence(&self) -> Option<u32> {
+        self.field(GRE_SEQUENCE)
+    }
+
+    pub fn set_sequence(&mut self, sequence: Option<u32>) {
+        self.set_field(GRE_SEQUENCE, sequence);
+    }
+
+    pub fn with_sequence(&mut self, sequence: Option<u32>) -> &mut Self {
+        self.set_sequence(sequence);
+        self
+    }
+
+    pub fn ack(&self) -> Option<u32> {
+        self.field(GRE_ACK)
+    }
+
+    pub fn set_ack(&mut self, ack: Option<u32>) {
+        self.set_field(GRE_ACK, ack);
+    }
+
+    pub fn with_ack(&mut self, ack: Option<u32>) -> &mut Self {
+        self.set_ack(ack);
+        self
+    }
+}
+
+register_pdu!(Ipv4Type(IP_PROTO_GRE), Gre, IPV4_DISSECTION_TABLE);
+register_pdu!(Ipv6Type(IP_PROTO_GRE), Gre, IPV6_DISSECTION_TABLE);
+
+register_fields!(Gre, "gre", {
+    "gre.flags_and_version" => flags {
+        name: "Flags and Version",
+        bits: (GRE_FLAGS_OFFSET * 8, 16),
+        base: Hex,
+    },
+    "gre.proto" => protocol {
+        name: "Protocol Type",
+        bits: (GRE_PROTO_OFFSET * 8, 16),
+        base: Hex,
+        values: ETHER_TYPE_NAMES,
+    },
+    // The checksum is always the first optional word, the others move
+    // depending on which precede them.
+    "gre.checksum" => checksum { name: "Checksum", bits: (GRE_MIN_HEADER_LEN * 8, 16), base: Hex },
+    "gre.key" => key { name: "Key", base: Hex },
+    "gre.sequence_number" => sequence { name: "Sequence Number" },
+    "gre.ack_number" => ack { name: "Acknowledgment Number" },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethernet::Ethernet;
+    use crate::ip::Ip;
+
+    const INNER_IPV4: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x14, 0x00, 0x01, 0x00, 0x00, 0x40, 0xfd, 0x00, 0x00, 0x0a, 0x00, 0x00,
+        0x01, 0x0a, 0x00, 0x00, 0x02,
+    ];
+
+    #[test]
+    fn test_gre_fields() {
+        // Key and sequence present, IPv4 payload.
+        let mut bytes = vec![0x30, 0x00, 0x08, 0x00];
+        bytes.extend_from_slice(&1234u32.to_be_bytes());
+        bytes.extend_from_slice(&7u32.to_be_bytes());
+        bytes.extend_from_slice(&INNER_IPV4);
+
+        let pdu = Gre::from_bytes(&bytes).unwrap();
+        let gre = pdu.downcast_ref::<Gre>().unwrap();
+        assert!(gre.checksum().is_none());
+        assert!(gre.key() == Some(1234));
+        assert!(gre.sequence() == Some(7));
+        assert!(gre.version() == 0);
+        assert!(gre.protocol() == 0x0800);
+        assert!(pdu.find::<Ip>().is_some());
+        assert!(pdu.chain_to_bytes() == bytes);
+
+        assert!(Gre::from_bytes(&bytes[..8]).is_err());
+    }
+
+    #[test]
+    fn test_set_fields() {
+        let mut gre = Gre::new();
+        gre.with_protocol(0x6558)
+            .with_sequence(Some(7))
+            .with_key(Some(1234));
+        assert!(gre.flags() == GRE_KEY | GRE_SEQUENCE);
+        assert!(gre.key() == Some(1234));
+        assert!(gre.sequence() == Some(7));
+
+        gre.set_checksum(Some(0xBEEF));
+        assert!(gre.to_bytes()[4..8] == [0xbe, 0xef, 0x00, 0x00]);
+        assert!(gre.key() == Some(1234));
+
+        gre.set_key(None);
+        assert!(gre.to_bytes().len() == 12);
+        assert!(gre.key().is_none());
+        assert!(gre.sequence() == Some(7));
+        assert!(gre.checksum() == Some(0xBEEF));
+    }
+
+    #[test]
+    fn test_gre_ethernet() {
+        let mut bytes = vec![0x00, 0x00, 0x65, 0x58];
+        bytes.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        bytes.extend_from_slice(&[0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00]);
+        bytes.extend_from_slice(&INNER_IPV4);
+
+        let pdu = Gre::from_bytes(&bytes).unwrap();
+        assert!(pdu.find::<Ethernet>().is_some());
+        assert!(pdu.find::<Ip>().is_some());
+    }
+
+    #[test]
+    fn test_outer_inner_ip() {
+        let mut bytes = vec![
+            0x45, 0x00, 0x00, 0x2c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x2f, 0x00, 0x00, 0xc0, 0x00,
+            0x02, 0x01, 0xc6, 0x33, 0x64, 0x02,
+        ];
+        bytes.extend_from_slice(&[0x00, 0x00, 0x08, 0x00]);
+        bytes.extend_from_slice(&INNER_IPV4);
+
+        let mut pdu = Ip::from_bytes(&bytes).unwrap();
+        assert!(pdu.find_all::<Ip>().len() == 2);
+        assert!(pdu.find::<Ip>().unwrap().protocol() == IP_PROTO_GRE);
+        assert!(pdu.find_nth::<Ip>(0).unwrap().protocol() == IP_PROTO_GRE);
+        assert!(pdu.find_inner::<Ip>().unwrap().protocol() == 0xfd);
+        assert!(pdu.find_nth::<Ip>(2).is_none());
+
+        pdu.find_inner_mut::<Ip>().unwrap().set_ttl(1);
+        assert!(pdu.find_nth::<Ip>(1).unwrap().ttl() == 1);
+        assert!(pdu.find::<Ip>().unwrap().ttl() == 64);
+    }
+}
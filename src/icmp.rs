@@ -1,153 +1,562 @@
-#![allow(dead_code)]
-
+use crate::ip::{IPV4_DISSECTION_TABLE, Ip, Ipv4Type};
 use crate::prelude::*;
 
-const ECHO_REPLY_ID_OFFSET: usize = 0;
-const ECHO_REPLY_SEQNUM_OFFSET: usize = 2;
-const ECHO_REPLY_DATA_OFFSET: usize = 4;
-//
-// #[pdu_type]
-// pub struct EchoReply<'a> {
-//     data: Cow<'a, [u8]>,
-// }
-//
-// impl<'a> EchoReply<'a> {
-//     pub fn new() -> Self {
-//         Self {
-//             header: Cow::Owned(Vec::new()),
-//             parent: None,
-//             child: None,
-//         }
-//     }
-//
-//     pub fn id(&self) -> u16 {
-//         parse_bytes::<u16>(
-//             &self.header[ECHO_REPLY_ID_OFFSET..ECHO_REPLY_SEQNUM_OFFSET],
-//             Endian::Big,
-//         )
-//     }
-//
-//     pub fn set_id(&mut self, id: u16) {
-//         self.header.to_mut()[ECHO_REPLY_ID_OFFSET..ECHO_REPLY_SEQNUM_OFFSET]
-//             .copy_from_slice(&id.to_be_bytes());
-//     }
-//
-//     pub fn with_id(&mut self, id: u16) -> &mut Self {
-//         self.set_id(id);
-//         self
-//     }
-//
-//     pub fn seq_number(&self) -> u16 {
-//         parse_bytes::<u16>(
-//             &self.header[ECHO_REPLY_SEQNUM_OFFSET..ECHO_REPLY_DATA_OFFSET],
-//             Endian::Big,
-//         )
-//     }
-//
-//     pub fn set_seq_number(&mut self, seq_number: u16) {
-//         self.header.to_mut()[ECHO_REPLY_SEQNUM_OFFSET..ECHO_REPLY_DATA_OFFSET]
-//             .copy_from_slice(&seq_number.to_be_bytes());
-//     }
-//
-//     pub fn with_seq_number(&mut self, seq_number: u16) -> &mut Self {
-//         self.set_seq_number(seq_number);
-//         self
-//     }
-//
-//     pub fn data(&self) -> &[u8] {
-//         &self.header[ECHO_REPLY_DATA_OFFSET..]
-//     }
-//
-//     pub fn set_data(&mut self, data: &[u8]) {
-//         self.data = Cow::Owned(data.to_vec());
-//     }
-//
-//     pub fn with_data(&mut self, data: &[u8]) -> &mut Self {
-//         self.set_data(data);
-//         self
-//     }
-// }
-//
-// #[pdu_impl]
-// impl<'a> Pdu<'a> for EchoReply<'a> {
-//     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
-//         Ok(Box::new(Self {
-//             header: Cow::Borrowed(&bytes[..ECHO_REPLY_DATA_OFFSET]),
-//             parent: None,
-//             child: None,
-//         }))
-//     }
-//
-//     fn to_bytes(&self) -> Vec<u8> {
-//         let mut res = Vec::new();
-//         res.extend_from_slice(&self.header);
-//         res.extend_from_slice(&self.data);
-//         res
-//     }
-//
-//     fn to_json(&self) -> Result<serde_json::Value, serde_json::error::Error> {
-//         Ok(json!({
-//             "echo_reply": {
-//                 "echo_reply.id": self.id(),
-//                 "echo_reply.seq_number": self.seq_number(),
-//                 "echo_reply.data": printable_ascii(&self.data)
-//             }
-//         }))
-//     }
-// }
-//
-// register_pdu!(IcmpType(0), EchoReply, ICMP_DISSECTION_TABLE);
-//
-#[pdu_type]
-pub struct DestUnreachable<'a> {}
+use std::net::Ipv4Addr;
+
+const ICMP_TYPE_OFFSET: usize = 0;
+const ICMP_CODE_OFFSET: usize = 1;
+pub(crate) const ICMP_CHECKSUM_OFFSET: usize = 2;
+const ICMP_MIN_SIZE: usize = 4;
+
+pub(crate) const ICMP_ID_OFFSET: usize = 0;
+pub(crate) const ICMP_SEQNUM_OFFSET: usize = 2;
+pub(crate) const ICMP_ECHO_LEN: usize = 4;
+
+const ICMP_ORIGINATE_OFFSET: usize = 4;
+const ICMP_RECEIVE_OFFSET: usize = 8;
+const ICMP_TRANSMIT_OFFSET: usize = 12;
+const ICMP_TIMESTAMP_LEN: usize = 16;
+
+pub(crate) const ICMP_ERROR_LEN: usize = 4;
+const ICMP_PARAM_POINTER_OFFSET: usize = 0;
+const ICMP_ERROR_LENGTH_OFFSET: usize = 1;
+const ICMP_NEXT_HOP_MTU_OFFSET: usize = 2;
+const ICMP_GATEWAY_OFFSET: usize = 0;
+
+/// Destination Unreachable codes
+pub const UNREACH_NET: u8 = 0;
+pub const UNREACH_HOST: u8 = 1;
+pub const UNREACH_PROTOCOL: u8 = 2;
+pub const UNREACH_PORT: u8 = 3;
+pub const UNREACH_NEEDFRAG: u8 = 4;
+
+/// Time Exceeded codes
+pub const TIMXCEED_INTRANS: u8 = 0;
+pub const TIMXCEED_REASS: u8 = 1;
+
+pub(crate) fn get_icmp_u16(bytes: &[u8], offset: usize) -> u16 {
+    parse_bytes::<u16>(&bytes[offset..offset + 2], Endian::Big)
+}
+
+fn get_icmp_u32(bytes: &[u8], offset: usize) -> u32 {
+    parse_bytes::<u32>(&bytes[offset..offset + 4], Endian::Big)
+}
+
+/// Error messages quote the offending IP header and at least the first 8
+/// bytes of its payload. Falls back to `Raw` when the quote can't be parsed.
+fn original_datagram(bytes: &[u8]) -> Pob<'_> {
+    if bytes.is_empty() {
+        return None;
+    }
+    Some(Raw::or_malformed(Ip::from_bytes(bytes), bytes))
+}
+
+/// The fixed fields of a message body as (name, offset, length).
+pub(crate) type MessageFields = &'static [(&'static str, usize, usize)];
+
+pub(crate) const ICMP_ECHO_FIELDS: MessageFields = &[
+    ("id", ICMP_ID_OFFSET, 2),
+    ("sequence", ICMP_SEQNUM_OFFSET, 2),
+];
+
+/// Fails naming the first of `fields` that runs past the end of `bytes`.
+pub(crate) fn check_fields(
+    protocol: &'static str,
+    bytes: &[u8],
+    fields: MessageFields,
+) -> Result<(), ParseError> {
+    for &(field, offset, len) in fields {
+        ParseError::check_len(protocol, field, bytes, offset, len)?;
+    }
+    Ok(())
+}
+
+macro_rules! impl_icmp_id_seq {
+    ($pdu:ident) => {
+        impl<'a> $pdu<'a> {
+            pub fn id(&self) -> u16 {
+                $crate::icmp::get_icmp_u16(&self.header, $crate::icmp::ICMP_ID_OFFSET)
+            }
+
+            pub fn set_id(&mut self, id: u16) {
+                self.header.to_mut()
+                    [$crate::icmp::ICMP_ID_OFFSET..$crate::icmp::ICMP_SEQNUM_OFFSET]
+                    .copy_from_slice(&id.to_be_bytes());
+            }
+
+            pub fn with_id(&mut self, id: u16) -> &mut Self {
+                self.set_id(id);
+                self
+            }
 
-// impl<'a> DestUnreachable<'a> {
-//     pub fn new() {
-//         Self {
-//             header: Cow::Owned(),
-//         }
-//     }
-// }
+            pub fn seq_number(&self) -> u16 {
+                $crate::icmp::get_icmp_u16(&self.header, $crate::icmp::ICMP_SEQNUM_OFFSET)
+            }
+
+            pub fn set_seq_number(&mut self, seq_number: u16) {
+                self.header.to_mut()
+                    [$crate::icmp::ICMP_SEQNUM_OFFSET..$crate::icmp::ICMP_SEQNUM_OFFSET + 2]
+                    .copy_from_slice(&seq_number.to_be_bytes());
+            }
+
+            pub fn with_seq_number(&mut self, seq_number: u16) -> &mut Self {
+                self.set_seq_number(seq_number);
+                self
+            }
+        }
+    };
+}
+
+/// Shared by the echo messages, `$protocol` names the layer in errors.
+macro_rules! impl_icmp_echo {
+    ($pdu:ident, $name:literal, $protocol:expr) => {
+        impl<'a> $pdu<'a> {
+            pub fn new() -> Self {
+                Self {
+                    header: Cow::Owned(vec![0; $crate::icmp::ICMP_ECHO_LEN]),
+                    parent: None,
+                    child: None,
+                }
+            }
+        }
+
+        $crate::icmp::impl_icmp_id_seq!($pdu);
+
+        #[pdu_impl]
+        impl<'a> Pdu<'a> for $pdu<'a> {
+            fn to_bytes(&self) -> Vec<u8> {
+                self.header.to_vec()
+            }
+
+            default_pdu_clone!($pdu);
+
+            fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+                $crate::icmp::check_fields($protocol, bytes, $crate::icmp::ICMP_ECHO_FIELDS)?;
+                let data = &bytes[$crate::icmp::ICMP_ECHO_LEN..];
+                Ok(Box::new(Self {
+                    header: Cow::Borrowed(&bytes[..$crate::icmp::ICMP_ECHO_LEN]),
+                    parent: None,
+                    child: if data.is_empty() {
+                        None
+                    } else {
+                        Raw::from_bytes(data).ok()
+                    },
+                }))
+            }
+
+            fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+                Ok(json!({
+                    $name: {
+                        concat!($name, ".id"): self.id(),
+                        concat!($name, ".seq_number"): self.seq_number(),
+                        concat!($name, ".data"): self.child_to_json(),
+                    }
+                }))
+            }
+        }
+    };
+}
+
+macro_rules! impl_icmp_timestamp {
+    ($pdu:ident, $name:literal) => {
+        impl<'a> $pdu<'a> {
+            pub fn new() -> Self {
+                Self {
+                    header: Cow::Owned(vec![0; ICMP_TIMESTAMP_LEN]),
+                    parent: None,
+                    child: None,
+                }
+            }
+
+            /// Milliseconds since midnight UT.
+            pub fn originate(&self) -> u32 {
+                get_icmp_u32(&self.header, ICMP_ORIGINATE_OFFSET)
+            }
+
+            pub fn set_originate(&mut self, originate: u32) {
+                self.header.to_mut()[ICMP_ORIGINATE_OFFSET..ICMP_RECEIVE_OFFSET]
+                    .copy_from_slice(&originate.to_be_bytes());
+            }
+
+            pub fn with_originate(&mut self, originate: u32) -> &mut Self {
+                self.set_originate(originate);
+                self
+            }
+
+            pub fn receive(&self) -> u32 {
+                get_icmp_u32(&self.header, ICMP_RECEIVE_OFFSET)
+            }
+
+            pub fn set_receive(&mut self, receive: u32) {
+                self.header.to_mut()[ICMP_RECEIVE_OFFSET..ICMP_TRANSMIT_OFFSET]
+                    .copy_from_slice(&receive.to_be_bytes());
+            }
+
+            pub fn with_receive(&mut self, receive: u32) -> &mut Self {
+                self.set_receive(receive);
+                self
+            }
+
+            pub fn transmit(&self) -> u32 {
+                get_icmp_u32(&self.header, ICMP_TRANSMIT_OFFSET)
+            }
+
+            pub fn set_transmit(&mut self, transmit: u32) {
+                self.header.to_mut()[ICMP_TRANSMIT_OFFSET..ICMP_TIMESTAMP_LEN]
+                    .copy_from_slice(&transmit.to_be_bytes());
+            }
+
+            pub fn with_transmit(&mut self, transmit: u32) -> &mut Self {
+                self.set_transmit(transmit);
+                self
+            }
+        }
+
+        impl_icmp_id_seq!($pdu);
+
+        #[pdu_impl]
+        impl<'a> Pdu<'a> for $pdu<'a> {
+            fn to_bytes(&self) -> Vec<u8> {
+                self.header.to_vec()
+            }
+
+            default_pdu_clone!($pdu);
+
+            fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+                check_fields(
+                    "icmp",
+                    bytes,
+                    &[
+                        ("id", ICMP_ID_OFFSET, 2),
+                        ("sequence", ICMP_SEQNUM_OFFSET, 2),
+                        ("originate", ICMP_ORIGINATE_OFFSET, 4),
+                        ("receive", ICMP_RECEIVE_OFFSET, 4),
+                        ("transmit", ICMP_TRANSMIT_OFFSET, 4),
+                    ],
+                )?;
+                Ok(Box::new(Self {
+                    header: Cow::Borrowed(&bytes[..ICMP_TIMESTAMP_LEN]),
+                    parent: None,
+                    child: None,
+                }))
+            }
+
+            fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+                Ok(json!({
+                    $name: {
+                        concat!($name, ".id"): self.id(),
+                        concat!($name, ".seq_number"): self.seq_number(),
+                        concat!($name, ".originate"): self.originate(),
+                        concat!($name, ".receive"): self.receive(),
+                        concat!($name, ".transmit"): self.transmit(),
+                    }
+                }))
+            }
+        }
+    };
+}
+
+/// Shared by the error messages: a 4 byte header followed by the quoted
+/// datagram, dissected by `$datagram`. `$protocol` names the layer in errors
+/// and `$fields` the fields of the header.
+macro_rules! impl_icmp_error {
+    ($pdu:ident, $datagram:path, $protocol:expr, $fields:expr) => {
+        impl<'a> $pdu<'a> {
+            pub fn new() -> Self {
+                Self {
+                    header: Cow::Owned(vec![0; $crate::icmp::ICMP_ERROR_LEN]),
+                    parent: None,
+                    child: None,
+                }
+            }
+        }
+
+        #[pdu_impl]
+        impl<'a> Pdu<'a> for $pdu<'a> {
+            fn to_bytes(&self) -> Vec<u8> {
+                self.header.to_vec()
+            }
+
+            default_pdu_clone!($pdu);
+
+            fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+                $crate::icmp::check_fields($protocol, bytes, $fields)?;
+                Ok(Box::new(Self {
+                    header: Cow::Borrowed(&bytes[..$crate::icmp::ICMP_ERROR_LEN]),
+                    parent: None,
+                    child: $datagram(&bytes[$crate::icmp::ICMP_ERROR_LEN..]),
+                }))
+            }
+
+            fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+                self.json_fields()
+            }
+        }
+    };
+}
+
+macro_rules! impl_icmp_orig_length {
+    ($pdu:ident) => {
+        impl<'a> $pdu<'a> {
+            /// Length of the quoted datagram in 32 bit words, as defined by
+            /// RFC 4884. Zero when no extension structure follows.
+            pub fn orig_length(&self) -> u8 {
+                self.header[ICMP_ERROR_LENGTH_OFFSET]
+            }
+
+            pub fn set_orig_length(&mut self, length: u8) {
+                self.header.to_mut()[ICMP_ERROR_LENGTH_OFFSET] = length;
+            }
+
+            pub fn with_orig_length(&mut self, length: u8) -> &mut Self {
+                self.set_orig_length(length);
+                self
+            }
+        }
+    };
+}
+
+pub(crate) use {impl_icmp_echo, impl_icmp_error, impl_icmp_id_seq};
 
 #[pdu_type]
-pub struct RedirectMessage<'a> {}
+pub struct EchoReply<'a> {}
+
+impl_icmp_echo!(EchoReply, "echo_reply", "icmp");
 
 #[pdu_type]
 pub struct EchoRequest<'a> {}
 
+impl_icmp_echo!(EchoRequest, "echo_request", "icmp");
+
 #[pdu_type]
-pub struct RouterAdvertisement<'a> {}
+pub struct DestUnreachable<'a> {}
+
+impl_icmp_error!(
+    DestUnreachable,
+    original_datagram,
+    "icmp",
+    &[
+        ("unused", 0, 1),
+        ("length", ICMP_ERROR_LENGTH_OFFSET, 1),
+        ("next-hop mtu", ICMP_NEXT_HOP_MTU_OFFSET, 2),
+    ]
+);
+impl_icmp_orig_length!(DestUnreachable);
+
+impl<'a> DestUnreachable<'a> {
+    /// Only meaningful with code `UNREACH_NEEDFRAG`.
+    /// Refer to <https://datatracker.ietf.org/doc/html/rfc1191#section-4>
+    pub fn next_hop_mtu(&self) -> u16 {
+        get_icmp_u16(&self.header, ICMP_NEXT_HOP_MTU_OFFSET)
+    }
+
+    pub fn set_next_hop_mtu(&mut self, mtu: u16) {
+        self.header.to_mut()[ICMP_NEXT_HOP_MTU_OFFSET..ICMP_ERROR_LEN]
+            .copy_from_slice(&mtu.to_be_bytes());
+    }
+
+    pub fn with_next_hop_mtu(&mut self, mtu: u16) -> &mut Self {
+        self.set_next_hop_mtu(mtu);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "dest_unreachable": {
+                "dest_unreachable.length": self.orig_length(),
+                "dest_unreachable.mtu": self.next_hop_mtu(),
+                "dest_unreachable.data": self.child_to_json(),
+            }
+        }))
+    }
+}
 
 #[pdu_type]
-pub struct RouterSolicitation<'a> {}
+pub struct RedirectMessage<'a> {}
+
+impl_icmp_error!(
+    RedirectMessage,
+    original_datagram,
+    "icmp",
+    &[("gateway", ICMP_GATEWAY_OFFSET, 4)]
+);
+
+impl<'a> RedirectMessage<'a> {
+    pub fn gateway(&self) -> Ipv4Addr {
+        Ipv4Addr::from_bits(get_icmp_u32(&self.header, ICMP_GATEWAY_OFFSET))
+    }
+
+    pub fn set_gateway(&mut self, gateway: Ipv4Addr) {
+        self.header.to_mut()[ICMP_GATEWAY_OFFSET..ICMP_ERROR_LEN]
+            .copy_from_slice(&gateway.octets());
+    }
+
+    pub fn with_gateway(&mut self, gateway: Ipv4Addr) -> &mut Self {
+        self.set_gateway(gateway);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "redirect": {
+                "redirect.gateway": self.gateway().to_string(),
+                "redirect.data": self.child_to_json(),
+            }
+        }))
+    }
+}
 
 #[pdu_type]
 pub struct TimeExceeded<'a> {}
 
+impl_icmp_error!(
+    TimeExceeded,
+    original_datagram,
+    "icmp",
+    &[
+        ("unused", 0, 1),
+        ("length", ICMP_ERROR_LENGTH_OFFSET, 1),
+        ("unused", 2, 2),
+    ]
+);
+impl_icmp_orig_length!(TimeExceeded);
+
+impl<'a> TimeExceeded<'a> {
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "time_exceeded": {
+                "time_exceeded.length": self.orig_length(),
+                "time_exceeded.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
 #[pdu_type]
 pub struct ParameterProblem<'a> {}
 
+impl_icmp_error!(
+    ParameterProblem,
+    original_datagram,
+    "icmp",
+    &[
+        ("pointer", ICMP_PARAM_POINTER_OFFSET, 1),
+        ("length", ICMP_ERROR_LENGTH_OFFSET, 1),
+        ("unused", 2, 2),
+    ]
+);
+impl_icmp_orig_length!(ParameterProblem);
+
+impl<'a> ParameterProblem<'a> {
+    /// Offset of the offending byte in the quoted datagram.
+    pub fn pointer(&self) -> u8 {
+        self.header[ICMP_PARAM_POINTER_OFFSET]
+    }
+
+    pub fn set_pointer(&mut self, pointer: u8) {
+        self.header.to_mut()[ICMP_PARAM_POINTER_OFFSET] = pointer;
+    }
+
+    pub fn with_pointer(&mut self, pointer: u8) -> &mut Self {
+        self.set_pointer(pointer);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "parameter_problem": {
+                "parameter_problem.pointer": self.pointer(),
+                "parameter_problem.length": self.orig_length(),
+                "parameter_problem.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
 #[pdu_type]
 pub struct Timestamp<'a> {}
 
+impl_icmp_timestamp!(Timestamp, "timestamp");
+
 #[pdu_type]
 pub struct TimestampReply<'a> {}
 
+impl_icmp_timestamp!(TimestampReply, "timestamp_reply");
+
+#[pdu_type]
+pub struct RouterAdvertisement<'a> {}
+
+#[pdu_type]
+pub struct RouterSolicitation<'a> {}
+
 #[pdu_type]
 pub struct ExtEchoRequest<'a> {}
 
 #[pdu_type]
 pub struct ExtEchoReply<'a> {}
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct IcmpType(pub u8);
 
 pub static ICMP_DISSECTION_TABLE: DissectionTable<IcmpType> = create_table();
 
-// register_pdu!(IcmpType(3), DestUnreachable, ICMP_DISSECTION_TABLE);
-// register_pdu!(IcmpType(5), RedirectMessage, ICMP_DISSECTION_TABLE);
+register_pdu!(IcmpType(0), EchoReply, ICMP_DISSECTION_TABLE);
+register_pdu!(IcmpType(3), DestUnreachable, ICMP_DISSECTION_TABLE);
+register_pdu!(IcmpType(5), RedirectMessage, ICMP_DISSECTION_TABLE);
+register_pdu!(IcmpType(8), EchoRequest, ICMP_DISSECTION_TABLE);
+register_pdu!(IcmpType(11), TimeExceeded, ICMP_DISSECTION_TABLE);
+register_pdu!(IcmpType(12), ParameterProblem, ICMP_DISSECTION_TABLE);
+register_pdu!(IcmpType(13), Timestamp, ICMP_DISSECTION_TABLE);
+register_pdu!(IcmpType(14), TimestampReply, ICMP_DISSECTION_TABLE);
+
+register_fields!(EchoReply, "echo_reply", {
+    "echo_reply.id" => id { name: "Identifier", bits: (ICMP_ID_OFFSET * 8, 16), base: HexDec },
+    "echo_reply.seq_number" => seq_number { name: "Sequence Number", bits: (ICMP_SEQNUM_OFFSET * 8, 16) },
+});
+
+register_fields!(EchoRequest, "echo_request", {
+    "echo_request.id" => id { name: "Identifier", bits: (ICMP_ID_OFFSET * 8, 16), base: HexDec },
+    "echo_request.seq_number" => seq_number { name: "Sequence Number", bits: (ICMP_SEQNUM_OFFSET * 8, 16) },
+});
+
+register_fields!(DestUnreachable, "dest_unreachable", {
+    "dest_unreachable.length" => orig_length { name: "Length", bits: (ICMP_ERROR_LENGTH_OFFSET * 8, 8) },
+    "dest_unreachable.mtu" => next_hop_mtu { name: "Next-Hop MTU", bits: (ICMP_NEXT_HOP_MTU_OFFSET * 8, 16) },
+});
+
+register_fields!(RedirectMessage, "redirect", {
+    "redirect.gateway" => gateway { name: "Gateway Address", bits: (ICMP_GATEWAY_OFFSET * 8, 32) },
+});
+
+register_fields!(TimeExceeded, "time_exceeded", {
+    "time_exceeded.length" => orig_length { name: "Length", bits: (ICMP_ERROR_LENGTH_OFFSET * 8, 8) },
+});
 
-#[derive(Clone)]
+register_fields!(ParameterProblem, "parameter_problem", {
+    "parameter_problem.pointer" => pointer { name: "Pointer", bits: (ICMP_PARAM_POINTER_OFFSET * 8, 8) },
+    "parameter_problem.length" => orig_length { name: "Length", bits: (ICMP_ERROR_LENGTH_OFFSET * 8, 8) },
+});
+
+register_fields!(Timestamp, "timestamp", {
+    "timestamp.id" => id { name: "Identifier", bits: (ICMP_ID_OFFSET * 8, 16), base: HexDec },
+    "timestamp.seq_number" => seq_number { name: "Sequence Number", bits: (ICMP_SEQNUM_OFFSET * 8, 16) },
+    "timestamp.originate" => originate { name: "Originate Timestamp", bits: (ICMP_ORIGINATE_OFFSET * 8, 32) },
+    "timestamp.receive" => receive { name: "Receive Timestamp", bits: (ICMP_RECEIVE_OFFSET * 8, 32) },
+    "timestamp.transmit" => transmit { name: "Transmit Timestamp", bits: (ICMP_TRANSMIT_OFFSET * 8, 32) },
+});
+
+register_fields!(TimestampReply, "timestamp_reply", {
+    "timestamp_reply.id" => id { name: "Identifier", bits: (ICMP_ID_OFFSET * 8, 16), base: HexDec },
+    "timestamp_reply.seq_number" => seq_number { name: "Sequence Number", bits: (ICMP_SEQNUM_OFFSET * 8, 16) },
+    "timestamp_reply.originate" => originate {
+        name: "Originate Timestamp",
+        bits: (ICMP_ORIGINATE_OFFSET * 8, 32),
+    },
+    "timestamp_reply.receive" => receive { name: "Receive Timestamp", bits: (ICMP_RECEIVE_OFFSET * 8, 32) },
+    "timestamp_reply.transmit" => transmit { name: "Transmit Timestamp", bits: (ICMP_TRANSMIT_OFFSET * 8, 32) },
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ControlMessage {
     EchoReply = 0,
     EchoRequest = 8,
@@ -164,22 +573,80 @@ pub enum ControlMessage {
     Undefined,
 }
 
-const ICMP_MIN_SIZE: usize = 4;
+impl From<u8> for ControlMessage {
+    fn from(msg_type: u8) -> Self {
+        match msg_type {
+            0 => ControlMessage::EchoReply,
+            3 => ControlMessage::DestUnreachable,
+            5 => ControlMessage::RedirectMessage,
+            8 => ControlMessage::EchoRequest,
+            9 => ControlMessage::RouterAdvertisement,
+            10 => ControlMessage::RouterSolicitation,
+            11 => ControlMessage::TimeExceeded,
+            12 => ControlMessage::ParameterProblem,
+            13 => ControlMessage::Timestamp,
+            14 => ControlMessage::TimestampReply,
+            42 => ControlMessage::ExtEchoRequest,
+            43 => ControlMessage::ExtEchoReply,
+            _ => ControlMessage::Undefined,
+        }
+    }
+}
 
 #[pdu_type]
-pub struct Icmp<'a> {
-    pub msg_type: ControlMessage,
-    pub msg_body: Pob<'a>,
-    header_len: usize,
-}
+pub struct Icmp<'a> {}
 
 impl<'a> Icmp<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; ICMP_MIN_SIZE]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn control_message(&self) -> ControlMessage {
+        ControlMessage::from(self.msg_type())
+    }
+
     pub fn msg_type(&self) -> u8 {
-        0
+        self.header[ICMP_TYPE_OFFSET]
+    }
+
+    pub fn set_msg_type(&mut self, msg_type: u8) {
+        self.header.to_mut()[ICMP_TYPE_OFFSET] = msg_type;
+    }
+
+    pub fn with_msg_type(&mut self, msg_type: u8) -> &mut Self {
+        self.set_msg_type(msg_type);
+        self
     }
 
     pub fn msg_code(&self) -> u8 {
-        0
+        self.header[ICMP_CODE_OFFSET]
+    }
+
+    pub fn set_msg_code(&mut self, msg_code: u8) {
+        self.header.to_mut()[ICMP_CODE_OFFSET] = msg_code;
+    }
+
+    pub fn with_msg_code(&mut self, msg_code: u8) -> &mut Self {
+        self.set_msg_code(msg_code);
+        self
+    }
+
+    pub fn checksum(&self) -> u16 {
+        get_icmp_u16(&self.header, ICMP_CHECKSUM_OFFSET)
+    }
+
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.header.to_mut()[ICMP_CHECKSUM_OFFSET..ICMP_MIN_SIZE]
+            .copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    pub fn with_checksum(&mut self, checksum: u16) -> &mut Self {
+        self.set_checksum(checksum);
+        self
     }
 }
 
@@ -191,34 +658,188 @@ impl<'a> Pdu<'a> for Icmp<'a> {
         res
     }
 
-    fn clone(&self) -> Box<dyn Pdu<'static>> {
-        Box::new(Icmp {
-            header: Cow::Owned(self.header.to_vec()),
-            child: None,
-            parent: None,
-            msg_type: self.msg_type.clone(),
-            msg_body: None,
-            header_len: self.header_len,
-        })
-    }
+    default_pdu_clone!(Icmp);
 
     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
-        // TODO: parse message type
+        ParseError::check_len("icmp", "header length", bytes, 0, ICMP_MIN_SIZE)?;
+
+        let body = &bytes[ICMP_MIN_SIZE..];
+        let child = if body.is_empty() {
+            None
+        } else {
+            Some(build_from_table(
+                &ICMP_DISSECTION_TABLE,
+                IcmpType(bytes[ICMP_TYPE_OFFSET]),
+                body,
+            ))
+        };
+
         Ok(Box::new(Self {
             header: Cow::Borrowed(&bytes[..ICMP_MIN_SIZE]),
-            msg_type: ControlMessage::Undefined,
-            msg_body: None,
-            header_len: ICMP_MIN_SIZE,
             parent: None,
-            child: None,
+            child,
         }))
     }
 
     fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
         Ok(json!({
             "icmp": {
+                "icmp.type": self.msg_type(),
+                "icmp.code": self.msg_code(),
+                "icmp.checksum": self.checksum(),
                 "icmp.data": self.child_to_json(),
             }
         }))
     }
 }
+
+register_pdu!(Ipv4Type(1), Icmp, IPV4_DISSECTION_TABLE);
+
+const ICMP_TYPE_NAMES: ValueStrings = &[
+    (0, "Echo Reply"),
+    (3, "Destination Unreachable"),
+    (4, "Source Quench"),
+    (5, "Redirect"),
+    (8, "Echo Request"),
+    (9, "Router Advertisement"),
+    (10, "Router Solicitation"),
+    (11, "Time Exceeded"),
+    (12, "Parameter Problem"),
+    (13, "Timestamp"),
+    (14, "Timestamp Reply"),
+];
+
+register_fields!(Icmp, "icmp", {
+    "icmp.type" => msg_type {
+        name: "Type",
+        bits: (ICMP_TYPE_OFFSET * 8, 8),
+        values: ICMP_TYPE_NAMES,
+    },
+    "icmp.code" => msg_code { name: "Code", bits: (ICMP_CODE_OFFSET * 8, 8) },
+    "icmp.checksum" => checksum { name: "Checksum", bits: (ICMP_CHECKSUM_OFFSET * 8, 16), base: Hex },
+});
+
+#[cfg(test)]
+mod tests {
+    use crate::tcp::Tcp;
+    use crate::udp::Udp;
+
+    use super::*;
+
+    const ICMP_ECHO_REQUEST: [u8; 12] = [
+        0x08, 0x00, 0x19, 0x2c, // Type 8, code 0, checksum
+        0x00, 0x01, 0x00, 0x02, // Id 1, sequence 2
+        b'p', b'i', b'n', b'g',
+    ];
+
+    const ICMP_PORT_UNREACHABLE: [u8; 36] = [
+        0x03, 0x03, 0x00, 0x00, // Type 3, code 3 (port), checksum
+        0x00, 0x00, 0x00, 0x00, // Unused, length, next hop MTU
+        // Quoted IPv4 header
+        0x45, 0x00, 0x00, 0x25, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0x00, 0x02,
+        0x01, 0xc6, 0x33, 0x64, 0x02, // First 8 bytes of the UDP datagram
+        0x30, 0x39, 0x00, 0x35, 0x00, 0x11, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_echo_request() {
+        let icmp = Icmp::from_bytes(&ICMP_ECHO_REQUEST).unwrap();
+        let echo = icmp.find::<EchoRequest>().unwrap();
+        assert!(echo.id() == 1);
+        assert!(echo.seq_number() == 2);
+        assert!(icmp.find::<Raw>().unwrap().to_bytes() == b"ping");
+
+        let json = icmp.to_json().unwrap();
+        assert!(json["icmp"]["icmp.type"] == 8);
+        assert!(json["icmp"]["icmp.data"]["echo_request"]["echo_request.seq_number"] == 2);
+    }
+
+    #[test]
+    fn test_echo_reply() {
+        let mut bytes = ICMP_ECHO_REQUEST;
+        bytes[0] = 0;
+        let icmp = Icmp::from_bytes(&bytes).unwrap();
+        assert!(icmp.find::<EchoReply>().unwrap().id() == 1);
+        assert!(icmp.find::<EchoRequest>().is_none());
+    }
+
+    #[test]
+    fn test_dest_unreachable() {
+        let icmp = Icmp::from_bytes(&ICMP_PORT_UNREACHABLE).unwrap();
+        assert!(icmp.find::<DestUnreachable>().is_some());
+
+        let ip = icmp.find::<Ip>().unwrap();
+        assert!(ip.src_addr() == Ipv4Addr::new(192, 0, 2, 1));
+        assert!(ip.protocol() == 17);
+        let udp = icmp.find::<Udp>().unwrap();
+        assert!(udp.dst_port() == 53);
+    }
+
+    #[test]
+    fn test_time_exceeded_truncated_tcp() {
+        let mut bytes = ICMP_PORT_UNREACHABLE;
+        bytes[0] = 11;
+        bytes[1] = TIMXCEED_INTRANS;
+        bytes[8 + 9] = 6;
+        let icmp = Icmp::from_bytes(&bytes).unwrap();
+        assert!(icmp.find::<TimeExceeded>().is_some());
+        assert!(icmp.find::<Ip>().is_some());
+        // Only 8 bytes of the TCP header are quoted.
+        assert!(icmp.find::<Tcp>().is_none());
+        assert!(icmp.find::<Raw>().unwrap().to_bytes() == bytes[28..]);
+    }
+
+    #[test]
+    fn test_redirect_and_parameter_problem() {
+        let mut bytes = ICMP_PORT_UNREACHABLE;
+        bytes[0] = 5;
+        bytes[4..8].copy_from_slice(&[10, 0, 0, 1]);
+        let icmp = Icmp::from_bytes(&bytes).unwrap();
+        let redirect = icmp.find::<RedirectMessage>().unwrap();
+        assert!(redirect.gateway() == Ipv4Addr::new(10, 0, 0, 1));
+
+        let mut problem_bytes = bytes;
+        problem_bytes[0] = 12;
+        let icmp = Icmp::from_bytes(&problem_bytes).unwrap();
+        let problem = icmp.find::<ParameterProblem>().unwrap();
+        assert!(problem.pointer() == 10);
+        assert!(problem.orig_length() == 0);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let bytes = [
+            0x0e, 0x00, 0x00, 0x00, 0x00, 0x07, 0x00, 0x01, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00,
+            0x00, 0x0b, 0x00, 0x00, 0x00, 0x0c,
+        ];
+        let icmp = Icmp::from_bytes(&bytes).unwrap();
+        let reply = icmp.find::<TimestampReply>().unwrap();
+        assert!(reply.id() == 7);
+        assert!(reply.originate() == 10);
+        assert!(reply.receive() == 11);
+        assert!(reply.transmit() == 12);
+
+        let mut ts = Timestamp::new();
+        ts.with_id(1).with_seq_number(2).with_transmit(3);
+        assert!(ts.to_bytes() == [0, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(Icmp::from_bytes(&[0x08, 0x00, 0x00]).is_err());
+        // A truncated body falls back to raw bytes.
+        let icmp = Icmp::from_bytes(&[0x08, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        assert!(icmp.find::<EchoRequest>().is_none());
+        assert!(icmp.find::<Raw>().is_some());
+        assert!(
+            icmp.parse_error().unwrap().to_string()
+                == "icmp: id 2 exceeds remaining 1 bytes at offset 4"
+        );
+
+        let icmp = Icmp::from_bytes(&[0x05, 0x00, 0x00, 0x00, 0xc0, 0x00]).unwrap();
+        assert!(
+            icmp.parse_error().unwrap().to_string()
+                == "icmp: gateway 4 exceeds remaining 2 bytes at offset 4"
+        );
+    }
+}
@@ -0,0 +1,1423 @@
+use crate::checksum::{checksum_add, checksum_finish, ipv6_pseudo_header_sum};
+use crate::icmp::{
+    ICMP_ID_OFFSET, ICMP_SEQNUM_OFFSET, MessageFields, check_fields, get_icmp_u16, impl_icmp_echo,
+    impl_icmp_error,
+};
+use crate::ip6::{IPV6_DISSECTION_TABLE, Ipv6, Ipv6Type};
+use crate::ndp_opt::{NdpOption, ndp_options_to_bytes, parse_ndp_options};
+use crate::prelude::*;
+
+use std::net::Ipv6Addr;
+
+pub const ICMPV6: u8 = 58;
+
+const PROTOCOL: &str = "icmpv6";
+
+const ICMPV6_TYPE_OFFSET: usize = 0;
+const ICMPV6_CODE_OFFSET: usize = 1;
+pub(crate) const ICMPV6_CHECKSUM_OFFSET: usize = 2;
+const ICMPV6_HEADER_LEN: usize = 4;
+
+const ICMPV6_LENGTH_OFFSET: usize = 0;
+const ICMPV6_MTU_OFFSET: usize = 0;
+const ICMPV6_POINTER_OFFSET: usize = 0;
+
+const ND_RS_LEN: usize = 4;
+const ND_RA_HOP_LIMIT_OFFSET: usize = 0;
+const ND_RA_FLAGS_OFFSET: usize = 1;
+const ND_RA_LIFETIME_OFFSET: usize = 2;
+const ND_RA_REACHABLE_OFFSET: usize = 4;
+const ND_RA_RETRANS_OFFSET: usize = 8;
+const ND_RA_LEN: usize = 12;
+const ND_FLAGS_OFFSET: usize = 0;
+const ND_TARGET_OFFSET: usize = 4;
+const ND_NS_LEN: usize = 20;
+const ND_NA_LEN: usize = 20;
+const ND_DEST_OFFSET: usize = 20;
+const ND_REDIRECT_LEN: usize = 36;
+
+const ND_RA_FLAG_MANAGED: u8 = 0x80;
+const ND_RA_FLAG_OTHER: u8 = 0x40;
+const ND_NA_FLAG_ROUTER: u8 = 0x80;
+const ND_NA_FLAG_SOLICITED: u8 = 0x40;
+const ND_NA_FLAG_OVERRIDE: u8 = 0x20;
+
+const MLD_MAX_RESP_OFFSET: usize = 0;
+const MLD_ADDR_OFFSET: usize = 4;
+const MLD_LEN: usize = 20;
+const MLDV2_QUERY_FLAGS_OFFSET: usize = 20;
+const MLDV2_QUERY_QQIC_OFFSET: usize = 21;
+const MLDV2_QUERY_NSRC_OFFSET: usize = 22;
+const MLDV2_QUERY_LEN: usize = 24;
+const MLDV2_REPORT_NREC_OFFSET: usize = 2;
+const MLDV2_REPORT_LEN: usize = 4;
+const MLDV2_RECORD_LEN: usize = 20;
+
+const MLD_FIELDS: MessageFields = &[
+    ("max response delay", MLD_MAX_RESP_OFFSET, 2),
+    ("reserved", 2, 2),
+    ("multicast address", MLD_ADDR_OFFSET, 16),
+];
+
+/// MLDv2 multicast address record types
+pub const MODE_IS_INCLUDE: u8 = 1;
+pub const MODE_IS_EXCLUDE: u8 = 2;
+pub const CHANGE_TO_INCLUDE_MODE: u8 = 3;
+pub const CHANGE_TO_EXCLUDE_MODE: u8 = 4;
+pub const ALLOW_NEW_SOURCES: u8 = 5;
+pub const BLOCK_OLD_SOURCES: u8 = 6;
+
+fn get_icmpv6_u32(bytes: &[u8], offset: usize) -> u32 {
+    parse_bytes::<u32>(&bytes[offset..offset + 4], Endian::Big)
+}
+
+fn get_ipv6_addr(bytes: &[u8], offset: usize) -> Ipv6Addr {
+    Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[offset..offset + 16]).unwrap())
+}
+
+fn get_ipv6_addrs(
+    protocol: &'static str,
+    bytes: &[u8],
+    offset: usize,
+    count: usize,
+) -> Result<Vec<Ipv6Addr>, ParseError> {
+    ParseError::check_len(protocol, "sources", bytes, offset, count * 16)?;
+    Ok(bytes[offset..]
+        .chunks_exact(16)
+        .take(count)
+        .map(|addr| get_ipv6_addr(addr, 0))
+        .collect())
+}
+
+/// Error messages quote as much of the offending packet as fits in the
+/// minimum MTU. Falls back to `Raw` when the quote can't be parsed.
+fn original_datagram(bytes: &[u8]) -> Pob<'_> {
+    if bytes.is_empty() {
+        return None;
+    }
+    Some(Raw::or_malformed(Ipv6::from_bytes(bytes), bytes))
+}
+
+#[pdu_type]
+pub struct EchoRequest<'a> {}
+
+impl_icmp_echo!(EchoRequest, "icmpv6.echo_request", "icmpv6");
+
+#[pdu_type]
+pub struct EchoReply<'a> {}
+
+impl_icmp_echo!(EchoReply, "icmpv6.echo_reply", "icmpv6");
+
+#[pdu_type]
+pub struct DestUnreachable<'a> {}
+
+impl_icmp_error!(
+    DestUnreachable,
+    original_datagram,
+    "icmpv6",
+    &[("length", ICMPV6_LENGTH_OFFSET, 1), ("unused", 1, 3)]
+);
+
+impl<'a> DestUnreachable<'a> {
+    /// Length of the quoted packet in 64 bit words, as defined by RFC 4884.
+    pub fn orig_length(&self) -> u8 {
+        self.header[ICMPV6_LENGTH_OFFSET]
+    }
+
+    pub fn set_orig_length(&mut self, length: u8) {
+        self.header.to_mut()[ICMPV6_LENGTH_OFFSET] = length;
+    }
+
+    pub fn with_orig_length(&mut self, length: u8) -> &mut Self {
+        self.set_orig_length(length);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "icmpv6.dest_unreachable": {
+                "icmpv6.dest_unreachable.length": self.orig_length(),
+                "icmpv6.dest_unreachable.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+#[pdu_type]
+pub struct PacketTooBig<'a> {}
+
+impl_icmp_error!(
+    PacketTooBig,
+    original_datagram,
+    "icmpv6",
+    &[("mtu", ICMPV6_MTU_OFFSET, 4)]
+);
+
+impl<'a> PacketTooBig<'a> {
+    pub fn mtu(&self) -> u32 {
+        get_icmpv6_u32(&self.header, ICMPV6_MTU_OFFSET)
+    }
+
+    pub fn set_mtu(&mut self, mtu: u32) {
+        self.header.to_mut()[ICMPV6_MTU_OFFSET..ICMPV6_MTU_OFFSET + 4]
+            .copy_from_slice(&mtu.to_be_bytes());
+    }
+
+    pub fn with_mtu(&mut self, mtu: u32) -> &mut Self {
+        self.set_mtu(mtu);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "icmpv6.packet_too_big": {
+                "icmpv6.packet_too_big.mtu": self.mtu(),
+                "icmpv6.packet_too_big.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+#[pdu_type]
+pub struct TimeExceeded<'a> {}
+
+impl_icmp_error!(
+    TimeExceeded,
+    original_datagram,
+    "icmpv6",
+    &[("length", ICMPV6_LENGTH_OFFSET, 1), ("unused", 1, 3)]
+);
+
+impl<'a> TimeExceeded<'a> {
+    /// Length of the quoted packet in 64 bit words, as defined by RFC 4884.
+    pub fn orig_length(&self) -> u8 {
+        self.header[ICMPV6_LENGTH_OFFSET]
+    }
+
+    pub fn set_orig_length(&mut self, length: u8) {
+        self.header.to_mut()[ICMPV6_LENGTH_OFFSET] = length;
+    }
+
+    pub fn with_orig_length(&mut self, length: u8) -> &mut Self {
+        self.set_orig_length(length);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "icmpv6.time_exceeded": {
+                "icmpv6.time_exceeded.length": self.orig_length(),
+                "icmpv6.time_exceeded.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+#[pdu_type]
+pub struct ParameterProblem<'a> {}
+
+impl_icmp_error!(
+    ParameterProblem,
+    original_datagram,
+    "icmpv6",
+    &[("pointer", ICMPV6_POINTER_OFFSET, 4)]
+);
+
+impl<'a> ParameterProblem<'a> {
+    /// Offset of the offending byte in the quoted packet.
+    pub fn pointer(&self) -> u32 {
+        get_icmpv6_u32(&self.header, ICMPV6_POINTER_OFFSET)
+    }
+
+    pub fn set_pointer(&mut self, pointer: u32) {
+        self.header.to_mut()[ICMPV6_POINTER_OFFSET..ICMPV6_POINTER_OFFSET + 4]
+            .copy_from_slice(&pointer.to_be_bytes());
+    }
+
+    pub fn with_pointer(&mut self, pointer: u32) -> &mut Self {
+        self.set_pointer(pointer);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "icmpv6.parameter_problem": {
+                "icmpv6.parameter_problem.pointer": self.pointer(),
+                "icmpv6.parameter_problem.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+/// Neighbor Discovery messages: a fixed part followed by TLV options, all of
+/// which is kept in the header.
+macro_rules! impl_ndp_message {
+    ($pdu:ident, $fixed_len:expr, $fields:expr) => {
+        impl<'a> $pdu<'a> {
+            pub fn new() -> Self {
+                Self {
+                    header: Cow::Owned(vec![0; $fixed_len]),
+                    parent: None,
+                    child: None,
+                }
+            }
+
+            pub fn options_bytes(&self) -> &[u8] {
+                &self.header[$fixed_len..]
+            }
+
+            pub fn options(&self) -> Result<Vec<NdpOption>, ParseError> {
+                parse_ndp_options(self.options_bytes())
+                    .map_err(|err| err.within(PROTOCOL, $fixed_len))
+            }
+
+            pub fn set_options(&mut self, opts: &[NdpOption]) {
+                let header = self.header.to_mut();
+                header.truncate($fixed_len);
+                header.extend_from_slice(&ndp_options_to_bytes(opts));
+            }
+
+            pub fn with_options(&mut self, opts: &[NdpOption]) -> &mut Self {
+                self.set_options(opts);
+                self
+            }
+
+            pub fn add_option(&mut self, opt: NdpOption) {
+                self.header.to_mut().extend_from_slice(&opt.to_bytes());
+            }
+
+            pub fn with_option(&mut self, opt: NdpOption) -> &mut Self {
+                self.add_option(opt);
+                self
+            }
+
+            fn options_json(&self) -> serde_json::Value {
+                match self.options() {
+                    Ok(opts) => json!(opts.iter().map(|opt| opt.to_json()).collect::<Vec<_>>()),
+                    Err(_) => json!(printable_ascii(self.options_bytes())),
+                }
+            }
+        }
+
+        #[pdu_impl]
+        impl<'a> Pdu<'a> for $pdu<'a> {
+            fn to_bytes(&self) -> Vec<u8> {
+                self.header.to_vec()
+            }
+
+            default_pdu_clone!($pdu);
+
+            fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+                check_fields(PROTOCOL, bytes, $fields)?;
+                parse_ndp_options(&bytes[$fixed_len..])
+                    .map_err(|err| err.within(PROTOCOL, $fixed_len))?;
+                Ok(Box::new(Self {
+                    header: Cow::Borrowed(bytes),
+                    parent: None,
+                    child: None,
+                }))
+            }
+
+            fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+                self.json_fields()
+            }
+        }
+    };
+}
+
+macro_rules! impl_nd_target {
+    ($pdu:ident) => {
+        impl<'a> $pdu<'a> {
+            pub fn target_addr(&self) -> Ipv6Addr {
+                get_ipv6_addr(&self.header, ND_TARGET_OFFSET)
+            }
+
+            pub fn set_target_addr(&mut self, target_addr: Ipv6Addr) {
+                self.header.to_mut()[ND_TARGET_OFFSET..ND_TARGET_OFFSET + 16]
+                    .copy_from_slice(&target_addr.octets());
+            }
+
+            pub fn with_target_addr(&mut self, target_addr: Ipv6Addr) -> &mut Self {
+                self.set_target_addr(target_addr);
+                self
+            }
+        }
+    };
+}
+
+#[pdu_type]
+pub struct RouterSolicitation<'a> {}
+
+impl_ndp_message!(RouterSolicitation, ND_RS_LEN, &[("reserved", 0, 4)]);
+
+impl<'a> RouterSolicitation<'a> {
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "nd.rs": {
+                "nd.rs.opts": self.options_json(),
+            }
+        }))
+    }
+}
+
+#[pdu_type]
+pub struct RouterAdvertisement<'a> {}
+
+impl_ndp_message!(
+    RouterAdvertisement,
+    ND_RA_LEN,
+    &[
+        ("hop limit", ND_RA_HOP_LIMIT_OFFSET, 1),
+        ("flags", ND_RA_FLAGS_OFFSET, 1),
+        ("router lifetime", ND_RA_LIFETIME_OFFSET, 2),
+        ("reachable time", ND_RA_REACHABLE_OFFSET, 4),
+        ("retrans timer", ND_RA_RETRANS_OFFSET, 4),
+    ]
+);
+
+impl<'a> RouterAdvertisement<'a> {
+    pub fn cur_hop_limit(&self) -> u8 {
+        self.header[ND_RA_HOP_LIMIT_OFFSET]
+    }
+
+    pub fn set_cur_hop_limit(&mut self, hop_limit: u8) {
+        self.header.to_mut()[ND_RA_HOP_LIMIT_OFFSET] = hop_limit;
+    }
+
+    pub fn with_cur_hop_limit(&mut self, hop_limit: u8) -> &mut Self {
+        self.set_cur_hop_limit(hop_limit);
+        self
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.header[ND_RA_FLAGS_OFFSET]
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.header.to_mut()[ND_RA_FLAGS_OFFSET] = flags;
+    }
+
+    pub fn with_flags(&mut self, flags: u8) -> &mut Self {
+        self.set_flags(flags);
+        self
+    }
+
+    pub fn managed(&self) -> bool {
+        self.flags() & ND_RA_FLAG_MANAGED != 0
+    }
+
+    pub fn other_config(&self) -> bool {
+        self.flags() & ND_RA_FLAG_OTHER != 0
+    }
+
+    /// Router lifetime in seconds.
+    pub fn router_lifetime(&self) -> u16 {
+        get_icmp_u16(&self.header, ND_RA_LIFETIME_OFFSET)
+    }
+
+    pub fn set_router_lifetime(&mut self, lifetime: u16) {
+        self.header.to_mut()[ND_RA_LIFETIME_OFFSET..ND_RA_REACHABLE_OFFSET]
+            .copy_from_slice(&lifetime.to_be_bytes());
+    }
+
+    pub fn with_router_lifetime(&mut self, lifetime: u16) -> &mut Self {
+        self.set_router_lifetime(lifetime);
+        self
+    }
+
+    /// Reachable time in milliseconds.
+    pub fn reachable_time(&self) -> u32 {
+        get_icmpv6_u32(&self.header, ND_RA_REACHABLE_OFFSET)
+    }
+
+    pub fn set_reachable_time(&mut self, reachable_time: u32) {
+        self.header.to_mut()[ND_RA_REACHABLE_OFFSET..ND_RA_RETRANS_OFFSET]
+            .copy_from_slice(&reachable_time.to_be_bytes());
+    }
+
+    pub fn with_reachable_time(&mut self, reachable_time: u32) -> &mut Self {
+        self.set_reachable_time(reachable_time);
+        self
+    }
+
+    /// Retransmission timer in milliseconds.
+    pub fn retrans_timer(&self) -> u32 {
+        get_icmpv6_u32(&self.header, ND_RA_RETRANS_OFFSET)
+    }
+
+    pub fn set_retrans_timer(&mut self, retrans_timer: u32) {
+        self.header.to_mut()[ND_RA_RETRANS_OFFSET..ND_RA_LEN]
+            .copy_from_slice(&retrans_timer.to_be_bytes());
+    }
+
+    pub fn with_retrans_timer(&mut self, retrans_timer: u32) -> &mut Self {
+        self.set_retrans_timer(retrans_timer);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "nd.ra": {
+                "nd.ra.cur_hop_limit": self.cur_hop_limit(),
+                "nd.ra.managed": self.managed(),
+                "nd.ra.other": self.other_config(),
+                "nd.ra.router_lifetime": self.router_lifetime(),
+                "nd.ra.reachable_time": self.reachable_time(),
+                "nd.ra.retrans_timer": self.retrans_timer(),
+                "nd.ra.opts": self.options_json(),
+            }
+        }))
+    }
+}
+
+#[pdu_type]
+pub struct NeighborSolicitation<'a> {}
+
+impl_ndp_message!(
+    NeighborSolicitation,
+    ND_NS_LEN,
+    &[("reserved", 0, 4), ("target", ND_TARGET_OFFSET, 16)]
+);
+impl_nd_target!(NeighborSolicitation);
+
+impl<'a> NeighborSolicitation<'a> {
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "nd.ns": {
+                "nd.ns.target": self.target_addr().to_string(),
+                "nd.ns.opts": self.options_json(),
+            }
+        }))
+    }
+}
+
+#[pdu_type]
+pub struct NeighborAdvertisement<'a> {}
+
+impl_ndp_message!(
+    NeighborAdvertisement,
+    ND_NA_LEN,
+    &[
+        ("flags", ND_FLAGS_OFFSET, 1),
+        ("reserved", 1, 3),
+        ("target", ND_TARGET_OFFSET, 16),
+    ]
+);
+impl_nd_target!(NeighborAdvertisement);
+
+impl<'a> NeighborAdvertisement<'a> {
+    pub fn flags(&self) -> u8 {
+        self.header[ND_FLAGS_OFFSET]
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.header.to_mut()[ND_FLAGS_OFFSET] = flags;
+    }
+
+    pub fn with_flags(&mut self, flags: u8) -> &mut Self {
+        self.set_flags(flags);
+        self
+    }
+
+    pub fn router(&self) -> bool {
+        self.flags() & ND_NA_FLAG_ROUTER != 0
+    }
+
+    pub fn solicited(&self) -> bool {
+        self.flags() & ND_NA_FLAG_SOLICITED != 0
+    }
+
+    pub fn override_flag(&self) -> bool {
+        self.flags() & ND_NA_FLAG_OVERRIDE != 0
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "nd.na": {
+                "nd.na.router": self.router(),
+                "nd.na.solicited": self.solicited(),
+                "nd.na.override": self.override_flag(),
+                "nd.na.target": self.target_addr().to_string(),
+                "nd.na.opts": self.options_json(),
+            }
+        }))
+    }
+}
+
+#[pdu_type]
+pub struct Redirect<'a> {}
+
+impl_ndp_message!(
+    Redirect,
+    ND_REDIRECT_LEN,
+    &[
+        ("reserved", 0, 4),
+        ("target", ND_TARGET_OFFSET, 16),
+        ("destination", ND_DEST_OFFSET, 16),
+    ]
+);
+impl_nd_target!(Redirect);
+
+impl<'a> Redirect<'a> {
+    pub fn dest_addr(&self) -> Ipv6Addr {
+        get_ipv6_addr(&self.header, ND_DEST_OFFSET)
+    }
+
+    pub fn set_dest_addr(&mut self, dest_addr: Ipv6Addr) {
+        self.header.to_mut()[ND_DEST_OFFSET..ND_REDIRECT_LEN].copy_from_slice(&dest_addr.octets());
+    }
+
+    pub fn with_dest_addr(&mut self, dest_addr: Ipv6Addr) -> &mut Self {
+        self.set_dest_addr(dest_addr);
+        self
+    }
+
+    fn json_fields(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "nd.redirect": {
+                "nd.redirect.target": self.target_addr().to_string(),
+                "nd.redirect.dest": self.dest_addr().to_string(),
+                "nd.redirect.opts": self.options_json(),
+            }
+        }))
+    }
+}
+
+/// MLDv1 messages share a layout: maximum response delay and a multicast
+/// address.
+macro_rules! impl_mld_message {
+    ($pdu:ident) => {
+        impl<'a> $pdu<'a> {
+            pub fn new() -> Self {
+                Self {
+                    header: Cow::Owned(vec![0; MLD_LEN]),
+                    parent: None,
+                    child: None,
+                }
+            }
+
+            /// Maximum response delay in milliseconds.
+            pub fn max_response_delay(&self) -> u16 {
+                get_icmp_u16(&self.header, MLD_MAX_RESP_OFFSET)
+            }
+
+            pub fn set_max_response_delay(&mut self, delay: u16) {
+                self.header.to_mut()[MLD_MAX_RESP_OFFSET..MLD_MAX_RESP_OFFSET + 2]
+                    .copy_from_slice(&delay.to_be_bytes());
+            }
+
+            pub fn with_max_response_delay(&mut self, delay: u16) -> &mut Self {
+                self.set_max_response_delay(delay);
+                self
+            }
+
+            pub fn multicast_addr(&self) -> Ipv6Addr {
+                get_ipv6_addr(&self.header, MLD_ADDR_OFFSET)
+            }
+
+            pub fn set_multicast_addr(&mut self, addr: Ipv6Addr) {
+                self.header.to_mut()[MLD_ADDR_OFFSET..MLD_LEN].copy_from_slice(&addr.octets());
+            }
+
+            pub fn with_multicast_addr(&mut self, addr: Ipv6Addr) -> &mut Self {
+                self.set_multicast_addr(addr);
+                self
+            }
+        }
+    };
+}
+
+/// A Multicast Listener Query. Queries longer than the MLDv1 layout are
+/// MLDv2 queries.
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc3810#section-5.1>
+#[pdu_type]
+pub struct MldQuery<'a> {}
+
+impl_mld_message!(MldQuery);
+
+impl<'a> MldQuery<'a> {
+    pub fn is_v2(&self) -> bool {
+        self.header.len() >= MLDV2_QUERY_LEN
+    }
+
+    /// Suppress router-side processing flag, MLDv2 only.
+    pub fn s_flag(&self) -> bool {
+        self.is_v2() && self.header[MLDV2_QUERY_FLAGS_OFFSET] & 0x08 != 0
+    }
+
+    /// Querier's robustness variable, MLDv2 only.
+    pub fn qrv(&self) -> u8 {
+        if self.is_v2() {
+            self.header[MLDV2_QUERY_FLAGS_OFFSET] & 0x07
+        } else {
+            0
+        }
+    }
+
+    /// Querier's query interval code, MLDv2 only.
+    pub fn qqic(&self) -> u8 {
+        if self.is_v2() {
+            self.header[MLDV2_QUERY_QQIC_OFFSET]
+        } else {
+            0
+        }
+    }
+
+    pub fn sources(&self) -> Vec<Ipv6Addr> {
+        if !self.is_v2() {
+            return Vec::new();
+        }
+        let count = get_icmp_u16(&self.header, MLDV2_QUERY_NSRC_OFFSET) as usize;
+        get_ipv6_addrs(PROTOCOL, &self.header, MLDV2_QUERY_LEN, count).unwrap_or_default()
+    }
+}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for MldQuery<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.header.to_vec()
+    }
+
+    default_pdu_clone!(MldQuery);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        check_fields(PROTOCOL, bytes, MLD_FIELDS)?;
+        let len = if bytes.len() >= MLDV2_QUERY_LEN {
+            let count = get_icmp_u16(bytes, MLDV2_QUERY_NSRC_OFFSET) as usize;
+            get_ipv6_addrs(PROTOCOL, bytes, MLDV2_QUERY_LEN, count)?;
+            MLDV2_QUERY_LEN + 16 * count
+        } else {
+            MLD_LEN
+        };
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..len]),
+            parent: None,
+            child: None,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        let mut res = json!({
+            "mld.query": {
+                "mld.query.max_resp": self.max_response_delay(),
+                "mld.query.addr": self.multicast_addr().to_string(),
+            }
+        });
+        if self.is_v2() {
+            let fields = res["mld.query"].as_object_mut().unwrap();
+            fields.insert("mld.query.s".into(), json!(self.s_flag()));
+            fields.insert("mld.query.qrv".into(), json!(self.qrv()));
+            fields.insert("mld.query.qqic".into(), json!(self.qqic()));
+            fields.insert(
+                "mld.query.sources".into(),
+                json!(
+                    self.sources()
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<_>>()
+                ),
+            );
+        }
+        Ok(res)
+    }
+}
+
+#[pdu_type]
+pub struct MldReport<'a> {}
+
+impl_mld_message!(MldReport);
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for MldReport<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.header.to_vec()
+    }
+
+    default_pdu_clone!(MldReport);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        check_fields(PROTOCOL, bytes, MLD_FIELDS)?;
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..MLD_LEN]),
+            parent: None,
+            child: None,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "mld.report": {
+                "mld.report.addr": self.multicast_addr().to_string(),
+            }
+        }))
+    }
+}
+
+#[pdu_type]
+pub struct MldDone<'a> {}
+
+impl_mld_message!(MldDone);
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for MldDone<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.header.to_vec()
+    }
+
+    default_pdu_clone!(MldDone);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        check_fields(PROTOCOL, bytes, MLD_FIELDS)?;
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..MLD_LEN]),
+            parent: None,
+            child: None,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "mld.done": {
+                "mld.done.addr": self.multicast_addr().to_string(),
+            }
+        }))
+    }
+}
+
+/// A multicast address record of an MLDv2 report.
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc3810#section-5.2.4>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MldRecord {
+    pub record_type: u8,
+    pub multicast_addr: Ipv6Addr,
+    pub sources: Vec<Ipv6Addr>,
+    pub aux_data: Vec<u8>,
+}
+
+impl MldRecord {
+    pub fn new(record_type: u8, multicast_addr: Ipv6Addr, sources: Vec<Ipv6Addr>) -> Self {
+        Self {
+            record_type,
+            multicast_addr,
+            sources,
+            aux_data: Vec::new(),
+        }
+    }
+
+    /// Parses the record at the start of `bytes`, returning it and its length.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize), ParseError> {
+        check_fields(
+            "mld record",
+            bytes,
+            &[
+                ("record type", 0, 1),
+                ("aux data length", 1, 1),
+                ("number of sources", 2, 2),
+                ("multicast address", 4, 16),
+            ],
+        )?;
+        let aux_len = bytes[1] as usize * 4;
+        let count = get_icmp_u16(bytes, 2) as usize;
+        let sources = get_ipv6_addrs("mld record", bytes, MLDV2_RECORD_LEN, count)?;
+        let aux_start = MLDV2_RECORD_LEN + 16 * count;
+        ParseError::check_len("mld record", "aux data", bytes, aux_start, aux_len)?;
+        Ok((
+            Self {
+                record_type: bytes[0],
+                multicast_addr: get_ipv6_addr(bytes, 4),
+                sources,
+                aux_data: bytes[aux_start..aux_start + aux_len].to_vec(),
+            },
+            aux_start + aux_len,
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = vec![self.record_type, (self.aux_data.len() / 4) as u8];
+        res.extend_from_slice(&(self.sources.len() as u16).to_be_bytes());
+        res.extend_from_slice(&self.multicast_addr.octets());
+        for source in &self.sources {
+            res.extend_from_slice(&source.octets());
+        }
+        res.extend_from_slice(&self.aux_data);
+        res
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "mld.record.type": self.record_type,
+            "mld.record.addr": self.multicast_addr.to_string(),
+            "mld.record.sources": self.sources.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn parse_mld_records(bytes: &[u8], count: usize) -> Result<(Vec<MldRecord>, usize), ParseError> {
+    let mut records = Vec::with_capacity(count);
+    let mut offset = 0;
+    for _ in 0..count {
+        let (record, len) =
+            MldRecord::from_bytes(&bytes[offset..]).map_err(|err| err.shifted(offset))?;
+        records.push(record);
+        offset += len;
+    }
+    Ok((records, offset))
+}
+
+#[pdu_type]
+pub struct Mldv2Report<'a> {}
+
+impl<'a> Mldv2Report<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; MLDV2_REPORT_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn num_records(&self) -> u16 {
+        get_icmp_u16(&self.header, MLDV2_REPORT_NREC_OFFSET)
+    }
+
+    pub fn records(&self) -> Vec<MldRecord> {
+        parse_mld_records(
+            &self.header[MLDV2_REPORT_LEN..],
+            self.num_records() as usize,
+        )
+        .map(|(records, _)| records)
+        .unwrap_or_default()
+    }
+
+    /// Replaces the records and updates the record count.
+    pub fn set_records(&mut self, records: &[MldRecord]) {
+        let header = self.header.to_mut();
+        header.truncate(MLDV2_REPORT_LEN);
+        header[MLDV2_REPORT_NREC_OFFSET..MLDV2_REPORT_LEN]
+            .copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for record in records {
+            header.extend_from_slice(&record.to_bytes());
+        }
+    }
+
+    pub fn with_records(&mut self, records: &[MldRecord]) -> &mut Self {
+        self.set_records(records);
+        self
+    }
+}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Mldv2Report<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.header.to_vec()
+    }
+
+    default_pdu_clone!(Mldv2Report);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        check_fields(
+            PROTOCOL,
+            bytes,
+            &[
+                ("reserved", 0, 2),
+                ("number of records", MLDV2_REPORT_NREC_OFFSET, 2),
+            ],
+        )?;
+        let count = get_icmp_u16(bytes, MLDV2_REPORT_NREC_OFFSET) as usize;
+        let (_, len) = parse_mld_records(&bytes[MLDV2_REPORT_LEN..], count)
+            .map_err(|err| err.within(PROTOCOL, MLDV2_REPORT_LEN))?;
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..MLDV2_REPORT_LEN + len]),
+            parent: None,
+            child: None,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "mld.v2_report": {
+                "mld.v2_report.records": self
+                    .records()
+                    .iter()
+                    .map(|record| record.to_json())
+                    .collect::<Vec<_>>(),
+            }
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct Icmpv6Type(pub u8);
+
+pub static ICMPV6_DISSECTION_TABLE: DissectionTable<Icmpv6Type> = create_table();
+
+register_pdu!(Icmpv6Type(1), DestUnreachable, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(2), PacketTooBig, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(3), TimeExceeded, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(4), ParameterProblem, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(128), EchoRequest, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(129), EchoReply, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(130), MldQuery, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(131), MldReport, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(132), MldDone, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(133), RouterSolicitation, ICMPV6_DISSECTION_TABLE);
+register_pdu!(
+    Icmpv6Type(134),
+    RouterAdvertisement,
+    ICMPV6_DISSECTION_TABLE
+);
+register_pdu!(
+    Icmpv6Type(135),
+    NeighborSolicitation,
+    ICMPV6_DISSECTION_TABLE
+);
+register_pdu!(
+    Icmpv6Type(136),
+    NeighborAdvertisement,
+    ICMPV6_DISSECTION_TABLE
+);
+register_pdu!(Icmpv6Type(137), Redirect, ICMPV6_DISSECTION_TABLE);
+register_pdu!(Icmpv6Type(143), Mldv2Report, ICMPV6_DISSECTION_TABLE);
+
+register_fields!(DestUnreachable, "icmpv6.dest_unreachable", {
+    "icmpv6.dest_unreachable.length" => orig_length { name: "Length", bits: (ICMPV6_LENGTH_OFFSET * 8, 8) },
+});
+
+register_fields!(PacketTooBig, "icmpv6.packet_too_big", {
+    "icmpv6.packet_too_big.mtu" => mtu { name: "MTU", bits: (ICMPV6_MTU_OFFSET * 8, 32) },
+});
+
+register_fields!(TimeExceeded, "icmpv6.time_exceeded", {
+    "icmpv6.time_exceeded.length" => orig_length { name: "Length", bits: (ICMPV6_LENGTH_OFFSET * 8, 8) },
+});
+
+register_fields!(ParameterProblem, "icmpv6.parameter_problem", {
+    "icmpv6.parameter_problem.pointer" => pointer { name: "Pointer", bits: (ICMPV6_POINTER_OFFSET * 8, 32) },
+});
+
+register_fields!(EchoRequest, "icmpv6.echo_request", {
+    "icmpv6.echo_request.id" => id { name: "Identifier", bits: (ICMP_ID_OFFSET * 8, 16), base: HexDec },
+    "icmpv6.echo_request.seq_number" => seq_number {
+        name: "Sequence Number",
+        bits: (ICMP_SEQNUM_OFFSET * 8, 16),
+    },
+});
+
+register_fields!(EchoReply, "icmpv6.echo_reply", {
+    "icmpv6.echo_reply.id" => id { name: "Identifier", bits: (ICMP_ID_OFFSET * 8, 16), base: HexDec },
+    "icmpv6.echo_reply.seq_number" => seq_number {
+        name: "Sequence Number",
+        bits: (ICMP_SEQNUM_OFFSET * 8, 16),
+    },
+});
+
+register_fields!(MldQuery, "mld.query", {
+    "mld.query.max_resp" => max_response_delay {
+        name: "Maximum Response Delay",
+        bits: (MLD_MAX_RESP_OFFSET * 8, 16),
+    },
+    "mld.query.addr" => multicast_addr { name: "Multicast Address", bits: (MLD_ADDR_OFFSET * 8, 128) },
+});
+
+register_fields!(MldReport, "mld.report", {
+    "mld.report.addr" => multicast_addr { name: "Multicast Address", bits: (MLD_ADDR_OFFSET * 8, 128) },
+});
+
+register_fields!(MldDone, "mld.done", {
+    "mld.done.addr" => multicast_addr { name: "Multicast Address", bits: (MLD_ADDR_OFFSET * 8, 128) },
+});
+
+register_fields!(RouterSolicitation, "nd.rs", {});
+
+register_fields!(RouterAdvertisement, "nd.ra", {
+    "nd.ra.cur_hop_limit" => cur_hop_limit { name: "Cur Hop Limit", bits: (ND_RA_HOP_LIMIT_OFFSET * 8, 8) },
+    "nd.ra.managed" => managed { name: "Managed Address Configuration", bits: (ND_RA_FLAGS_OFFSET * 8, 1) },
+    "nd.ra.other" => other_config { name: "Other Configuration", bits: (ND_RA_FLAGS_OFFSET * 8 + 1, 1) },
+    "nd.ra.router_lifetime" => router_lifetime {
+        name: "Router Lifetime",
+        bits: (ND_RA_LIFETIME_OFFSET * 8, 16),
+    },
+    "nd.ra.reachable_time" => reachable_time {
+        name: "Reachable Time",
+        bits: (ND_RA_REACHABLE_OFFSET * 8, 32),
+    },
+    "nd.ra.retrans_timer" => retrans_timer { name: "Retrans Timer", bits: (ND_RA_RETRANS_OFFSET * 8, 32) },
+});
+
+register_fields!(NeighborSolicitation, "nd.ns", {
+    "nd.ns.target" => target_addr { name: "Target Address", bits: (ND_TARGET_OFFSET * 8, 128) },
+});
+
+register_fields!(NeighborAdvertisement, "nd.na", {
+    "nd.na.router" => router { name: "Router", bits: (ND_FLAGS_OFFSET * 8, 1) },
+    "nd.na.solicited" => solicited { name: "Solicited", bits: (ND_FLAGS_OFFSET * 8 + 1, 1) },
+    "nd.na.override" => override_flag { name: "Override", bits: (ND_FLAGS_OFFSET * 8 + 2, 1) },
+    "nd.na.target" => target_addr { name: "Target Address", bits: (ND_TARGET_OFFSET * 8, 128) },
+});
+
+register_fields!(Redirect, "nd.redirect", {
+    "nd.redirect.target" => target_addr { name: "Target Address", bits: (ND_TARGET_OFFSET * 8, 128) },
+    "nd.redirect.dest" => dest_addr { name: "Destination Address", bits: (ND_DEST_OFFSET * 8, 128) },
+});
+
+register_fields!(Mldv2Report, "mld.v2_report", {
+    "mld.v2_report.num_records" => num_records {
+        name: "Number of Records",
+        bits: (MLDV2_REPORT_NREC_OFFSET * 8, 16),
+    },
+});
+
+#[pdu_type]
+pub struct Icmpv6<'a> {}
+
+impl<'a> Icmpv6<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; ICMPV6_HEADER_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn msg_type(&self) -> u8 {
+        self.header[ICMPV6_TYPE_OFFSET]
+    }
+
+    pub fn set_msg_type(&mut self, msg_type: u8) {
+        self.header.to_mut()[ICMPV6_TYPE_OFFSET] = msg_type;
+    }
+
+    pub fn with_msg_type(&mut self, msg_type: u8) -> &mut Self {
+        self.set_msg_type(msg_type);
+        self
+    }
+
+    pub fn msg_code(&self) -> u8 {
+        self.header[ICMPV6_CODE_OFFSET]
+    }
+
+    pub fn set_msg_code(&mut self, msg_code: u8) {
+        self.header.to_mut()[ICMPV6_CODE_OFFSET] = msg_code;
+    }
+
+    pub fn with_msg_code(&mut self, msg_code: u8) -> &mut Self {
+        self.set_msg_code(msg_code);
+        self
+    }
+
+    pub fn checksum(&self) -> u16 {
+        get_icmp_u16(&self.header, ICMPV6_CHECKSUM_OFFSET)
+    }
+
+    pub fn set_checksum(&mut self, checksum: u16) {
+        self.header.to_mut()[ICMPV6_CHECKSUM_OFFSET..ICMPV6_HEADER_LEN]
+            .copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    pub fn with_checksum(&mut self, checksum: u16) -> &mut Self {
+        self.set_checksum(checksum);
+        self
+    }
+
+    /// Checksum of the message and its children over the IPv6 pseudo-header.
+    pub fn compute_checksum(&self, src: Ipv6Addr, dst: Ipv6Addr) -> u16 {
+        let mut bytes = self.chain_to_bytes();
+        bytes[ICMPV6_CHECKSUM_OFFSET..ICMPV6_HEADER_LEN].fill(0);
+        let sum = ipv6_pseudo_header_sum(src, dst, ICMPV6, bytes.len() as u32);
+        checksum_finish(checksum_add(sum, &bytes))
+    }
+
+    pub fn verify_checksum(&self, src: Ipv6Addr, dst: Ipv6Addr) -> bool {
+        self.compute_checksum(src, dst) == self.checksum()
+    }
+}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Icmpv6<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        self.header.to_vec()
+    }
+
+    default_pdu_clone!(Icmpv6);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len(PROTOCOL, "header length", bytes, 0, ICMPV6_HEADER_LEN)?;
+
+        let body = &bytes[ICMPV6_HEADER_LEN..];
+        let child = if body.is_empty() {
+            None
+        } else {
+            Some(build_from_table(
+                &ICMPV6_DISSECTION_TABLE,
+                Icmpv6Type(bytes[ICMPV6_TYPE_OFFSET]),
+                body,
+            ))
+        };
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..ICMPV6_HEADER_LEN]),
+            parent: None,
+            child,
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "icmpv6": {
+                "icmpv6.type": self.msg_type(),
+                "icmpv6.code": self.msg_code(),
+                "icmpv6.checksum": self.checksum(),
+                "icmpv6.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+register_pdu!(Ipv6Type(ICMPV6), Icmpv6, IPV6_DISSECTION_TABLE);
+
+const ICMPV6_TYPE_NAMES: ValueStrings = &[
+    (1, "Destination Unreachable"),
+    (2, "Packet Too Big"),
+    (3, "Time Exceeded"),
+    (4, "Parameter Problem"),
+    (128, "Echo Request"),
+    (129, "Echo Reply"),
+    (130, "Multicast Listener Query"),
+    (131, "Multicast Listener Report"),
+    (132, "Multicast Listener Done"),
+    (133, "Router Solicitation"),
+    (134, "Router Advertisement"),
+    (135, "Neighbor Solicitation"),
+    (136, "Neighbor Advertisement"),
+    (137, "Redirect"),
+    (143, "Multicast Listener Report Message v2"),
+];
+
+register_fields!(Icmpv6, "icmpv6", {
+    "icmpv6.type" => msg_type {
+        name: "Type",
+        bits: (ICMPV6_TYPE_OFFSET * 8, 8),
+        values: ICMPV6_TYPE_NAMES,
+    },
+    "icmpv6.code" => msg_code { name: "Code", bits: (ICMPV6_CODE_OFFSET * 8, 8) },
+    "icmpv6.checksum" => checksum {
+        name: "Checksum",
+        bits: (ICMPV6_CHECKSUM_OFFSET * 8, 16),
+        base: Hex,
+    },
+});
+
+#[cfg(test)]
+mod tests {
+    use crate::ndp_opt::SOURCE_LL_ADDR;
+    use crate::pdu::deserialize;
+    use crate::udp::Udp;
+
+    use super::*;
+
+    fn addr(s: &str) -> Ipv6Addr {
+        s.parse().unwrap()
+    }
+
+    // Neighbor solicitation for fe80::2 from fe80::1 to its solicited-node address.
+    const ND_NS: [u8; 32] = [
+        0x87, 0x00, 0x15, 0xff, // Type 135, code 0, checksum
+        0x00, 0x00, 0x00, 0x00, // Reserved
+        0xfe, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x02, // Target fe80::2
+        0x01, 0x01, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, // Source link-layer address
+    ];
+
+    #[test]
+    fn test_neighbor_solicitation() {
+        let icmp = Icmpv6::from_bytes(&ND_NS).unwrap();
+        let ns = icmp.find::<NeighborSolicitation>().unwrap();
+        assert!(ns.target_addr() == addr("fe80::2"));
+        let opts = ns.options().unwrap();
+        assert!(opts[0].kind() == SOURCE_LL_ADDR);
+        assert!(icmp.chain_to_bytes() == ND_NS);
+
+        let json = icmp.to_json().unwrap();
+        assert!(json["icmpv6"]["icmpv6.data"]["nd.ns"]["nd.ns.target"] == "fe80::2");
+    }
+
+    #[test]
+    fn test_checksum() {
+        let icmp = deserialize::<Icmpv6>(&ND_NS).unwrap();
+        let checksum = icmp.compute_checksum(addr("fe80::1"), addr("ff02::1:ff00:2"));
+        assert!(icmp.checksum() == checksum);
+        assert!(icmp.verify_checksum(addr("fe80::1"), addr("ff02::1:ff00:2")));
+        assert!(!icmp.verify_checksum(addr("fe80::3"), addr("ff02::1:ff00:2")));
+    }
+
+    #[test]
+    fn test_router_advertisement() {
+        let mut ra = RouterAdvertisement::new();
+        ra.with_cur_hop_limit(64)
+            .with_flags(ND_RA_FLAG_MANAGED)
+            .with_router_lifetime(1800)
+            .with_option(NdpOption::Mtu(1500))
+            .with_option(NdpOption::Rdnss {
+                lifetime: 600,
+                servers: vec![addr("2001:db8::53")],
+            });
+
+        let bytes = [vec![0x86, 0, 0, 0], ra.to_bytes()].concat();
+        let icmp = Icmpv6::from_bytes(&bytes).unwrap();
+        let ra = icmp.find::<RouterAdvertisement>().unwrap();
+        assert!(ra.cur_hop_limit() == 64);
+        assert!(ra.managed());
+        assert!(!ra.other_config());
+        assert!(ra.router_lifetime() == 1800);
+        assert!(ra.options().unwrap()[0] == NdpOption::Mtu(1500));
+    }
+
+    #[test]
+    fn test_neighbor_advertisement() {
+        let mut bytes = vec![0x88, 0x00, 0x00, 0x00, 0xe0, 0x00, 0x00, 0x00];
+        bytes.extend_from_slice(&addr("2001:db8::1").octets());
+        let icmp = Icmpv6::from_bytes(&bytes).unwrap();
+        let na = icmp.find::<NeighborAdvertisement>().unwrap();
+        assert!(na.router() && na.solicited() && na.override_flag());
+        assert!(na.target_addr() == addr("2001:db8::1"));
+        assert!(na.options().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_echo_and_packet_too_big() {
+        let icmp =
+            Icmpv6::from_bytes(&[0x80, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x01, b'h', b'i'])
+                .unwrap();
+        let echo = icmp.find::<EchoRequest>().unwrap();
+        assert!(echo.id() == 0x1234);
+        assert!(echo.seq_number() == 1);
+
+        // Quoted IPv6/UDP packet
+        let mut bytes = vec![0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00];
+        bytes.extend_from_slice(&[0x60, 0, 0, 0, 0x00, 0x08, 17, 64]);
+        bytes.extend_from_slice(&addr("2001:db8::1").octets());
+        bytes.extend_from_slice(&addr("2001:db8::2").octets());
+        bytes.extend_from_slice(&[0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]);
+        let icmp = Icmpv6::from_bytes(&bytes).unwrap();
+        assert!(icmp.find::<PacketTooBig>().unwrap().mtu() == 1280);
+        assert!(icmp.find::<Ipv6>().unwrap().dst_addr() == addr("2001:db8::2"));
+        assert!(icmp.find::<Udp>().unwrap().dst_port() == 53);
+    }
+
+    #[test]
+    fn test_mld() {
+        let mut bytes = vec![0x83, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        bytes.extend_from_slice(&addr("ff02::fb").octets());
+        let icmp = Icmpv6::from_bytes(&bytes).unwrap();
+        assert!(icmp.find::<MldReport>().unwrap().multicast_addr() == addr("ff02::fb"));
+
+        let mut query = vec![0x82, 0x00, 0x00, 0x00, 0x27, 0x10, 0x00, 0x00];
+        query.extend_from_slice(&[0; 16]);
+        query.extend_from_slice(&[0x0a, 0x7d, 0x00, 0x01]);
+        query.extend_from_slice(&addr("2001:db8::9").octets());
+        let icmp = Icmpv6::from_bytes(&query).unwrap();
+        let query = icmp.find::<MldQuery>().unwrap();
+        assert!(query.is_v2());
+        assert!(query.max_response_delay() == 10000);
+        assert!(query.s_flag());
+        assert!(query.qrv() == 2);
+        assert!(query.qqic() == 125);
+        assert!(query.sources() == [addr("2001:db8::9")]);
+    }
+
+    #[test]
+    fn test_mldv2_report() {
+        let records = [
+            MldRecord::new(CHANGE_TO_EXCLUDE_MODE, addr("ff02::fb"), Vec::new()),
+            MldRecord::new(
+                ALLOW_NEW_SOURCES,
+                addr("ff3e::1"),
+                vec![addr("2001:db8::7")],
+            ),
+        ];
+        let mut report = Mldv2Report::new();
+        report.set_records(&records);
+
+        let bytes = [vec![0x8f, 0, 0, 0], report.to_bytes()].concat();
+        let icmp = Icmpv6::from_bytes(&bytes).unwrap();
+        let report = icmp.find::<Mldv2Report>().unwrap();
+        assert!(report.num_records() == 2);
+        assert!(report.records() == records);
+    }
+
+    #[test]
+    fn test_field_layout() {
+        // The bits registered for each message field hold the getter's value.
+        let mut ra = RouterAdvertisement::new();
+        ra.with_cur_hop_limit(64)
+            .with_flags(ND_RA_FLAG_OTHER)
+            .with_router_lifetime(1800)
+            .with_reachable_time(30000)
+            .with_retrans_timer(1000);
+        let mut na = vec![0x88, 0x00, 0x00, 0x00, 0xa0, 0x00, 0x00, 0x00];
+        na.extend_from_slice(&addr("2001:db8::1").octets());
+        let mut redirect = vec![0x89, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        redirect.extend_from_slice(&addr("fe80::1").octets());
+        redirect.extend_from_slice(&addr("2001:db8::2").octets());
+        let mut query = vec![0x82, 0x00, 0x00, 0x00, 0x27, 0x10, 0x00, 0x00];
+        query.extend_from_slice(&addr("ff02::fb").octets());
+        let mut report = Mldv2Report::new();
+        report.set_records(&[MldRecord::new(
+            MODE_IS_EXCLUDE,
+            addr("ff02::fb"),
+            Vec::new(),
+        )]);
+
+        let messages = [
+            ND_NS.to_vec(),
+            [vec![0x86, 0, 0, 0], ra.to_bytes()].concat(),
+            na,
+            redirect,
+            query,
+            [vec![0x8f, 0, 0, 0], report.to_bytes()].concat(),
+            vec![0x80, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x01],
+            vec![0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00],
+            vec![0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2a],
+        ];
+        let mut checked = 0;
+        for bytes in &messages {
+            let icmp = Icmpv6::from_bytes(bytes).unwrap();
+            let msg = icmp.child_pdu().as_deref().unwrap();
+            let header = msg.to_bytes();
+            for (field, value) in msg.fields() {
+                let (offset, width) = field.bits.unwrap();
+                match field.raw_bits(&header) {
+                    Some(raw) if width <= 64 => {
+                        assert!(value.as_u64() == Some(raw), "{}", field.name)
+                    }
+                    _ => {
+                        let bytes = &header[offset / 8..(offset + width) / 8];
+                        assert!(value.to_bytes() == bytes, "{}", field.name);
+                    }
+                }
+                checked += 1;
+            }
+        }
+        assert!(checked == 20);
+    }
+
+    #[test]
+    fn test_malformed_falls_back_to_raw() {
+        let icmp = Icmpv6::from_bytes(&ND_NS[..20]).unwrap();
+        assert!(icmp.find::<NeighborSolicitation>().is_none());
+        assert!(icmp.find::<Raw>().is_some());
+        assert!(
+            icmp.parse_error().unwrap().to_string()
+                == "icmpv6: target 16 exceeds remaining 12 bytes at offset 8"
+        );
+
+        let mut bytes = ND_NS;
+        bytes[25] = 0;
+        let icmp = Icmpv6::from_bytes(&bytes).unwrap();
+        assert!(
+            icmp.parse_error().unwrap().to_string()
+                == "icmpv6: ndp option: length 0 is not allowed at offset 25"
+        );
+        assert!(Icmpv6::from_bytes(&[0x80, 0x00]).is_err());
+    }
+}
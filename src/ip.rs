@@ -1,5 +1,6 @@
 use crate::ethernet::{ETHER_DISSECTION_TABLE, EtherType};
-use crate::ip_opt::IpOption;
+use crate::ip_opt::{IpOption, ip_options_to_bytes, parse_ip_options};
+use crate::pcap::{LINKTYPE_DISSECTION_TABLE, LINKTYPE_IPV4, LinkType};
 use crate::prelude::*;
 
 use std::net::Ipv4Addr;
@@ -12,13 +13,19 @@ const IPV4_ID_OFFSET: usize = 4;
 const IPV4_FRAG_FLAG_OFFSET: usize = 6;
 const IPV4_TTL_OFFSET: usize = 8;
 const IPV4_PROTO_OFFSET: usize = 9;
-const IPV4_CHECKSUM_OFFSET: usize = 10;
+pub(crate) const IPV4_CHECKSUM_OFFSET: usize = 10;
 const IPV4_SRC_ADDR_OFFSET: usize = 12;
 const IPV4_DST_ADDR_OFFSET: usize = 16;
 const IPV4_OPT_OFFSET: usize = 20;
 const IPV4_HEADER_LEN: usize = 20;
 
-fn get_ip_header_len<'a>(ip_header_bytes: &'a [u8]) -> usize {
+const IPV4_FRAG_OFFSET_MASK: u16 = 0x1FFF;
+const IPV4_FRAG_OFFSET_HIGH_MASK: u8 = 0x1F;
+const IPV4_MF_MASK: u8 = 0x20;
+/// Fragment offsets count 8 byte units.
+pub const IPV4_FRAG_UNIT: usize = 8;
+
+fn get_ip_header_len(ip_header_bytes: &[u8]) -> usize {
     (ip_header_bytes[IPV4_VERSION_OFFSET] & 0xF) as usize * IPV4_BYTE_MULTIPLE
 }
 
@@ -27,19 +34,17 @@ pub struct Ip<'a> {
     opts: Vec<IpOption<'a>>,
 }
 
-fn get_ip_type<'a>(bytes: &'a [u8]) -> u8 {
-    // TODO: shouldn't assume we have the proper amount of data
+fn get_ip_type(bytes: &[u8]) -> u8 {
     bytes[IPV4_PROTO_OFFSET]
 }
 
-fn pdu_from_type<'a>(ether_type: Ipv4Type, bytes: &'a [u8]) -> Pob<'a> {
-    // TODO: replace unwrap() with proper error handling
-    let table = IPV4_DISSECTION_TABLE.read().unwrap();
-    if let Some(builder) = table.get(&ether_type) {
-        builder(bytes).ok()
-    } else {
-        Raw::from_bytes(bytes).ok()
-    }
+fn get_ip_frag_offset(bytes: &[u8]) -> u16 {
+    parse_bytes::<u16>(&bytes[IPV4_FRAG_FLAG_OFFSET..IPV4_TTL_OFFSET], Endian::Big)
+        & IPV4_FRAG_OFFSET_MASK
+}
+
+fn get_ip_total_len(bytes: &[u8]) -> usize {
+    parse_bytes::<u16>(&bytes[IPV4_TOTAL_LEN_OFFSET..IPV4_ID_OFFSET], Endian::Big) as usize
 }
 
 #[pdu_impl]
@@ -47,40 +52,71 @@ impl<'a> Pdu<'a> for Ip<'a> {
     fn clone(&self) -> Box<dyn Pdu<'static> + 'static> {
         Box::new(Ip {
             header: Cow::Owned(self.header.to_vec()),
-            opts: Vec::new(),
+            opts: self.opts.iter().map(|opt| opt.to_owned()).collect(),
             parent: None,
             child: None,
         })
     }
 
     fn to_bytes(&self) -> Vec<u8> {
-        let mut res = vec![0; IPV4_HEADER_LEN as usize];
-        for idx in 0..self.opts.len() {
-            res.extend_from_slice(&self.opts[idx].to_bytes());
-        }
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res.extend_from_slice(&ip_options_to_bytes(&self.opts));
         res
     }
 
     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
-        if bytes.len() < IPV4_HEADER_LEN {
-            return Err(ParseError::NotEnoughData);
-        }
+        ParseError::check_len("ip", "header length", bytes, 0, IPV4_HEADER_LEN)?;
 
         let header_len = get_ip_header_len(&bytes[..IPV4_HEADER_LEN]);
-        if header_len > bytes.len() {
-            return Err(ParseError::NotEnoughData);
+        if header_len < IPV4_HEADER_LEN {
+            return Err(ParseError::InvalidHeader {
+                protocol: "ip",
+                field: "header length",
+                offset: IPV4_VERSION_OFFSET,
+                value: header_len as u64,
+                reason: "is shorter than the minimum header",
+            });
         }
-
-        let Some(inner) = pdu_from_type(Ipv4Type(get_ip_type(bytes)), &bytes[IPV4_HEADER_LEN..])
-        else {
-            return Err(ParseError::UnsupportedProtocol);
+        ParseError::check_len("ip", "header length", bytes, 0, header_len)?;
+
+        let opts = parse_ip_options(&bytes[IPV4_OPT_OFFSET..header_len])
+            .map_err(|err| err.within("ip", IPV4_OPT_OFFSET))?;
+
+        // Trim to the total length so trailing link-layer padding is not
+        // dissected. A total length of zero is seen with TCP segmentation
+        // offload.
+        let payload_end = match get_ip_total_len(bytes) {
+            0 => bytes.len(),
+            total_len if total_len < header_len => {
+                return Err(ParseError::InvalidHeader {
+                    protocol: "ip",
+                    field: "total length",
+                    offset: IPV4_TOTAL_LEN_OFFSET,
+                    value: total_len as u64,
+                    reason: "is shorter than the header",
+                });
+            }
+            total_len => total_len.min(bytes.len()),
         };
 
-        // TODO: actually parse the options
+        // Fragments can't be dissected until they are reassembled.
+        let payload = &bytes[header_len..payload_end];
+        let fragmented =
+            get_ip_frag_offset(bytes) != 0 || bytes[IPV4_FRAG_FLAG_OFFSET] & IPV4_MF_MASK != 0;
+        let inner = if fragmented {
+            Raw::from_bytes(payload)?
+        } else {
+            build_from_table(
+                &IPV4_DISSECTION_TABLE,
+                Ipv4Type(get_ip_type(bytes)),
+                payload,
+            )
+        };
 
         let result = Self {
-            opts: Vec::new(),
-            header: Cow::Borrowed(&bytes[..header_len]),
+            opts,
+            header: Cow::Borrowed(&bytes[..IPV4_HEADER_LEN]),
             child: Some(inner),
             parent: None,
         };
@@ -89,8 +125,26 @@ impl<'a> Pdu<'a> for Ip<'a> {
     }
 
     fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        let opts_json = self
+            .opts
+            .iter()
+            .map(|opt| opt.to_json())
+            .collect::<Result<Vec<serde_json::Value>, serde_json::Error>>()?;
+
         Ok(json!({
             "ip": {
+                "ip.version": self.version(),
+                "ip.ihl": self.ihl(),
+                "ip.tos": self.tos(),
+                "ip.total_len": self.total_len(),
+                "ip.id": self.id(),
+                "ip.flags": self.flags(),
+                "ip.ttl": self.ttl(),
+                "ip.protocol": self.protocol(),
+                "ip.checksum": self.checksum(),
+                "ip.src": self.src_addr().to_string(),
+                "ip.dst": self.dst_addr().to_string(),
+                "ip.opts": opts_json,
                 "ip.data": self.child_to_json(),
             }
         }))
@@ -99,14 +153,39 @@ impl<'a> Pdu<'a> for Ip<'a> {
 
 impl<'a> Ip<'a> {
     pub fn new() -> Self {
+        let mut header = vec![0; IPV4_HEADER_LEN];
+        header[IPV4_VERSION_OFFSET] = 4 << 4;
         Self {
             opts: Vec::new(),
-            header: Cow::Owned(vec![0; IPV4_HEADER_LEN]),
+            header: Cow::Owned(header),
             child: None,
             parent: None,
         }
     }
 
+    pub fn opts(&self) -> &[IpOption<'a>] {
+        &self.opts
+    }
+
+    /// Replaces the options. The IHL is left untouched.
+    pub fn set_opts(&mut self, opts: Vec<IpOption<'a>>) {
+        self.opts = opts;
+    }
+
+    pub fn with_opts(&mut self, opts: Vec<IpOption<'a>>) -> &mut Self {
+        self.set_opts(opts);
+        self
+    }
+
+    pub fn add_opt(&mut self, opt: IpOption<'a>) {
+        self.opts.push(opt);
+    }
+
+    pub fn with_opt(&mut self, opt: IpOption<'a>) -> &mut Self {
+        self.add_opt(opt);
+        self
+    }
+
     pub fn version(&self) -> u8 {
         (self.header[IPV4_VERSION_OFFSET] & 0xF0) >> 4
     }
@@ -215,8 +294,8 @@ impl<'a> Ip<'a> {
     }
 
     pub fn set_flags(&mut self, flags: u8) {
-        self.header.to_mut()[IPV4_FRAG_FLAG_OFFSET..IPV4_FRAG_FLAG_OFFSET + 1]
-            .copy_from_slice(&flags.to_be_bytes());
+        let flags_byte = &mut self.header.to_mut()[IPV4_FRAG_FLAG_OFFSET];
+        *flags_byte = (*flags_byte & IPV4_FRAG_OFFSET_HIGH_MASK) | (flags << 5);
     }
 
     pub fn with_flags(&mut self, flags: u8) -> &mut Self {
@@ -224,14 +303,22 @@ impl<'a> Ip<'a> {
         self
     }
 
+    fn set_flag(&mut self, mask: u8, value: u8) {
+        let flags = self.flags() & !mask;
+        self.set_flags(if value != 0 { flags | mask } else { flags });
+    }
+
+    /// Whether this is one fragment of a larger datagram.
+    pub fn is_fragment(&self) -> bool {
+        self.mf() || self.frag_offset() != 0
+    }
+
     pub fn rf(&self) -> bool {
         ((self.flags() >> 2) & 0b1) != 0
     }
 
     pub fn set_rf(&mut self, rf: u8) {
-        let flags_byte = &mut self.header.to_mut()[IPV4_FRAG_FLAG_OFFSET];
-        *flags_byte &= 0b0111_1111;
-        *flags_byte &= rf;
+        self.set_flag(0b100, rf);
     }
 
     pub fn with_rf(&mut self, rf: u8) -> &mut Self {
@@ -244,9 +331,7 @@ impl<'a> Ip<'a> {
     }
 
     pub fn set_df(&mut self, df: u8) {
-        let flags_byte = &mut self.header.to_mut()[IPV4_FRAG_FLAG_OFFSET];
-        *flags_byte &= 0b1011_1111;
-        *flags_byte &= df;
+        self.set_flag(0b010, df);
     }
 
     pub fn with_df(&mut self, df: u8) -> &mut Self {
@@ -259,9 +344,7 @@ impl<'a> Ip<'a> {
     }
 
     pub fn set_mf(&mut self, mf: u8) {
-        let flags_byte = &mut self.header.to_mut()[IPV4_FRAG_FLAG_OFFSET];
-        *flags_byte &= 0b1101_1111;
-        *flags_byte &= mf;
+        self.set_flag(0b001, mf);
     }
 
     pub fn with_mf(&mut self, mf: u8) -> &mut Self {
@@ -269,18 +352,15 @@ impl<'a> Ip<'a> {
         self
     }
 
+    /// Offset of this fragment's data in 8 byte units.
     pub fn frag_offset(&self) -> u16 {
-        todo!();
-        parse_bytes::<u16>(
-            &self.header[IPV4_FRAG_FLAG_OFFSET..IPV4_TTL_OFFSET],
-            Endian::Big,
-        ) & 0x1FFF
+        get_ip_frag_offset(&self.header)
     }
 
     pub fn set_frag_offset(&mut self, offset: u16) {
-        todo!();
+        let field = ((self.flags() as u16) << 13) | (offset & IPV4_FRAG_OFFSET_MASK);
         self.header.to_mut()[IPV4_FRAG_FLAG_OFFSET..IPV4_TTL_OFFSET]
-            .copy_from_slice(&offset.to_be_bytes());
+            .copy_from_slice(&field.to_be_bytes());
     }
 
     pub fn with_frag_offset(&mut self, offset: u16) -> &mut Self {
@@ -366,12 +446,55 @@ impl<'a> Ip<'a> {
     }
 }
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct Ipv4Type(pub u8);
 
 pub static IPV4_DISSECTION_TABLE: DissectionTable<Ipv4Type> = create_table();
 
 register_pdu!(EtherType(0x0800), Ip, ETHER_DISSECTION_TABLE);
+register_pdu!(LinkType(LINKTYPE_IPV4), Ip, LINKTYPE_DISSECTION_TABLE);
+
+/// IP protocol numbers, as found in `ip.protocol` and `ipv6.nxt`.
+pub const IP_PROTOCOL_NAMES: ValueStrings = &[
+    (0, "IPv6 Hop-by-Hop Option"),
+    (1, "ICMP"),
+    (2, "IGMP"),
+    (4, "IPIP"),
+    (6, "TCP"),
+    (17, "UDP"),
+    (41, "IPv6"),
+    (43, "Routing Header for IPv6"),
+    (44, "Fragment Header for IPv6"),
+    (47, "GRE"),
+    (50, "ESP"),
+    (51, "AH"),
+    (58, "ICMPv6"),
+    (59, "No Next Header for IPv6"),
+    (60, "Destination Options for IPv6"),
+    (132, "SCTP"),
+];
+
+register_fields!(Ip, "ip", {
+    "ip.version" => version { name: "Version", bits: (IPV4_VERSION_OFFSET * 8, 4) },
+    "ip.ihl" => ihl { name: "Header Length", bits: (IPV4_VERSION_OFFSET * 8 + 4, 4) },
+    "ip.tos" => tos { name: "Type of Service", bits: (IPV4_TOS_OFFSET * 8, 8), base: Hex },
+    "ip.total_len" => total_len { name: "Total Length", bits: (IPV4_TOTAL_LEN_OFFSET * 8, 16) },
+    "ip.id" => id { name: "Identification", bits: (IPV4_ID_OFFSET * 8, 16), base: HexDec },
+    "ip.flags" => flags { name: "Flags", bits: (IPV4_FRAG_FLAG_OFFSET * 8, 3), base: Hex },
+    "ip.ttl" => ttl { name: "Time to Live", bits: (IPV4_TTL_OFFSET * 8, 8) },
+    "ip.protocol" => protocol {
+        name: "Protocol",
+        bits: (IPV4_PROTO_OFFSET * 8, 8),
+        values: IP_PROTOCOL_NAMES,
+    },
+    "ip.checksum" => checksum {
+        name: "Header Checksum",
+        bits: (IPV4_CHECKSUM_OFFSET * 8, 16),
+        base: Hex,
+    },
+    "ip.src" => src_addr { name: "Source Address", bits: (IPV4_SRC_ADDR_OFFSET * 8, 32) },
+    "ip.dst" => dst_addr { name: "Destination Address", bits: (IPV4_DST_ADDR_OFFSET * 8, 32) },
+});
 
 #[cfg(test)]
 mod tests {
@@ -387,7 +510,7 @@ mod tests {
         0x40, 0x00, // Flags (DF) + Fragment offset
         0x40, // TTL = 64
         0x06, // Protocol = TCP (6)
-        0x32, 0x4E, // Header checksum (0x324E) -- correct for this header
+        0x32, 0x12, // Header checksum (0x3212) -- correct for this header
         0xC0, 0x00, 0x02, 0x01, // Src IP: 192.0.2.1
         0xC6, 0x33, 0x64, 0x02, // Dst IP: 198.51.100.2
         // TCP header (20 bytes)
@@ -412,6 +535,56 @@ mod tests {
         Ip::from_bytes(&IPV4_TCP_HELLO).unwrap();
     }
 
+    #[test]
+    fn test_to_bytes() {
+        let ip_pdu = test_ip_pdu();
+        assert!(ip_pdu.to_bytes() == IPV4_TCP_HELLO[..IPV4_HEADER_LEN]);
+    }
+
+    #[test]
+    fn test_invalid_ihl() {
+        let mut bytes = IPV4_TCP_HELLO;
+        bytes[IPV4_VERSION_OFFSET] = 0x44;
+        assert!(Ip::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let mut bytes = IPV4_TCP_HELLO[..IPV4_HEADER_LEN].to_vec();
+        bytes[IPV4_VERSION_OFFSET] = 0x46;
+        bytes[IPV4_TOTAL_LEN_OFFSET..IPV4_TOTAL_LEN_OFFSET + 2].copy_from_slice(&[0, 24]);
+        bytes.extend_from_slice(&[0x07, 0x01, 0x00, 0x00]);
+
+        let err = Ip::from_bytes(&bytes).err().unwrap();
+        assert!(
+            err.to_string() == "ip: ip option: length 1 is too short for the option at offset 21"
+        );
+        assert!(err.protocol() == "ip" && err.offset() == IPV4_OPT_OFFSET);
+        assert!(err.root().protocol() == "ip option" && err.root().offset() == 21);
+        assert!(std::error::Error::source(&err).unwrap().to_string() == err.root().to_string());
+    }
+
+    #[test]
+    fn test_truncated_payload() {
+        let bytes = &IPV4_TCP_HELLO[..IPV4_HEADER_LEN + 8];
+        let ip_pdu = Ip::from_bytes(bytes).unwrap();
+        assert!(ip_pdu.find::<Raw>().unwrap().to_bytes() == bytes[IPV4_HEADER_LEN..]);
+
+        let err = ip_pdu.parse_error().unwrap();
+        assert!(
+            err.to_string() == "ip: tcp: header length 20 exceeds remaining 8 bytes at offset 20"
+        );
+    }
+
+    #[test]
+    fn test_trims_padding() {
+        let mut bytes = IPV4_TCP_HELLO.to_vec();
+        bytes.extend_from_slice(&[0x00; 6]);
+        let ip_pdu = Ip::from_bytes(&bytes).unwrap();
+        let raw = ip_pdu.find::<Raw>().unwrap();
+        assert!(raw.to_bytes() == b"hello");
+    }
+
     #[test]
     fn test_get_version() {
         let ip_pdu = test_ip_pdu();
@@ -503,6 +676,34 @@ mod tests {
         assert!(ip_pdu.dst_addr() == std::net::Ipv4Addr::from_bits(0x00_11_44_55));
     }
 
+    #[test]
+    fn test_frag_fields() {
+        let mut ip_pdu = test_ip_pdu();
+        assert!(ip_pdu.df());
+        assert!(ip_pdu.frag_offset() == 0);
+        assert!(!ip_pdu.is_fragment());
+
+        ip_pdu.with_frag_offset(0x1ABC).with_mf(1).with_df(0);
+        assert!(ip_pdu.frag_offset() == 0x1ABC);
+        assert!(ip_pdu.mf());
+        assert!(!ip_pdu.df());
+        assert!(ip_pdu.flags() == 0b001);
+        assert!(ip_pdu.to_bytes()[IPV4_FRAG_FLAG_OFFSET..IPV4_TTL_OFFSET] == [0x3A, 0xBC]);
+
+        ip_pdu.set_flags(0b010);
+        assert!(ip_pdu.frag_offset() == 0x1ABC);
+        assert!(ip_pdu.df() && !ip_pdu.mf());
+    }
+
+    #[test]
+    fn test_fragment_payload_is_raw() {
+        let mut bytes = IPV4_TCP_HELLO;
+        bytes[IPV4_FRAG_FLAG_OFFSET] = 0x20;
+        let ip_pdu = Ip::from_bytes(&bytes).unwrap();
+        let raw = ip_pdu.child_pdu().as_ref().unwrap().downcast_ref::<Raw>();
+        assert!(raw.unwrap().to_bytes() == IPV4_TCP_HELLO[IPV4_HEADER_LEN..]);
+    }
+
     // #[test]
     // fn test_get_payload() {
     //     let payload = [
@@ -1,41 +1,537 @@
 use crate::ethernet::{ETHER_DISSECTION_TABLE, EtherType};
+use crate::ip::IP_PROTOCOL_NAMES;
+use crate::ip6_ext::{FRAGMENT, Ipv6ExtHeader, parse_ipv6_ext_headers};
+use crate::pcap::{LINKTYPE_DISSECTION_TABLE, LINKTYPE_IPV6, LinkType};
 use crate::prelude::*;
 
+use std::net::Ipv6Addr;
+
+const IPV6_VERSION_OFFSET: usize = 0;
+const IPV6_PAYLOAD_LEN_OFFSET: usize = 4;
+const IPV6_NEXT_HEADER_OFFSET: usize = 6;
+const IPV6_HOP_LIMIT_OFFSET: usize = 7;
+const IPV6_SRC_ADDR_OFFSET: usize = 8;
+const IPV6_DST_ADDR_OFFSET: usize = 24;
 const IPV6_HEADER_LEN: usize = 40;
 
+const IPV6_FLOW_LABEL_MASK: u32 = 0x000F_FFFF;
+
+fn get_ipv6_payload_len(bytes: &[u8]) -> usize {
+    parse_bytes::<u16>(
+        &bytes[IPV6_PAYLOAD_LEN_OFFSET..IPV6_NEXT_HEADER_OFFSET],
+        Endian::Big,
+    ) as usize
+}
+
+fn get_ipv6_next_header(bytes: &[u8]) -> u8 {
+    bytes[IPV6_NEXT_HEADER_OFFSET]
+}
+
 #[pdu_type]
-pub struct Ipv6<'a> {}
+pub struct Ipv6<'a> {
+    ext_headers: Vec<Ipv6ExtHeader<'a>>,
+}
 
 #[pdu_impl]
 impl<'a> Pdu<'a> for Ipv6<'a> {
+    fn clone(&self) -> Box<dyn Pdu<'static> + 'static> {
+        Box::new(Ipv6 {
+            header: Cow::Owned(self.header.to_vec()),
+            ext_headers: self.ext_headers.iter().map(|ext| ext.to_owned()).collect(),
+            parent: None,
+            child: None,
+        })
+    }
+
     fn to_bytes(&self) -> Vec<u8> {
         let mut res = Vec::new();
         res.extend_from_slice(&self.header);
+        for ext in &self.ext_headers {
+            res.extend_from_slice(&ext.to_bytes());
+        }
         res
     }
 
-    default_pdu_clone!(Ipv6);
-
     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("ipv6", "header length", bytes, 0, IPV6_HEADER_LEN)?;
+
+        // Trim to the payload length so trailing link-layer padding is not
+        // dissected. A payload length of zero is used by jumbograms and TCP
+        // segmentation offload.
+        let payload_end = match get_ipv6_payload_len(bytes) {
+            0 => bytes.len(),
+            payload_len => (IPV6_HEADER_LEN + payload_len).min(bytes.len()),
+        };
+        let payload = &bytes[IPV6_HEADER_LEN..payload_end];
+
+        let (ext_headers, upper_layer, offset) =
+            parse_ipv6_ext_headers(get_ipv6_next_header(bytes), payload)
+                .map_err(|err| err.within("ipv6", IPV6_HEADER_LEN))?;
+
+        // Fragments can't be dissected until they are reassembled, except for
+        // atomic fragments which carry the whole datagram.
+        let fragmented = ext_headers
+            .iter()
+            .any(|ext| ext.kind() == FRAGMENT && ext.is_fragmented());
+
+        let inner = if fragmented {
+            Raw::from_bytes(&payload[offset..])?
+        } else {
+            build_from_table(
+                &IPV6_DISSECTION_TABLE,
+                Ipv6Type(upper_layer),
+                &payload[offset..],
+            )
+        };
+
         Ok(Box::new(Self {
+            ext_headers,
             header: Cow::Borrowed(&bytes[..IPV6_HEADER_LEN]),
             parent: None,
-            child: None,
+            child: Some(inner),
         }))
     }
 
     fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        let ext_json: Vec<serde_json::Value> =
+            self.ext_headers.iter().map(|ext| ext.to_json()).collect();
+
         Ok(json!({
             "ipv6": {
+                "ipv6.version": self.version(),
+                "ipv6.tclass": self.traffic_class(),
+                "ipv6.flow": self.flow_label(),
+                "ipv6.plen": self.payload_len(),
+                "ipv6.nxt": self.next_header(),
+                "ipv6.hlim": self.hop_limit(),
+                "ipv6.src": self.src_addr().to_string(),
+                "ipv6.dst": self.dst_addr().to_string(),
+                "ipv6.ext_headers": ext_json,
                 "ipv6.data": self.child_to_json(),
             }
         }))
     }
 }
 
+impl<'a> Ipv6<'a> {
+    pub fn new() -> Self {
+        let mut header = vec![0; IPV6_HEADER_LEN];
+        header[IPV6_VERSION_OFFSET] = 6 << 4;
+        Self {
+            ext_headers: Vec::new(),
+            header: Cow::Owned(header),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn ext_headers(&self) -> &[Ipv6ExtHeader<'a>] {
+        &self.ext_headers
+    }
+
+    pub fn ext_headers_mut(&mut self) -> &mut [Ipv6ExtHeader<'a>] {
+        &mut self.ext_headers
+    }
+
+    pub fn set_ext_headers(&mut self, ext_headers: Vec<Ipv6ExtHeader<'a>>) {
+        self.ext_headers = ext_headers;
+    }
+
+    pub fn with_ext_headers(&mut self, ext_headers: Vec<Ipv6ExtHeader<'a>>) -> &mut Self {
+        self.set_ext_headers(ext_headers);
+        self
+    }
+
+    /// Appends `ext` to the chain. Next header fields are left as they are.
+    pub fn add_ext_header(&mut self, ext: Ipv6ExtHeader<'a>) {
+        self.ext_headers.push(ext);
+    }
+
+    pub fn with_ext_header(&mut self, ext: Ipv6ExtHeader<'a>) -> &mut Self {
+        self.add_ext_header(ext);
+        self
+    }
+
+    /// First extension header of the given kind.
+    pub fn ext_header(&self, kind: u8) -> Option<&Ipv6ExtHeader<'a>> {
+        self.ext_headers.iter().find(|ext| ext.kind() == kind)
+    }
+
+    /// Protocol of the data following the extension header chain.
+    pub fn upper_layer_protocol(&self) -> u8 {
+        self.ext_headers
+            .last()
+            .map_or(self.next_header(), |ext| ext.next_header())
+    }
+
+    fn version_class_flow(&self) -> u32 {
+        parse_bytes::<u32>(
+            &self.header[IPV6_VERSION_OFFSET..IPV6_PAYLOAD_LEN_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    fn set_version_class_flow(&mut self, field: u32) {
+        self.header.to_mut()[IPV6_VERSION_OFFSET..IPV6_PAYLOAD_LEN_OFFSET]
+            .copy_from_slice(&field.to_be_bytes());
+    }
+
+    pub fn version(&self) -> u8 {
+        self.header[IPV6_VERSION_OFFSET] >> 4
+    }
+
+    pub fn set_version(&mut self, version: u8) {
+        let version_ref = &mut self.header.to_mut()[IPV6_VERSION_OFFSET];
+        *version_ref = (*version_ref & 0x0F) | (version << 4);
+    }
+
+    pub fn with_version(&mut self, version: u8) -> &mut Self {
+        self.set_version(version);
+        self
+    }
+
+    pub fn traffic_class(&self) -> u8 {
+        (self.version_class_flow() >> 20) as u8
+    }
+
+    pub fn set_traffic_class(&mut self, traffic_class: u8) {
+        let field = self.version_class_flow();
+        self.set_version_class_flow((field & 0xF00F_FFFF) | ((traffic_class as u32) << 20));
+    }
+
+    pub fn with_traffic_class(&mut self, traffic_class: u8) -> &mut Self {
+        self.set_traffic_class(traffic_class);
+        self
+    }
+
+    pub fn dscp(&self) -> u8 {
+        self.traffic_class() >> 2
+    }
+
+    pub fn set_dscp(&mut self, dscp: u8) {
+        self.set_traffic_class((self.traffic_class() & 0b0000_0011) | (dscp << 2));
+    }
+
+    pub fn with_dscp(&mut self, dscp: u8) -> &mut Self {
+        self.set_dscp(dscp);
+        self
+    }
+
+    pub fn ecn(&self) -> u8 {
+        self.traffic_class() & 0b0000_0011
+    }
+
+    pub fn set_ecn(&mut self, ecn: u8) {
+        self.set_traffic_class((self.traffic_class() & 0b1111_1100) | (ecn & 0b0000_0011));
+    }
+
+    pub fn with_ecn(&mut self, ecn: u8) -> &mut Self {
+        self.set_ecn(ecn);
+        self
+    }
+
+    pub fn flow_label(&self) -> u32 {
+        self.version_class_flow() & IPV6_FLOW_LABEL_MASK
+    }
+
+    pub fn set_flow_label(&mut self, flow_label: u32) {
+        let field = self.version_class_flow();
+        self.set_version_class_flow(
+            (field & !IPV6_FLOW_LABEL_MASK) | (flow_label & IPV6_FLOW_LABEL_MASK),
+        );
+    }
+
+    pub fn with_flow_label(&mut self, flow_label: u32) -> &mut Self {
+        self.set_flow_label(flow_label);
+        self
+    }
+
+    pub fn payload_len(&self) -> u16 {
+        get_ipv6_payload_len(&self.header) as u16
+    }
+
+    pub fn set_payload_len(&mut self, payload_len: u16) {
+        self.header.to_mut()[IPV6_PAYLOAD_LEN_OFFSET..IPV6_NEXT_HEADER_OFFSET]
+            .copy_from_slice(&payload_len.to_be_bytes());
+    }
+
+    pub fn with_payload_len(&mut self, payload_len: u16) -> &mut Self {
+        self.set_payload_len(payload_len);
+        self
+    }
+
+    pub fn next_header(&self) -> u8 {
+        get_ipv6_next_header(&self.header)
+    }
+
+    pub fn set_next_header(&mut self, next_header: u8) {
+        self.header.to_mut()[IPV6_NEXT_HEADER_OFFSET] = next_header;
+    }
+
+    pub fn with_next_header(&mut self, next_header: u8) -> &mut Self {
+        self.set_next_header(next_header);
+        self
+    }
+
+    pub fn hop_limit(&self) -> u8 {
+        self.header[IPV6_HOP_LIMIT_OFFSET]
+    }
+
+    pub fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.header.to_mut()[IPV6_HOP_LIMIT_OFFSET] = hop_limit;
+    }
+
+    pub fn with_hop_limit(&mut self, hop_limit: u8) -> &mut Self {
+        self.set_hop_limit(hop_limit);
+        self
+    }
+
+    pub fn src_addr(&self) -> Ipv6Addr {
+        Ipv6Addr::from_bits(parse_bytes(
+            &self.header[IPV6_SRC_ADDR_OFFSET..IPV6_DST_ADDR_OFFSET],
+            Endian::Big,
+        ))
+    }
+
+    pub fn set_src_addr(&mut self, src_addr: Ipv6Addr) {
+        self.header.to_mut()[IPV6_SRC_ADDR_OFFSET..IPV6_DST_ADDR_OFFSET]
+            .copy_from_slice(&src_addr.octets());
+    }
+
+    pub fn with_src_addr(&mut self, src_addr: Ipv6Addr) -> &mut Self {
+        self.set_src_addr(src_addr);
+        self
+    }
+
+    pub fn dst_addr(&self) -> Ipv6Addr {
+        Ipv6Addr::from_bits(parse_bytes(
+            &self.header[IPV6_DST_ADDR_OFFSET..IPV6_HEADER_LEN],
+            Endian::Big,
+        ))
+    }
+
+    pub fn set_dst_addr(&mut self, dst_addr: Ipv6Addr) {
+        self.header.to_mut()[IPV6_DST_ADDR_OFFSET..IPV6_HEADER_LEN]
+            .copy_from_slice(&dst_addr.octets());
+    }
+
+    pub fn with_dst_addr(&mut self, dst_addr: Ipv6Addr) -> &mut Self {
+        self.set_dst_addr(dst_addr);
+        self
+    }
+}
+
 register_pdu!(EtherType(0x86DD), Ipv6, ETHER_DISSECTION_TABLE);
+register_pdu!(LinkType(LINKTYPE_IPV6), Ipv6, LINKTYPE_DISSECTION_TABLE);
 
-#[derive(Hash, Eq, PartialEq)]
+register_fields!(Ipv6, "ipv6", {
+    "ipv6.version" => version { name: "Version", bits: (IPV6_VERSION_OFFSET * 8, 4) },
+    "ipv6.tclass" => traffic_class {
+        name: "Traffic Class",
+        bits: (IPV6_VERSION_OFFSET * 8 + 4, 8),
+        base: Hex,
+    },
+    "ipv6.flow" => flow_label {
+        name: "Flow Label",
+        bits: (IPV6_VERSION_OFFSET * 8 + 12, 20),
+        base: Hex,
+    },
+    "ipv6.plen" => payload_len { name: "Payload Length", bits: (IPV6_PAYLOAD_LEN_OFFSET * 8, 16) },
+    "ipv6.nxt" => next_header {
+        name: "Next Header",
+        bits: (IPV6_NEXT_HEADER_OFFSET * 8, 8),
+        values: IP_PROTOCOL_NAMES,
+    },
+    "ipv6.hlim" => hop_limit { name: "Hop Limit", bits: (IPV6_HOP_LIMIT_OFFSET * 8, 8) },
+    "ipv6.src" => src_addr { name: "Source Address", bits: (IPV6_SRC_ADDR_OFFSET * 8, 128) },
+    "ipv6.dst" => dst_addr { name: "Destination Address", bits: (IPV6_DST_ADDR_OFFSET * 8, 128) },
+});
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct Ipv6Type(pub u8);
 
 pub static IPV6_DISSECTION_TABLE: DissectionTable<Ipv6Type> = create_table();
+
+#[cfg(test)]
+mod tests {
+    use crate::ip6_ext::{AUTH, DEST_OPTS, HOP_BY_HOP, ROUTING, RT_SEGMENT_ROUTING};
+    use crate::pdu::deserialize;
+    use crate::raw::Raw;
+    use crate::tcp::Tcp;
+    use crate::udp::Udp;
+
+    use super::*;
+
+    fn ipv6_header(payload_len: u16, next_header: u8) -> Vec<u8> {
+        let mut bytes = vec![
+            0x6A, 0xB1, 0x23, 0x45, // Version 6, tclass 0xAB, flow 0x12345
+        ];
+        bytes.extend_from_slice(&payload_len.to_be_bytes());
+        bytes.extend_from_slice(&[next_header, 0x40]); // next header, hop limit 64
+        bytes.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+        bytes.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2).octets());
+        bytes
+    }
+
+    const UDP_HELLO: [u8; 13] = [
+        0x30, 0x39, 0x30, 0x3a, 0x00, 0x0d, 0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+    ];
+
+    const TCP_SYN: [u8; 20] = [
+        0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x50, 0x02, 0xff,
+        0xff, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_header_fields() {
+        let bytes = [ipv6_header(13, 17), UDP_HELLO.to_vec()].concat();
+        let ipv6 = deserialize::<Ipv6>(&bytes).unwrap();
+        assert!(ipv6.version() == 6);
+        assert!(ipv6.traffic_class() == 0xAB);
+        assert!(ipv6.dscp() == 0xAB >> 2);
+        assert!(ipv6.ecn() == 0b11);
+        assert!(ipv6.flow_label() == 0x12345);
+        assert!(ipv6.payload_len() == 13);
+        assert!(ipv6.next_header() == 17);
+        assert!(ipv6.hop_limit() == 64);
+        assert!(ipv6.src_addr() == "2001:db8::1".parse::<Ipv6Addr>().unwrap());
+        assert!(ipv6.dst_addr() == "2001:db8::2".parse::<Ipv6Addr>().unwrap());
+        assert!(Ipv6::from_bytes(&bytes).unwrap().find::<Udp>().is_some());
+        assert!(ipv6.to_bytes() == bytes[..IPV6_HEADER_LEN]);
+    }
+
+    #[test]
+    fn test_set_fields() {
+        let mut ipv6 = Ipv6::new();
+        ipv6.with_traffic_class(0xAB)
+            .with_flow_label(0xFFFFF)
+            .with_payload_len(20)
+            .with_next_header(6)
+            .with_hop_limit(255)
+            .with_src_addr("fe80::1".parse().unwrap())
+            .with_dst_addr("ff02::1".parse().unwrap());
+        assert!(ipv6.version() == 6);
+        assert!(ipv6.traffic_class() == 0xAB);
+        assert!(ipv6.flow_label() == 0xFFFFF);
+
+        ipv6.set_dscp(0);
+        ipv6.set_flow_label(0x1);
+        assert!(ipv6.traffic_class() == 0b11);
+        assert!(ipv6.flow_label() == 0x1);
+        assert!(ipv6.version() == 6);
+        assert!(ipv6.payload_len() == 20);
+        assert!(ipv6.hop_limit() == 255);
+        assert!(ipv6.src_addr() == "fe80::1".parse::<Ipv6Addr>().unwrap());
+        assert!(ipv6.dst_addr() == "ff02::1".parse::<Ipv6Addr>().unwrap());
+    }
+
+    #[test]
+    fn test_ext_header_chain() {
+        let hop_by_hop = [DEST_OPTS, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00];
+        let dest_opts = [ROUTING, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00];
+        let mut srh = vec![AUTH, 0x04, RT_SEGMENT_ROUTING, 0x01, 0x01, 0x00, 0x00, 0x07];
+        srh.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0xa).octets());
+        srh.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0xb).octets());
+        let mut ah = vec![
+            6, 0x04, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x05,
+        ];
+        ah.extend_from_slice(&[0xcc; 12]);
+
+        let exts = [hop_by_hop.to_vec(), dest_opts.to_vec(), srh, ah].concat();
+        let payload_len = (exts.len() + TCP_SYN.len()) as u16;
+        let bytes = [ipv6_header(payload_len, HOP_BY_HOP), exts, TCP_SYN.to_vec()].concat();
+
+        let ipv6 = deserialize::<Ipv6>(&bytes).unwrap();
+        let kinds: Vec<u8> = ipv6.ext_headers().iter().map(|ext| ext.kind()).collect();
+        assert!(kinds == [HOP_BY_HOP, DEST_OPTS, ROUTING, AUTH]);
+        assert!(ipv6.upper_layer_protocol() == 6);
+
+        let srh = ipv6.ext_header(ROUTING).unwrap();
+        assert!(srh.len() == 40);
+        assert!(srh.routing_type() == RT_SEGMENT_ROUTING);
+        assert!(srh.segments_left() == 1);
+        assert!(srh.srh_tag() == 7);
+        assert!(srh.addresses()[1] == "2001:db8::b".parse::<Ipv6Addr>().unwrap());
+
+        let ah = ipv6.ext_header(AUTH).unwrap();
+        assert!(ah.len() == 24);
+        assert!(ah.spi() == 0x100);
+        assert!(ah.ah_seq_number() == 5);
+        assert!(ah.icv() == [0xcc; 12]);
+
+        assert!(Ipv6::from_bytes(&bytes).unwrap().find::<Tcp>().is_some());
+        assert!(ipv6.to_bytes() == bytes[..bytes.len() - TCP_SYN.len()]);
+    }
+
+    #[test]
+    fn test_fragment_not_dissected() {
+        let frag = [17, 0x00, 0x00, 0x01, 0xde, 0xad, 0xbe, 0xef];
+        let payload_len = (frag.len() + UDP_HELLO.len()) as u16;
+        let bytes = [
+            ipv6_header(payload_len, FRAGMENT),
+            frag.to_vec(),
+            UDP_HELLO.to_vec(),
+        ]
+        .concat();
+
+        let ipv6 = deserialize::<Ipv6>(&bytes).unwrap();
+        let frag = ipv6.ext_header(FRAGMENT).unwrap();
+        assert!(frag.frag_offset() == 0);
+        assert!(frag.more_fragments());
+        assert!(frag.identification() == 0xdeadbeef);
+        assert!(Ipv6::from_bytes(&bytes).unwrap().find::<Raw>().is_some());
+
+        // An atomic fragment holds the whole datagram.
+        let mut bytes = bytes.clone();
+        bytes[IPV6_HEADER_LEN + 3] = 0;
+        assert!(Ipv6::from_bytes(&bytes).unwrap().find::<Udp>().is_some());
+    }
+
+    #[test]
+    fn test_trims_padding() {
+        let mut bytes = [ipv6_header(13, 17), UDP_HELLO.to_vec()].concat();
+        bytes.extend_from_slice(&[0; 6]);
+        assert!(
+            Ipv6::from_bytes(&bytes)
+                .unwrap()
+                .find::<Raw>()
+                .unwrap()
+                .to_bytes()
+                == b"hello"
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(Ipv6::from_bytes(&[0x60; 39]).is_err());
+        let bytes = [ipv6_header(4, HOP_BY_HOP), vec![17, 0x00, 0x00, 0x00]].concat();
+        assert!(Ipv6::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_short_auth_header() {
+        let ah = [17, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01];
+        let err = Ipv6ExtHeader::from_bytes(AUTH, &ah).err().unwrap();
+        assert!(
+            err.to_string()
+                == "ipv6 ext header: length 0 is too short for an authentication header at offset 1"
+        );
+
+        let bytes = [ipv6_header(21, AUTH), ah.to_vec(), UDP_HELLO.to_vec()].concat();
+        assert!(Ipv6::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_build_ext_header() {
+        let mut frag = Ipv6ExtHeader::new(FRAGMENT, 0);
+        frag.with_next_header(17)
+            .with_frag_offset(185)
+            .with_more_fragments(true)
+            .with_identification(42);
+        assert!(frag.to_bytes() == [17, 0, 0x05, 0xC9, 0, 0, 0, 42]);
+
+        let opts = Ipv6ExtHeader::new(DEST_OPTS, 10);
+        assert!(opts.len() == 16);
+        assert!(opts.to_bytes()[1] == 1);
+    }
+}
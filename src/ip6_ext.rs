@@ -0,0 +1,420 @@
+use crate::prelude::*;
+
+use std::net::Ipv6Addr;
+
+const IPV6_EXT_NEXT_HEADER_OFFSET: usize = 0;
+const IPV6_EXT_LEN_OFFSET: usize = 1;
+const IPV6_EXT_DATA_OFFSET: usize = 2;
+const IPV6_EXT_LEN_UNIT: usize = 8;
+const IPV6_EXT_MIN_LEN: usize = 8;
+
+const IPV6_AH_LEN_UNIT: usize = 4;
+const IPV6_AH_SPI_OFFSET: usize = 4;
+const IPV6_AH_SEQ_OFFSET: usize = 8;
+const IPV6_AH_ICV_OFFSET: usize = 12;
+
+const IPV6_RT_TYPE_OFFSET: usize = 2;
+const IPV6_RT_SEGS_LEFT_OFFSET: usize = 3;
+const IPV6_RT_DATA_OFFSET: usize = 8;
+const IPV6_SRH_LAST_ENTRY_OFFSET: usize = 4;
+const IPV6_SRH_FLAGS_OFFSET: usize = 5;
+const IPV6_SRH_TAG_OFFSET: usize = 6;
+
+const IPV6_FRAG_OFFSET_OFFSET: usize = 2;
+const IPV6_FRAG_ID_OFFSET: usize = 4;
+const IPV6_FRAG_LEN: usize = 8;
+
+const IPV6_MH_TYPE_OFFSET: usize = 2;
+
+pub const HOP_BY_HOP: u8 = 0;
+pub const ROUTING: u8 = 43;
+pub const FRAGMENT: u8 = 44;
+pub const ESP: u8 = 50;
+pub const AUTH: u8 = 51;
+pub const NO_NEXT_HEADER: u8 = 59;
+pub const DEST_OPTS: u8 = 60;
+pub const MOBILITY: u8 = 135;
+
+/// Routing header types
+pub const RT_SOURCE_ROUTE: u8 = 0;
+pub const RT_MOBILE_IP: u8 = 2;
+pub const RT_SEGMENT_ROUTING: u8 = 4;
+
+/// Hop-by-Hop and Destination option types
+pub const OPT_PAD1: u8 = 0;
+pub const OPT_PADN: u8 = 1;
+pub const OPT_JUMBO: u8 = 0xC2;
+
+/// Whether `next_header` is an extension header this crate walks through.
+pub fn is_ipv6_ext_header(next_header: u8) -> bool {
+    matches!(
+        next_header,
+        HOP_BY_HOP | ROUTING | FRAGMENT | AUTH | DEST_OPTS | MOBILITY
+    )
+}
+
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc8200#section-4>
+pub fn get_ipv6_ext_length(kind: u8, bytes: &[u8]) -> Result<usize, ParseError> {
+    ParseError::check_len(
+        "ipv6 ext header",
+        "header length",
+        bytes,
+        0,
+        IPV6_EXT_MIN_LEN,
+    )?;
+
+    let ext_len = bytes[IPV6_EXT_LEN_OFFSET] as usize;
+    let len = match kind {
+        FRAGMENT => IPV6_FRAG_LEN,
+        AUTH => (ext_len + 2) * IPV6_AH_LEN_UNIT,
+        _ => (ext_len + 1) * IPV6_EXT_LEN_UNIT,
+    };
+    // AH always carries an SPI and a sequence number before the ICV.
+    if kind == AUTH && len < IPV6_AH_ICV_OFFSET {
+        return Err(ParseError::InvalidHeader {
+            protocol: "ipv6 ext header",
+            field: "length",
+            offset: IPV6_EXT_LEN_OFFSET,
+            value: ext_len as u64,
+            reason: "is too short for an authentication header",
+        });
+    }
+
+    ParseError::check_len("ipv6 ext header", "length", bytes, 0, len)?;
+    Ok(len)
+}
+
+/// Walks the extension header chain starting with `next_header`. Returns the
+/// headers, the upper layer protocol and the offset of the upper layer data.
+pub fn parse_ipv6_ext_headers(
+    mut next_header: u8,
+    bytes: &[u8],
+) -> Result<(Vec<Ipv6ExtHeader<'_>>, u8, usize), ParseError> {
+    let mut headers = Vec::new();
+    let mut offset = 0;
+    while is_ipv6_ext_header(next_header) {
+        let len = get_ipv6_ext_length(next_header, &bytes[offset..])
+            .map_err(|err| err.shifted(offset))?;
+        let header = Ipv6ExtHeader {
+            kind: next_header,
+            header: Cow::Borrowed(&bytes[offset..offset + len]),
+        };
+        next_header = header.next_header();
+        offset += len;
+        // Whatever follows a fragment header belongs to the fragmentable
+        // part, which can't be walked until it is reassembled.
+        let fragmented = header.kind == FRAGMENT && header.is_fragmented();
+        headers.push(header);
+        if fragmented {
+            break;
+        }
+    }
+    Ok((headers, next_header, offset))
+}
+
+/// An IPv6 extension header. Its kind comes from the previous header's next
+/// header field.
+pub struct Ipv6ExtHeader<'a> {
+    kind: u8,
+    header: Cow<'a, [u8]>,
+}
+
+impl<'a> Ipv6ExtHeader<'a> {
+    /// Builds a zeroed extension header of the given kind and total length.
+    /// Lengths are rounded up to the unit the kind is measured in.
+    pub fn new(kind: u8, len: usize) -> Self {
+        let mut res = Self {
+            kind,
+            header: Cow::Owned(Vec::new()),
+        };
+        res.set_len(len);
+        res
+    }
+
+    /// Parses a single extension header of the given kind from the start of
+    /// `bytes`.
+    pub fn from_bytes(kind: u8, bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let len = get_ipv6_ext_length(kind, bytes)?;
+        Ok(Self {
+            kind,
+            header: Cow::Borrowed(&bytes[..len]),
+        })
+    }
+
+    pub fn to_owned(&self) -> Ipv6ExtHeader<'static> {
+        Ipv6ExtHeader {
+            kind: self.kind,
+            header: Cow::Owned(self.header.to_vec()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.header.to_vec()
+    }
+
+    pub fn kind(&self) -> u8 {
+        self.kind
+    }
+
+    pub fn next_header(&self) -> u8 {
+        self.header[IPV6_EXT_NEXT_HEADER_OFFSET]
+    }
+
+    pub fn set_next_header(&mut self, next_header: u8) {
+        self.header.to_mut()[IPV6_EXT_NEXT_HEADER_OFFSET] = next_header;
+    }
+
+    pub fn with_next_header(&mut self, next_header: u8) -> &mut Self {
+        self.set_next_header(next_header);
+        self
+    }
+
+    /// Total length of the header in bytes.
+    pub fn len(&self) -> usize {
+        self.header.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty()
+    }
+
+    /// Resizes the header, zero filling or truncating the data, and updates
+    /// the length field to match.
+    pub fn set_len(&mut self, len: usize) {
+        let (len, ext_len) = match self.kind {
+            FRAGMENT => (IPV6_FRAG_LEN, 0),
+            AUTH => {
+                let len = len
+                    .max(IPV6_AH_ICV_OFFSET)
+                    .next_multiple_of(IPV6_AH_LEN_UNIT);
+                (len, len / IPV6_AH_LEN_UNIT - 2)
+            }
+            _ => {
+                let len = len
+                    .max(IPV6_EXT_MIN_LEN)
+                    .next_multiple_of(IPV6_EXT_LEN_UNIT);
+                (len, len / IPV6_EXT_LEN_UNIT - 1)
+            }
+        };
+        let header = self.header.to_mut();
+        header.resize(len, 0);
+        header[IPV6_EXT_LEN_OFFSET] = ext_len as u8;
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.header[IPV6_EXT_DATA_OFFSET..]
+    }
+
+    /// Hop-by-Hop and Destination Options TLVs as (type, data) pairs, padding
+    /// included.
+    pub fn options(&self) -> Vec<(u8, &[u8])> {
+        let mut opts = Vec::new();
+        let data = self.data();
+        let mut offset = 0;
+        while offset < data.len() {
+            let opt_type = data[offset];
+            if opt_type == OPT_PAD1 {
+                opts.push((opt_type, &data[offset..offset]));
+                offset += 1;
+                continue;
+            }
+            let Some(&len) = data.get(offset + 1) else {
+                break;
+            };
+            let end = (offset + 2 + len as usize).min(data.len());
+            opts.push((opt_type, &data[offset + 2..end]));
+            offset = end;
+        }
+        opts
+    }
+
+    /// Payload length carried by a Jumbo Payload option, if present.
+    pub fn jumbo_payload_len(&self) -> Option<u32> {
+        self.options()
+            .into_iter()
+            .find(|(opt_type, data)| *opt_type == OPT_JUMBO && data.len() == 4)
+            .map(|(_, data)| parse_bytes::<u32>(data, Endian::Big))
+    }
+
+    pub fn routing_type(&self) -> u8 {
+        self.header[IPV6_RT_TYPE_OFFSET]
+    }
+
+    pub fn segments_left(&self) -> u8 {
+        self.header[IPV6_RT_SEGS_LEFT_OFFSET]
+    }
+
+    pub fn set_segments_left(&mut self, segments_left: u8) {
+        self.header.to_mut()[IPV6_RT_SEGS_LEFT_OFFSET] = segments_left;
+    }
+
+    pub fn with_segments_left(&mut self, segments_left: u8) -> &mut Self {
+        self.set_segments_left(segments_left);
+        self
+    }
+
+    /// Addresses carried by a type 0, type 2 or Segment Routing header. SRH
+    /// segments are listed in reverse order, as on the wire.
+    pub fn addresses(&self) -> Vec<Ipv6Addr> {
+        let data = &self.header[IPV6_RT_DATA_OFFSET..];
+        let count = match self.routing_type() {
+            RT_SEGMENT_ROUTING => self.last_entry() as usize + 1,
+            _ => data.len() / 16,
+        };
+        data.chunks_exact(16)
+            .take(count)
+            .map(|addr| Ipv6Addr::from(<[u8; 16]>::try_from(addr).unwrap()))
+            .collect()
+    }
+
+    /// Segment Routing Header last entry index.
+    /// Refer to <https://datatracker.ietf.org/doc/html/rfc8754#section-2>
+    pub fn last_entry(&self) -> u8 {
+        self.header[IPV6_SRH_LAST_ENTRY_OFFSET]
+    }
+
+    pub fn srh_flags(&self) -> u8 {
+        self.header[IPV6_SRH_FLAGS_OFFSET]
+    }
+
+    pub fn srh_tag(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[IPV6_SRH_TAG_OFFSET..IPV6_RT_DATA_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    /// Segment Routing Header TLVs following the segment list.
+    pub fn srh_tlvs(&self) -> &[u8] {
+        let start = IPV6_RT_DATA_OFFSET + 16 * (self.last_entry() as usize + 1);
+        self.header.get(start..).unwrap_or_default()
+    }
+
+    /// Fragment offset in 8 byte units.
+    pub fn frag_offset(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[IPV6_FRAG_OFFSET_OFFSET..IPV6_FRAG_ID_OFFSET],
+            Endian::Big,
+        ) >> 3
+    }
+
+    pub fn set_frag_offset(&mut self, frag_offset: u16) {
+        let field = (frag_offset << 3) | (self.more_fragments() as u16);
+        self.header.to_mut()[IPV6_FRAG_OFFSET_OFFSET..IPV6_FRAG_ID_OFFSET]
+            .copy_from_slice(&field.to_be_bytes());
+    }
+
+    pub fn with_frag_offset(&mut self, frag_offset: u16) -> &mut Self {
+        self.set_frag_offset(frag_offset);
+        self
+    }
+
+    /// Whether this fragment header splits its datagram, which an atomic
+    /// fragment doesn't.
+    pub fn is_fragmented(&self) -> bool {
+        self.frag_offset() != 0 || self.more_fragments()
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        (self.header[IPV6_FRAG_ID_OFFSET - 1] & 0x1) != 0
+    }
+
+    pub fn set_more_fragments(&mut self, more_fragments: bool) {
+        let flags = &mut self.header.to_mut()[IPV6_FRAG_ID_OFFSET - 1];
+        *flags = (*flags & !0x1) | more_fragments as u8;
+    }
+
+    pub fn with_more_fragments(&mut self, more_fragments: bool) -> &mut Self {
+        self.set_more_fragments(more_fragments);
+        self
+    }
+
+    pub fn identification(&self) -> u32 {
+        parse_bytes::<u32>(
+            &self.header[IPV6_FRAG_ID_OFFSET..IPV6_FRAG_LEN],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_identification(&mut self, identification: u32) {
+        self.header.to_mut()[IPV6_FRAG_ID_OFFSET..IPV6_FRAG_LEN]
+            .copy_from_slice(&identification.to_be_bytes());
+    }
+
+    pub fn with_identification(&mut self, identification: u32) -> &mut Self {
+        self.set_identification(identification);
+        self
+    }
+
+    pub fn spi(&self) -> u32 {
+        parse_bytes::<u32>(
+            &self.header[IPV6_AH_SPI_OFFSET..IPV6_AH_SEQ_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn ah_seq_number(&self) -> u32 {
+        parse_bytes::<u32>(
+            &self.header[IPV6_AH_SEQ_OFFSET..IPV6_AH_ICV_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn icv(&self) -> &[u8] {
+        &self.header[IPV6_AH_ICV_OFFSET..]
+    }
+
+    pub fn mh_type(&self) -> u8 {
+        self.header[IPV6_MH_TYPE_OFFSET]
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut res = json!({
+            "ipv6.ext.type": self.kind(),
+            "ipv6.ext.nxt": self.next_header(),
+            "ipv6.ext.len": self.len(),
+        });
+        let fields = res.as_object_mut().unwrap();
+
+        match self.kind {
+            HOP_BY_HOP | DEST_OPTS => {
+                let opts: Vec<serde_json::Value> = self
+                    .options()
+                    .iter()
+                    .filter(|(opt_type, _)| *opt_type != OPT_PAD1 && *opt_type != OPT_PADN)
+                    .map(|(opt_type, data)| {
+                        json!({
+                            "ipv6.opt.type": opt_type,
+                            "ipv6.opt.data": printable_ascii(data),
+                        })
+                    })
+                    .collect();
+                fields.insert("ipv6.ext.opts".into(), json!(opts));
+            }
+            ROUTING => {
+                fields.insert("ipv6.routing.type".into(), json!(self.routing_type()));
+                fields.insert("ipv6.routing.segleft".into(), json!(self.segments_left()));
+                let addrs: Vec<String> = self.addresses().iter().map(|a| a.to_string()).collect();
+                fields.insert("ipv6.routing.addrs".into(), json!(addrs));
+                if self.routing_type() == RT_SEGMENT_ROUTING {
+                    fields.insert("ipv6.routing.srh.tag".into(), json!(self.srh_tag()));
+                }
+            }
+            FRAGMENT => {
+                fields.insert("ipv6.fragment.offset".into(), json!(self.frag_offset()));
+                fields.insert("ipv6.fragment.more".into(), json!(self.more_fragments()));
+                fields.insert("ipv6.fragment.id".into(), json!(self.identification()));
+            }
+            AUTH => {
+                fields.insert("ipv6.ah.spi".into(), json!(self.spi()));
+                fields.insert("ipv6.ah.seq".into(), json!(self.ah_seq_number()));
+            }
+            MOBILITY => {
+                fields.insert("ipv6.mobility.type".into(), json!(self.mh_type()));
+            }
+            _ => (),
+        }
+
+        res
+    }
+}
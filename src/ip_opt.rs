@@ -1,14 +1,29 @@
 use crate::{default_pdu_clone, prelude::*};
 
+use std::net::Ipv4Addr;
+
 const IPV4_OPT_TYPE_OFFSET: usize = 0;
 const IPV4_OPT_SIZE_OFFSET: usize = 1;
-// const IPV4_OPT_DATA_OFFSET: usize = 3;
+const IPV4_OPT_DATA_OFFSET: usize = 2;
 const IPV4_OPT_SIZE: usize = 2;
+const IPV4_OPT_POINTER_OFFSET: usize = 2;
+const IPV4_OPT_ROUTE_OFFSET: usize = 3;
+const IPV4_OPT_TS_FLAGS_OFFSET: usize = 3;
+const IPV4_OPT_TS_OFFSET: usize = 4;
+const IPV4_OPT_SEC_LEN: usize = 11;
+const IPV4_OPT_SID_LEN: usize = 4;
+const IPV4_OPT_NUMBER_MASK: u8 = 0x1F;
+const IPV4_OPT_ALIGN: usize = 4;
 
 pub fn get_ip_opt_type(bytes: &[u8]) -> u8 {
     bytes[IPV4_OPT_TYPE_OFFSET]
 }
 
+/// Option number without the copied flag and class bits.
+pub fn get_ip_opt_number(bytes: &[u8]) -> u8 {
+    get_ip_opt_type(bytes) & IPV4_OPT_NUMBER_MASK
+}
+
 pub const END: u8 = 0;
 pub const NOP: u8 = 1;
 pub const SEC: u8 = 2;
@@ -18,21 +33,85 @@ pub const REC: u8 = 7;
 pub const SID: u8 = 8;
 pub const ITS: u8 = 4;
 
+/// Timestamp option flags
+pub const TS_ONLY: u8 = 0;
+pub const TS_ADDR: u8 = 1;
+pub const TS_PRESPEC: u8 = 3;
+
 /// Refer to <https://datatracker.ietf.org/doc/html/rfc791#section-3.1>
 pub fn get_ip_opt_length(bytes: &[u8]) -> Result<usize, ParseError> {
     let opt_len = match get_ip_opt_type(bytes) {
-        END => 1,
-        NOP => 1,
-        SEC => 11,
-        SID => 4,
-        LSR | ITS | SSR | REC => IPV4_OPT_SIZE + bytes[IPV4_OPT_SIZE_OFFSET] as usize,
-        _ => 0,
+        END | NOP => return Ok(1),
+        _ => match bytes.get(IPV4_OPT_SIZE_OFFSET) {
+            Some(&len) => len as usize,
+            None => {
+                return Err(ParseError::NotEnoughData {
+                    protocol: "ip option",
+                    field: "length",
+                    offset: IPV4_OPT_SIZE_OFFSET,
+                    expected: 1,
+                    actual: 0,
+                });
+            }
+        },
+    };
+
+    let min_len = match get_ip_opt_number(bytes) {
+        SEC => IPV4_OPT_SEC_LEN,
+        SID => IPV4_OPT_SID_LEN,
+        LSR | SSR | REC => IPV4_OPT_ROUTE_OFFSET,
+        ITS => IPV4_OPT_TS_OFFSET,
+        _ => IPV4_OPT_SIZE,
     };
-    if opt_len > 0 {
-        Ok(opt_len)
-    } else {
-        Err(ParseError::UnsupportedProtocol)
+
+    if opt_len < min_len {
+        return Err(ParseError::InvalidHeader {
+            protocol: "ip option",
+            field: "length",
+            offset: IPV4_OPT_SIZE_OFFSET,
+            value: opt_len as u64,
+            reason: "is too short for the option",
+        });
+    }
+    ParseError::check_len("ip option", "length", bytes, 0, opt_len)?;
+    Ok(opt_len)
+}
+
+/// Parses every option in `bytes`. Anything after an END option is padding
+/// and is ignored.
+pub fn parse_ip_options(bytes: &[u8]) -> Result<Vec<IpOption<'_>>, ParseError> {
+    let mut opts = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let opt_len = get_ip_opt_length(&bytes[offset..]).map_err(|err| err.shifted(offset))?;
+        let opt = IpOption {
+            header: Cow::Borrowed(&bytes[offset..offset + opt_len]),
+            parent: None,
+            child: None,
+        };
+        offset += opt_len;
+
+        let end = opt.opt_type() == END;
+        opts.push(opt);
+        if end {
+            break;
+        }
     }
+    Ok(opts)
+}
+
+/// Serializes `opts`, zero padding to a 4 byte boundary.
+pub fn ip_options_to_bytes(opts: &[IpOption]) -> Vec<u8> {
+    let mut res: Vec<u8> = opts.iter().flat_map(|opt| opt.to_bytes()).collect();
+    res.resize(res.len().next_multiple_of(IPV4_OPT_ALIGN), END);
+    res
+}
+
+fn parse_addrs(bytes: &[u8]) -> Vec<Ipv4Addr> {
+    bytes
+        .chunks_exact(4)
+        .map(|addr| Ipv4Addr::from_bits(parse_bytes(addr, Endian::Big)))
+        .collect()
 }
 
 #[pdu_type]
@@ -49,15 +128,63 @@ impl<'a> Pdu<'a> for IpOption<'a> {
     default_pdu_clone!(IpOption);
 
     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        let opt_len = get_ip_opt_length(bytes)?;
         Ok(Box::new(Self {
-            header: Cow::Borrowed(&bytes),
+            header: Cow::Borrowed(&bytes[..opt_len]),
             parent: None,
             child: None,
         }))
     }
 
     fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
-        todo!()
+        let addrs_json = |addrs: Vec<Ipv4Addr>| -> Vec<String> {
+            addrs.iter().map(|addr| addr.to_string()).collect()
+        };
+
+        let mut res = json!({
+            "ip.opt.type": self.opt_type(),
+            "ip.opt.len": self.opt_length(),
+        });
+        let fields = res.as_object_mut().unwrap();
+
+        match self.opt_number() {
+            END | NOP if self.header.len() == 1 => (),
+            LSR | SSR | REC => {
+                fields.insert("ip.opt.ptr".into(), json!(self.pointer()));
+                fields.insert("ip.opt.route".into(), json!(addrs_json(self.route())));
+            }
+            ITS => {
+                fields.insert("ip.opt.ptr".into(), json!(self.pointer()));
+                fields.insert("ip.opt.ts.overflow".into(), json!(self.ts_overflow()));
+                fields.insert("ip.opt.ts.flags".into(), json!(self.ts_flags()));
+                let entries: Vec<serde_json::Value> = self
+                    .timestamps()
+                    .iter()
+                    .map(|(addr, ts)| json!({"ip.opt.ts.addr": addr.map(|a| a.to_string()), "ip.opt.ts.value": ts}))
+                    .collect();
+                fields.insert("ip.opt.ts.entries".into(), json!(entries));
+            }
+            SEC if self.header.len() == IPV4_OPT_SEC_LEN => {
+                fields.insert("ip.opt.sec.level".into(), json!(self.security()));
+                fields.insert("ip.opt.sec.compartments".into(), json!(self.compartments()));
+                fields.insert(
+                    "ip.opt.sec.handling".into(),
+                    json!(self.handling_restrictions()),
+                );
+                fields.insert("ip.opt.sec.tcc".into(), json!(self.tcc()));
+            }
+            SID if self.header.len() == IPV4_OPT_SID_LEN => {
+                fields.insert("ip.opt.sid".into(), json!(self.stream_id()));
+            }
+            _ => {
+                fields.insert(
+                    "ip.opt.data".into(),
+                    json!(printable_ascii(self.opt_data())),
+                );
+            }
+        }
+
+        Ok(json!({ "ip.opt": res }))
     }
 }
 
@@ -70,6 +197,14 @@ impl<'a> IpOption<'a> {
         }
     }
 
+    pub fn to_owned(&self) -> IpOption<'static> {
+        IpOption {
+            header: Cow::Owned(self.header.to_vec()),
+            child: None,
+            parent: None,
+        }
+    }
+
     pub fn opt_type(&self) -> u8 {
         self.header[IPV4_OPT_TYPE_OFFSET]
     }
@@ -83,37 +218,163 @@ impl<'a> IpOption<'a> {
         self
     }
 
+    pub fn copied(&self) -> bool {
+        (self.opt_type() & 0x80) != 0
+    }
+
+    pub fn opt_class(&self) -> u8 {
+        (self.opt_type() >> 5) & 0x3
+    }
+
+    pub fn opt_number(&self) -> u8 {
+        get_ip_opt_number(&self.header)
+    }
+
+    /// END and NOP are single byte options without a length field.
     pub fn opt_length(&self) -> u8 {
-        self.header[IPV4_OPT_SIZE_OFFSET]
+        match self.header.get(IPV4_OPT_SIZE_OFFSET) {
+            Some(&len) if self.header.len() > 1 => len,
+            _ => 1,
+        }
     }
 
-    pub fn set_opt_length(&mut self, opt_type: u8) {
-        self.header.to_mut()[IPV4_OPT_SIZE_OFFSET] = opt_type;
+    pub fn set_opt_length(&mut self, opt_length: u8) {
+        self.header.to_mut()[IPV4_OPT_SIZE_OFFSET] = opt_length;
     }
 
-    pub fn with_opt_length(&mut self, opt_type: u8) -> &mut Self {
-        self.set_opt_type(opt_type);
+    pub fn with_opt_length(&mut self, opt_length: u8) -> &mut Self {
+        self.set_opt_length(opt_length);
+        self
+    }
+
+    pub fn opt_data(&self) -> &[u8] {
+        self.header.get(IPV4_OPT_DATA_OFFSET..).unwrap_or_default()
+    }
+
+    /// Replaces the option data and updates the length field to match.
+    pub fn set_opt_data(&mut self, data: &[u8]) {
+        let header = self.header.to_mut();
+        header.resize(IPV4_OPT_DATA_OFFSET, 0);
+        header.extend_from_slice(data);
+        header[IPV4_OPT_SIZE_OFFSET] = (IPV4_OPT_DATA_OFFSET + data.len()) as u8;
+    }
+
+    pub fn with_opt_data(&mut self, data: &[u8]) -> &mut Self {
+        self.set_opt_data(data);
         self
     }
 
-    // pub fn opt_data(&self) -> &[u8] {
-    //     &self.header[IPV4_OPT_DATA_OFFSET..self.opt_length() as usize]
-    // }
-    //
-    // pub fn set_opt_data(&mut self, data: &[u8]) {
-    //     self.data.to_mut().extend_from_slice(data);
-    // }
-    //
-    // pub fn with_opt_data(&mut self, data: &[u8]) -> &mut Self {
-    //     self.set_opt_data(data);
-    //     self
-    // }
+    /// Record Route, Source Route and Timestamp pointer. One based, relative
+    /// to the start of the option.
+    pub fn pointer(&self) -> u8 {
+        self.header[IPV4_OPT_POINTER_OFFSET]
+    }
+
+    pub fn set_pointer(&mut self, pointer: u8) {
+        self.header.to_mut()[IPV4_OPT_POINTER_OFFSET] = pointer;
+    }
+
+    pub fn with_pointer(&mut self, pointer: u8) -> &mut Self {
+        self.set_pointer(pointer);
+        self
+    }
+
+    /// Every address slot of a Record Route, Loose or Strict Source Route
+    /// option, recorded or not.
+    pub fn route(&self) -> Vec<Ipv4Addr> {
+        parse_addrs(&self.header[IPV4_OPT_ROUTE_OFFSET..])
+    }
+
+    /// The addresses already recorded, as indicated by the pointer.
+    pub fn recorded_route(&self) -> Vec<Ipv4Addr> {
+        let end = (self.pointer() as usize)
+            .saturating_sub(1)
+            .clamp(IPV4_OPT_ROUTE_OFFSET, self.header.len());
+        parse_addrs(&self.header[IPV4_OPT_ROUTE_OFFSET..end])
+    }
+
+    pub fn ts_overflow(&self) -> u8 {
+        self.header[IPV4_OPT_TS_FLAGS_OFFSET] >> 4
+    }
+
+    pub fn ts_flags(&self) -> u8 {
+        self.header[IPV4_OPT_TS_FLAGS_OFFSET] & 0x0F
+    }
+
+    /// Timestamp entries, paired with the address that recorded them when the
+    /// flags call for one.
+    pub fn timestamps(&self) -> Vec<(Option<Ipv4Addr>, u32)> {
+        let entries = &self.header[IPV4_OPT_TS_OFFSET..];
+        match self.ts_flags() {
+            TS_ADDR | TS_PRESPEC => entries
+                .chunks_exact(8)
+                .map(|entry| {
+                    (
+                        Some(Ipv4Addr::from_bits(parse_bytes(&entry[..4], Endian::Big))),
+                        parse_bytes::<u32>(&entry[4..], Endian::Big),
+                    )
+                })
+                .collect(),
+            _ => entries
+                .chunks_exact(4)
+                .map(|entry| (None, parse_bytes::<u32>(entry, Endian::Big)))
+                .collect(),
+        }
+    }
+
+    pub fn security(&self) -> u16 {
+        parse_bytes::<u16>(&self.header[2..4], Endian::Big)
+    }
+
+    pub fn compartments(&self) -> u16 {
+        parse_bytes::<u16>(&self.header[4..6], Endian::Big)
+    }
+
+    pub fn handling_restrictions(&self) -> u16 {
+        parse_bytes::<u16>(&self.header[6..8], Endian::Big)
+    }
+
+    /// Transmission control code, 24 bits.
+    pub fn tcc(&self) -> u32 {
+        let mut tcc = [0; 4];
+        tcc[1..].copy_from_slice(&self.header[8..IPV4_OPT_SEC_LEN]);
+        u32::from_be_bytes(tcc)
+    }
+
+    pub fn stream_id(&self) -> u16 {
+        parse_bytes::<u16>(&self.header[2..IPV4_OPT_SID_LEN], Endian::Big)
+    }
 }
 
+const IP_OPT_NUMBER_NAMES: ValueStrings = &[
+    (END as u64, "End of Options List"),
+    (NOP as u64, "No Operation"),
+    (SEC as u64, "Security"),
+    (LSR as u64, "Loose Source Route"),
+    (ITS as u64, "Time Stamp"),
+    (REC as u64, "Record Route"),
+    (SID as u64, "Stream ID"),
+    (SSR as u64, "Strict Source Route"),
+];
+
+// END and NOP have no length byte, so the length has no fixed place.
+register_fields!(IpOption, "ip.opt", {
+    "ip.opt.type" => opt_type { name: "Type", bits: (IPV4_OPT_TYPE_OFFSET * 8, 8), base: Hex },
+    "ip.opt.copied" => copied { name: "Copied", bits: (IPV4_OPT_TYPE_OFFSET * 8, 1) },
+    "ip.opt.class" => opt_class { name: "Class", bits: (IPV4_OPT_TYPE_OFFSET * 8 + 1, 2) },
+    "ip.opt.number" => opt_number {
+        name: "Number",
+        bits: (IPV4_OPT_TYPE_OFFSET * 8 + 3, 5),
+        values: IP_OPT_NUMBER_NAMES,
+    },
+    "ip.opt.len" => opt_length { name: "Length" },
+});
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::ip::Ip;
+    use crate::pdu::deserialize;
 
     const IPV4_NO_OPTIONS: &[u8] = &[
         0x45, 0x00, // Version=4, IHL=5, DSCP/ECN
@@ -173,13 +434,14 @@ mod tests {
         0x3a, 0x79, // Header checksum
         0xc0, 0xa8, 0x00, 0x64, // Src 192.168.0.100
         0xc0, 0xa8, 0x00, 0x65, // Dst 192.168.0.101
-        // ---- Timestamp option (10 bytes) ----
+        // ---- Timestamp option (8 bytes) ----
         0x44, // Type = Timestamp
-        0x0a, // Length = 10
-        0x05, // Pointer = 5 (next timestamp slot)
+        0x08, // Length = 8
+        0x09, // Pointer = 9 (option full)
         0x00, // Overflow + flags = 0
         0x00, 0x00, 0x00, 0x01, // Timestamp entry (1)
-        0x00, 0x00, // Padding to IHL
+        0x00, // End of Options List (EOL)
+        0x00, 0x00, 0x00, // Padding to IHL
         // Payload
         0xde, 0xad, 0xfa, 0xce, 0x12, 0x34, 0x56, 0x78,
     ];
@@ -197,24 +459,91 @@ mod tests {
         0xf1, 0xf0, // Security
         0x00, 0x00, // Compartment
         0x00, 0x00, // Handling restrictions
-        0x00, 0x01, // TCC
+        0x00, 0x00, 0x01, // TCC
         0x00, // Padding to 32 bytes
         // Payload (8 bytes)
         0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff, 0x11, 0x22,
     ];
 
+    fn assert_round_trip(bytes: &[u8]) {
+        let ip = deserialize::<Ip>(bytes).unwrap();
+        let header_len = ip.ihl() as usize * 4;
+        assert!(ip.to_bytes() == bytes[..header_len]);
+    }
+
     #[test]
-    fn test_no_opt() {}
+    fn test_no_opt() {
+        let ip = deserialize::<Ip>(IPV4_NO_OPTIONS).unwrap();
+        assert!(ip.opts().is_empty());
+        assert_round_trip(IPV4_NO_OPTIONS);
+    }
 
     #[test]
-    fn test_rr_opt() {}
+    fn test_rr_opt() {
+        let ip = deserialize::<Ip>(IPV4_RR).unwrap();
+        let opts = ip.opts();
+        assert!(opts.len() == 2);
+        assert!(opts[0].opt_number() == REC);
+        assert!(opts[0].opt_length() == 7);
+        assert!(opts[0].pointer() == 4);
+        assert!(opts[0].route() == [Ipv4Addr::UNSPECIFIED]);
+        assert!(opts[0].recorded_route().is_empty());
+        assert!(opts[1].opt_type() == END);
+        assert_round_trip(IPV4_RR);
+    }
+
+    #[test]
+    fn test_mixed_opt() {
+        let ip = deserialize::<Ip>(IPV4_MIXED_OPTIONS).unwrap();
+        let types: Vec<u8> = ip.opts().iter().map(|opt| opt.opt_type()).collect();
+        assert!(types == [NOP, NOP, REC, END]);
+        assert_round_trip(IPV4_MIXED_OPTIONS);
+    }
+
+    #[test]
+    fn test_ts_opt() {
+        let ip = deserialize::<Ip>(IPV4_TS).unwrap();
+        let opt = &ip.opts()[0];
+        assert!(opt.opt_number() == ITS);
+        assert!(opt.ts_flags() == TS_ONLY);
+        assert!(opt.ts_overflow() == 0);
+        assert!(opt.timestamps() == [(None, 1)]);
+        assert_round_trip(IPV4_TS);
+    }
 
     #[test]
-    fn test_mixed_opt() {}
+    fn test_sec_opt() {
+        let ip = deserialize::<Ip>(IPV4_SECURITY).unwrap();
+        let opt = &ip.opts()[0];
+        assert!(opt.opt_number() == SEC);
+        assert!(opt.copied());
+        assert!(opt.security() == 0xf1f0);
+        assert!(opt.compartments() == 0);
+        assert!(opt.handling_restrictions() == 0);
+        assert!(opt.tcc() == 1);
+        assert_round_trip(IPV4_SECURITY);
+    }
 
     #[test]
-    fn test_ts_opt() {}
+    fn test_sid_and_source_route() {
+        let opts = parse_ip_options(&[
+            0x88, 0x04, 0x12, 0x34, // Stream ID
+            0x83, 0x0b, 0x08, // LSRR, pointer at second hop
+            0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x02, // route
+            0x00, // END
+        ])
+        .unwrap();
+        assert!(opts[0].stream_id() == 0x1234);
+        assert!(opts[1].opt_number() == LSR);
+        assert!(opts[1].route() == [Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)]);
+        assert!(opts[1].recorded_route() == [Ipv4Addr::new(10, 0, 0, 1)]);
+        assert!(ip_options_to_bytes(&opts).len() == 16);
+    }
 
     #[test]
-    fn test_sec_opt() {}
+    fn test_malformed_opt() {
+        assert!(parse_ip_options(&[0x07, 0x01, 0x00, 0x00]).is_err());
+        assert!(parse_ip_options(&[0x07, 0x0b, 0x04, 0x00]).is_err());
+        assert!(parse_ip_options(&[0x82, 0x04, 0x00, 0x00]).is_err());
+    }
 }
@@ -1,15 +1,38 @@
+#![allow(clippy::new_without_default)]
+
+pub mod arp;
+pub mod checksum;
+pub mod defrag;
+pub mod dns;
 pub mod error;
 pub mod ethernet;
+pub mod field;
+pub mod filter;
+pub mod flow;
+pub mod geneve;
+pub mod gre;
 pub mod icmp;
+pub mod icmpv6;
 pub mod ip;
 pub mod ip6;
+pub mod ip6_ext;
 pub mod ip_opt;
+pub mod loopback;
 pub mod mac_address;
+pub mod mpls;
+pub mod ndp_opt;
 pub mod packet;
+pub mod pcap;
 pub mod pdu;
 pub mod prelude;
+pub mod radiotap;
 pub mod raw;
+pub mod sll;
+pub mod stream;
 pub mod table;
 pub mod tcp;
+pub mod tcp_opt;
 pub mod udp;
 pub mod utils;
+pub mod vlan;
+pub mod vxlan;
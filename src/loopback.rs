@@ -0,0 +1,175 @@
+use crate::ethernet::{ETHER_DISSECTION_TABLE, EtherType};
+use crate::pcap::{LINKTYPE_DISSECTION_TABLE, LINKTYPE_LOOP, LINKTYPE_NULL, LinkType};
+use crate::prelude::*;
+
+const LOOPBACK_HEADER_LEN: usize = 4;
+
+pub const AF_INET: u32 = 2;
+/// AF_INET6 on Linux.
+pub const AF_INET6_LINUX: u32 = 10;
+/// AF_INET6 on NetBSD and OpenBSD, the value most readers expect.
+pub const AF_INET6_BSD: u32 = 24;
+pub const AF_INET6_FREEBSD: u32 = 28;
+pub const AF_INET6_DARWIN: u32 = 30;
+
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86DD;
+
+/// The family is written in the capturing host's byte order. Families are
+/// small, so a value that only fits when read big endian means the header
+/// is big endian.
+fn get_loopback_endian(bytes: &[u8]) -> Endian {
+    if parse_bytes::<u32>(&bytes[..LOOPBACK_HEADER_LEN], Endian::Little) > 0xFFFF {
+        Endian::Big
+    } else {
+        Endian::Little
+    }
+}
+
+/// The EtherType for an address family, used to dissect the payload.
+pub fn family_to_ether_type(family: u32) -> Option<u16> {
+    match family {
+        AF_INET => Some(ETHER_TYPE_IPV4),
+        AF_INET6_LINUX | AF_INET6_BSD | AF_INET6_FREEBSD | AF_INET6_DARWIN => Some(ETHER_TYPE_IPV6),
+        _ => None,
+    }
+}
+
+pub fn ether_type_to_family(ether_type: u16) -> Option<u32> {
+    match ether_type {
+        ETHER_TYPE_IPV4 => Some(AF_INET),
+        ETHER_TYPE_IPV6 => Some(AF_INET6_BSD),
+        _ => None,
+    }
+}
+
+/// BSD loopback (NULL) encapsulation: a 4 byte address family.
+/// Refer to <https://www.tcpdump.org/linktypes/LINKTYPE_NULL.html>
+#[pdu_type]
+pub struct Loopback<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Loopback<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(Loopback);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("null", "header length", bytes, 0, LOOPBACK_HEADER_LEN)?;
+
+        let family = parse_bytes::<u32>(&bytes[..LOOPBACK_HEADER_LEN], get_loopback_endian(bytes));
+        let payload = &bytes[LOOPBACK_HEADER_LEN..];
+        let inner = match family_to_ether_type(family) {
+            Some(ether_type) => {
+                build_from_table(&ETHER_DISSECTION_TABLE, EtherType(ether_type), payload)
+            }
+            None => Raw::from_bytes(payload)?,
+        };
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..LOOPBACK_HEADER_LEN]),
+            parent: None,
+            child: Some(inner),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "null": {
+                "null.family": self.family(),
+                "null.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> Loopback<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; LOOPBACK_HEADER_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn family(&self) -> u32 {
+        parse_bytes::<u32>(&self.header, get_loopback_endian(&self.header))
+    }
+
+    /// Sets the family, keeping the byte order of the current value. New
+    /// headers are little endian.
+    pub fn set_family(&mut self, family: u32) {
+        let bytes = match get_loopback_endian(&self.header) {
+            Endian::Big => family.to_be_bytes(),
+            Endian::Little => family.to_le_bytes(),
+        };
+        self.header.to_mut().copy_from_slice(&bytes);
+    }
+
+    pub fn with_family(&mut self, family: u32) -> &mut Self {
+        self.set_family(family);
+        self
+    }
+}
+
+register_pdu!(
+    LinkType(LINKTYPE_NULL),
+    Loopback,
+    LINKTYPE_DISSECTION_TABLE,
+    default
+);
+register_pdu!(LinkType(LINKTYPE_LOOP), Loopback, LINKTYPE_DISSECTION_TABLE);
+
+const LOOPBACK_FAMILY_NAMES: ValueStrings = &[
+    (AF_INET as u64, "IPv4"),
+    (AF_INET6_LINUX as u64, "IPv6"),
+    (AF_INET6_BSD as u64, "IPv6"),
+    (AF_INET6_FREEBSD as u64, "IPv6"),
+    (AF_INET6_DARWIN as u64, "IPv6"),
+];
+
+// The family is in the capturing host's byte order, so it has no fixed
+// bit layout.
+register_fields!(Loopback, "null", {
+    "null.family" => family { name: "Family", values: LOOPBACK_FAMILY_NAMES },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip6::Ipv6;
+    use crate::pcap::dissect;
+    use crate::pdu::deserialize;
+
+    #[test]
+    fn test_byte_orders() {
+        let mut bytes = vec![0x1e, 0x00, 0x00, 0x00, 0x60, 0, 0, 0, 0, 0, 59, 64];
+        bytes.extend_from_slice(&[0; 32]);
+
+        let pdu = dissect(LINKTYPE_NULL, &bytes);
+        assert!(pdu.downcast_ref::<Loopback>().unwrap().family() == AF_INET6_DARWIN);
+        assert!(pdu.find::<Ipv6>().is_some());
+
+        let mut be_bytes = bytes.clone();
+        be_bytes[..4].copy_from_slice(&AF_INET6_BSD.to_be_bytes());
+        let pdu = dissect(LINKTYPE_LOOP, &be_bytes);
+        assert!(pdu.downcast_ref::<Loopback>().unwrap().family() == AF_INET6_BSD);
+        assert!(pdu.find::<Ipv6>().is_some());
+    }
+
+    #[test]
+    fn test_set_family() {
+        let bytes = 2u32.to_be_bytes();
+        let mut lo = deserialize::<Loopback>(&bytes).unwrap();
+        lo.set_family(AF_INET6_BSD);
+        assert!(lo.to_bytes() == AF_INET6_BSD.to_be_bytes());
+
+        let mut lo = Loopback::new();
+        lo.set_family(AF_INET);
+        assert!(lo.to_bytes() == [2, 0, 0, 0]);
+    }
+}
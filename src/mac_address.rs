@@ -21,7 +21,7 @@ impl<'a> MacAddress<'a> {
     }
 
     pub fn to_bytes(&self) -> [u8; 6] {
-        self.address.clone()
+        *self.address
     }
 
     pub fn to_str(&self) -> String {
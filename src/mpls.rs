@@ -0,0 +1,532 @@
+use crate::ethernet::{ETHER_DISSECTION_TABLE, EtherType, Ethernet};
+use crate::prelude::*;
+
+const MPLS_ENTRY_LEN: usize = 4;
+
+const MPLS_LABEL_SHIFT: u32 = 12;
+const MPLS_TC_SHIFT: u32 = 9;
+const MPLS_BOS_MASK: u32 = 0x0000_0100;
+const MPLS_TTL_MASK: u32 = 0x0000_00FF;
+const MPLS_LABEL_MASK: u32 = 0x000F_FFFF;
+
+const PW_CW_FLAGS_OFFSET: usize = 0;
+const PW_CW_LENGTH_OFFSET: usize = 1;
+const PW_CW_SEQUENCE_OFFSET: usize = 2;
+const PW_CW_LEN: usize = 4;
+
+pub const ETHER_TYPE_MPLS_UNICAST: u16 = 0x8847;
+pub const ETHER_TYPE_MPLS_MULTICAST: u16 = 0x8848;
+
+pub const IPV4_EXPLICIT_NULL: u32 = 0;
+pub const IPV6_EXPLICIT_NULL: u32 = 2;
+
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86DD;
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct MplsLabel(pub u32);
+
+/// Payloads pinned to a bottom-of-stack label with `register_pdu!`. Labels
+/// are assigned per network, so nothing is registered here by default.
+pub static MPLS_LABEL_DISSECTION_TABLE: DissectionTable<MplsLabel> = create_table();
+
+/// Dissects what follows the bottom label. A label registered in
+/// `MPLS_LABEL_DISSECTION_TABLE` wins, then the explicit null labels, then
+/// the first nibble of the payload: 4 and 6 are IP versions and 0 is a
+/// pseudowire control word. Only the labels keep a failed payload as
+/// malformed, as the nibble is just a guess.
+fn build_mpls_payload(label: u32, bytes: &[u8]) -> Box<dyn Pdu<'_> + '_> {
+    if lookup_type(&MPLS_LABEL_DISSECTION_TABLE, &MplsLabel(label)).is_some() {
+        return build_from_table(&MPLS_LABEL_DISSECTION_TABLE, MplsLabel(label), bytes);
+    }
+
+    let ether_type = match (label, bytes.first().map(|byte| byte >> 4)) {
+        (IPV4_EXPLICIT_NULL, _) => {
+            return build_from_table(&ETHER_DISSECTION_TABLE, EtherType(ETHER_TYPE_IPV4), bytes);
+        }
+        (IPV6_EXPLICIT_NULL, _) => {
+            return build_from_table(&ETHER_DISSECTION_TABLE, EtherType(ETHER_TYPE_IPV6), bytes);
+        }
+        (_, Some(4)) => ETHER_TYPE_IPV4,
+        (_, Some(6)) => ETHER_TYPE_IPV6,
+        (_, Some(0)) => {
+            return PwControlWord::from_bytes(bytes)
+                .or_else(|_| Raw::from_bytes(bytes))
+                .expect("Raw accepts any bytes");
+        }
+        _ => return Raw::from_bytes(bytes).expect("Raw accepts any bytes"),
+    };
+    build_from_table_first(&ETHER_DISSECTION_TABLE, [EtherType(ether_type)], bytes)
+        .expect("Raw accepts any bytes")
+}
+
+/// One label stack entry.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct MplsEntry(u32);
+
+impl MplsEntry {
+    pub fn new(label: u32) -> Self {
+        let mut entry = Self(0);
+        entry.set_label(label);
+        entry
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self(parse_bytes::<u32>(&bytes[..MPLS_ENTRY_LEN], Endian::Big))
+    }
+
+    pub fn to_bytes(&self) -> [u8; MPLS_ENTRY_LEN] {
+        self.0.to_be_bytes()
+    }
+
+    pub fn label(&self) -> u32 {
+        self.0 >> MPLS_LABEL_SHIFT
+    }
+
+    pub fn set_label(&mut self, label: u32) {
+        self.0 = (self.0 & !(MPLS_LABEL_MASK << MPLS_LABEL_SHIFT))
+            | ((label & MPLS_LABEL_MASK) << MPLS_LABEL_SHIFT);
+    }
+
+    pub fn with_label(&mut self, label: u32) -> &mut Self {
+        self.set_label(label);
+        self
+    }
+
+    /// Traffic class, formerly the experimental bits.
+    pub fn tc(&self) -> u8 {
+        ((self.0 >> MPLS_TC_SHIFT) & 0x7) as u8
+    }
+
+    pub fn set_tc(&mut self, tc: u8) {
+        self.0 = (self.0 & !(0x7 << MPLS_TC_SHIFT)) | (((tc & 0x7) as u32) << MPLS_TC_SHIFT);
+    }
+
+    pub fn with_tc(&mut self, tc: u8) -> &mut Self {
+        self.set_tc(tc);
+        self
+    }
+
+    /// Bottom of stack.
+    pub fn bos(&self) -> bool {
+        self.0 & MPLS_BOS_MASK != 0
+    }
+
+    pub fn set_bos(&mut self, bos: bool) {
+        self.0 = if bos {
+            self.0 | MPLS_BOS_MASK
+        } else {
+            self.0 & !MPLS_BOS_MASK
+        };
+    }
+
+    pub fn with_bos(&mut self, bos: bool) -> &mut Self {
+        self.set_bos(bos);
+        self
+    }
+
+    pub fn ttl(&self) -> u8 {
+        (self.0 & MPLS_TTL_MASK) as u8
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.0 = (self.0 & !MPLS_TTL_MASK) | ttl as u32;
+    }
+
+    pub fn with_ttl(&mut self, ttl: u8) -> &mut Self {
+        self.set_ttl(ttl);
+        self
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "mpls.label": self.label(),
+            "mpls.tc": self.tc(),
+            "mpls.bottom": self.bos(),
+            "mpls.ttl": self.ttl(),
+        })
+    }
+}
+
+/// An MPLS label stack, from the top entry down to the one with the bottom
+/// of stack bit set.
+/// Refer to <https://www.rfc-editor.org/rfc/rfc3032>
+#[pdu_type]
+pub struct Mpls<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Mpls<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(Mpls);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        let mut stack_len = 0;
+        loop {
+            ParseError::check_len("mpls", "label entry", bytes, stack_len, MPLS_ENTRY_LEN)?;
+            let entry = MplsEntry::from_bytes(&bytes[stack_len..]);
+            stack_len += MPLS_ENTRY_LEN;
+            if entry.bos() {
+                break;
+            }
+        }
+
+        let bottom = MplsEntry::from_bytes(&bytes[stack_len - MPLS_ENTRY_LEN..]);
+        let inner = build_mpls_payload(bottom.label(), &bytes[stack_len..]);
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..stack_len]),
+            parent: None,
+            child: Some(inner),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        let entries_json: Vec<serde_json::Value> =
+            self.entries().iter().map(|entry| entry.to_json()).collect();
+
+        Ok(json!({
+            "mpls": {
+                "mpls.entries": entries_json,
+                "mpls.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> Mpls<'a> {
+    /// An empty stack. Entries are added from the top down.
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(Vec::new()),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.len() / MPLS_ENTRY_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty()
+    }
+
+    pub fn entries(&self) -> Vec<MplsEntry> {
+        self.header
+            .chunks_exact(MPLS_ENTRY_LEN)
+            .map(MplsEntry::from_bytes)
+            .collect()
+    }
+
+    pub fn entry(&self, idx: usize) -> Option<MplsEntry> {
+        let offset = idx * MPLS_ENTRY_LEN;
+        (offset < self.header.len()).then(|| MplsEntry::from_bytes(&self.header[offset..]))
+    }
+
+    /// Replaces the entry at `idx`, which must already exist.
+    pub fn set_entry(&mut self, idx: usize, entry: MplsEntry) {
+        let offset = idx * MPLS_ENTRY_LEN;
+        self.header.to_mut()[offset..offset + MPLS_ENTRY_LEN].copy_from_slice(&entry.to_bytes());
+    }
+
+    pub fn with_entry(&mut self, idx: usize, entry: MplsEntry) -> &mut Self {
+        self.set_entry(idx, entry);
+        self
+    }
+
+    /// Appends `entry` below the current bottom. Bottom of stack bits are
+    /// left as they are.
+    pub fn push_entry(&mut self, entry: MplsEntry) {
+        self.header.to_mut().extend_from_slice(&entry.to_bytes());
+    }
+
+    pub fn with_pushed_entry(&mut self, entry: MplsEntry) -> &mut Self {
+        self.push_entry(entry);
+        self
+    }
+
+    /// The label that selects the payload.
+    pub fn bottom_label(&self) -> Option<u32> {
+        self.entries().last().map(|entry| entry.label())
+    }
+
+    /// The label the next hop switches on.
+    pub fn top_label(&self) -> Option<u32> {
+        self.entry(0).map(|entry| entry.label())
+    }
+
+    pub fn top_tc(&self) -> Option<u8> {
+        self.entry(0).map(|entry| entry.tc())
+    }
+
+    /// Set when the top entry is the only one.
+    pub fn top_bos(&self) -> Option<bool> {
+        self.entry(0).map(|entry| entry.bos())
+    }
+
+    pub fn top_ttl(&self) -> Option<u8> {
+        self.entry(0).map(|entry| entry.ttl())
+    }
+}
+
+/// The generic pseudowire control word that precedes an Ethernet frame.
+/// Refer to <https://www.rfc-editor.org/rfc/rfc4385>
+#[pdu_type]
+pub struct PwControlWord<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for PwControlWord<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(PwControlWord);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("pwethcw", "header length", bytes, 0, PW_CW_LEN)?;
+        let nibble = bytes[PW_CW_FLAGS_OFFSET] >> 4;
+        if nibble != 0 {
+            return Err(ParseError::InvalidHeader {
+                protocol: "pwethcw",
+                field: "first nibble",
+                offset: PW_CW_FLAGS_OFFSET,
+                value: nibble as u64,
+                reason: "isn't zero",
+            });
+        }
+
+        let payload = &bytes[PW_CW_LEN..];
+        let inner = Raw::or_malformed(Ethernet::from_bytes(payload), payload);
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..PW_CW_LEN]),
+            parent: None,
+            child: Some(inner),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "pwethcw": {
+                "pwethcw.flags": self.flags(),
+                "pwethcw.frag": self.frag(),
+                "pwethcw.length": self.length(),
+                "pwethcw.seqno": self.sequence(),
+                "pwethcw.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> PwControlWord<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; PW_CW_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.header[PW_CW_FLAGS_OFFSET] & 0x0F
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.header.to_mut()[PW_CW_FLAGS_OFFSET] = flags & 0x0F;
+    }
+
+    pub fn with_flags(&mut self, flags: u8) -> &mut Self {
+        self.set_flags(flags);
+        self
+    }
+
+    pub fn frag(&self) -> u8 {
+        self.header[PW_CW_LENGTH_OFFSET] >> 6
+    }
+
+    pub fn set_frag(&mut self, frag: u8) {
+        let byte = &mut self.header.to_mut()[PW_CW_LENGTH_OFFSET];
+        *byte = (*byte & 0x3F) | ((frag & 0x3) << 6);
+    }
+
+    pub fn with_frag(&mut self, frag: u8) -> &mut Self {
+        self.set_frag(frag);
+        self
+    }
+
+    /// Payload length when the frame is padded to the minimum, otherwise 0.
+    pub fn length(&self) -> u8 {
+        self.header[PW_CW_LENGTH_OFFSET] & 0x3F
+    }
+
+    pub fn set_length(&mut self, length: u8) {
+        let byte = &mut self.header.to_mut()[PW_CW_LENGTH_OFFSET];
+        *byte = (*byte & 0xC0) | (length & 0x3F);
+    }
+
+    pub fn with_length(&mut self, length: u8) -> &mut Self {
+        self.set_length(length);
+        self
+    }
+
+    pub fn sequence(&self) -> u16 {
+        parse_bytes::<u16>(&self.header[PW_CW_SEQUENCE_OFFSET..PW_CW_LEN], Endian::Big)
+    }
+
+    pub fn set_sequence(&mut self, sequence: u16) {
+        self.header.to_mut()[PW_CW_SEQUENCE_OFFSET..PW_CW_LEN]
+            .copy_from_slice(&sequence.to_be_bytes());
+    }
+
+    pub fn with_sequence(&mut self, sequence: u16) -> &mut Self {
+        self.set_sequence(sequence);
+        self
+    }
+}
+
+register_pdu!(
+    EtherType(ETHER_TYPE_MPLS_UNICAST),
+    Mpls,
+    ETHER_DISSECTION_TABLE,
+    default
+);
+register_pdu!(
+    EtherType(ETHER_TYPE_MPLS_MULTICAST),
+    Mpls,
+    ETHER_DISSECTION_TABLE
+);
+
+// Fields of the top entry, like the labels a filter sees hop by hop.
+register_fields!(Mpls, "mpls", {
+    "mpls.label" => top_label { name: "Label", bits: (0, 20) },
+    "mpls.tc" => top_tc { name: "Traffic Class", bits: (20, 3) },
+    "mpls.bottom" => top_bos { name: "Bottom of Stack", bits: (23, 1) },
+    "mpls.ttl" => top_ttl { name: "Time to Live", bits: (24, 8) },
+});
+register_fields!(PwControlWord, "pwethcw", {
+    "pwethcw.flags" => flags { name: "Flags", bits: (PW_CW_FLAGS_OFFSET * 8 + 4, 4), base: Hex },
+    "pwethcw.frag" => frag { name: "Fragmentation", bits: (PW_CW_LENGTH_OFFSET * 8, 2) },
+    "pwethcw.length" => length { name: "Length", bits: (PW_CW_LENGTH_OFFSET * 8 + 2, 6) },
+    "pwethcw.seqno" => sequence {
+        name: "Sequence Number",
+        bits: (PW_CW_SEQUENCE_OFFSET * 8, 16),
+    },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::FieldValue;
+    use crate::filter::Filter;
+    use crate::ip::Ip;
+    use crate::ip6::Ipv6;
+
+    const IPV4: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x14, 0x00, 0x01, 0x00, 0x00, 0x40, 0xfd, 0x00, 0x00, 0xc0, 0x00, 0x02,
+        0x01, 0xc6, 0x33, 0x64, 0x02,
+    ];
+
+    fn stack(entries: &[MplsEntry], payload: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = entries.iter().flat_map(|entry| entry.to_bytes()).collect();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn test_label_stack() {
+        let top = *MplsEntry::new(16001).with_tc(5).with_ttl(64);
+        let bottom = *MplsEntry::new(24).with_bos(true).with_ttl(63);
+        assert!(top.to_bytes() == [0x03, 0xe8, 0x1a, 0x40]);
+
+        let bytes = stack(&[top, bottom], &IPV4);
+        let mpls = Mpls::from_bytes(&bytes).unwrap();
+        let entries = mpls.downcast_ref::<Mpls>().unwrap().entries();
+        assert!(entries.len() == 2);
+        assert!(entries[0].label() == 16001);
+        assert!(entries[0].tc() == 5);
+        assert!(!entries[0].bos());
+        assert!(entries[1].label() == 24);
+        assert!(entries[1].bos());
+        assert!(entries[1].ttl() == 63);
+        assert!(mpls.find::<Ip>().is_some());
+        assert!(mpls.chain_to_bytes() == bytes);
+
+        assert!(mpls.get_field("mpls.label") == Some(FieldValue::U32(16001)));
+        assert!(mpls.get_field("mpls.tc") == Some(FieldValue::U8(5)));
+        assert!(mpls.get_field("mpls.bottom") == Some(FieldValue::Bool(false)));
+        assert!(mpls.get_field("mpls.ttl") == Some(FieldValue::U8(64)));
+        assert!(
+            Filter::new("mpls.label == 16001 && mpls.ttl > 63")
+                .unwrap()
+                .matches(&*mpls)
+        );
+        assert!(!Filter::new("mpls.label == 100").unwrap().matches(&*mpls));
+        assert!(Mpls::new().top_label().is_none());
+
+        assert!(Mpls::from_bytes(&top.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_payload_heuristics() {
+        // IPv6 explicit null wins over the nibble.
+        let mut ipv6 = vec![0x60, 0, 0, 0, 0, 0, 59, 64];
+        ipv6.extend_from_slice(&[0; 32]);
+        let bytes = stack(&[*MplsEntry::new(IPV6_EXPLICIT_NULL).with_bos(true)], &ipv6);
+        assert!(Mpls::from_bytes(&bytes).unwrap().find::<Ipv6>().is_some());
+
+        let mut frame = vec![0x00, 0x00, 0x00, 0x07];
+        frame.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        frame.extend_from_slice(&[0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00]);
+        frame.extend_from_slice(&IPV4);
+        let bytes = stack(&[*MplsEntry::new(100).with_bos(true)], &frame);
+        let mpls = Mpls::from_bytes(&bytes).unwrap();
+        assert!(mpls.find::<PwControlWord>().unwrap().sequence() == 7);
+        assert!(mpls.find::<Ethernet>().is_some());
+        assert!(mpls.find::<Ip>().is_some());
+
+        let bytes = stack(&[*MplsEntry::new(100).with_bos(true)], &[0xff; 8]);
+        assert!(Mpls::from_bytes(&bytes).unwrap().find::<Raw>().is_some());
+    }
+
+    #[test]
+    fn test_label_table() {
+        // An Ethernet frame without a control word whose destination MAC
+        // looks like IPv4.
+        let mut frame = vec![0x45, 0x11, 0x22, 0x33, 0x44, 0x55];
+        frame.extend_from_slice(&[0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00]);
+        frame.extend_from_slice(&IPV4);
+        let bytes = stack(&[*MplsEntry::new(0xBEEF).with_bos(true)], &frame);
+
+        register_pdu!(MplsLabel(0xBEEF), Ethernet, MPLS_LABEL_DISSECTION_TABLE);
+        let mpls = Mpls::from_bytes(&bytes).unwrap();
+        assert!(
+            mpls.child_pdu()
+                .as_ref()
+                .unwrap()
+                .downcast_ref::<Ethernet>()
+                .is_some()
+        );
+        assert!(mpls.find::<Ip>().is_some());
+    }
+
+    #[test]
+    fn test_build_stack() {
+        let mut mpls = Mpls::new();
+        mpls.with_pushed_entry(MplsEntry::new(16001))
+            .with_pushed_entry(*MplsEntry::new(24).with_bos(true));
+        assert!(mpls.len() == 2);
+        assert!(mpls.bottom_label() == Some(24));
+
+        let entry = *mpls.entry(0).unwrap().with_ttl(1);
+        mpls.set_entry(0, entry);
+        assert!(mpls.entry(0).unwrap().ttl() == 1);
+        assert!(mpls.entry(2).is_none());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::prelude::*;
+
+use std::net::Ipv6Addr;
+
+const NDP_OPT_TYPE_OFFSET: usize = 0;
+const NDP_OPT_LEN_OFFSET: usize = 1;
+const NDP_OPT_DATA_OFFSET: usize = 2;
+const NDP_OPT_LEN_UNIT: usize = 8;
+
+pub const SOURCE_LL_ADDR: u8 = 1;
+pub const TARGET_LL_ADDR: u8 = 2;
+pub const PREFIX_INFO: u8 = 3;
+pub const REDIRECTED_HEADER: u8 = 4;
+pub const MTU: u8 = 5;
+pub const RDNSS: u8 = 25;
+
+const PREFIX_INFO_LEN: usize = 32;
+const MTU_LEN: usize = 8;
+const RDNSS_MIN_LEN: usize = 8;
+const REDIRECTED_HEADER_DATA_OFFSET: usize = 8;
+
+const PREFIX_FLAG_ON_LINK: u8 = 0x80;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NdpOption {
+    SourceLinkLayerAddr(Vec<u8>),
+    TargetLinkLayerAddr(Vec<u8>),
+    PrefixInfo {
+        prefix_len: u8,
+        on_link: bool,
+        autonomous: bool,
+        valid_lifetime: u32,
+        preferred_lifetime: u32,
+        prefix: Ipv6Addr,
+    },
+    /// Leading part of the redirected packet.
+    RedirectedHeader(Vec<u8>),
+    Mtu(u32),
+    Rdnss {
+        lifetime: u32,
+        servers: Vec<Ipv6Addr>,
+    },
+    Unknown {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+pub fn get_ndp_opt_kind(bytes: &[u8]) -> u8 {
+    bytes[NDP_OPT_TYPE_OFFSET]
+}
+
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc4861#section-4.6>
+pub fn get_ndp_opt_length(bytes: &[u8]) -> Result<usize, ParseError> {
+    ParseError::check_len("ndp option", "length", bytes, NDP_OPT_LEN_OFFSET, 1)?;
+    let len = bytes[NDP_OPT_LEN_OFFSET] as usize * NDP_OPT_LEN_UNIT;
+    if len == 0 {
+        return Err(ParseError::InvalidHeader {
+            protocol: "ndp option",
+            field: "length",
+            offset: NDP_OPT_LEN_OFFSET,
+            value: 0,
+            reason: "is not allowed",
+        });
+    }
+    ParseError::check_len("ndp option", "length", bytes, 0, len)?;
+    Ok(len)
+}
+
+/// Parses every option in `bytes`.
+pub fn parse_ndp_options(bytes: &[u8]) -> Result<Vec<NdpOption>, ParseError> {
+    let mut opts = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let opt_len = get_ndp_opt_length(&bytes[offset..]).map_err(|err| err.shifted(offset))?;
+        let opt = NdpOption::from_bytes(&bytes[offset..offset + opt_len])
+            .map_err(|err| err.shifted(offset))?;
+        opts.push(opt);
+        offset += opt_len;
+    }
+    Ok(opts)
+}
+
+pub fn ndp_options_to_bytes(opts: &[NdpOption]) -> Vec<u8> {
+    opts.iter().flat_map(|opt| opt.to_bytes()).collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+fn ipv6_at(bytes: &[u8], offset: usize) -> Ipv6Addr {
+    Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[offset..offset + 16]).unwrap())
+}
+
+impl NdpOption {
+    /// Parses a single option. `bytes` must hold exactly one option, as
+    /// sized by `get_ndp_opt_length`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let data = &bytes[NDP_OPT_DATA_OFFSET..];
+        let invalid_len = || ParseError::InvalidHeader {
+            protocol: "ndp option",
+            field: "length",
+            offset: NDP_OPT_LEN_OFFSET,
+            value: bytes.len() as u64,
+            reason: "doesn't match the option kind",
+        };
+        let opt = match get_ndp_opt_kind(bytes) {
+            SOURCE_LL_ADDR => NdpOption::SourceLinkLayerAddr(data.to_vec()),
+            TARGET_LL_ADDR => NdpOption::TargetLinkLayerAddr(data.to_vec()),
+            PREFIX_INFO => {
+                if bytes.len() != PREFIX_INFO_LEN {
+                    return Err(invalid_len());
+                }
+                NdpOption::PrefixInfo {
+                    prefix_len: bytes[2],
+                    on_link: bytes[3] & PREFIX_FLAG_ON_LINK != 0,
+                    autonomous: bytes[3] & PREFIX_FLAG_AUTONOMOUS != 0,
+                    valid_lifetime: parse_bytes::<u32>(&bytes[4..8], Endian::Big),
+                    preferred_lifetime: parse_bytes::<u32>(&bytes[8..12], Endian::Big),
+                    prefix: ipv6_at(bytes, 16),
+                }
+            }
+            REDIRECTED_HEADER => {
+                NdpOption::RedirectedHeader(bytes[REDIRECTED_HEADER_DATA_OFFSET..].to_vec())
+            }
+            MTU => {
+                if bytes.len() != MTU_LEN {
+                    return Err(invalid_len());
+                }
+                NdpOption::Mtu(parse_bytes::<u32>(&bytes[4..8], Endian::Big))
+            }
+            RDNSS => {
+                if bytes.len() < RDNSS_MIN_LEN || !(bytes.len() - RDNSS_MIN_LEN).is_multiple_of(16)
+                {
+                    return Err(invalid_len());
+                }
+                NdpOption::Rdnss {
+                    lifetime: parse_bytes::<u32>(&bytes[4..8], Endian::Big),
+                    servers: bytes[RDNSS_MIN_LEN..]
+                        .chunks_exact(16)
+                        .map(|addr| ipv6_at(addr, 0))
+                        .collect(),
+                }
+            }
+            kind => NdpOption::Unknown {
+                kind,
+                data: data.to_vec(),
+            },
+        };
+        Ok(opt)
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            NdpOption::SourceLinkLayerAddr(_) => SOURCE_LL_ADDR,
+            NdpOption::TargetLinkLayerAddr(_) => TARGET_LL_ADDR,
+            NdpOption::PrefixInfo { .. } => PREFIX_INFO,
+            NdpOption::RedirectedHeader(_) => REDIRECTED_HEADER,
+            NdpOption::Mtu(_) => MTU,
+            NdpOption::Rdnss { .. } => RDNSS,
+            NdpOption::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// Serializes the option, zero padding it to a multiple of 8 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = vec![self.kind(), 0];
+        match self {
+            NdpOption::SourceLinkLayerAddr(addr) | NdpOption::TargetLinkLayerAddr(addr) => {
+                res.extend_from_slice(addr)
+            }
+            NdpOption::PrefixInfo {
+                prefix_len,
+                on_link,
+                autonomous,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            } => {
+                let mut flags = 0;
+                if *on_link {
+                    flags |= PREFIX_FLAG_ON_LINK;
+                }
+                if *autonomous {
+                    flags |= PREFIX_FLAG_AUTONOMOUS;
+                }
+                res.extend_from_slice(&[*prefix_len, flags]);
+                res.extend_from_slice(&valid_lifetime.to_be_bytes());
+                res.extend_from_slice(&preferred_lifetime.to_be_bytes());
+                res.extend_from_slice(&[0; 4]);
+                res.extend_from_slice(&prefix.octets());
+            }
+            NdpOption::RedirectedHeader(packet) => {
+                res.extend_from_slice(&[0; 6]);
+                res.extend_from_slice(packet);
+            }
+            NdpOption::Mtu(mtu) => {
+                res.extend_from_slice(&[0; 2]);
+                res.extend_from_slice(&mtu.to_be_bytes());
+            }
+            NdpOption::Rdnss { lifetime, servers } => {
+                res.extend_from_slice(&[0; 2]);
+                res.extend_from_slice(&lifetime.to_be_bytes());
+                for server in servers {
+                    res.extend_from_slice(&server.octets());
+                }
+            }
+            NdpOption::Unknown { data, .. } => res.extend_from_slice(data),
+        }
+        res.resize(res.len().next_multiple_of(NDP_OPT_LEN_UNIT), 0);
+        res[NDP_OPT_LEN_OFFSET] = (res.len() / NDP_OPT_LEN_UNIT) as u8;
+        res
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let value = match self {
+            NdpOption::SourceLinkLayerAddr(addr) => json!({ "nd.opt.src_lladdr": to_hex(addr) }),
+            NdpOption::TargetLinkLayerAddr(addr) => json!({ "nd.opt.tgt_lladdr": to_hex(addr) }),
+            NdpOption::PrefixInfo {
+                prefix_len,
+                on_link,
+                autonomous,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            } => json!({
+                "nd.opt.prefix": prefix.to_string(),
+                "nd.opt.prefix_len": prefix_len,
+                "nd.opt.on_link": on_link,
+                "nd.opt.autonomous": autonomous,
+                "nd.opt.valid_lifetime": valid_lifetime,
+                "nd.opt.preferred_lifetime": preferred_lifetime,
+            }),
+            NdpOption::RedirectedHeader(packet) => {
+                json!({ "nd.opt.redirected": printable_ascii(packet) })
+            }
+            NdpOption::Mtu(mtu) => json!({ "nd.opt.mtu": mtu }),
+            NdpOption::Rdnss { lifetime, servers } => json!({
+                "nd.opt.rdnss.lifetime": lifetime,
+                "nd.opt.rdnss.servers": servers.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            }),
+            NdpOption::Unknown { data, .. } => json!({ "nd.opt.data": to_hex(data) }),
+        };
+
+        let mut res = json!({ "nd.opt.type": self.kind() });
+        if let serde_json::Value::Object(fields) = value {
+            res.as_object_mut().unwrap().extend(fields);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ra_options() {
+        let mut bytes = vec![0x01, 0x01, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+        bytes.extend_from_slice(&[0x05, 0x01, 0x00, 0x00, 0x00, 0x00, 0x05, 0xdc]);
+        bytes.extend_from_slice(&[
+            0x03, 0x04, 0x40, 0xc0, 0x00, 0x27, 0x8d, 0x00, 0x00, 0x09, 0x3a, 0x80, 0x00, 0x00,
+            0x00, 0x00,
+        ]);
+        bytes.extend_from_slice(&"2001:db8:1::".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&[0x19, 0x03, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x10]);
+        bytes.extend_from_slice(&"2001:db8::53".parse::<Ipv6Addr>().unwrap().octets());
+
+        let opts = parse_ndp_options(&bytes).unwrap();
+        assert!(
+            opts[0] == NdpOption::SourceLinkLayerAddr(vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55])
+        );
+        assert!(opts[1] == NdpOption::Mtu(1500));
+        assert!(
+            opts[2]
+                == NdpOption::PrefixInfo {
+                    prefix_len: 64,
+                    on_link: true,
+                    autonomous: true,
+                    valid_lifetime: 2592000,
+                    preferred_lifetime: 604800,
+                    prefix: "2001:db8:1::".parse().unwrap(),
+                }
+        );
+        assert!(
+            opts[3]
+                == NdpOption::Rdnss {
+                    lifetime: 3600,
+                    servers: vec!["2001:db8::53".parse().unwrap()],
+                }
+        );
+        assert!(ndp_options_to_bytes(&opts) == bytes);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(parse_ndp_options(&[0x01, 0x00, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(parse_ndp_options(&[0x01, 0x02, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(
+            parse_ndp_options(&[0x05, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err()
+        );
+    }
+}
@@ -1,57 +1,270 @@
+use crate::checksum::{for_each_layer_mut, push_layers};
+use crate::ethernet::{ETHER_DISSECTION_TABLE, EtherType, Ethernet};
+use crate::geneve::Geneve;
+use crate::gre::Gre;
+use crate::icmp::{ICMP_DISSECTION_TABLE, Icmp, IcmpType};
+use crate::icmpv6::{ICMPV6_DISSECTION_TABLE, Icmpv6, Icmpv6Type};
+use crate::ip::{IPV4_DISSECTION_TABLE, Ip, Ipv4Type};
+use crate::ip6::{IPV6_DISSECTION_TABLE, Ipv6, Ipv6Type};
+use crate::loopback::{Loopback, ether_type_to_family};
 use crate::pdu::Pdu;
+use crate::sll::{LinuxSll, LinuxSll2};
+use crate::table::{lookup_type, lookup_value};
+use crate::udp::Udp;
+use crate::vlan::Vlan;
+
+use std::any::TypeId;
 
 #[macro_export]
 macro_rules! packet {
     ( $( $pdu:expr ),* $(,)? ) => {{
-        let mut v: Vec<Box<dyn Pdu>> = Vec::new();
-        $( v.push(Box::new($pdu)); )*
-        Packet{ pdu_chain: v }
+        let v: Vec<Box<dyn Pdu>> = vec![$( Box::new($pdu) ),*];
+        Packet::new(v)
     }};
 }
 
+/// What `Packet::to_bytes` fills in before serializing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Finalize {
+    /// Serialize the layers as they are.
+    #[default]
+    Off,
+    /// Fill in length, next-protocol and checksum fields that are still zero.
+    Missing,
+    /// Recompute those fields, replacing values set by the caller.
+    Overwrite,
+}
+
 pub struct Packet<'a> {
     pub pdu_chain: Vec<Box<dyn Pdu<'a>>>,
+    finalize: Finalize,
 }
 
 impl<'a> Packet<'a> {
     pub fn new(pdu_chain: Vec<Box<dyn Pdu<'a>>>) -> Self {
         Self {
-            pdu_chain: pdu_chain,
+            pdu_chain,
+            finalize: Finalize::Off,
         }
     }
 
+    pub fn finalize_mode(&self) -> Finalize {
+        self.finalize
+    }
+
+    pub fn set_finalize(&mut self, finalize: Finalize) {
+        self.finalize = finalize;
+    }
+
+    pub fn with_finalize(&mut self, finalize: Finalize) -> &mut Self {
+        self.set_finalize(finalize);
+        self
+    }
+
     pub fn to_bytes(&mut self) -> Vec<u8> {
+        match self.finalize {
+            Finalize::Off => {}
+            Finalize::Missing => self.finalize(false),
+            Finalize::Overwrite => self.finalize(true),
+        }
+
         let mut packet = Vec::new();
         for idx in 0..self.pdu_chain.len() {
-            packet.extend_from_slice(&self.pdu_chain[idx].to_bytes());
+            packet.extend_from_slice(&self.pdu_chain[idx].chain_to_bytes());
         }
         packet
     }
 
-    pub fn find<T: Pdu<'a>>(&self) -> Option<&'a T> {
-        for pdu in &self.pdu_chain {
-            if pdu.self_id() == T::id() {
-                return unsafe { Some(&*(&pdu as *const _ as *const T)) };
+    /// Fills in length and next-protocol fields from the layers below each
+    /// one, then the checksums. Fields that are already non-zero are kept
+    /// unless `overwrite` is set.
+    pub fn finalize(&mut self, overwrite: bool) {
+        // Sizes and types are gathered up front since the layers can't be
+        // borrowed while they are being changed.
+        let layers: Vec<(usize, TypeId)> = {
+            let mut layers = Vec::new();
+            for pdu in &self.pdu_chain {
+                push_layers(pdu.as_ref(), &mut layers);
             }
+            layers
+                .iter()
+                .map(|layer| (layer.to_bytes().len(), layer.self_id()))
+                .collect()
+        };
+
+        let mut idx = 0;
+        for pdu in &mut self.pdu_chain {
+            for_each_layer_mut(pdu.as_mut(), &mut idx, &mut |idx, layer| {
+                let payload_len = layers[idx + 1..].iter().map(|(len, _)| len).sum();
+                let next = layers.get(idx + 1).map(|(_, type_id)| *type_id);
+                finalize_layer(layer, payload_len, next, overwrite);
+            });
         }
-        None
+
+        self.fill_checksums(overwrite);
+    }
+
+    pub fn find<T: Pdu<'a>>(&self) -> Option<&'a T> {
+        self.pdu_chain.iter().find_map(|pdu| pdu.find::<T>())
     }
 
     pub fn find_mut<T: Pdu<'a>>(&mut self) -> Option<&'a mut T> {
-        for pdu in &mut self.pdu_chain {
-            if pdu.self_id() == T::id() {
-                return unsafe { Some(&mut *(pdu as *mut _ as *mut T)) };
+        self.pdu_chain
+            .iter_mut()
+            .find_map(|pdu| pdu.find_mut::<T>())
+    }
+
+    /// Every instance of `T`, outermost first.
+    pub fn find_all<T: Pdu<'a>>(&self) -> Vec<&'a T> {
+        self.pdu_chain
+            .iter()
+            .flat_map(|pdu| pdu.find_all::<T>())
+            .collect()
+    }
+
+    pub fn find_nth<T: Pdu<'a>>(&self, n: usize) -> Option<&'a T> {
+        self.find_all::<T>().get(n).copied()
+    }
+
+    /// The innermost instance of `T`, e.g. the `Ip` carried by a tunnel.
+    pub fn find_inner<T: Pdu<'a>>(&self) -> Option<&'a T> {
+        self.find_all::<T>().last().copied()
+    }
+}
+
+/// Some types are registered under several EtherTypes, like VLAN tags under
+/// both TPIDs, so a value that already names the next layer is kept even
+/// when overwriting.
+fn fill_ether_type(current: u16, next: Option<TypeId>, overwrite: bool) -> bool {
+    current == 0 || (overwrite && lookup_type(&ETHER_DISSECTION_TABLE, &EtherType(current)) != next)
+}
+
+/// Sets the length and next-protocol fields of one layer, given the size of
+/// everything after it and the type of the layer right below it.
+fn finalize_layer<'a>(
+    layer: &mut (dyn Pdu<'a> + 'a),
+    payload_len: usize,
+    next: Option<TypeId>,
+    overwrite: bool,
+) {
+    let ether_type = next
+        .and_then(|next| lookup_value(&ETHER_DISSECTION_TABLE, next))
+        .map(|EtherType(ether_type)| ether_type);
+
+    if let Some(eth) = layer.downcast_mut::<Ethernet>() {
+        if let Some(ether_type) = ether_type
+            && fill_ether_type(eth.ether_type(), next, overwrite)
+        {
+            eth.set_ether_type(ether_type);
+        }
+    } else if let Some(vlan) = layer.downcast_mut::<Vlan>() {
+        if let Some(ether_type) = ether_type
+            && fill_ether_type(vlan.ether_type(), next, overwrite)
+        {
+            vlan.set_ether_type(ether_type);
+        }
+    } else if let Some(gre) = layer.downcast_mut::<Gre>() {
+        if let Some(ether_type) = ether_type
+            && fill_ether_type(gre.protocol(), next, overwrite)
+        {
+            gre.set_protocol(ether_type);
+        }
+    } else if let Some(geneve) = layer.downcast_mut::<Geneve>() {
+        if let Some(ether_type) = ether_type
+            && fill_ether_type(geneve.protocol(), next, overwrite)
+        {
+            geneve.set_protocol(ether_type);
+        }
+    } else if let Some(sll) = layer.downcast_mut::<LinuxSll>() {
+        if let Some(ether_type) = ether_type
+            && fill_ether_type(sll.protocol(), next, overwrite)
+        {
+            sll.set_protocol(ether_type);
+        }
+    } else if let Some(sll2) = layer.downcast_mut::<LinuxSll2>() {
+        if let Some(ether_type) = ether_type
+            && fill_ether_type(sll2.protocol(), next, overwrite)
+        {
+            sll2.set_protocol(ether_type);
+        }
+    } else if let Some(lo) = layer.downcast_mut::<Loopback>() {
+        if let Some(family) = ether_type.and_then(ether_type_to_family)
+            && (overwrite || lo.family() == 0)
+        {
+            lo.set_family(family);
+        }
+    } else if let Some(ip) = layer.downcast_mut::<Ip>() {
+        let header_len = ip.to_bytes().len();
+        if overwrite || ip.ihl() == 0 {
+            ip.set_ihl((header_len / 4) as u8);
+        }
+        if overwrite || ip.total_len() == 0 {
+            ip.set_total_len((header_len + payload_len) as u16);
+        }
+        if let Some(Ipv4Type(protocol)) =
+            next.and_then(|next| lookup_value(&IPV4_DISSECTION_TABLE, next))
+            && (overwrite || ip.protocol() == 0)
+        {
+            ip.set_protocol(protocol);
+        }
+    } else if let Some(ipv6) = layer.downcast_mut::<Ipv6>() {
+        let ext_len: usize = ipv6.ext_headers().iter().map(|ext| ext.len()).sum();
+        if overwrite || ipv6.payload_len() == 0 {
+            ipv6.set_payload_len((ext_len + payload_len) as u16);
+        }
+
+        // Each header names the one after it: the extension headers in
+        // order, then the upper layer.
+        let upper = next
+            .and_then(|next| lookup_value(&IPV6_DISSECTION_TABLE, next))
+            .map(|Ipv6Type(next_header)| next_header);
+        let next_headers: Vec<Option<u8>> = ipv6
+            .ext_headers()
+            .iter()
+            .map(|ext| Some(ext.kind()))
+            .chain([upper])
+            .collect();
+        if let Some(next_header) = next_headers[0]
+            && (overwrite || ipv6.next_header() == 0)
+        {
+            ipv6.set_next_header(next_header);
+        }
+        for (ext, next_header) in ipv6.ext_headers_mut().iter_mut().zip(&next_headers[1..]) {
+            if let Some(next_header) = *next_header
+                && (overwrite || ext.next_header() == 0)
+            {
+                ext.set_next_header(next_header);
             }
         }
-        None
+    } else if let Some(udp) = layer.downcast_mut::<Udp>() {
+        if overwrite || udp.length() == 0 {
+            udp.set_length((udp.to_bytes().len() + payload_len) as u16);
+        }
+    } else if let Some(icmp) = layer.downcast_mut::<Icmp>() {
+        if let Some(IcmpType(msg_type)) =
+            next.and_then(|next| lookup_value(&ICMP_DISSECTION_TABLE, next))
+            && (overwrite || icmp.msg_type() == 0)
+        {
+            icmp.set_msg_type(msg_type);
+        }
+    } else if let Some(icmpv6) = layer.downcast_mut::<Icmpv6>()
+        && let Some(Icmpv6Type(msg_type)) =
+            next.and_then(|next| lookup_value(&ICMPV6_DISSECTION_TABLE, next))
+        && (overwrite || icmpv6.msg_type() == 0)
+    {
+        icmpv6.set_msg_type(msg_type);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::checksum::ChecksumStatus;
     use crate::ethernet::Ethernet;
     use crate::ip::Ip;
+    use crate::raw::Raw;
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
 
     #[test]
     fn test_pdu() {
@@ -64,4 +277,79 @@ mod tests {
         // let unboxed_pdu = boxed_pdu.as_any().downcast_ref::<Ethernet>();
         // println!("{:?}", &unboxed_pdu);
     }
+
+    fn udp_packet<'a>() -> Packet<'a> {
+        let mut ip = Ip::new();
+        ip.with_ttl(64)
+            .with_src_addr(Ipv4Addr::new(192, 0, 2, 1))
+            .with_dst_addr(Ipv4Addr::new(198, 51, 100, 2));
+        let mut udp = Udp::new();
+        udp.with_src_port(12345).with_dst_port(9999);
+        packet!(Ethernet::new(), ip, udp, Raw::new(b"hello".to_vec()))
+    }
+
+    #[test]
+    fn test_finalize_ipv4_udp() {
+        let mut packet = udp_packet();
+        let bytes = packet.with_finalize(Finalize::Missing).to_bytes();
+        assert!(bytes.len() == 14 + 20 + 8 + 5);
+
+        let eth = Ethernet::from_bytes(&bytes).unwrap();
+        assert!(eth.downcast_ref::<Ethernet>().unwrap().ether_type() == 0x0800);
+        let ip = eth.find::<Ip>().unwrap();
+        assert!(ip.ihl() == 5);
+        assert!(ip.total_len() == 33);
+        assert!(ip.protocol() == 17);
+        let udp = eth.find::<Udp>().unwrap();
+        assert!(udp.length() == 13);
+        assert!(udp.checksum() != 0);
+        assert!(
+            eth.verify_checksums()
+                .iter()
+                .all(|layer| layer.status == ChecksumStatus::Good)
+        );
+    }
+
+    #[test]
+    fn test_finalize_vlan_ether_type() {
+        let mut packet = packet!(Ethernet::new(), Vlan::new(10), Ip::new(), Udp::new());
+        packet.finalize(false);
+        assert!(packet.find::<Ethernet>().unwrap().ether_type() == 0x8100);
+        assert!(packet.find::<Vlan>().unwrap().ether_type() == 0x0800);
+    }
+
+    #[test]
+    fn test_finalize_keeps_set_values() {
+        let mut packet = udp_packet();
+        packet.find_mut::<Ip>().unwrap().set_total_len(100);
+        packet.find_mut::<Udp>().unwrap().set_length(7);
+        packet.finalize(false);
+        assert!(packet.find::<Ip>().unwrap().total_len() == 100);
+        assert!(packet.find::<Udp>().unwrap().length() == 7);
+        assert!(packet.find::<Ip>().unwrap().protocol() == 17);
+
+        packet.finalize(true);
+        assert!(packet.find::<Ip>().unwrap().total_len() == 33);
+        assert!(packet.find::<Udp>().unwrap().length() == 13);
+    }
+
+    #[test]
+    fn test_finalize_ipv6_ext_headers() {
+        use crate::ip6_ext::{DEST_OPTS, Ipv6ExtHeader};
+
+        let mut ipv6 = Ipv6::new();
+        ipv6.with_src_addr("2001:db8::1".parse::<Ipv6Addr>().unwrap())
+            .with_dst_addr("2001:db8::2".parse::<Ipv6Addr>().unwrap())
+            .with_ext_header(Ipv6ExtHeader::new(DEST_OPTS, 8));
+        let mut packet = packet!(Ethernet::new(), ipv6, Udp::new());
+        let bytes = packet.with_finalize(Finalize::Missing).to_bytes();
+
+        let eth = Ethernet::from_bytes(&bytes).unwrap();
+        assert!(eth.downcast_ref::<Ethernet>().unwrap().ether_type() == 0x86DD);
+        let ipv6 = eth.find::<Ipv6>().unwrap();
+        assert!(ipv6.payload_len() == 16);
+        assert!(ipv6.next_header() == DEST_OPTS);
+        assert!(ipv6.upper_layer_protocol() == 17);
+        assert!(eth.find::<Udp>().unwrap().length() == 8);
+    }
 }
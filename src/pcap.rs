@@ -0,0 +1,1241 @@
+use crate::error::PcapError;
+use crate::packet::Packet;
+use crate::prelude::*;
+
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_SNAPLEN_OFFSET: usize = 16;
+const PCAP_LINKTYPE_OFFSET: usize = 20;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const BLOCK_HEADER_LEN: usize = 8;
+const BLOCK_TRAILER_LEN: usize = 4;
+const BLOCK_MIN_LEN: usize = BLOCK_HEADER_LEN + BLOCK_TRAILER_LEN;
+const BLOCK_ALIGN: usize = 4;
+
+pub const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+pub const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+pub const SIMPLE_PACKET_BLOCK: u32 = 3;
+pub const NAME_RESOLUTION_BLOCK: u32 = 4;
+pub const INTERFACE_STATISTICS_BLOCK: u32 = 5;
+pub const ENHANCED_PACKET_BLOCK: u32 = 6;
+
+const SHB_OPTS_OFFSET: usize = 16;
+const IDB_SNAPLEN_OFFSET: usize = 4;
+const IDB_OPTS_OFFSET: usize = 8;
+const EPB_TS_OFFSET: usize = 4;
+const EPB_CAPLEN_OFFSET: usize = 12;
+const EPB_ORIGLEN_OFFSET: usize = 16;
+const EPB_DATA_OFFSET: usize = 20;
+const SPB_DATA_OFFSET: usize = 4;
+const ISB_TS_OFFSET: usize = 4;
+const ISB_OPTS_OFFSET: usize = 12;
+
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const IF_DESCRIPTION: u16 = 3;
+const IF_TSRESOL: u16 = 9;
+const IF_TSOFFSET: u16 = 14;
+const ISB_STARTTIME: u16 = 2;
+const ISB_ENDTIME: u16 = 3;
+const ISB_IFRECV: u16 = 4;
+const ISB_IFDROP: u16 = 5;
+const ISB_FILTERACCEPT: u16 = 6;
+const ISB_OSDROP: u16 = 7;
+const ISB_USRDELIV: u16 = 8;
+
+const NRB_RECORD_END: u16 = 0;
+const NRB_RECORD_IPV4: u16 = 1;
+const NRB_RECORD_IPV6: u16 = 2;
+
+const MICROS_PER_SEC: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+pub const LINKTYPE_NULL: u16 = 0;
+pub const LINKTYPE_ETHERNET: u16 = 1;
+pub const LINKTYPE_RAW: u16 = 101;
+pub const LINKTYPE_LOOP: u16 = 108;
+pub const LINKTYPE_LINUX_SLL: u16 = 113;
+pub const LINKTYPE_IEEE802_11_RADIOTAP: u16 = 127;
+pub const LINKTYPE_IPV4: u16 = 228;
+pub const LINKTYPE_IPV6: u16 = 229;
+pub const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+/// A pcap link-layer header type.
+/// Refer to <https://www.tcpdump.org/linktypes.html>
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct LinkType(pub u16);
+
+pub static LINKTYPE_DISSECTION_TABLE: DissectionTable<LinkType> = create_table();
+
+/// A capture interface. Classic pcap files describe a single one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub link_type: u16,
+    pub snaplen: u32,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Timestamp units per second.
+    pub ts_resolution: u64,
+    /// Seconds added to every timestamp.
+    pub ts_offset: i64,
+    pub comments: Vec<String>,
+    /// Counters from the latest statistics block for this interface.
+    pub statistics: Option<InterfaceStatistics>,
+}
+
+impl Interface {
+    pub fn new(link_type: u16, snaplen: u32) -> Self {
+        Self {
+            link_type,
+            snaplen,
+            name: None,
+            description: None,
+            ts_resolution: MICROS_PER_SEC,
+            ts_offset: 0,
+            comments: Vec::new(),
+            statistics: None,
+        }
+    }
+
+    /// Fails with `InvalidBlock` if the offset carries it past `Duration`.
+    fn timestamp(&self, units: u64) -> Result<Duration, PcapError> {
+        let secs = units / self.ts_resolution;
+        let frac = (units % self.ts_resolution) as u128;
+        let nanos = (frac * NANOS_PER_SEC as u128 / self.ts_resolution as u128) as u32;
+        let ts = Duration::new(secs, nanos);
+        let offset = Duration::from_secs(self.ts_offset.unsigned_abs());
+        if self.ts_offset < 0 {
+            Ok(ts.saturating_sub(offset))
+        } else {
+            ts.checked_add(offset).ok_or(PcapError::InvalidBlock)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceStatistics {
+    pub timestamp: Duration,
+    pub start_time: Option<Duration>,
+    pub end_time: Option<Duration>,
+    pub received: Option<u64>,
+    pub dropped: Option<u64>,
+    pub filter_accepted: Option<u64>,
+    pub os_dropped: Option<u64>,
+    pub delivered: Option<u64>,
+    pub comments: Vec<String>,
+}
+
+/// A captured packet, dissected from its link-layer header.
+pub struct Record<'a> {
+    /// Time since the Unix epoch. Simple packet blocks carry none and
+    /// report zero.
+    pub timestamp: Duration,
+    pub interface: u32,
+    pub link_type: u16,
+    /// Length of the packet on the wire, which may exceed `data`.
+    pub orig_len: u32,
+    pub data: &'a [u8],
+    pub comments: Vec<String>,
+    pub pdu: Box<dyn Pdu<'a> + 'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Pcap,
+    PcapNg,
+}
+
+/// Iterates over the packets of a classic pcap or pcapng capture held in
+/// memory. Reading stops after the first error.
+pub struct PcapReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    format: Format,
+    endian: Endian,
+    interfaces: Vec<Interface>,
+    section_comments: Vec<String>,
+    resolved_names: HashMap<IpAddr, Vec<String>>,
+    done: bool,
+}
+
+fn read_u16(bytes: &[u8], offset: usize, endian: Endian) -> Result<u16, PcapError> {
+    let field = bytes.get(offset..offset + 2).ok_or(PcapError::Truncated)?;
+    Ok(parse_bytes::<u16>(field, endian))
+}
+
+fn read_u32(bytes: &[u8], offset: usize, endian: Endian) -> Result<u32, PcapError> {
+    let field = bytes.get(offset..offset + 4).ok_or(PcapError::Truncated)?;
+    Ok(parse_bytes::<u32>(field, endian))
+}
+
+fn read_u64(bytes: &[u8], offset: usize, endian: Endian) -> Result<u64, PcapError> {
+    let field = bytes.get(offset..offset + 8).ok_or(PcapError::Truncated)?;
+    Ok(parse_bytes::<u64>(field, endian))
+}
+
+/// pcapng splits 64-bit timestamps into a high and a low word.
+fn read_pcapng_ts(bytes: &[u8], offset: usize, endian: Endian) -> Result<u64, PcapError> {
+    let high = read_u32(bytes, offset, endian)? as u64;
+    let low = read_u32(bytes, offset + 4, endian)? as u64;
+    Ok((high << 32) | low)
+}
+
+/// Refer to <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-03.html#section-3.5>
+fn parse_options(bytes: &[u8], endian: Endian) -> Result<Vec<(u16, &[u8])>, PcapError> {
+    let mut opts = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= bytes.len() {
+        let code = read_u16(bytes, offset, endian)?;
+        let len = read_u16(bytes, offset + 2, endian)? as usize;
+        if code == OPT_END {
+            break;
+        }
+        let value = bytes
+            .get(offset + 4..offset + 4 + len)
+            .ok_or(PcapError::InvalidBlock)?;
+        opts.push((code, value));
+        offset += 4 + len.next_multiple_of(BLOCK_ALIGN);
+    }
+    Ok(opts)
+}
+
+fn opt_string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).into_owned()
+}
+
+fn comments(opts: &[(u16, &[u8])]) -> Vec<String> {
+    opts.iter()
+        .filter(|(code, _)| *code == OPT_COMMENT)
+        .map(|(_, value)| opt_string(value))
+        .collect()
+}
+
+/// Units per second from an `if_tsresol` option: a power of ten, or of two
+/// if the top bit is set.
+fn ts_resolution(value: u8) -> Result<u64, PcapError> {
+    let resolution = if value & 0x80 != 0 {
+        1u64.checked_shl((value & 0x7F) as u32)
+    } else {
+        10u64.checked_pow(value as u32)
+    };
+    resolution.ok_or(PcapError::InvalidBlock)
+}
+
+/// Dissects a captured frame starting from its link-layer header, falling
+/// back to `Raw` for unknown link types or malformed frames. Raw IP frames
+/// are dispatched on their version.
+pub fn dissect<'a>(link_type: u16, bytes: &'a [u8]) -> Box<dyn Pdu<'a> + 'a> {
+    let link_type = match link_type {
+        LINKTYPE_RAW if bytes.first().is_some_and(|b| b >> 4 == 6) => LINKTYPE_IPV6,
+        LINKTYPE_RAW => LINKTYPE_IPV4,
+        link_type => link_type,
+    };
+    build_from_table(&LINKTYPE_DISSECTION_TABLE, LinkType(link_type), bytes)
+}
+
+impl<'a> PcapReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, PcapError> {
+        let mut reader = Self {
+            bytes,
+            offset: 0,
+            format: Format::PcapNg,
+            endian: Endian::Little,
+            interfaces: Vec::new(),
+            section_comments: Vec::new(),
+            resolved_names: HashMap::new(),
+            done: false,
+        };
+
+        match read_u32(bytes, 0, Endian::Little)? {
+            SECTION_HEADER_BLOCK => {
+                let (block_type, body) = reader.next_block()?;
+                reader.read_block(block_type, body)?;
+            }
+            magic => {
+                let (endian, resolution) = match (magic, magic.swap_bytes()) {
+                    (PCAP_MAGIC_MICROS, _) => (Endian::Little, MICROS_PER_SEC),
+                    (PCAP_MAGIC_NANOS, _) => (Endian::Little, NANOS_PER_SEC),
+                    (_, PCAP_MAGIC_MICROS) => (Endian::Big, MICROS_PER_SEC),
+                    (_, PCAP_MAGIC_NANOS) => (Endian::Big, NANOS_PER_SEC),
+                    _ => return Err(PcapError::UnknownFormat),
+                };
+                if bytes.len() < PCAP_HEADER_LEN {
+                    return Err(PcapError::Truncated);
+                }
+                let link_type = read_u32(bytes, PCAP_LINKTYPE_OFFSET, endian)? as u16;
+                let mut interface =
+                    Interface::new(link_type, read_u32(bytes, PCAP_SNAPLEN_OFFSET, endian)?);
+                interface.ts_resolution = resolution;
+
+                reader.format = Format::Pcap;
+                reader.endian = endian;
+                reader.interfaces.push(interface);
+                reader.offset = PCAP_HEADER_LEN;
+            }
+        }
+        Ok(reader)
+    }
+
+    pub fn is_pcapng(&self) -> bool {
+        self.format == Format::PcapNg
+    }
+
+    /// Interfaces of the current section, indexed by interface id.
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    pub fn section_comments(&self) -> &[String] {
+        &self.section_comments
+    }
+
+    /// Names from the name resolution blocks read so far.
+    pub fn resolved_names(&self) -> &HashMap<IpAddr, Vec<String>> {
+        &self.resolved_names
+    }
+
+    fn interface(&self, id: u32) -> Result<&Interface, PcapError> {
+        self.interfaces
+            .get(id as usize)
+            .ok_or(PcapError::UnknownInterface(id))
+    }
+
+    fn read_pcap_record(&mut self) -> Result<Option<Record<'a>>, PcapError> {
+        let bytes = self.bytes;
+        let header = bytes
+            .get(self.offset..self.offset + PCAP_RECORD_HEADER_LEN)
+            .ok_or(PcapError::Truncated)?;
+        let secs = read_u32(header, 0, self.endian)? as u64;
+        let frac = read_u32(header, 4, self.endian)? as u64;
+        let incl_len = read_u32(header, 8, self.endian)? as usize;
+        let orig_len = read_u32(header, 12, self.endian)?;
+
+        let start = self.offset + PCAP_RECORD_HEADER_LEN;
+        let data = bytes
+            .get(start..start + incl_len)
+            .ok_or(PcapError::Truncated)?;
+        self.offset = start + incl_len;
+
+        let interface = self.interface(0)?;
+        Ok(Some(Record {
+            timestamp: interface.timestamp(secs * interface.ts_resolution + frac)?,
+            interface: 0,
+            link_type: interface.link_type,
+            orig_len,
+            data,
+            comments: Vec::new(),
+            pdu: dissect(interface.link_type, data),
+        }))
+    }
+
+    /// Returns the type and body of the next pcapng block. A section header
+    /// sets the byte order used from there on.
+    fn next_block(&mut self) -> Result<(u32, &'a [u8]), PcapError> {
+        let bytes = self.bytes;
+        let block_type = read_u32(bytes, self.offset, self.endian)?;
+        if block_type == SECTION_HEADER_BLOCK {
+            self.endian = match read_u32(bytes, self.offset + BLOCK_HEADER_LEN, Endian::Little)? {
+                PCAPNG_BYTE_ORDER_MAGIC => Endian::Little,
+                magic if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => Endian::Big,
+                _ => return Err(PcapError::InvalidBlock),
+            };
+        }
+
+        let len = read_u32(bytes, self.offset + 4, self.endian)? as usize;
+        if len < BLOCK_MIN_LEN || !len.is_multiple_of(BLOCK_ALIGN) {
+            return Err(PcapError::InvalidBlock);
+        }
+        let block = bytes
+            .get(self.offset..self.offset + len)
+            .ok_or(PcapError::Truncated)?;
+        if read_u32(block, len - BLOCK_TRAILER_LEN, self.endian)? as usize != len {
+            return Err(PcapError::InvalidBlock);
+        }
+
+        self.offset += len;
+        Ok((
+            block_type,
+            &block[BLOCK_HEADER_LEN..len - BLOCK_TRAILER_LEN],
+        ))
+    }
+
+    /// Handles one pcapng block, returning a record for packet blocks.
+    fn read_block(
+        &mut self,
+        block_type: u32,
+        body: &'a [u8],
+    ) -> Result<Option<Record<'a>>, PcapError> {
+        let endian = self.endian;
+        match block_type {
+            SECTION_HEADER_BLOCK => {
+                let opts = parse_options(body.get(SHB_OPTS_OFFSET..).unwrap_or(&[]), endian)?;
+                self.interfaces.clear();
+                self.resolved_names.clear();
+                self.section_comments = comments(&opts);
+            }
+            INTERFACE_DESCRIPTION_BLOCK => {
+                let mut interface = Interface::new(
+                    read_u16(body, 0, endian)?,
+                    read_u32(body, IDB_SNAPLEN_OFFSET, endian)?,
+                );
+                let opts = parse_options(&body[IDB_OPTS_OFFSET..], endian)?;
+                for (code, value) in &opts {
+                    match *code {
+                        IF_NAME => interface.name = Some(opt_string(value)),
+                        IF_DESCRIPTION => interface.description = Some(opt_string(value)),
+                        IF_TSRESOL => {
+                            let resolution = *value.first().ok_or(PcapError::InvalidBlock)?;
+                            interface.ts_resolution = ts_resolution(resolution)?;
+                        }
+                        IF_TSOFFSET => interface.ts_offset = read_u64(value, 0, endian)? as i64,
+                        _ => {}
+                    }
+                }
+                interface.comments = comments(&opts);
+                self.interfaces.push(interface);
+            }
+            ENHANCED_PACKET_BLOCK => {
+                let id = read_u32(body, 0, endian)?;
+                let ts = read_pcapng_ts(body, EPB_TS_OFFSET, endian)?;
+                let cap_len = read_u32(body, EPB_CAPLEN_OFFSET, endian)? as usize;
+                let orig_len = read_u32(body, EPB_ORIGLEN_OFFSET, endian)?;
+                let data = body
+                    .get(EPB_DATA_OFFSET..EPB_DATA_OFFSET + cap_len)
+                    .ok_or(PcapError::InvalidBlock)?;
+                let opts_offset = EPB_DATA_OFFSET + cap_len.next_multiple_of(BLOCK_ALIGN);
+                let opts = parse_options(body.get(opts_offset..).unwrap_or(&[]), endian)?;
+
+                let interface = self.interface(id)?;
+                return Ok(Some(Record {
+                    timestamp: interface.timestamp(ts)?,
+                    interface: id,
+                    link_type: interface.link_type,
+                    orig_len,
+                    data,
+                    comments: comments(&opts),
+                    pdu: dissect(interface.link_type, data),
+                }));
+            }
+            SIMPLE_PACKET_BLOCK => {
+                let orig_len = read_u32(body, 0, endian)?;
+                let interface = self.interface(0)?;
+                // The captured length is implied by the block length, less
+                // any padding.
+                let mut cap_len = (orig_len as usize).min(body.len() - SPB_DATA_OFFSET);
+                if interface.snaplen != 0 {
+                    cap_len = cap_len.min(interface.snaplen as usize);
+                }
+                let data = &body[SPB_DATA_OFFSET..SPB_DATA_OFFSET + cap_len];
+
+                return Ok(Some(Record {
+                    timestamp: Duration::ZERO,
+                    interface: 0,
+                    link_type: interface.link_type,
+                    orig_len,
+                    data,
+                    comments: Vec::new(),
+                    pdu: dissect(interface.link_type, data),
+                }));
+            }
+            NAME_RESOLUTION_BLOCK => self.read_name_records(body)?,
+            INTERFACE_STATISTICS_BLOCK => {
+                let id = read_u32(body, 0, endian)?;
+                let interface = self.interface(id)?;
+                let ts = read_pcapng_ts(body, ISB_TS_OFFSET, endian)?;
+                let mut stats = InterfaceStatistics {
+                    timestamp: interface.timestamp(ts)?,
+                    ..Default::default()
+                };
+                let opts = parse_options(body.get(ISB_OPTS_OFFSET..).unwrap_or(&[]), endian)?;
+                for (code, value) in &opts {
+                    match *code {
+                        ISB_STARTTIME => {
+                            stats.start_time =
+                                Some(interface.timestamp(read_pcapng_ts(value, 0, endian)?)?)
+                        }
+                        ISB_ENDTIME => {
+                            stats.end_time =
+                                Some(interface.timestamp(read_pcapng_ts(value, 0, endian)?)?)
+                        }
+                        ISB_IFRECV => stats.received = Some(read_u64(value, 0, endian)?),
+                        ISB_IFDROP => stats.dropped = Some(read_u64(value, 0, endian)?),
+                        ISB_FILTERACCEPT => {
+                            stats.filter_accepted = Some(read_u64(value, 0, endian)?)
+                        }
+                        ISB_OSDROP => stats.os_dropped = Some(read_u64(value, 0, endian)?),
+                        ISB_USRDELIV => stats.delivered = Some(read_u64(value, 0, endian)?),
+                        _ => {}
+                    }
+                }
+                stats.comments = comments(&opts);
+                self.interfaces[id as usize].statistics = Some(stats);
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Refer to <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-03.html#section-4.5>
+    fn read_name_records(&mut self, body: &[u8]) -> Result<(), PcapError> {
+        let mut offset = 0;
+        while offset < body.len() {
+            let record_type = read_u16(body, offset, self.endian)?;
+            let len = read_u16(body, offset + 2, self.endian)? as usize;
+            if record_type == NRB_RECORD_END {
+                break;
+            }
+            let value = body
+                .get(offset + 4..offset + 4 + len)
+                .ok_or(PcapError::InvalidBlock)?;
+            offset += 4 + len.next_multiple_of(BLOCK_ALIGN);
+
+            let (addr, names) = match record_type {
+                NRB_RECORD_IPV4 if len >= 4 => {
+                    let octets: [u8; 4] = value[..4].try_into().unwrap();
+                    (IpAddr::V4(Ipv4Addr::from(octets)), &value[4..])
+                }
+                NRB_RECORD_IPV6 if len >= 16 => {
+                    let octets: [u8; 16] = value[..16].try_into().unwrap();
+                    (IpAddr::V6(Ipv6Addr::from(octets)), &value[16..])
+                }
+                NRB_RECORD_IPV4 | NRB_RECORD_IPV6 => return Err(PcapError::InvalidBlock),
+                _ => continue,
+            };
+            let names = names
+                .split(|b| *b == 0)
+                .filter(|name| !name.is_empty())
+                .map(opt_string);
+            self.resolved_names.entry(addr).or_default().extend(names);
+        }
+        Ok(())
+    }
+
+    fn read_pcapng_record(&mut self) -> Result<Option<Record<'a>>, PcapError> {
+        while self.offset < self.bytes.len() {
+            let (block_type, body) = self.next_block()?;
+            if let Some(record) = self.read_block(block_type, body)? {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for PcapReader<'a> {
+    type Item = Result<Record<'a>, PcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.bytes.len() {
+            return None;
+        }
+
+        let res = match self.format {
+            Format::Pcap => self.read_pcap_record(),
+            Format::PcapNg => self.read_pcapng_record(),
+        };
+        match res {
+            Ok(record) => record.map(Ok),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Writes packets to a classic pcap or pcapng capture. Everything is
+/// written little endian.
+pub struct PcapWriter<W: Write> {
+    inner: W,
+    format: Format,
+    interfaces: Vec<Interface>,
+}
+
+fn option_bytes(code: u16, value: &[u8]) -> Vec<u8> {
+    let mut res = Vec::new();
+    res.extend_from_slice(&code.to_le_bytes());
+    res.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    res.extend_from_slice(value);
+    res.resize(res.len().next_multiple_of(BLOCK_ALIGN), 0);
+    res
+}
+
+/// Serializes options, closing the list with `opt_endofopt`.
+fn options_to_bytes(opts: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    if opts.is_empty() {
+        return Vec::new();
+    }
+    let mut res: Vec<u8> = opts
+        .iter()
+        .flat_map(|(code, value)| option_bytes(*code, value))
+        .collect();
+    res.extend_from_slice(&option_bytes(OPT_END, &[]));
+    res
+}
+
+fn comment_options(comments: &[&str]) -> Vec<(u16, Vec<u8>)> {
+    comments
+        .iter()
+        .map(|comment| (OPT_COMMENT, comment.as_bytes().to_vec()))
+        .collect()
+}
+
+/// The `if_tsresol` value for a resolution, if it is a power of ten or two.
+fn ts_resolution_option(resolution: u64) -> Option<u8> {
+    if resolution == 0 {
+        return None;
+    }
+    let mut exp = 0;
+    let mut units = resolution;
+    while units.is_multiple_of(10) {
+        units /= 10;
+        exp += 1;
+    }
+    if units == 1 {
+        Some(exp)
+    } else if resolution.is_power_of_two() {
+        Some(0x80 | resolution.trailing_zeros() as u8)
+    } else {
+        None
+    }
+}
+
+impl Interface {
+    /// Converts a timestamp back into this interface's units. Fails with
+    /// `InvalidBlock` if it doesn't fit in 64 bits.
+    fn units(&self, timestamp: Duration) -> Result<u64, PcapError> {
+        let offset = Duration::from_secs(self.ts_offset.unsigned_abs());
+        let ts = if self.ts_offset < 0 {
+            timestamp
+                .checked_add(offset)
+                .ok_or(PcapError::InvalidBlock)?
+        } else {
+            timestamp.saturating_sub(offset)
+        };
+        let frac =
+            (ts.subsec_nanos() as u128 * self.ts_resolution as u128 / NANOS_PER_SEC as u128) as u64;
+        ts.as_secs()
+            .checked_mul(self.ts_resolution)
+            .and_then(|units| units.checked_add(frac))
+            .ok_or(PcapError::InvalidBlock)
+    }
+
+    /// Truncates `data` to the snapshot length.
+    fn snap<'b>(&self, data: &'b [u8]) -> &'b [u8] {
+        match self.snaplen as usize {
+            0 => data,
+            snaplen => &data[..data.len().min(snaplen)],
+        }
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Starts a classic pcap file. Its single interface must use
+    /// microsecond or nanosecond timestamps.
+    pub fn pcap(mut inner: W, interface: Interface) -> Result<Self, PcapError> {
+        let magic = match interface.ts_resolution {
+            MICROS_PER_SEC => PCAP_MAGIC_MICROS,
+            NANOS_PER_SEC => PCAP_MAGIC_NANOS,
+            _ => return Err(PcapError::Unsupported("pcap timestamp resolution")),
+        };
+
+        let mut header = Vec::with_capacity(PCAP_HEADER_LEN);
+        header.extend_from_slice(&magic.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&interface.snaplen.to_le_bytes());
+        header.extend_from_slice(&(interface.link_type as u32).to_le_bytes());
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            format: Format::Pcap,
+            interfaces: vec![interface],
+        })
+    }
+
+    /// Starts a pcapng file with a single section. Interfaces are added
+    /// with `add_interface`.
+    pub fn pcapng(inner: W, comments: &[&str]) -> Result<Self, PcapError> {
+        let mut writer = Self {
+            inner,
+            format: Format::PcapNg,
+            interfaces: Vec::new(),
+        };
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // The section length is unknown until the file is complete.
+        body.extend_from_slice(&u64::MAX.to_le_bytes());
+        body.extend_from_slice(&options_to_bytes(&comment_options(comments)));
+        writer.write_block(SECTION_HEADER_BLOCK, &body)?;
+        Ok(writer)
+    }
+
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    /// Describes a new interface and returns its id. Classic pcap files have
+    /// exactly one, given when the file is started.
+    pub fn add_interface(&mut self, interface: Interface) -> Result<u32, PcapError> {
+        if self.format == Format::Pcap {
+            return Err(PcapError::Unsupported("pcap files have a single interface"));
+        }
+
+        let mut opts = Vec::new();
+        if let Some(name) = &interface.name {
+            opts.push((IF_NAME, name.as_bytes().to_vec()));
+        }
+        if let Some(description) = &interface.description {
+            opts.push((IF_DESCRIPTION, description.as_bytes().to_vec()));
+        }
+        if interface.ts_resolution != MICROS_PER_SEC {
+            let resolution = ts_resolution_option(interface.ts_resolution)
+                .ok_or(PcapError::Unsupported("pcapng timestamp resolution"))?;
+            opts.push((IF_TSRESOL, vec![resolution]));
+        }
+        if interface.ts_offset != 0 {
+            opts.push((IF_TSOFFSET, interface.ts_offset.to_le_bytes().to_vec()));
+        }
+        let comments: Vec<&str> = interface.comments.iter().map(String::as_str).collect();
+        opts.extend(comment_options(&comments));
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&interface.link_type.to_le_bytes());
+        body.extend_from_slice(&[0; 2]);
+        body.extend_from_slice(&interface.snaplen.to_le_bytes());
+        body.extend_from_slice(&options_to_bytes(&opts));
+        self.write_block(INTERFACE_DESCRIPTION_BLOCK, &body)?;
+
+        self.interfaces.push(interface);
+        Ok(self.interfaces.len() as u32 - 1)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> Result<(), PcapError> {
+        let len = (BLOCK_MIN_LEN + body.len()) as u32;
+        let mut block = Vec::with_capacity(len as usize);
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&len.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&len.to_le_bytes());
+        self.inner.write_all(&block)?;
+        Ok(())
+    }
+
+    /// Writes one packet, truncated to the interface's snapshot length.
+    /// `orig_len` is raised to at least the length of `data`. Comments are
+    /// dropped from classic pcap files.
+    pub fn write_bytes(
+        &mut self,
+        interface: u32,
+        timestamp: Duration,
+        data: &[u8],
+        orig_len: u32,
+        comments: &[&str],
+    ) -> Result<(), PcapError> {
+        let iface = self
+            .interfaces
+            .get(interface as usize)
+            .ok_or(PcapError::UnknownInterface(interface))?;
+        let units = iface.units(timestamp)?;
+        let captured = iface.snap(data);
+        let orig_len = orig_len.max(data.len() as u32);
+
+        match self.format {
+            Format::Pcap => {
+                let resolution = iface.ts_resolution;
+                let mut record = Vec::with_capacity(PCAP_RECORD_HEADER_LEN + captured.len());
+                record.extend_from_slice(&((units / resolution) as u32).to_le_bytes());
+                record.extend_from_slice(&((units % resolution) as u32).to_le_bytes());
+                record.extend_from_slice(&(captured.len() as u32).to_le_bytes());
+                record.extend_from_slice(&orig_len.to_le_bytes());
+                record.extend_from_slice(captured);
+                self.inner.write_all(&record)?;
+            }
+            Format::PcapNg => {
+                let mut body = Vec::new();
+                body.extend_from_slice(&interface.to_le_bytes());
+                body.extend_from_slice(&((units >> 32) as u32).to_le_bytes());
+                body.extend_from_slice(&(units as u32).to_le_bytes());
+                body.extend_from_slice(&(captured.len() as u32).to_le_bytes());
+                body.extend_from_slice(&orig_len.to_le_bytes());
+                body.extend_from_slice(captured);
+                body.resize(body.len().next_multiple_of(BLOCK_ALIGN), 0);
+                body.extend_from_slice(&options_to_bytes(&comment_options(comments)));
+                self.write_block(ENHANCED_PACKET_BLOCK, &body)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a Pdu and all of its children.
+    pub fn write_pdu<'a>(
+        &mut self,
+        interface: u32,
+        timestamp: Duration,
+        pdu: &(dyn Pdu<'a> + 'a),
+        comments: &[&str],
+    ) -> Result<(), PcapError> {
+        let bytes = pdu.chain_to_bytes();
+        self.write_bytes(interface, timestamp, &bytes, 0, comments)
+    }
+
+    /// Writes a packet, finalizing it first if its finalize mode asks to.
+    pub fn write_packet(
+        &mut self,
+        interface: u32,
+        timestamp: Duration,
+        packet: &mut Packet,
+        comments: &[&str],
+    ) -> Result<(), PcapError> {
+        let bytes = packet.to_bytes();
+        self.write_bytes(interface, timestamp, &bytes, 0, comments)
+    }
+
+    /// Writes a record read from another capture, e.g. when filtering. The
+    /// interface id is kept, so the writer needs the same interfaces.
+    /// Untouched records are written as they were read. Rewritten Pdus are
+    /// written as they are now, followed by the padding or trailer that
+    /// wasn't dissected.
+    pub fn write_record(&mut self, record: &Record) -> Result<(), PcapError> {
+        let comments: Vec<&str> = record.comments.iter().map(String::as_str).collect();
+        let original = dissect(record.link_type, record.data).chain_to_bytes();
+        let mut bytes = record.pdu.chain_to_bytes();
+        if bytes == original {
+            return self.write_bytes(
+                record.interface,
+                record.timestamp,
+                record.data,
+                record.orig_len,
+                &comments,
+            );
+        }
+
+        if let Some(trailer) = record.data.strip_prefix(original.as_slice()) {
+            bytes.extend_from_slice(trailer);
+        }
+        self.write_bytes(record.interface, record.timestamp, &bytes, 0, &comments)
+    }
+
+    pub fn flush(&mut self) -> Result<(), PcapError> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethernet::Ethernet;
+    use crate::ip::Ip;
+    use crate::udp::Udp;
+
+    const ETH_IPV4_UDP: [u8; 42] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08,
+        0x00, // Ethernet
+        0x45, 0x00, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0x00, 0x02,
+        0x01, 0xc6, 0x33, 0x64, 0x02, // IPv4
+        0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00, // UDP
+    ];
+
+    fn put_u16(bytes: &mut Vec<u8>, value: u16, endian: Endian) {
+        match endian {
+            Endian::Big => bytes.extend_from_slice(&value.to_be_bytes()),
+            Endian::Little => bytes.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    fn put_u32(bytes: &mut Vec<u8>, value: u32, endian: Endian) {
+        match endian {
+            Endian::Big => bytes.extend_from_slice(&value.to_be_bytes()),
+            Endian::Little => bytes.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    fn pcap_file(magic: u32, endian: Endian, link_type: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        put_u32(&mut bytes, magic, endian);
+        put_u16(&mut bytes, 2, endian);
+        put_u16(&mut bytes, 4, endian);
+        put_u32(&mut bytes, 0, endian);
+        put_u32(&mut bytes, 0, endian);
+        put_u32(&mut bytes, 65535, endian);
+        put_u32(&mut bytes, link_type, endian);
+        bytes
+    }
+
+    fn pcap_record(bytes: &mut Vec<u8>, secs: u32, frac: u32, data: &[u8], endian: Endian) {
+        put_u32(bytes, secs, endian);
+        put_u32(bytes, frac, endian);
+        put_u32(bytes, data.len() as u32, endian);
+        put_u32(bytes, data.len() as u32 + 4, endian);
+        bytes.extend_from_slice(data);
+    }
+
+    fn option(code: u16, value: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        put_u16(&mut bytes, code, Endian::Little);
+        put_u16(&mut bytes, value.len() as u16, Endian::Little);
+        bytes.extend_from_slice(value);
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let len = (BLOCK_MIN_LEN + body.len()) as u32;
+        let mut bytes = Vec::new();
+        put_u32(&mut bytes, block_type, Endian::Little);
+        put_u32(&mut bytes, len, Endian::Little);
+        bytes.extend_from_slice(body);
+        put_u32(&mut bytes, len, Endian::Little);
+        bytes
+    }
+
+    fn section_header(opts: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_u32(&mut body, PCAPNG_BYTE_ORDER_MAGIC, Endian::Little);
+        put_u16(&mut body, 1, Endian::Little);
+        put_u16(&mut body, 0, Endian::Little);
+        body.extend_from_slice(&u64::MAX.to_le_bytes());
+        body.extend_from_slice(opts);
+        block(SECTION_HEADER_BLOCK, &body)
+    }
+
+    fn interface_description(link_type: u16, opts: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_u16(&mut body, link_type, Endian::Little);
+        put_u16(&mut body, 0, Endian::Little);
+        put_u32(&mut body, 0, Endian::Little);
+        body.extend_from_slice(opts);
+        block(INTERFACE_DESCRIPTION_BLOCK, &body)
+    }
+
+    fn enhanced_packet(id: u32, ts: u64, data: &[u8], opts: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_u32(&mut body, id, Endian::Little);
+        put_u32(&mut body, (ts >> 32) as u32, Endian::Little);
+        put_u32(&mut body, ts as u32, Endian::Little);
+        put_u32(&mut body, data.len() as u32, Endian::Little);
+        put_u32(&mut body, data.len() as u32, Endian::Little);
+        body.extend_from_slice(data);
+        body.resize(body.len().next_multiple_of(4), 0);
+        body.extend_from_slice(opts);
+        block(ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    #[test]
+    fn test_pcap_little_endian_micros() {
+        let mut bytes = pcap_file(PCAP_MAGIC_MICROS, Endian::Little, 1);
+        pcap_record(
+            &mut bytes,
+            1_700_000_000,
+            250_000,
+            &ETH_IPV4_UDP,
+            Endian::Little,
+        );
+        pcap_record(
+            &mut bytes,
+            1_700_000_001,
+            0,
+            &ETH_IPV4_UDP[..20],
+            Endian::Little,
+        );
+
+        let mut reader = PcapReader::new(&bytes).unwrap();
+        assert!(!reader.is_pcapng());
+        assert!(reader.interfaces()[0].link_type == LINKTYPE_ETHERNET);
+
+        let record = reader.next().unwrap().unwrap();
+        assert!(record.timestamp == Duration::new(1_700_000_000, 250_000_000));
+        assert!(record.orig_len == 46);
+        assert!(record.data == ETH_IPV4_UDP);
+        assert!(record.pdu.find::<Udp>().unwrap().dst_port() == 53);
+
+        // A frame too short for its headers keeps the layers that parsed.
+        let record = reader.next().unwrap().unwrap();
+        assert!(record.pdu.downcast_ref::<Ethernet>().is_some());
+        assert!(record.pdu.parse_error().unwrap().root().protocol() == "ip");
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_dissect_malformed_layer() {
+        let mut bytes = ETH_IPV4_UDP[..34].to_vec();
+        bytes[17] = 40;
+        bytes[23] = 6;
+        let mut tcp = [0; 20];
+        tcp[12] = 0x30;
+        bytes.extend_from_slice(&tcp);
+
+        let pdu = dissect(LINKTYPE_ETHERNET, &bytes);
+        assert!(pdu.downcast_ref::<Ethernet>().is_some());
+        assert!(pdu.find::<Ip>().is_some());
+        let raw = pdu.find::<Raw>().unwrap();
+        assert!(raw.to_bytes() == tcp);
+        assert!(raw.error().unwrap().protocol() == "tcp");
+        let err = pdu.parse_error().unwrap();
+        assert!(err.root().protocol() == "tcp");
+        assert!(err.root().offset() == 34 + 12);
+    }
+
+    #[test]
+    fn test_pcap_big_endian_nanos() {
+        let mut bytes = pcap_file(PCAP_MAGIC_NANOS, Endian::Big, LINKTYPE_RAW as u32);
+        pcap_record(&mut bytes, 10, 5, &ETH_IPV4_UDP[14..], Endian::Big);
+
+        let records: Vec<_> = PcapReader::new(&bytes).unwrap().collect();
+        assert!(records.len() == 1);
+        let record = records[0].as_ref().unwrap();
+        assert!(record.timestamp == Duration::new(10, 5));
+        assert!(record.pdu.downcast_ref::<Ip>().is_some());
+    }
+
+    #[test]
+    fn test_pcapng_blocks() {
+        let mut bytes = section_header(&option(OPT_COMMENT, b"section"));
+        let mut opts = option(IF_NAME, b"eth0");
+        opts.extend_from_slice(&option(IF_TSRESOL, &[9]));
+        bytes.extend_from_slice(&interface_description(LINKTYPE_ETHERNET, &opts));
+        bytes.extend_from_slice(&enhanced_packet(
+            0,
+            1_500_000_000_123_456_789,
+            &ETH_IPV4_UDP,
+            &option(OPT_COMMENT, b"first"),
+        ));
+
+        let mut nrb = option(NRB_RECORD_IPV4, b"\xc0\x00\x02\x01host.example\0");
+        nrb.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&block(NAME_RESOLUTION_BLOCK, &nrb));
+
+        let mut spb = Vec::new();
+        put_u32(&mut spb, ETH_IPV4_UDP.len() as u32, Endian::Little);
+        spb.extend_from_slice(&ETH_IPV4_UDP);
+        spb.resize(spb.len().next_multiple_of(4), 0);
+        bytes.extend_from_slice(&block(SIMPLE_PACKET_BLOCK, &spb));
+
+        let mut isb = vec![0; 12];
+        isb.extend_from_slice(&option(ISB_IFDROP, &3u64.to_le_bytes()));
+        isb.extend_from_slice(&option(OPT_COMMENT, b"stats"));
+        bytes.extend_from_slice(&block(INTERFACE_STATISTICS_BLOCK, &isb));
+
+        let mut reader = PcapReader::new(&bytes).unwrap();
+        assert!(reader.is_pcapng());
+        assert!(reader.section_comments() == ["section"]);
+
+        let record = reader.next().unwrap().unwrap();
+        assert!(record.timestamp == Duration::new(1_500_000_000, 123_456_789));
+        assert!(record.comments == ["first"]);
+        assert!(record.pdu.find::<Ip>().is_some());
+        assert!(reader.interfaces()[0].name.as_deref() == Some("eth0"));
+
+        let record = reader.next().unwrap().unwrap();
+        assert!(record.timestamp == Duration::ZERO);
+        assert!(record.data == ETH_IPV4_UDP);
+        assert!(
+            reader.resolved_names()[&"192.0.2.1".parse::<IpAddr>().unwrap()] == ["host.example"]
+        );
+
+        assert!(reader.next().is_none());
+        let stats = reader.interfaces()[0].statistics.as_ref().unwrap();
+        assert!(stats.dropped == Some(3));
+        assert!(stats.comments == ["stats"]);
+    }
+
+    #[test]
+    fn test_pcapng_big_endian_section() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&SECTION_HEADER_BLOCK.to_be_bytes());
+        bytes.extend_from_slice(&28u32.to_be_bytes());
+        bytes.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_be_bytes());
+        bytes.extend_from_slice(&[0, 1, 0, 0]);
+        bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+        bytes.extend_from_slice(&28u32.to_be_bytes());
+        bytes.extend_from_slice(&INTERFACE_DESCRIPTION_BLOCK.to_be_bytes());
+        bytes.extend_from_slice(&20u32.to_be_bytes());
+        bytes.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&20u32.to_be_bytes());
+
+        let reader = PcapReader::new(&bytes).unwrap();
+        assert!(reader.count() == 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            PcapReader::new(&[0; 24]),
+            Err(PcapError::UnknownFormat)
+        ));
+
+        let mut bytes = section_header(&[]);
+        bytes.extend_from_slice(&enhanced_packet(1, 0, &ETH_IPV4_UDP, &[]));
+        let mut reader = PcapReader::new(&bytes).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(PcapError::UnknownInterface(1)))
+        ));
+        assert!(reader.next().is_none());
+
+        let mut bytes = section_header(&[]);
+        bytes.extend_from_slice(&interface_description(LINKTYPE_ETHERNET, &[]));
+        let packet = enhanced_packet(0, 0, &ETH_IPV4_UDP, &[]);
+        bytes.extend_from_slice(&packet[..packet.len() - 8]);
+        let mut reader = PcapReader::new(&bytes).unwrap();
+        assert!(matches!(reader.next(), Some(Err(PcapError::Truncated))));
+    }
+
+    #[test]
+    fn test_timestamp_overflow() {
+        let mut interface = Interface::new(LINKTYPE_ETHERNET, 0);
+        interface.ts_resolution = 1;
+        interface.ts_offset = i64::MAX;
+        assert!(matches!(
+            interface.timestamp(u64::MAX),
+            Err(PcapError::InvalidBlock)
+        ));
+
+        interface.ts_resolution = NANOS_PER_SEC;
+        interface.ts_offset = -1;
+        let mut writer = PcapWriter::pcapng(Vec::new(), &[]).unwrap();
+        writer.add_interface(interface).unwrap();
+        for ts in [Duration::MAX, Duration::from_secs(u64::MAX / 2)] {
+            assert!(matches!(
+                writer.write_bytes(0, ts, &ETH_IPV4_UDP, 0, &[]),
+                Err(PcapError::InvalidBlock)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_write_pcapng() {
+        let mut writer = PcapWriter::pcapng(Vec::new(), &["crafted"]).unwrap();
+        let mut eth0 = Interface::new(LINKTYPE_ETHERNET, 0);
+        eth0.name = Some("eth0".to_string());
+        assert!(writer.add_interface(eth0).unwrap() == 0);
+        let mut raw = Interface::new(LINKTYPE_RAW, 20);
+        raw.ts_resolution = NANOS_PER_SEC;
+        assert!(writer.add_interface(raw).unwrap() == 1);
+
+        let ts = Duration::new(1_700_000_000, 123_456_789);
+        writer
+            .write_bytes(0, ts, &ETH_IPV4_UDP, 0, &["first", "second"])
+            .unwrap();
+        writer
+            .write_bytes(1, ts, &ETH_IPV4_UDP[14..], 0, &[])
+            .unwrap();
+        assert!(matches!(
+            writer.write_bytes(2, ts, &[], 0, &[]),
+            Err(PcapError::UnknownInterface(2))
+        ));
+        let bytes = writer.into_inner();
+
+        let mut reader = PcapReader::new(&bytes).unwrap();
+        assert!(reader.section_comments() == ["crafted"]);
+        let first = reader.next().unwrap().unwrap();
+        assert!(first.timestamp == Duration::new(1_700_000_000, 123_456_000));
+        assert!(first.comments == ["first", "second"]);
+        assert!(first.pdu.find::<Udp>().is_some());
+
+        let second = reader.next().unwrap().unwrap();
+        assert!(second.interface == 1);
+        assert!(second.timestamp == ts);
+        assert!(second.data == &ETH_IPV4_UDP[14..34]);
+        assert!(second.orig_len == 28);
+        assert!(reader.next().is_none());
+        assert!(reader.interfaces()[0].name.as_deref() == Some("eth0"));
+        assert!(reader.interfaces()[1].ts_resolution == NANOS_PER_SEC);
+    }
+
+    #[test]
+    fn test_write_pcap_packet() {
+        use crate::packet;
+        use crate::packet::Finalize;
+
+        let mut interface = Interface::new(LINKTYPE_ETHERNET, 65535);
+        interface.ts_resolution = NANOS_PER_SEC;
+        let mut writer = PcapWriter::pcap(Vec::new(), interface).unwrap();
+        assert!(matches!(
+            writer.add_interface(Interface::new(LINKTYPE_ETHERNET, 0)),
+            Err(PcapError::Unsupported(_))
+        ));
+
+        let mut packet = packet!(Ethernet::new(), Ip::new(), Udp::new());
+        packet.set_finalize(Finalize::Missing);
+        writer
+            .write_packet(0, Duration::new(5, 7), &mut packet, &["dropped"])
+            .unwrap();
+        let bytes = writer.into_inner();
+
+        let records: Vec<_> = PcapReader::new(&bytes).unwrap().collect();
+        let record = records[0].as_ref().unwrap();
+        assert!(record.timestamp == Duration::new(5, 7));
+        assert!(record.orig_len == 42);
+        assert!(record.pdu.find::<Udp>().unwrap().length() == 8);
+    }
+
+    #[test]
+    fn test_write_records() {
+        let mut bytes = pcap_file(PCAP_MAGIC_MICROS, Endian::Big, 1);
+        pcap_record(&mut bytes, 1, 2, &ETH_IPV4_UDP, Endian::Big);
+        pcap_record(&mut bytes, 3, 4, &ETH_IPV4_UDP[..20], Endian::Big);
+
+        let reader = PcapReader::new(&bytes).unwrap();
+        let mut writer = PcapWriter::pcap(Vec::new(), reader.interfaces()[0].clone()).unwrap();
+        for record in reader {
+            let record = record.unwrap();
+            if record.pdu.find::<Udp>().is_some() {
+                writer.write_record(&record).unwrap();
+            }
+        }
+        let out = writer.into_inner();
+
+        let records: Vec<_> = PcapReader::new(&out).unwrap().collect();
+        assert!(records.len() == 1);
+        let record = records[0].as_ref().unwrap();
+        assert!(record.timestamp == Duration::new(1, 2_000));
+        assert!(record.orig_len == ETH_IPV4_UDP.len() as u32 + 4);
+    }
+
+    #[test]
+    fn test_write_records_keeps_padding() {
+        let mut padded = ETH_IPV4_UDP.to_vec();
+        padded.resize(60, 0);
+        let mut bytes = pcap_file(PCAP_MAGIC_MICROS, Endian::Big, 1);
+        pcap_record(&mut bytes, 1, 2, &padded, Endian::Big);
+        pcap_record(&mut bytes, 3, 4, &padded, Endian::Big);
+
+        let reader = PcapReader::new(&bytes).unwrap();
+        let mut writer = PcapWriter::pcap(Vec::new(), reader.interfaces()[0].clone()).unwrap();
+        for (i, record) in reader.enumerate() {
+            let mut record = record.unwrap();
+            if i == 1 {
+                record.pdu.find_mut::<Udp>().unwrap().set_src_port(53);
+            }
+            writer.write_record(&record).unwrap();
+        }
+        let out = writer.into_inner();
+
+        let records: Vec<_> = PcapReader::new(&out).unwrap().map(Result::unwrap).collect();
+        assert!(records[0].data == padded);
+        assert!(records[0].orig_len == padded.len() as u32 + 4);
+        assert!(records[1].data[..34] == padded[..34]);
+        assert!(records[1].data[34..36] == [0x00, 0x35]);
+        assert!(records[1].data[36..] == padded[36..]);
+        assert!(records[1].orig_len == padded.len() as u32);
+    }
+}
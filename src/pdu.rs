@@ -1,4 +1,7 @@
 use crate::error::ParseError;
+use crate::field::{FieldDescriptor, FieldValue, fields_of, lookup_field, protocol_name};
+use crate::ip::Ip;
+use crate::raw::Raw;
 
 use nexus_tid::Tid;
 use serde_json::Value;
@@ -14,9 +17,8 @@ pub trait Pdu<'a>: Tid<'a> + 'a {
 
     fn collect(&self, chain: &mut Vec<Box<dyn Pdu<'static> + 'static>>) {
         chain.push(self.clone());
-        match self.child_pdu() {
-            Some(inner) => inner.collect(chain),
-            None => (),
+        if let Some(inner) = self.child_pdu() {
+            inner.collect(chain);
         }
     }
 
@@ -24,6 +26,15 @@ pub trait Pdu<'a>: Tid<'a> + 'a {
 
     fn to_bytes(&self) -> Vec<u8>;
 
+    /// Serializes this Pdu followed by all of its children.
+    fn chain_to_bytes(&self) -> Vec<u8> {
+        let mut res = self.to_bytes();
+        if let Some(child) = self.child_pdu() {
+            res.extend_from_slice(&child.chain_to_bytes());
+        }
+        res
+    }
+
     fn set_parent(&mut self, parent: Pob<'static>)
     where
         'a: 'static;
@@ -134,6 +145,94 @@ impl<'a> dyn Pdu<'a> + 'a {
         None
     }
 
+    /// Every instance of `T` in the chain, outermost first. Tunnels repeat
+    /// layers, e.g. the outer and inner `Ip` of a GRE packet.
+    pub fn find_all<T: Pdu<'a> + 'a>(&self) -> Vec<&'a T> {
+        let mut found = Vec::new();
+        let mut next = self.find::<T>();
+        while let Some(pdu) = next {
+            found.push(pdu);
+            next = pdu.child_pdu().as_ref().and_then(|child| child.find::<T>());
+        }
+        found
+    }
+
+    /// The instance of `T` at `n`, counting from the outermost at 0.
+    pub fn find_nth<T: Pdu<'a> + 'a>(&self, n: usize) -> Option<&'a T> {
+        self.find_all::<T>().get(n).copied()
+    }
+
+    pub fn find_nth_mut<T: Pdu<'a> + 'a>(&mut self, n: usize) -> Option<&'a mut T> {
+        if self.self_id() == T::id() {
+            if n == 0 {
+                return unsafe { Some(&mut *(self as *mut _ as *mut T)) };
+            }
+            return self.child_pdu_mut().as_mut()?.find_nth_mut::<T>(n - 1);
+        }
+        self.child_pdu_mut().as_mut()?.find_nth_mut::<T>(n)
+    }
+
+    /// The innermost instance of `T`, where `find` returns the outermost.
+    pub fn find_inner<T: Pdu<'a> + 'a>(&self) -> Option<&'a T> {
+        self.find_all::<T>().last().copied()
+    }
+
+    pub fn find_inner_mut<T: Pdu<'a> + 'a>(&mut self) -> Option<&'a mut T> {
+        let count = self.find_all::<T>().len();
+        self.find_nth_mut::<T>(count.checked_sub(1)?)
+    }
+
+    /// This layer's registered fields with their values, in registration
+    /// order. Optional fields that are absent are left out.
+    pub fn fields(&self) -> impl Iterator<Item = (FieldDescriptor, FieldValue)> + '_ {
+        fields_of(self.self_id())
+            .into_iter()
+            .filter_map(move |field| (field.get)(self).map(|value| (field, value)))
+    }
+
+    /// The value of the named field in this layer, or the first layer under
+    /// it that has it.
+    pub fn get_field(&self, name: &str) -> Option<FieldValue> {
+        let fields = lookup_field(name)?;
+        let mut layer = Some(self);
+        while let Some(pdu) = layer {
+            let value = pdu
+                .parts()
+                .into_iter()
+                .find_map(|part| fields.iter().find_map(|field| (field.get)(part)));
+            if value.is_some() {
+                return value;
+            }
+            layer = pdu.child_pdu().as_deref();
+        }
+        None
+    }
+
+    /// This layer followed by the Pdus it holds outside the chain, like the
+    /// options of an IPv4 header.
+    pub fn parts(&self) -> Vec<&(dyn Pdu<'a> + 'a)> {
+        let mut parts = vec![self];
+        if let Some(ip) = self.downcast_ref::<Ip>() {
+            parts.extend(ip.opts().iter().map(|opt| opt as &(dyn Pdu<'a> + 'a)));
+        }
+        parts
+    }
+
+    /// Why the chain stops short: the error held by the first layer that
+    /// failed to parse, wrapped in the layers above it so its offsets are
+    /// from the start of this one. `None` if every layer parsed.
+    pub fn parse_error(&self) -> Option<ParseError> {
+        if let Some(raw) = self.downcast_ref::<Raw>() {
+            return raw.error().cloned();
+        }
+        let err = self.child_pdu().as_deref()?.parse_error()?;
+        let len = self.to_bytes().len();
+        Some(match protocol_name(self.self_id()) {
+            Some(protocol) => err.within(protocol, len),
+            None => err.shifted(len),
+        })
+    }
+
     pub fn downcast_ref<T: Pdu<'a> + 'a>(&self) -> Option<&'a T> {
         if self.self_id() == T::id() {
             unsafe { Some(&*(self as *const _ as *const T)) }
@@ -150,10 +249,7 @@ impl<'a> dyn Pdu<'a> + 'a {
         }
     }
 
-    pub fn downcast<T: Pdu<'a> + 'a>(self: Box<Self>) -> Option<Box<T>>
-    where
-        T: 'a,
-    {
+    pub fn downcast<T: Pdu<'a> + 'a>(self: Box<Self>) -> Option<Box<T>> {
         if self.self_id() == T::id() {
             let raw = Box::into_raw(self);
             unsafe { Some(Box::from_raw(raw as *mut T)) }
@@ -177,7 +273,7 @@ mod tests {
 
         let eth_inv: Box<dyn Pdu> = Box::new(Ip::new());
         let res = eth_inv.downcast::<Ethernet>();
-        assert!(!res.is_some());
+        assert!(res.is_none());
     }
 
     #[test]
@@ -188,6 +284,24 @@ mod tests {
 
         let eth_inv: Box<dyn Pdu> = Box::new(Ip::new());
         let res = eth_inv.downcast_ref::<Ethernet>();
-        assert!(!res.is_some());
+        assert!(res.is_none());
+    }
+
+    #[test]
+    fn test_fields() {
+        let mut ip = Ip::new();
+        ip.with_ihl(5).with_total_len(20).with_ttl(17);
+        let mut eth = Ethernet::new();
+        eth.set_ether_type(0x0800);
+        let bytes = [eth.to_bytes(), ip.to_bytes()].concat();
+        let pdu = Ethernet::from_bytes(&bytes).unwrap();
+
+        let fields: Vec<_> = pdu.fields().collect();
+        assert!(fields.len() == 3);
+        assert!(fields[2].0.name == "eth.type");
+        assert!(fields[2].1 == FieldValue::U16(0x0800));
+        assert!(pdu.get_field("ip.ttl") == Some(FieldValue::U8(17)));
+        assert!(pdu.get_field("tcp.flags").is_none());
+        assert!(pdu.get_field("nope").is_none());
     }
 }
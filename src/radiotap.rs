@@ -0,0 +1,161 @@
+use crate::pcap::{LINKTYPE_DISSECTION_TABLE, LINKTYPE_IEEE802_11_RADIOTAP, LinkType};
+use crate::prelude::*;
+
+const RADIOTAP_VERSION_OFFSET: usize = 0;
+const RADIOTAP_LEN_OFFSET: usize = 2;
+const RADIOTAP_PRESENT_OFFSET: usize = 4;
+const RADIOTAP_MIN_HEADER_LEN: usize = 8;
+
+/// Radiotap header in front of a captured 802.11 frame. Only the fixed part
+/// is decoded; the fields it announces are kept as header bytes and the
+/// 802.11 frame is left as `Raw`.
+/// Refer to <https://www.radiotap.org/>
+#[pdu_type]
+pub struct Radiotap<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Radiotap<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(Radiotap);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len(
+            "radiotap",
+            "header length",
+            bytes,
+            0,
+            RADIOTAP_MIN_HEADER_LEN,
+        )?;
+
+        let header_len = get_radiotap_length(bytes) as usize;
+        if header_len < RADIOTAP_MIN_HEADER_LEN {
+            return Err(ParseError::InvalidHeader {
+                protocol: "radiotap",
+                field: "length",
+                offset: RADIOTAP_LEN_OFFSET,
+                value: header_len as u64,
+                reason: "is shorter than the minimum header",
+            });
+        }
+        ParseError::check_len("radiotap", "fields", bytes, 0, header_len)?;
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..header_len]),
+            parent: None,
+            child: Some(Raw::from_bytes(&bytes[header_len..])?),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "radiotap": {
+                "radiotap.version": self.version(),
+                "radiotap.length": self.length(),
+                "radiotap.present": self.present(),
+                "radiotap.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+/// The header length is little endian, unlike most network fields.
+fn get_radiotap_length(bytes: &[u8]) -> u16 {
+    parse_bytes::<u16>(
+        &bytes[RADIOTAP_LEN_OFFSET..RADIOTAP_PRESENT_OFFSET],
+        Endian::Little,
+    )
+}
+
+impl<'a> Radiotap<'a> {
+    /// A header announcing no fields.
+    pub fn new() -> Self {
+        let mut header = vec![0; RADIOTAP_MIN_HEADER_LEN];
+        header[RADIOTAP_LEN_OFFSET..RADIOTAP_PRESENT_OFFSET]
+            .copy_from_slice(&(RADIOTAP_MIN_HEADER_LEN as u16).to_le_bytes());
+        Self {
+            header: Cow::Owned(header),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.header[RADIOTAP_VERSION_OFFSET]
+    }
+
+    /// Length of the whole radiotap header, fields included.
+    pub fn length(&self) -> u16 {
+        get_radiotap_length(&self.header)
+    }
+
+    /// The first presence bitmap. Bit 31 set means another bitmap follows.
+    pub fn present(&self) -> u32 {
+        parse_bytes::<u32>(
+            &self.header[RADIOTAP_PRESENT_OFFSET..RADIOTAP_MIN_HEADER_LEN],
+            Endian::Little,
+        )
+    }
+
+    /// The bytes after the presence bitmap, up to the header length.
+    pub fn fields_bytes(&self) -> &[u8] {
+        &self.header[RADIOTAP_MIN_HEADER_LEN..]
+    }
+}
+
+register_pdu!(
+    LinkType(LINKTYPE_IEEE802_11_RADIOTAP),
+    Radiotap,
+    LINKTYPE_DISSECTION_TABLE
+);
+
+// The length and presence bitmap are little endian, so only the version has
+// a bit layout.
+register_fields!(Radiotap, "radiotap", {
+    "radiotap.version" => version { name: "Header Revision", bits: (RADIOTAP_VERSION_OFFSET * 8, 8) },
+    "radiotap.length" => length { name: "Header Length" },
+    "radiotap.present" => present { name: "Present Flags", base: Hex },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::FieldValue;
+    use crate::pcap::dissect;
+
+    // Radiotap with flags and rate, then the start of an 802.11 beacon.
+    const RADIOTAP_BEACON: [u8; 14] = [
+        0x00, 0x00, 0x0a, 0x00, 0x06, 0x00, 0x00, 0x00, // version, length 10, flags | rate
+        0x10, 0x02, // flags, rate
+        0x80, 0x00, 0x00, 0x00, // frame control, duration
+    ];
+
+    #[test]
+    fn test_radiotap() {
+        let pdu = dissect(LINKTYPE_IEEE802_11_RADIOTAP, &RADIOTAP_BEACON);
+        let radiotap = pdu.downcast_ref::<Radiotap>().unwrap();
+        assert!(radiotap.version() == 0);
+        assert!(radiotap.length() == 10);
+        assert!(radiotap.present() == 0x06);
+        assert!(radiotap.fields_bytes() == [0x10, 0x02]);
+        assert!(pdu.find::<Raw>().unwrap().to_bytes() == RADIOTAP_BEACON[10..]);
+        assert!(pdu.chain_to_bytes() == RADIOTAP_BEACON);
+        assert!(pdu.get_field("radiotap.length") == Some(FieldValue::U16(10)));
+
+        let mut short = RADIOTAP_BEACON;
+        short[RADIOTAP_LEN_OFFSET] = 4;
+        let err = Radiotap::from_bytes(&short).err().unwrap();
+        assert!(
+            err.to_string() == "radiotap: length 4 is shorter than the minimum header at offset 2"
+        );
+        assert!(
+            dissect(LINKTYPE_IEEE802_11_RADIOTAP, &short)
+                .parse_error()
+                .is_some()
+        );
+    }
+}
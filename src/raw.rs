@@ -1,8 +1,11 @@
-use crate::default_pdu_clone;
 use crate::prelude::*;
 
+/// Bytes that aren't dissected. A layer that was expected but failed to
+/// parse is kept as `Raw` too, holding the error.
 #[pdu_type]
-pub struct Raw<'a> {}
+pub struct Raw<'a> {
+    error: Option<ParseError>,
+}
 
 #[pdu_impl]
 impl<'a> Pdu<'a> for Raw<'a> {
@@ -12,21 +15,65 @@ impl<'a> Pdu<'a> for Raw<'a> {
         res
     }
 
-    default_pdu_clone!(Raw);
+    fn clone(&self) -> Box<dyn Pdu<'static> + 'static> {
+        Box::new(Raw {
+            header: Cow::Owned(self.header.to_vec()),
+            error: self.error.clone(),
+            parent: None,
+            child: None,
+        })
+    }
 
     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
         Ok(Box::new(Self {
-            header: Cow::Borrowed(&bytes),
+            header: Cow::Borrowed(bytes),
+            error: None,
             parent: None,
             child: None,
         }))
     }
 
     fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
-        Ok(json!({
-            "raw": {
-                "raw.data": printable_ascii(&self.header),
-            }
-        }))
+        let mut raw = json!({
+            "raw.data": printable_ascii(&self.header),
+        });
+        if let Some(error) = &self.error {
+            raw["raw.error"] = json!(error.to_string());
+        }
+        Ok(json!({ "raw": raw }))
     }
 }
+
+impl<'a> Raw<'a> {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            header: Cow::Owned(data),
+            error: None,
+            parent: None,
+            child: None,
+        }
+    }
+
+    /// `bytes` that failed to parse as the layer `error` comes from.
+    pub fn malformed(bytes: &'a [u8], error: ParseError) -> Self {
+        Self {
+            header: Cow::Borrowed(bytes),
+            error: Some(error),
+            parent: None,
+            child: None,
+        }
+    }
+
+    /// The parsed layer, or `bytes` kept as a malformed `Raw` if it failed.
+    pub fn or_malformed(result: PduResult<'a>, bytes: &'a [u8]) -> Box<dyn Pdu<'a> + 'a> {
+        result.unwrap_or_else(|err| Box::new(Raw::malformed(bytes, err)))
+    }
+
+    /// Why these bytes couldn't be parsed, relative to their start. See
+    /// `parse_error` on `dyn Pdu` for the error in terms of the whole chain.
+    pub fn error(&self) -> Option<&ParseError> {
+        self.error.as_ref()
+    }
+}
+
+register_fields!(Raw, "raw", {});
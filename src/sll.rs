@@ -0,0 +1,454 @@
+use crate::arp::ARP_HW_TYPE_NAMES;
+use crate::ethernet::{ETHER_DISSECTION_TABLE, ETHER_TYPE_NAMES, EtherType};
+use crate::mac_address::MacAddress;
+use crate::pcap::{LINKTYPE_DISSECTION_TABLE, LINKTYPE_LINUX_SLL, LINKTYPE_LINUX_SLL2, LinkType};
+use crate::prelude::*;
+
+const SLL_PKTTYPE_OFFSET: usize = 0;
+const SLL_HATYPE_OFFSET: usize = 2;
+const SLL_HALEN_OFFSET: usize = 4;
+const SLL_ADDR_OFFSET: usize = 6;
+const SLL_PROTOCOL_OFFSET: usize = 14;
+const SLL_HEADER_LEN: usize = 16;
+
+const SLL2_PROTOCOL_OFFSET: usize = 0;
+const SLL2_IFINDEX_OFFSET: usize = 4;
+const SLL2_HATYPE_OFFSET: usize = 8;
+const SLL2_PKTTYPE_OFFSET: usize = 10;
+const SLL2_HALEN_OFFSET: usize = 11;
+const SLL2_ADDR_OFFSET: usize = 12;
+const SLL2_HEADER_LEN: usize = 20;
+
+const SLL_ADDR_MAX_LEN: usize = 8;
+
+pub const PACKET_HOST: u16 = 0;
+pub const PACKET_BROADCAST: u16 = 1;
+pub const PACKET_MULTICAST: u16 = 2;
+pub const PACKET_OTHERHOST: u16 = 3;
+pub const PACKET_OUTGOING: u16 = 4;
+
+fn format_addr(addr: &[u8]) -> String {
+    if addr.len() == 6 {
+        MacAddress::from_bytes(addr).to_string()
+    } else {
+        addr.iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(":")
+    }
+}
+
+/// The used part of the 8 byte address field.
+fn sll_addr(addr_field: &[u8], addr_len: u8) -> &[u8] {
+    &addr_field[..(addr_len as usize).min(SLL_ADDR_MAX_LEN)]
+}
+
+fn set_sll_addr(addr_field: &mut [u8], addr: &[u8]) {
+    let len = addr.len().min(SLL_ADDR_MAX_LEN);
+    addr_field.fill(0);
+    addr_field[..len].copy_from_slice(&addr[..len]);
+}
+
+/// Linux cooked capture header, as written by `any` device captures.
+/// Refer to <https://www.tcpdump.org/linktypes/LINKTYPE_LINUX_SLL.html>
+#[pdu_type]
+pub struct LinuxSll<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for LinuxSll<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(LinuxSll);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("sll", "header length", bytes, 0, SLL_HEADER_LEN)?;
+
+        let protocol = parse_bytes::<u16>(&bytes[SLL_PROTOCOL_OFFSET..SLL_HEADER_LEN], Endian::Big);
+        let inner = build_from_table(
+            &ETHER_DISSECTION_TABLE,
+            EtherType(protocol),
+            &bytes[SLL_HEADER_LEN..],
+        );
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..SLL_HEADER_LEN]),
+            parent: None,
+            child: Some(inner),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "sll": {
+                "sll.pkttype": self.packet_type(),
+                "sll.hatype": self.hatype(),
+                "sll.halen": self.addr_len(),
+                "sll.src": format_addr(self.addr()),
+                "sll.etype": self.protocol(),
+                "sll.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> LinuxSll<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; SLL_HEADER_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    pub fn packet_type(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[SLL_PKTTYPE_OFFSET..SLL_HATYPE_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_packet_type(&mut self, packet_type: u16) {
+        self.header.to_mut()[SLL_PKTTYPE_OFFSET..SLL_HATYPE_OFFSET]
+            .copy_from_slice(&packet_type.to_be_bytes());
+    }
+
+    pub fn with_packet_type(&mut self, packet_type: u16) -> &mut Self {
+        self.set_packet_type(packet_type);
+        self
+    }
+
+    /// ARPHRD_ type of the capturing device.
+    pub fn hatype(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[SLL_HATYPE_OFFSET..SLL_HALEN_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_hatype(&mut self, hatype: u16) {
+        self.header.to_mut()[SLL_HATYPE_OFFSET..SLL_HALEN_OFFSET]
+            .copy_from_slice(&hatype.to_be_bytes());
+    }
+
+    pub fn with_hatype(&mut self, hatype: u16) -> &mut Self {
+        self.set_hatype(hatype);
+        self
+    }
+
+    pub fn addr_len(&self) -> u16 {
+        parse_bytes::<u16>(&self.header[SLL_HALEN_OFFSET..SLL_ADDR_OFFSET], Endian::Big)
+    }
+
+    /// Link-layer source address, at most 8 bytes of it.
+    pub fn addr(&self) -> &[u8] {
+        sll_addr(
+            &self.header[SLL_ADDR_OFFSET..SLL_PROTOCOL_OFFSET],
+            self.addr_len().min(SLL_ADDR_MAX_LEN as u16) as u8,
+        )
+    }
+
+    /// Sets the address and its length. Addresses longer than 8 bytes are
+    /// truncated, as the kernel does.
+    pub fn set_addr(&mut self, addr: &[u8]) {
+        let header = self.header.to_mut();
+        header[SLL_HALEN_OFFSET..SLL_ADDR_OFFSET]
+            .copy_from_slice(&(addr.len() as u16).to_be_bytes());
+        set_sll_addr(&mut header[SLL_ADDR_OFFSET..SLL_PROTOCOL_OFFSET], addr);
+    }
+
+    pub fn with_addr(&mut self, addr: &[u8]) -> &mut Self {
+        self.set_addr(addr);
+        self
+    }
+
+    /// EtherType of the payload.
+    pub fn protocol(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[SLL_PROTOCOL_OFFSET..SLL_HEADER_LEN],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_protocol(&mut self, protocol: u16) {
+        self.header.to_mut()[SLL_PROTOCOL_OFFSET..SLL_HEADER_LEN]
+            .copy_from_slice(&protocol.to_be_bytes());
+    }
+
+    pub fn with_protocol(&mut self, protocol: u16) -> &mut Self {
+        self.set_protocol(protocol);
+        self
+    }
+}
+
+/// Linux cooked capture header, version 2, which adds the interface index.
+/// Refer to <https://www.tcpdump.org/linktypes/LINKTYPE_LINUX_SLL2.html>
+#[pdu_type]
+pub struct LinuxSll2<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for LinuxSll2<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(LinuxSll2);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("sll", "header length", bytes, 0, SLL2_HEADER_LEN)?;
+
+        let protocol = parse_bytes::<u16>(
+            &bytes[SLL2_PROTOCOL_OFFSET..SLL2_PROTOCOL_OFFSET + 2],
+            Endian::Big,
+        );
+        let inner = build_from_table(
+            &ETHER_DISSECTION_TABLE,
+            EtherType(protocol),
+            &bytes[SLL2_HEADER_LEN..],
+        );
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..SLL2_HEADER_LEN]),
+            parent: None,
+            child: Some(inner),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "sll": {
+                "sll.etype": self.protocol(),
+                "sll.ifindex": self.interface_index(),
+                "sll.hatype": self.hatype(),
+                "sll.pkttype": self.packet_type(),
+                "sll.halen": self.addr_len(),
+                "sll.src": format_addr(self.addr()),
+                "sll.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> LinuxSll2<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; SLL2_HEADER_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
+    /// EtherType of the payload.
+    pub fn protocol(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[SLL2_PROTOCOL_OFFSET..SLL2_PROTOCOL_OFFSET + 2],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_protocol(&mut self, protocol: u16) {
+        self.header.to_mut()[SLL2_PROTOCOL_OFFSET..SLL2_PROTOCOL_OFFSET + 2]
+            .copy_from_slice(&protocol.to_be_bytes());
+    }
+
+    pub fn with_protocol(&mut self, protocol: u16) -> &mut Self {
+        self.set_protocol(protocol);
+        self
+    }
+
+    pub fn interface_index(&self) -> u32 {
+        parse_bytes::<u32>(
+            &self.header[SLL2_IFINDEX_OFFSET..SLL2_HATYPE_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_interface_index(&mut self, interface_index: u32) {
+        self.header.to_mut()[SLL2_IFINDEX_OFFSET..SLL2_HATYPE_OFFSET]
+            .copy_from_slice(&interface_index.to_be_bytes());
+    }
+
+    pub fn with_interface_index(&mut self, interface_index: u32) -> &mut Self {
+        self.set_interface_index(interface_index);
+        self
+    }
+
+    /// ARPHRD_ type of the capturing device.
+    pub fn hatype(&self) -> u16 {
+        parse_bytes::<u16>(
+            &self.header[SLL2_HATYPE_OFFSET..SLL2_PKTTYPE_OFFSET],
+            Endian::Big,
+        )
+    }
+
+    pub fn set_hatype(&mut self, hatype: u16) {
+        self.header.to_mut()[SLL2_HATYPE_OFFSET..SLL2_PKTTYPE_OFFSET]
+            .copy_from_slice(&hatype.to_be_bytes());
+    }
+
+    pub fn with_hatype(&mut self, hatype: u16) -> &mut Self {
+        self.set_hatype(hatype);
+        self
+    }
+
+    pub fn packet_type(&self) -> u8 {
+        self.header[SLL2_PKTTYPE_OFFSET]
+    }
+
+    pub fn set_packet_type(&mut self, packet_type: u8) {
+        self.header.to_mut()[SLL2_PKTTYPE_OFFSET] = packet_type;
+    }
+
+    pub fn with_packet_type(&mut self, packet_type: u8) -> &mut Self {
+        self.set_packet_type(packet_type);
+        self
+    }
+
+    pub fn addr_len(&self) -> u8 {
+        self.header[SLL2_HALEN_OFFSET]
+    }
+
+    /// Link-layer source address, at most 8 bytes of it.
+    pub fn addr(&self) -> &[u8] {
+        sll_addr(
+            &self.header[SLL2_ADDR_OFFSET..SLL2_HEADER_LEN],
+            self.addr_len(),
+        )
+    }
+
+    /// Sets the address and its length. Addresses longer than 8 bytes are
+    /// truncated, as the kernel does.
+    pub fn set_addr(&mut self, addr: &[u8]) {
+        let header = self.header.to_mut();
+        header[SLL2_HALEN_OFFSET] = addr.len() as u8;
+        set_sll_addr(&mut header[SLL2_ADDR_OFFSET..SLL2_HEADER_LEN], addr);
+    }
+
+    pub fn with_addr(&mut self, addr: &[u8]) -> &mut Self {
+        self.set_addr(addr);
+        self
+    }
+}
+
+register_pdu!(
+    LinkType(LINKTYPE_LINUX_SLL),
+    LinuxSll,
+    LINKTYPE_DISSECTION_TABLE
+);
+register_pdu!(
+    LinkType(LINKTYPE_LINUX_SLL2),
+    LinuxSll2,
+    LINKTYPE_DISSECTION_TABLE
+);
+
+const SLL_PACKET_TYPE_NAMES: ValueStrings = &[
+    (PACKET_HOST as u64, "Unicast to us"),
+    (PACKET_BROADCAST as u64, "Broadcast"),
+    (PACKET_MULTICAST as u64, "Multicast"),
+    (PACKET_OTHERHOST as u64, "Unicast to another host"),
+    (PACKET_OUTGOING as u64, "Sent by us"),
+];
+
+register_fields!(LinuxSll, "sll", {
+    "sll.pkttype" => packet_type {
+        name: "Packet Type",
+        bits: (SLL_PKTTYPE_OFFSET * 8, 16),
+        values: SLL_PACKET_TYPE_NAMES,
+    },
+    "sll.hatype" => hatype {
+        name: "Link-Layer Address Type",
+        bits: (SLL_HATYPE_OFFSET * 8, 16),
+        values: ARP_HW_TYPE_NAMES,
+    },
+    "sll.halen" => addr_len { name: "Link-Layer Address Length", bits: (SLL_HALEN_OFFSET * 8, 16) },
+    "sll.src" => addr { name: "Source" },
+    "sll.etype" => protocol {
+        name: "Protocol",
+        bits: (SLL_PROTOCOL_OFFSET * 8, 16),
+        base: Hex,
+        values: ETHER_TYPE_NAMES,
+    },
+});
+register_fields!(LinuxSll2, "sll", {
+    "sll.etype" => protocol {
+        name: "Protocol",
+        bits: (SLL2_PROTOCOL_OFFSET * 8, 16),
+        base: Hex,
+        values: ETHER_TYPE_NAMES,
+    },
+    "sll.ifindex" => interface_index { name: "Interface Index", bits: (SLL2_IFINDEX_OFFSET * 8, 32) },
+    "sll.hatype" => hatype {
+        name: "Link-Layer Address Type",
+        bits: (SLL2_HATYPE_OFFSET * 8, 16),
+        values: ARP_HW_TYPE_NAMES,
+    },
+    "sll.pkttype" => packet_type {
+        name: "Packet Type",
+        bits: (SLL2_PKTTYPE_OFFSET * 8, 8),
+        values: SLL_PACKET_TYPE_NAMES,
+    },
+    "sll.halen" => addr_len { name: "Link-Layer Address Length", bits: (SLL2_HALEN_OFFSET * 8, 8) },
+    "sll.src" => addr { name: "Source" },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::Ip;
+    use crate::pcap::dissect;
+    use crate::udp::Udp;
+
+    const IPV4_UDP: [u8; 28] = [
+        0x45, 0x00, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0x00, 0x02,
+        0x01, 0xc6, 0x33, 0x64, 0x02, 0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_sll() {
+        let mut bytes = vec![
+            0x00, 0x04, 0x00, 0x01, 0x00, 0x06, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x00, 0x00,
+            0x08, 0x00,
+        ];
+        bytes.extend_from_slice(&IPV4_UDP);
+
+        let pdu = dissect(LINKTYPE_LINUX_SLL, &bytes);
+        let sll = pdu.downcast_ref::<LinuxSll>().unwrap();
+        assert!(sll.packet_type() == PACKET_OUTGOING);
+        assert!(sll.hatype() == 1);
+        assert!(sll.addr() == [0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        assert!(sll.protocol() == 0x0800);
+        assert!(pdu.find::<Udp>().unwrap().dst_port() == 53);
+        assert!(pdu.chain_to_bytes() == bytes);
+    }
+
+    #[test]
+    fn test_sll2() {
+        let mut bytes = vec![
+            0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x01, 0x00, 0x06, 0x00, 0x11,
+            0x22, 0x33, 0x44, 0x55, 0x00, 0x00,
+        ];
+        bytes.extend_from_slice(&IPV4_UDP);
+
+        let pdu = dissect(LINKTYPE_LINUX_SLL2, &bytes);
+        let sll2 = pdu.downcast_ref::<LinuxSll2>().unwrap();
+        assert!(sll2.interface_index() == 3);
+        assert!(sll2.packet_type() == PACKET_HOST as u8);
+        assert!(sll2.addr() == [0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        assert!(pdu.find::<Ip>().is_some());
+    }
+
+    #[test]
+    fn test_build() {
+        let mut sll = LinuxSll::new();
+        sll.with_addr(&[1, 2, 3, 4, 5, 6, 7, 8, 9])
+            .with_protocol(0x86DD);
+        assert!(sll.addr_len() == 9);
+        assert!(sll.addr() == [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(sll.protocol() == 0x86DD);
+    }
+}
@@ -0,0 +1,453 @@
+use crate::flow::{Direction, FlowKey, TcpState, locate, opener_key};
+use crate::prelude::*;
+use crate::tcp::{TCP_FIN, TCP_SYN, Tcp};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Linux's tcp_fin_timeout is far shorter, but captures often miss the end
+/// of a connection.
+pub const DEFAULT_STREAM_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Out of order bytes buffered per direction before giving up on a hole.
+pub const DEFAULT_STREAM_BUFFER: usize = 1024 * 1024;
+
+/// Receives the bytes of each direction of a connection, in order and
+/// without retransmissions. Closures taking the key, direction and data
+/// are handlers too.
+pub trait StreamHandler {
+    fn on_data(&mut self, key: &FlowKey, dir: Direction, data: &[u8]);
+
+    /// `len` bytes going in `dir` were never captured and are skipped.
+    fn on_gap(&mut self, _key: &FlowKey, _dir: Direction, _len: u64) {}
+
+    /// The connection is done. `state` is `Closed` or `Reset` unless it
+    /// timed out or was flushed.
+    fn on_close(&mut self, _key: &FlowKey, _state: TcpState) {}
+}
+
+impl<F: FnMut(&FlowKey, Direction, &[u8])> StreamHandler for F {
+    fn on_data(&mut self, key: &FlowKey, dir: Direction, data: &[u8]) {
+        self(key, dir, data)
+    }
+}
+
+/// One direction of a connection. Sequence numbers are turned into 64-bit
+/// offsets from the first byte, which don't wrap.
+#[derive(Default)]
+struct HalfStream {
+    /// Sequence number of offset 0.
+    base: Option<u32>,
+    /// Offset of the next byte to deliver.
+    next: u64,
+    segments: BTreeMap<u64, Vec<u8>>,
+    buffered: usize,
+    /// Offset the FIN was sent at.
+    fin: Option<u64>,
+}
+
+impl HalfStream {
+    /// The offset of `seq`, taken as the one closest to `next`.
+    fn offset(&self, seq: u32) -> i64 {
+        let rel = seq.wrapping_sub(self.base.unwrap_or(seq));
+        let delta = rel.wrapping_sub(self.next as u32) as i32;
+        self.next as i64 + delta as i64
+    }
+
+    /// Buffers `data` at `offset`, minus what was already delivered. Where
+    /// buffered segments overlap, the earlier one wins when delivering.
+    fn insert(&mut self, offset: i64, data: &[u8]) {
+        let skip = (self.next as i64).saturating_sub(offset).max(0) as usize;
+        if skip >= data.len() {
+            return;
+        }
+        let offset = (offset + skip as i64) as u64;
+        let data = &data[skip..];
+        let old_len = self.segments.get(&offset).map_or(0, |old| old.len());
+        if old_len >= data.len() {
+            return;
+        }
+        self.buffered += data.len() - old_len;
+        self.segments.insert(offset, data.to_vec());
+    }
+
+    /// Hands over every byte that follows on from `next`.
+    fn deliver(&mut self, key: &FlowKey, dir: Direction, handler: &mut dyn StreamHandler) {
+        while let Some(entry) = self.segments.first_entry() {
+            let offset = *entry.key();
+            if offset > self.next {
+                break;
+            }
+            let data = entry.remove();
+            self.buffered -= data.len();
+            let end = offset + data.len() as u64;
+            if end > self.next {
+                handler.on_data(key, dir, &data[(self.next - offset) as usize..]);
+                self.next = end;
+            }
+        }
+    }
+
+    /// Skips the hole before the first buffered segment, or before the FIN
+    /// once nothing is buffered. Returns false if there is no hole.
+    fn skip_gap(&mut self, key: &FlowKey, dir: Direction, handler: &mut dyn StreamHandler) -> bool {
+        let resume = match self.segments.first_key_value() {
+            Some((offset, _)) => *offset,
+            None => match self.fin {
+                Some(fin) if fin > self.next => fin,
+                _ => return false,
+            },
+        };
+        handler.on_gap(key, dir, resume - self.next);
+        self.next = resume;
+        true
+    }
+
+    fn flush(&mut self, key: &FlowKey, dir: Direction, handler: &mut dyn StreamHandler) {
+        loop {
+            self.deliver(key, dir, handler);
+            if !self.skip_gap(key, dir, handler) {
+                break;
+            }
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.fin.is_some_and(|fin| self.next >= fin)
+    }
+}
+
+struct Stream {
+    /// The key as sent by the opener.
+    key: FlowKey,
+    state: TcpState,
+    flags: [u8; 2],
+    halves: [HalfStream; 2],
+    last_seen: Duration,
+}
+
+impl Stream {
+    fn close(mut self, handler: &mut dyn StreamHandler) {
+        for dir in [Direction::Forward, Direction::Reverse] {
+            self.halves[dir as usize].flush(&self.key, dir, handler);
+        }
+        handler.on_close(&self.key, self.state);
+    }
+}
+
+/// Follows TCP connections and hands each direction's bytes to a
+/// `StreamHandler` in order, once. Timestamps are capture times, e.g.
+/// `Record::timestamp`.
+pub struct TcpReassembler {
+    timeout: Duration,
+    max_buffered: usize,
+    streams: HashMap<FlowKey, Stream>,
+}
+
+impl TcpReassembler {
+    pub fn new() -> Self {
+        Self {
+            timeout: DEFAULT_STREAM_TIMEOUT,
+            max_buffered: DEFAULT_STREAM_BUFFER,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// How long a connection may go without a segment before it's closed.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Out of order bytes buffered per direction. Past it, the hole in front
+    /// of them is reported as a gap and skipped.
+    pub fn max_buffered(&self) -> usize {
+        self.max_buffered
+    }
+
+    pub fn set_max_buffered(&mut self, max_buffered: usize) {
+        self.max_buffered = max_buffered;
+    }
+
+    pub fn with_max_buffered(&mut self, max_buffered: usize) -> &mut Self {
+        self.set_max_buffered(max_buffered);
+        self
+    }
+
+    /// Number of open connections.
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Adds a segment and hands whatever it completes to `handler`. Returns
+    /// the segment's direction, or `None` if the packet isn't TCP.
+    pub fn process<'a>(
+        &mut self,
+        ts: Duration,
+        pdu: &(dyn Pdu<'a> + 'a),
+        handler: &mut dyn StreamHandler,
+    ) -> Option<Direction> {
+        let located = locate(pdu)?;
+        let tcp = located.transport?.downcast_ref::<Tcp>()?;
+        let payload = tcp
+            .child_pdu()
+            .as_ref()
+            .map(|child| child.chain_to_bytes())
+            .unwrap_or_default();
+        let flags = tcp.flags();
+        let canonical = located.key.canonical();
+
+        if let Some(stream) = self.streams.get(&canonical)
+            && ts.saturating_sub(stream.last_seen) >= self.timeout
+            && let Some(stream) = self.streams.remove(&canonical)
+        {
+            stream.close(handler);
+        }
+
+        let stream = self.streams.entry(canonical).or_insert_with(|| Stream {
+            key: opener_key(located.key, flags),
+            state: TcpState::initial(flags),
+            flags: [0; 2],
+            halves: Default::default(),
+            last_seen: ts,
+        });
+        let dir = if located.key == stream.key {
+            Direction::Forward
+        } else {
+            Direction::Reverse
+        };
+        let idx = dir as usize;
+        stream.last_seen = stream.last_seen.max(ts);
+        let first = stream.flags == [0; 2];
+        stream.flags[idx] |= flags;
+        if !first {
+            stream.state = stream.state.advance(dir, flags, stream.flags);
+        }
+
+        // The SYN takes up one sequence number.
+        let syn = flags & TCP_SYN != 0;
+        let seq = tcp.seq_number().wrapping_add(syn as u32);
+        let half = &mut stream.halves[idx];
+        if half.base.is_none() || (syn && half.next == 0 && half.segments.is_empty()) {
+            half.base = Some(seq);
+        }
+        let offset = half.offset(seq);
+        half.insert(offset, &payload);
+        if flags & TCP_FIN != 0 && half.fin.is_none() {
+            half.fin = Some((offset + payload.len() as i64).max(0) as u64);
+        }
+        half.deliver(&stream.key, dir, handler);
+        while half.buffered > self.max_buffered && half.skip_gap(&stream.key, dir, handler) {
+            half.deliver(&stream.key, dir, handler);
+        }
+
+        let done = match stream.state {
+            TcpState::Reset => true,
+            TcpState::Closed => stream.halves.iter().all(HalfStream::is_finished),
+            _ => false,
+        };
+        if done && let Some(stream) = self.streams.remove(&canonical) {
+            stream.close(handler);
+        }
+        Some(dir)
+    }
+
+    /// Closes connections idle at `now`, handing over what they buffered
+    /// around any holes. Returns how many were closed.
+    pub fn expire(&mut self, now: Duration, handler: &mut dyn StreamHandler) -> usize {
+        let idle: Vec<FlowKey> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| now.saturating_sub(stream.last_seen) >= self.timeout)
+            .map(|(key, _)| *key)
+            .collect();
+        for key in &idle {
+            if let Some(stream) = self.streams.remove(key) {
+                stream.close(handler);
+            }
+        }
+        idle.len()
+    }
+
+    /// Closes every connection, e.g. at the end of a capture.
+    pub fn flush(&mut self, handler: &mut dyn StreamHandler) {
+        for (_, stream) in self.streams.drain() {
+            stream.close(handler);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::Ip;
+    use crate::tcp::{TCP_ACK, TCP_RST};
+
+    #[derive(Default)]
+    struct Collector {
+        data: [Vec<u8>; 2],
+        gaps: Vec<(Direction, u64)>,
+        closed: Vec<TcpState>,
+    }
+
+    impl StreamHandler for Collector {
+        fn on_data(&mut self, _key: &FlowKey, dir: Direction, data: &[u8]) {
+            self.data[dir as usize].extend_from_slice(data);
+        }
+
+        fn on_gap(&mut self, _key: &FlowKey, dir: Direction, len: u64) {
+            self.gaps.push((dir, len));
+        }
+
+        fn on_close(&mut self, _key: &FlowKey, state: TcpState) {
+            self.closed.push(state);
+        }
+    }
+
+    /// A TCP segment between 192.0.2.1:40000 and 198.51.100.2:9999.
+    fn segment(client_to_server: bool, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let (src, dst, sport, dport) = match client_to_server {
+            true => ([192, 0, 2, 1], [198, 51, 100, 2], 40000u16, 9999u16),
+            false => ([198, 51, 100, 2], [192, 0, 2, 1], 9999, 40000),
+        };
+        let total_len = (40 + payload.len()) as u16;
+        let mut bytes = vec![0x45, 0x00];
+        bytes.extend_from_slice(&total_len.to_be_bytes());
+        bytes.extend_from_slice(&[0x00, 0x01, 0x40, 0x00, 0x40, 6, 0x00, 0x00]);
+        bytes.extend_from_slice(&src);
+        bytes.extend_from_slice(&dst);
+        bytes.extend_from_slice(&sport.to_be_bytes());
+        bytes.extend_from_slice(&dport.to_be_bytes());
+        bytes.extend_from_slice(&seq.to_be_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn feed(
+        reassembler: &mut TcpReassembler,
+        handler: &mut dyn StreamHandler,
+        ts: u64,
+        bytes: &[u8],
+    ) -> Option<Direction> {
+        let pdu = Ip::from_bytes(bytes).unwrap();
+        reassembler.process(Duration::from_secs(ts), &*pdu, handler)
+    }
+
+    #[test]
+    fn test_connection() {
+        let mut reassembler = TcpReassembler::new();
+        let mut collector = Collector::default();
+        let c = &mut collector;
+        let r = &mut reassembler;
+
+        feed(r, c, 0, &segment(true, 100, TCP_SYN, &[]));
+        feed(r, c, 0, &segment(false, 500, TCP_SYN | TCP_ACK, &[]));
+        feed(r, c, 0, &segment(true, 101, TCP_ACK, b"GET / "));
+        feed(r, c, 1, &segment(true, 107, TCP_ACK, b"HTTP/1.1\r\n"));
+        feed(r, c, 1, &segment(false, 501, TCP_ACK, b"200 OK"));
+        feed(r, c, 2, &segment(false, 507, TCP_FIN | TCP_ACK, &[]));
+        assert!(r.len() == 1);
+        feed(r, c, 2, &segment(true, 117, TCP_FIN | TCP_ACK, &[]));
+        assert!(r.is_empty());
+
+        assert!(collector.data[0] == b"GET / HTTP/1.1\r\n");
+        assert!(collector.data[1] == b"200 OK");
+        assert!(collector.gaps.is_empty());
+        assert!(collector.closed == [TcpState::Closed]);
+    }
+
+    #[test]
+    fn test_out_of_order_and_retransmission() {
+        let mut reassembler = TcpReassembler::new();
+        let mut collector = Collector::default();
+        let c = &mut collector;
+        let r = &mut reassembler;
+
+        feed(r, c, 0, &segment(true, 1000, TCP_SYN, &[]));
+        feed(r, c, 0, &segment(true, 1007, TCP_ACK, b"world"));
+        feed(r, c, 0, &segment(true, 1004, TCP_ACK, b"lo wor"));
+        assert!(c.data[0].is_empty());
+        feed(r, c, 0, &segment(true, 1001, TCP_ACK, b"hel"));
+        feed(r, c, 0, &segment(true, 1001, TCP_ACK, b"hello"));
+        feed(r, c, 0, &segment(true, 1012, TCP_ACK, b"!"));
+        // Picked up midstream, then a retransmission from before the start.
+        feed(r, c, 0, &segment(false, 1005, TCP_ACK, b"fg"));
+        feed(r, c, 0, &segment(false, 1001, TCP_ACK, b"bcdefghij"));
+        assert!(collector.data[0] == b"hello world!");
+        assert!(collector.data[1] == b"fghij");
+        assert!(collector.gaps.is_empty());
+    }
+
+    #[test]
+    fn test_sequence_wraparound() {
+        let mut reassembler = TcpReassembler::new();
+        let mut data = Vec::new();
+        let mut handler = |_: &FlowKey, _: Direction, bytes: &[u8]| data.extend_from_slice(bytes);
+        let r = &mut reassembler;
+
+        // Picked up midstream, four bytes before the wrap.
+        feed(
+            r,
+            &mut handler,
+            0,
+            &segment(true, u32::MAX - 3, TCP_ACK, b"abcd"),
+        );
+        feed(r, &mut handler, 0, &segment(true, 4, TCP_ACK, b"ij"));
+        feed(r, &mut handler, 0, &segment(true, 0, TCP_ACK, b"efgh"));
+        assert!(data == b"abcdefghij");
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut reassembler = TcpReassembler::new();
+        reassembler.set_max_buffered(4);
+        let mut collector = Collector::default();
+        let c = &mut collector;
+        let r = &mut reassembler;
+
+        feed(r, c, 0, &segment(true, 1, TCP_ACK, b"ab"));
+        feed(r, c, 0, &segment(true, 5, TCP_ACK, b"ef"));
+        assert!(c.gaps.is_empty());
+        feed(r, c, 0, &segment(true, 7, TCP_ACK, b"ghi"));
+        assert!(c.gaps == [(Direction::Forward, 2)]);
+        assert!(c.data[0] == b"abefghi");
+
+        // A hole still open when the connection is reset.
+        feed(r, c, 0, &segment(true, 12, TCP_ACK, b"lm"));
+        feed(r, c, 1, &segment(false, 77, TCP_RST, &[]));
+        assert!(r.is_empty());
+        assert!(collector.gaps[1] == (Direction::Forward, 2));
+        assert!(collector.data[0] == b"abefghilm");
+        assert!(collector.closed == [TcpState::Reset]);
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut reassembler = TcpReassembler::new();
+        reassembler.set_timeout(Duration::from_secs(10));
+        let mut collector = Collector::default();
+        let c = &mut collector;
+        let r = &mut reassembler;
+
+        feed(r, c, 0, &segment(true, 1, TCP_ACK, b"ab"));
+        feed(r, c, 0, &segment(true, 1, TCP_FIN | TCP_ACK, b"ab"));
+        feed(r, c, 5, &segment(false, 1, TCP_ACK, b"x"));
+        assert!(r.expire(Duration::from_secs(9), c) == 0);
+        assert!(r.expire(Duration::from_secs(15), c) == 1);
+        assert!(collector.data[0] == b"ab");
+        assert!(collector.closed == [TcpState::Closing]);
+    }
+}
@@ -1,44 +1,170 @@
 use crate::prelude::*;
 use std::hash::Hash;
 
-pub type DissectionTable<T> = LazyLock<RwLock<HashMap<T, PduBuilder>>>;
+pub type DissectionTable<T> = LazyLock<RwLock<PduTable<T>>>;
 
 pub const fn create_table<K>() -> DissectionTable<K> {
-    LazyLock::new(|| RwLock::new(HashMap::new()))
+    LazyLock::new(|| RwLock::new(PduTable::new()))
 }
 
-pub trait Dissect<T>
+/// Builders keyed by value, plus the mappings between values and the Pdu
+/// types registered under them.
+pub struct PduTable<T> {
+    builders: HashMap<T, PduBuilder>,
+    values: HashMap<TypeId, T>,
+    types: HashMap<T, TypeId>,
+}
+
+impl<T> PduTable<T> {
+    pub fn new() -> Self {
+        Self {
+            builders: HashMap::new(),
+            values: HashMap::new(),
+            types: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Default for PduTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> PduTable<T>
 where
     T: Hash + Eq + PartialEq,
+{
+    pub fn get(&self, value: &T) -> Option<&PduBuilder> {
+        self.builders.get(value)
+    }
+
+    pub fn value_of(&self, type_id: TypeId) -> Option<&T> {
+        self.values.get(&type_id)
+    }
+
+    pub fn type_of(&self, value: &T) -> Option<TypeId> {
+        self.types.get(value).copied()
+    }
+}
+
+impl<T> PduTable<T>
+where
+    T: Hash + Eq + PartialEq + Clone,
+{
+    /// A type registered under several values keeps the first one for
+    /// reverse lookups, unless one is registered with `insert_default`.
+    pub fn insert(&mut self, value: T, type_id: TypeId, builder: PduBuilder) -> Option<PduBuilder> {
+        self.values.entry(type_id).or_insert_with(|| value.clone());
+        self.types.insert(value.clone(), type_id);
+        self.builders.insert(value, builder)
+    }
+
+    /// Like `insert`, but makes `value` the one reverse lookups give for the
+    /// type, whatever order its values are registered in.
+    pub fn insert_default(
+        &mut self,
+        value: T,
+        type_id: TypeId,
+        builder: PduBuilder,
+    ) -> Option<PduBuilder> {
+        let previous = self.insert(value.clone(), type_id, builder);
+        self.values.insert(type_id, value);
+        previous
+    }
+
+    pub fn remove(&mut self, value: &T) -> Option<PduBuilder> {
+        self.values.retain(|_, v| v != value);
+        self.types.remove(value);
+        self.builders.remove(value)
+    }
+}
+
+pub trait Dissect<T>
+where
+    T: Hash + Eq + PartialEq + Clone,
 {
     fn add<U: for<'a> Pdu<'a>>(&self, value: T);
 
     fn remove(&self, value: T);
 }
 
+/// Dissects `bytes` with the builder registered under `value`, or as `Raw`
+/// if there is none. If the builder fails, the bytes are kept as a `Raw`
+/// holding its error, so the layers around them survive.
 pub fn build_from_table<'a, T>(
     dissect_table: &DissectionTable<T>,
     value: T,
     bytes: &'a [u8],
+) -> Box<dyn Pdu<'a> + 'a>
+where
+    T: Hash + Eq + PartialEq,
+{
+    let Ok(table) = dissect_table.read() else {
+        panic!("Failed to secure dissection table.")
+    };
+
+    match table.get(&value) {
+        Some(builder) => Raw::or_malformed(builder(bytes), bytes),
+        None => Raw::from_bytes(bytes).expect("Raw accepts any bytes"),
+    }
+}
+
+/// Tries each value in order and returns the first Pdu that dissects
+/// successfully, falling back to `Raw` if none do. For values that are only
+/// a guess, like ports, where a failure means the guess was wrong.
+pub fn build_from_table_first<'a, T, I>(
+    dissect_table: &DissectionTable<T>,
+    values: I,
+    bytes: &'a [u8],
 ) -> Pob<'a>
 where
     T: Hash + Eq + PartialEq,
+    I: IntoIterator<Item = T>,
 {
     let Ok(table) = dissect_table.read() else {
         panic!("Failed to secure dissection table.")
     };
 
-    if let Some(builder) = table.get(&value) {
-        println!("table dissect {}", bytes.len());
-        builder(bytes).ok()
-    } else {
-        Raw::from_bytes(bytes).ok()
+    for value in values {
+        if let Some(builder) = table.get(&value)
+            && let Ok(pdu) = builder(bytes)
+        {
+            return Some(pdu);
+        }
     }
+
+    Raw::from_bytes(bytes).ok()
 }
 
-impl<T> Dissect<T> for DissectionTable<T>
+/// Finds the value a Pdu type is registered under, e.g. to fill in the
+/// next-protocol field of the layer above it.
+pub fn lookup_value<T>(dissect_table: &DissectionTable<T>, type_id: TypeId) -> Option<T>
+where
+    T: Hash + Eq + PartialEq + Clone,
+{
+    let Ok(table) = dissect_table.read() else {
+        panic!("Failed to secure dissection table.")
+    };
+
+    table.value_of(type_id).cloned()
+}
+
+/// Finds the Pdu type registered under a value.
+pub fn lookup_type<T>(dissect_table: &DissectionTable<T>, value: &T) -> Option<TypeId>
 where
     T: Hash + Eq + PartialEq,
+{
+    let Ok(table) = dissect_table.read() else {
+        panic!("Failed to secure dissection table.")
+    };
+
+    table.type_of(value)
+}
+
+impl<T> Dissect<T> for DissectionTable<T>
+where
+    T: Hash + Eq + PartialEq + Clone,
 {
     fn add<U: for<'a> Pdu<'a>>(&self, value: T) {
         let Ok(mut table) = self.write() else {
@@ -46,9 +172,11 @@ where
         };
 
         if table
-            .insert(value, |bytes: &'_ [u8]| -> PduResult<'_> {
-                U::from_bytes(bytes)
-            })
+            .insert(
+                value,
+                <U as Tid>::id(),
+                |bytes: &'_ [u8]| -> PduResult<'_> { U::from_bytes(bytes) },
+            )
             .is_some()
         {
             panic!("Pdu types can only be added to tables once.")
@@ -64,27 +192,39 @@ where
     }
 }
 
+/// Registers a Pdu type under a value at startup. Each registration gets its
+/// own scope, so a type can be registered under several values of a table.
+/// Startup order isn't fixed, so a type with several values marks the one
+/// to write when building packets as `default`.
 #[macro_export]
 macro_rules! register_pdu {
     ($value_type:expr, $builder:ident, $table:ident) => {
-        paste! {
-            #[ctor]
-            fn [<__nexus_register_ $table:lower _ $builder:lower>]() {
-                pdu_trait_assert::<$builder>();
-                let Ok(mut d_table) = $table.write() else {
-                    panic!("Failed to secure dissection table.")
-                };
-
-                if d_table
-                    .insert($value_type, |bytes: &'_ [u8]| -> PduResult<'_> {
-                        $builder::from_bytes(bytes)
-                    })
-                    .is_some()
-                {
-                    panic!("PDU types can only be added to tables once.")
-                };
+        $crate::register_pdu!(@insert insert, $value_type, $builder, $table);
+    };
+    ($value_type:expr, $builder:ident, $table:ident, default) => {
+        $crate::register_pdu!(@insert insert_default, $value_type, $builder, $table);
+    };
+    (@insert $insert:ident, $value_type:expr, $builder:ident, $table:ident) => {
+        const _: () = {
+            paste! {
+                #[ctor]
+                fn [<__nexus_register_ $table:lower _ $builder:lower>]() {
+                    pdu_trait_assert::<$builder>();
+                    let Ok(mut d_table) = $table.write() else {
+                        panic!("Failed to secure dissection table.")
+                    };
+
+                    if d_table
+                        .$insert($value_type, <$builder as Tid>::id(), |bytes: &'_ [u8]| -> PduResult<'_> {
+                            $builder::from_bytes(bytes)
+                        })
+                        .is_some()
+                    {
+                        panic!("PDU types can only be added to tables once.")
+                    };
+                }
             }
-        }
+        };
     };
 }
 
@@ -98,4 +238,35 @@ mod tests {
         static TEST_TABLE: DissectionTable<u8> = create_table();
         register_pdu!(0, Raw, TEST_TABLE);
     }
+
+    #[test]
+    fn test_lookup_value() {
+        use crate::ip::{IPV4_DISSECTION_TABLE, Ipv4Type};
+        use crate::tcp::Tcp;
+        use crate::udp::Udp;
+
+        assert!(lookup_value(&IPV4_DISSECTION_TABLE, <Tcp as Tid>::id()) == Some(Ipv4Type(6)));
+        assert!(lookup_value(&IPV4_DISSECTION_TABLE, <Udp as Tid>::id()) == Some(Ipv4Type(17)));
+        assert!(lookup_value(&IPV4_DISSECTION_TABLE, <Raw as Tid>::id()).is_none());
+        assert!(lookup_type(&IPV4_DISSECTION_TABLE, &Ipv4Type(6)) == Some(<Tcp as Tid>::id()));
+        assert!(lookup_type(&IPV4_DISSECTION_TABLE, &Ipv4Type(255)).is_none());
+    }
+
+    #[test]
+    fn test_default_value() {
+        use crate::raw::Raw;
+        let builder: PduBuilder = |bytes| Raw::from_bytes(bytes);
+        let raw = <Raw as Tid>::id();
+
+        let mut table = PduTable::new();
+        table.insert(1u8, raw, builder);
+        table.insert_default(2, raw, builder);
+        table.insert(3, raw, builder);
+        assert!(table.value_of(raw) == Some(&2));
+
+        let mut table = PduTable::new();
+        table.insert_default(2u8, raw, builder);
+        table.insert(1, raw, builder);
+        assert!(table.value_of(raw) == Some(&2));
+    }
 }
@@ -1,8 +1,11 @@
+use crate::error::AllocError;
 use crate::ip::{IPV4_DISSECTION_TABLE, Ipv4Type};
+use crate::ip6::{IPV6_DISSECTION_TABLE, Ipv6Type};
 use crate::prelude::*;
+use crate::tcp_opt::{TCP_MAX_OPT_LEN, TcpOption, parse_tcp_options, tcp_options_to_bytes};
 use crate::{default_pdu_clone, register_pdu};
 
-const TCP_MIN_HEADER_LEN: usize = 24;
+const TCP_MIN_HEADER_LEN: usize = 20;
 const TCP_DATA_SIZE_OFFSET: usize = 12;
 const TCP_HEADER_MULT: usize = 4;
 const TCP_SPORT_OFFSET: usize = 0;
@@ -12,16 +15,29 @@ const TCP_AKNUM_OFFSET: usize = 8;
 const TCP_DR_OFFSET: usize = 12;
 const TCP_FLAGS_OFFSET: usize = 13;
 const TCP_WINDOW_OFFSET: usize = 14;
-const TCP_CHECKSUM_OFFSET: usize = 16;
+pub(crate) const TCP_CHECKSUM_OFFSET: usize = 16;
 const TCP_URGPTR_OFFSET: usize = 18;
 
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+pub const TCP_URG: u8 = 0x20;
+pub const TCP_ECE: u8 = 0x40;
+pub const TCP_CWR: u8 = 0x80;
+
 #[pdu_type]
 pub struct Tcp<'a> {}
 
-fn get_data_offset<'a>(bytes: &'a [u8]) -> usize {
+fn get_data_offset(bytes: &[u8]) -> usize {
     (bytes[TCP_DATA_SIZE_OFFSET] >> 4) as usize * TCP_HEADER_MULT
 }
 
+fn get_tcp_port(bytes: &[u8], offset: usize) -> u16 {
+    parse_bytes::<u16>(&bytes[offset..offset + 2], Endian::Big)
+}
+
 #[pdu_impl]
 impl<'a> Pdu<'a> for Tcp<'a> {
     fn to_bytes(&self) -> Vec<u8> {
@@ -33,16 +49,38 @@ impl<'a> Pdu<'a> for Tcp<'a> {
     default_pdu_clone!(Tcp);
 
     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("tcp", "header length", bytes, 0, TCP_MIN_HEADER_LEN)?;
+
         let header_size = get_data_offset(bytes);
-        // println!("tcp deserialize attempt {} {}", header_size, bytes.len());
-        if header_size > bytes.len() {
-            return Err(ParseError::NotEnoughData);
+        if header_size < TCP_MIN_HEADER_LEN {
+            return Err(ParseError::InvalidHeader {
+                protocol: "tcp",
+                field: "data offset",
+                offset: TCP_DATA_SIZE_OFFSET,
+                value: header_size as u64,
+                reason: "is shorter than the minimum header",
+            });
         }
+        ParseError::check_len("tcp", "data offset", bytes, 0, header_size)?;
+
+        let payload = &bytes[header_size..];
+        let child = if payload.is_empty() {
+            None
+        } else {
+            build_from_table_first(
+                &TCP_DISSECTION_TABLE,
+                [
+                    TcpType(get_tcp_port(bytes, TCP_DPORT_OFFSET)),
+                    TcpType(get_tcp_port(bytes, TCP_SPORT_OFFSET)),
+                ],
+                payload,
+            )
+        };
 
         Ok(Box::new(Self {
             header: Cow::Borrowed(&bytes[..header_size]),
             parent: None,
-            child: None,
+            child,
         }))
     }
 
@@ -59,6 +97,10 @@ impl<'a> Pdu<'a> for Tcp<'a> {
                 "tcp.window": self.window(),
                 "tcp.checksum": self.checksum(),
                 "tcp.urg_pointer": self.urg_pointer(),
+                "tcp.options": match self.options() {
+                    Ok(opts) => json!(opts.iter().map(|o| o.to_json()).collect::<Vec<_>>()),
+                    Err(_) => json!(printable_ascii(self.options_bytes())),
+                },
                 "tcp.data": self.child_to_json(),
             }
         }))
@@ -125,7 +167,7 @@ impl<'a> Tcp<'a> {
     }
 
     pub fn set_ack_number(&mut self, ack_num: u32) {
-        self.header.to_mut()[TCP_AKNUM_OFFSET..TCP_AKNUM_OFFSET]
+        self.header.to_mut()[TCP_AKNUM_OFFSET..TCP_DR_OFFSET]
             .copy_from_slice(&ack_num.to_be_bytes());
     }
 
@@ -138,11 +180,12 @@ impl<'a> Tcp<'a> {
         (self.header[TCP_DR_OFFSET] >> 4) * 4
     }
 
+    /// Takes the header length in bytes, as returned by `data_offset`.
     pub fn set_data_offset(&mut self, data_offset: u8) {
         let data_offset_bits = &mut self.header.to_mut()[TCP_DR_OFFSET];
         let mask = 0b0000_1111;
         *data_offset_bits &= mask;
-        *data_offset_bits &= data_offset << 4;
+        *data_offset_bits |= (data_offset / TCP_HEADER_MULT as u8) << 4;
     }
 
     pub fn with_data_offset(&mut self, data_offset: u8) -> &mut Self {
@@ -158,7 +201,7 @@ impl<'a> Tcp<'a> {
         let reserved_bits = &mut self.header.to_mut()[TCP_DR_OFFSET];
         let mask = 0b1111_0000;
         *reserved_bits &= mask;
-        *reserved_bits &= reserved;
+        *reserved_bits |= reserved & !mask;
     }
 
     pub fn with_reserved(&mut self, reserved: u8) -> &mut Self {
@@ -204,7 +247,7 @@ impl<'a> Tcp<'a> {
     }
 
     pub fn set_checksum(&mut self, checksum: u16) {
-        self.header.to_mut()[TCP_CHECKSUM_OFFSET..TCP_AKNUM_OFFSET]
+        self.header.to_mut()[TCP_CHECKSUM_OFFSET..TCP_URGPTR_OFFSET]
             .copy_from_slice(&checksum.to_be_bytes());
     }
 
@@ -224,7 +267,265 @@ impl<'a> Tcp<'a> {
         self.header.to_mut()[TCP_URGPTR_OFFSET..TCP_MIN_HEADER_LEN]
             .copy_from_slice(&urg_pointer.to_be_bytes());
     }
+
+    pub fn with_urg_pointer(&mut self, urg_pointer: u16) -> &mut Self {
+        self.set_urg_pointer(urg_pointer);
+        self
+    }
+
+    /// Raw option bytes between the fixed header and the data offset,
+    /// including any padding.
+    pub fn options_bytes(&self) -> &[u8] {
+        &self.header[TCP_MIN_HEADER_LEN..]
+    }
+
+    pub fn options(&self) -> Result<Vec<TcpOption>, ParseError> {
+        parse_tcp_options(self.options_bytes()).map_err(|err| err.within("tcp", TCP_MIN_HEADER_LEN))
+    }
+
+    /// Rewrites the option area and updates the data offset to match.
+    pub fn set_options(&mut self, options: &[TcpOption]) -> Result<(), AllocError> {
+        let opt_bytes = tcp_options_to_bytes(options);
+        if opt_bytes.len() > TCP_MAX_OPT_LEN {
+            return Err(AllocError::InsufficientSpace);
+        }
+
+        let header = self.header.to_mut();
+        header.truncate(TCP_MIN_HEADER_LEN);
+        header.extend_from_slice(&opt_bytes);
+        self.set_data_offset((TCP_MIN_HEADER_LEN + opt_bytes.len()) as u8);
+        Ok(())
+    }
+
+    pub fn with_options(&mut self, options: &[TcpOption]) -> Result<&mut Self, AllocError> {
+        self.set_options(options)?;
+        Ok(self)
+    }
+
+    /// Appends an option, keeping a trailing EOL last. Fails without
+    /// touching the header if the current options don't parse.
+    pub fn add_option(&mut self, option: TcpOption) -> Result<(), AllocError> {
+        let mut options = self.options()?;
+        let eol = options.last() == Some(&TcpOption::Eol);
+        if eol {
+            options.pop();
+        }
+        options.push(option);
+        if eol {
+            options.push(TcpOption::Eol);
+        }
+        self.set_options(&options)
+    }
+
+    pub fn with_option(&mut self, option: TcpOption) -> Result<&mut Self, AllocError> {
+        self.add_option(option)?;
+        Ok(self)
+    }
+
+    /// Removes every option of the given kind. Fails without touching the
+    /// header if the current options don't parse.
+    pub fn remove_option(&mut self, kind: u8) -> Result<(), AllocError> {
+        let mut options = self.options()?;
+        options.retain(|opt| opt.kind() != kind);
+        self.set_options(&options)
+    }
 }
 
 register_pdu!(Ipv4Type(0x6), Tcp, IPV4_DISSECTION_TABLE);
+register_pdu!(Ipv6Type(0x6), Tcp, IPV6_DISSECTION_TABLE);
+
+register_fields!(Tcp, "tcp", {
+    "tcp.sport" => src_port { name: "Source Port", bits: (TCP_SPORT_OFFSET * 8, 16) },
+    "tcp.dport" => dst_port { name: "Destination Port", bits: (TCP_DPORT_OFFSET * 8, 16) },
+    "tcp.seq_number" => seq_number { name: "Sequence Number", bits: (TCP_SQNUM_OFFSET * 8, 32) },
+    "tcp.ack_number" => ack_number {
+        name: "Acknowledgment Number",
+        bits: (TCP_AKNUM_OFFSET * 8, 32),
+    },
+    "tcp.data_offset" => data_offset { name: "Header Length", bits: (TCP_DR_OFFSET * 8, 4) },
+    "tcp.reserved" => reserved { name: "Reserved", bits: (TCP_DR_OFFSET * 8 + 4, 4) },
+    "tcp.flags" => flags { name: "Flags", bits: (TCP_FLAGS_OFFSET * 8, 8), base: Hex },
+    "tcp.window" => window { name: "Window", bits: (TCP_WINDOW_OFFSET * 8, 16) },
+    "tcp.checksum" => checksum { name: "Checksum", bits: (TCP_CHECKSUM_OFFSET * 8, 16), base: Hex },
+    "tcp.urg_pointer" => urg_pointer { name: "Urgent Pointer", bits: (TCP_URGPTR_OFFSET * 8, 16) },
+});
 // register_ipv4_type!(Ipv4Type(0x6), Tcp);
+
+/// A TCP port number. Payloads are dispatched on the destination port first,
+/// then on the source port, and fall back to `Raw` if neither is registered.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub struct TcpType(pub u16);
+
+pub static TCP_DISSECTION_TABLE: DissectionTable<TcpType> = create_table();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{Dns, DnsTcp};
+    use crate::pdu::deserialize;
+
+    const TCP_HELLO: [u8; 25] = [
+        0x30, 0x39, // Src port = 12345
+        0x00, 0x50, // Dst port = 80
+        0x01, 0x02, 0x03, 0x04, // Seq number
+        0x00, 0x00, 0x00, 0x00, // Ack number
+        0x50, 0x18, // Data offset (5) << 4 , Flags (PSH+ACK)
+        0xFF, 0xFF, // Window size
+        0x00, 0x00, // Checksum
+        0x00, 0x00, // Urgent pointer
+        // Payload: "hello"
+        0x68, 0x65, 0x6C, 0x6C, 0x6F,
+    ];
+
+    const TCP_DNS_QUERY: [u8; 51] = [
+        0xd4, 0x31, // Src port = 54321
+        0x00, 0x35, // Dst port = 53
+        0x00, 0x00, 0x00, 0x01, // Seq number
+        0x00, 0x00, 0x00, 0x01, // Ack number
+        0x50, 0x18, // Data offset (5) << 4 , Flags (PSH+ACK)
+        0xFF, 0xFF, // Window size
+        0x00, 0x00, // Checksum
+        0x00, 0x00, // Urgent pointer
+        // DNS length prefix + query for example.com A
+        0x00, 0x1d, 0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07,
+        b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00,
+        0x01,
+    ];
+
+    #[test]
+    fn test_raw_payload() {
+        let tcp = Tcp::from_bytes(&TCP_HELLO).unwrap();
+        let raw = tcp.find::<Raw>().unwrap();
+        assert!(raw.to_bytes() == b"hello");
+        assert!(tcp.to_json().unwrap()["tcp"]["tcp.data"]["raw"]["raw.data"] == "hello");
+    }
+
+    #[test]
+    fn test_port_dispatch() {
+        let tcp = Tcp::from_bytes(&TCP_DNS_QUERY).unwrap();
+        assert!(tcp.find::<DnsTcp>().unwrap().length() == 29);
+        assert!(tcp.find::<Dns>().unwrap().id() == 0x1234);
+    }
+
+    #[test]
+    fn test_no_payload() {
+        let tcp = deserialize::<Tcp>(&TCP_HELLO[..20]).unwrap();
+        assert!(tcp.child_pdu().is_none());
+        assert!(tcp.urg_pointer() == 0);
+    }
+
+    #[test]
+    fn test_bounds() {
+        assert!(Tcp::from_bytes(&TCP_HELLO[..12]).is_err());
+
+        let mut bytes = TCP_HELLO;
+        bytes[TCP_DR_OFFSET] = 0x40;
+        assert!(Tcp::from_bytes(&bytes).is_err());
+
+        bytes[TCP_DR_OFFSET] = 0x80;
+        assert!(Tcp::from_bytes(&bytes).is_err());
+
+        bytes[TCP_DR_OFFSET] = 0xf0;
+        let err = Tcp::from_bytes(&bytes).err().unwrap();
+        assert!(err.to_string() == "tcp: data offset 60 exceeds remaining 25 bytes at offset 0");
+        let err = Tcp::from_bytes(&TCP_HELLO[..12]).err().unwrap();
+        assert!(
+            err == ParseError::NotEnoughData {
+                protocol: "tcp",
+                field: "header length",
+                offset: 0,
+                expected: 20,
+                actual: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let mut bytes = TCP_HELLO[..20].to_vec();
+        bytes[TCP_DR_OFFSET] = 0x80;
+        bytes.extend_from_slice(&[
+            0x02, 0x04, 0x05, 0xb4, // MSS = 1460
+            0x01, 0x03, 0x03, 0x07, // NOP, Window scale = 7
+            0x04, 0x02, 0x01, 0x00, // SACK permitted, NOP, EOL
+        ]);
+
+        let tcp = deserialize::<Tcp>(&bytes).unwrap();
+        assert!(tcp.data_offset() == 32);
+        assert!(
+            tcp.options().unwrap()
+                == [
+                    TcpOption::Mss(1460),
+                    TcpOption::Nop,
+                    TcpOption::WindowScale(7),
+                    TcpOption::SackPermitted,
+                    TcpOption::Nop,
+                    TcpOption::Eol,
+                ]
+        );
+        let json = tcp.to_json().unwrap();
+        assert!(json["tcp"]["tcp.options"][0]["tcp.option.mss"] == 1460);
+    }
+
+    #[test]
+    fn test_set_options() {
+        let mut tcp = deserialize::<Tcp>(&TCP_HELLO).unwrap();
+        tcp.with_option(TcpOption::Mss(1400))
+            .unwrap()
+            .with_option(TcpOption::Timestamps {
+                value: 1,
+                echo_reply: 2,
+            })
+            .unwrap();
+        assert!(tcp.data_offset() == 36);
+        assert!(tcp.to_bytes().len() == 36);
+        assert!(tcp.src_port() == 12345);
+
+        tcp.remove_option(crate::tcp_opt::MSS).unwrap();
+        assert!(tcp.data_offset() == 32);
+        assert!(
+            tcp.options().unwrap()
+                == [
+                    TcpOption::Timestamps {
+                        value: 1,
+                        echo_reply: 2
+                    },
+                    TcpOption::Eol,
+                ]
+        );
+
+        let too_many = vec![
+            TcpOption::Sack(vec![(0, 0); 4]),
+            TcpOption::Timestamps {
+                value: 0,
+                echo_reply: 0,
+            },
+        ];
+        assert!(tcp.set_options(&too_many).is_err());
+        assert!(tcp.set_options(&[]).is_ok());
+        assert!(tcp.data_offset() == 20);
+    }
+
+    #[test]
+    fn test_edit_malformed_options() {
+        let mut bytes = TCP_HELLO[..20].to_vec();
+        bytes[TCP_DR_OFFSET] = 0x70;
+        bytes.extend_from_slice(&[0x02, 0x04, 0x05, 0xb4, 0x08, 0x0a, 0x00, 0x00]);
+        let mut tcp = deserialize::<Tcp>(&bytes).unwrap();
+
+        let err = tcp.add_option(TcpOption::SackPermitted).err().unwrap();
+        assert!(matches!(err, AllocError::Malformed(_)));
+        assert!(tcp.remove_option(crate::tcp_opt::MSS).is_err());
+        assert!(tcp.with_option(TcpOption::Nop).is_err());
+        assert!(tcp.to_bytes() == bytes);
+    }
+
+    #[test]
+    fn test_set_ack_number() {
+        let mut tcp = deserialize::<Tcp>(&TCP_HELLO).unwrap();
+        tcp.set_ack_number(0xdeadbeef);
+        tcp.set_checksum(0x1234);
+        assert!(tcp.ack_number() == 0xdeadbeef);
+        assert!(tcp.checksum() == 0x1234);
+    }
+}
@@ -0,0 +1,304 @@
+use crate::prelude::*;
+
+const TCP_OPT_KIND_OFFSET: usize = 0;
+const TCP_OPT_SIZE_OFFSET: usize = 1;
+const TCP_OPT_DATA_OFFSET: usize = 2;
+const TCP_OPT_ALIGN: usize = 4;
+
+pub const EOL: u8 = 0;
+pub const NOP: u8 = 1;
+pub const MSS: u8 = 2;
+pub const WSCALE: u8 = 3;
+pub const SACK_PERM: u8 = 4;
+pub const SACK: u8 = 5;
+pub const TIMESTAMPS: u8 = 8;
+pub const MD5: u8 = 19;
+pub const FAST_OPEN: u8 = 34;
+
+const MSS_LEN: usize = 4;
+const WSCALE_LEN: usize = 3;
+const SACK_PERM_LEN: usize = 2;
+const SACK_BLOCK_LEN: usize = 8;
+const TIMESTAMPS_LEN: usize = 10;
+const MD5_LEN: usize = 18;
+
+/// Maximum space for options given the 4 bit data offset field.
+pub const TCP_MAX_OPT_LEN: usize = 40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TcpOption {
+    Eol,
+    Nop,
+    Mss(u16),
+    WindowScale(u8),
+    SackPermitted,
+    /// Left and right edges of each SACK block.
+    Sack(Vec<(u32, u32)>),
+    Timestamps {
+        value: u32,
+        echo_reply: u32,
+    },
+    /// An empty cookie is a cookie request.
+    FastOpen(Vec<u8>),
+    Md5Signature([u8; 16]),
+    Unknown {
+        kind: u8,
+        data: Vec<u8>,
+    },
+}
+
+pub fn get_tcp_opt_kind(bytes: &[u8]) -> u8 {
+    bytes[TCP_OPT_KIND_OFFSET]
+}
+
+/// Refer to <https://datatracker.ietf.org/doc/html/rfc9293#section-3.2>
+pub fn get_tcp_opt_length(bytes: &[u8]) -> Result<usize, ParseError> {
+    match get_tcp_opt_kind(bytes) {
+        EOL | NOP => Ok(1),
+        _ => {
+            ParseError::check_len("tcp option", "length", bytes, TCP_OPT_SIZE_OFFSET, 1)?;
+            let len = bytes[TCP_OPT_SIZE_OFFSET] as usize;
+            if len < TCP_OPT_DATA_OFFSET {
+                return Err(ParseError::InvalidHeader {
+                    protocol: "tcp option",
+                    field: "length",
+                    offset: TCP_OPT_SIZE_OFFSET,
+                    value: len as u64,
+                    reason: "is shorter than the option header",
+                });
+            }
+            ParseError::check_len("tcp option", "length", bytes, 0, len)?;
+            Ok(len)
+        }
+    }
+}
+
+/// Parses every option in `bytes`. Anything after an EOL option is padding
+/// and is ignored.
+pub fn parse_tcp_options(bytes: &[u8]) -> Result<Vec<TcpOption>, ParseError> {
+    let mut opts = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let opt_len = get_tcp_opt_length(&bytes[offset..]).map_err(|err| err.shifted(offset))?;
+        let opt = TcpOption::from_bytes(&bytes[offset..offset + opt_len])
+            .map_err(|err| err.shifted(offset))?;
+        offset += opt_len;
+        if opt == TcpOption::Eol {
+            opts.push(opt);
+            break;
+        }
+        opts.push(opt);
+    }
+    Ok(opts)
+}
+
+/// Serializes `opts`, zero padding to a 4 byte boundary.
+pub fn tcp_options_to_bytes(opts: &[TcpOption]) -> Vec<u8> {
+    let mut res: Vec<u8> = opts.iter().flat_map(|opt| opt.to_bytes()).collect();
+    res.resize(res.len().next_multiple_of(TCP_OPT_ALIGN), EOL);
+    res
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl TcpOption {
+    /// Parses a single option. `bytes` must hold exactly one option, as
+    /// sized by `get_tcp_opt_length`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let data = bytes.get(TCP_OPT_DATA_OFFSET..).unwrap_or_default();
+        let invalid_len = |reason| ParseError::InvalidHeader {
+            protocol: "tcp option",
+            field: "length",
+            offset: TCP_OPT_SIZE_OFFSET,
+            value: bytes.len() as u64,
+            reason,
+        };
+        let expect_len = |len: usize| {
+            if bytes.len() == len {
+                Ok(())
+            } else {
+                Err(invalid_len("doesn't match the option kind"))
+            }
+        };
+
+        let opt = match get_tcp_opt_kind(bytes) {
+            EOL => TcpOption::Eol,
+            NOP => TcpOption::Nop,
+            MSS => {
+                expect_len(MSS_LEN)?;
+                TcpOption::Mss(parse_bytes::<u16>(data, Endian::Big))
+            }
+            WSCALE => {
+                expect_len(WSCALE_LEN)?;
+                TcpOption::WindowScale(data[0])
+            }
+            SACK_PERM => {
+                expect_len(SACK_PERM_LEN)?;
+                TcpOption::SackPermitted
+            }
+            SACK => {
+                if data.len() % SACK_BLOCK_LEN != 0 {
+                    return Err(invalid_len("isn't a whole number of sack blocks"));
+                }
+                TcpOption::Sack(
+                    data.chunks_exact(SACK_BLOCK_LEN)
+                        .map(|block| {
+                            (
+                                parse_bytes::<u32>(&block[..4], Endian::Big),
+                                parse_bytes::<u32>(&block[4..], Endian::Big),
+                            )
+                        })
+                        .collect(),
+                )
+            }
+            TIMESTAMPS => {
+                expect_len(TIMESTAMPS_LEN)?;
+                TcpOption::Timestamps {
+                    value: parse_bytes::<u32>(&data[..4], Endian::Big),
+                    echo_reply: parse_bytes::<u32>(&data[4..], Endian::Big),
+                }
+            }
+            FAST_OPEN => TcpOption::FastOpen(data.to_vec()),
+            MD5 => {
+                expect_len(MD5_LEN)?;
+                TcpOption::Md5Signature(data.try_into().unwrap())
+            }
+            kind => TcpOption::Unknown {
+                kind,
+                data: data.to_vec(),
+            },
+        };
+
+        Ok(opt)
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            TcpOption::Eol => EOL,
+            TcpOption::Nop => NOP,
+            TcpOption::Mss(_) => MSS,
+            TcpOption::WindowScale(_) => WSCALE,
+            TcpOption::SackPermitted => SACK_PERM,
+            TcpOption::Sack(_) => SACK,
+            TcpOption::Timestamps { .. } => TIMESTAMPS,
+            TcpOption::FastOpen(_) => FAST_OPEN,
+            TcpOption::Md5Signature(_) => MD5,
+            TcpOption::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data = match self {
+            TcpOption::Eol => return vec![EOL],
+            TcpOption::Nop => return vec![NOP],
+            TcpOption::Mss(mss) => mss.to_be_bytes().to_vec(),
+            TcpOption::WindowScale(shift) => vec![*shift],
+            TcpOption::SackPermitted => Vec::new(),
+            TcpOption::Sack(blocks) => blocks
+                .iter()
+                .flat_map(|(left, right)| [left.to_be_bytes(), right.to_be_bytes()])
+                .flatten()
+                .collect(),
+            TcpOption::Timestamps { value, echo_reply } => {
+                [value.to_be_bytes(), echo_reply.to_be_bytes()].concat()
+            }
+            TcpOption::FastOpen(cookie) => cookie.clone(),
+            TcpOption::Md5Signature(digest) => digest.to_vec(),
+            TcpOption::Unknown { data, .. } => data.clone(),
+        };
+
+        let mut res = vec![self.kind(), (TCP_OPT_DATA_OFFSET + data.len()) as u8];
+        res.extend_from_slice(&data);
+        res
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let value = match self {
+            TcpOption::Eol | TcpOption::Nop | TcpOption::SackPermitted => json!(null),
+            TcpOption::Mss(mss) => json!({ "tcp.option.mss": mss }),
+            TcpOption::WindowScale(shift) => json!({ "tcp.option.wscale": shift }),
+            TcpOption::Sack(blocks) => json!({ "tcp.option.sack": blocks }),
+            TcpOption::Timestamps { value, echo_reply } => json!({
+                "tcp.option.tsval": value,
+                "tcp.option.tsecr": echo_reply,
+            }),
+            TcpOption::FastOpen(cookie) => json!({ "tcp.option.tfo_cookie": to_hex(cookie) }),
+            TcpOption::Md5Signature(digest) => json!({ "tcp.option.md5": to_hex(digest) }),
+            TcpOption::Unknown { data, .. } => json!({ "tcp.option.data": to_hex(data) }),
+        };
+
+        let mut res = json!({ "tcp.option.kind": self.kind() });
+        if let serde_json::Value::Object(fields) = value {
+            res.as_object_mut().unwrap().extend(fields);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYN_OPTIONS: [u8; 20] = [
+        0x02, 0x04, 0x05, 0xb4, // MSS = 1460
+        0x04, 0x02, // SACK permitted
+        0x08, 0x0a, 0x00, 0x00, 0x12, 0x34, 0x00, 0x00, 0x00, 0x00, // Timestamps
+        0x01, // NOP
+        0x03, 0x03, 0x07, // Window scale = 7
+    ];
+
+    #[test]
+    fn test_parse_syn_options() {
+        let opts = parse_tcp_options(&SYN_OPTIONS).unwrap();
+        assert!(
+            opts == [
+                TcpOption::Mss(1460),
+                TcpOption::SackPermitted,
+                TcpOption::Timestamps {
+                    value: 0x1234,
+                    echo_reply: 0
+                },
+                TcpOption::Nop,
+                TcpOption::WindowScale(7),
+            ]
+        );
+        assert!(tcp_options_to_bytes(&opts) == SYN_OPTIONS);
+    }
+
+    #[test]
+    fn test_parse_sack_md5_tfo() {
+        let mut bytes = vec![0x01, 0x01, 0x05, 0x12];
+        bytes.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4]);
+        bytes.extend_from_slice(&[0x13, 0x12]);
+        bytes.extend_from_slice(&[0xaa; 16]);
+        bytes.extend_from_slice(&[0x22, 0x02, 0xfd, 0x03, 0xff]);
+
+        let opts = parse_tcp_options(&bytes).unwrap();
+        assert!(opts[2] == TcpOption::Sack(vec![(1, 2), (3, 4)]));
+        assert!(opts[3] == TcpOption::Md5Signature([0xaa; 16]));
+        assert!(opts[4] == TcpOption::FastOpen(Vec::new()));
+        assert!(
+            opts[5]
+                == TcpOption::Unknown {
+                    kind: 0xfd,
+                    data: vec![0xff]
+                }
+        );
+    }
+
+    #[test]
+    fn test_eol_stops_parsing() {
+        let opts = parse_tcp_options(&[0x01, 0x00, 0xff, 0xff]).unwrap();
+        assert!(opts == [TcpOption::Nop, TcpOption::Eol]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(parse_tcp_options(&[0x02, 0x04, 0x05]).is_err());
+        assert!(parse_tcp_options(&[0x02, 0x03, 0x05, 0x00]).is_err());
+        assert!(parse_tcp_options(&[0x08, 0x01, 0x00, 0x00]).is_err());
+        assert!(parse_tcp_options(&[0x05, 0x06, 0x00, 0x00, 0x00, 0x00]).is_err());
+    }
+}
@@ -6,7 +6,15 @@ const UDP_HEADER_LEN: usize = 8;
 const UDP_SPORT_OFFSET: usize = 0;
 const UDP_DPORT_OFFSET: usize = 2;
 const UDP_LENGTH_OFFSET: usize = 4;
-const UDP_CHECKSUM_OFFSET: usize = 6;
+pub(crate) const UDP_CHECKSUM_OFFSET: usize = 6;
+
+fn get_udp_port(bytes: &[u8], offset: usize) -> u16 {
+    parse_bytes::<u16>(&bytes[offset..offset + 2], Endian::Big)
+}
+
+fn get_udp_length(bytes: &[u8]) -> usize {
+    parse_bytes::<u16>(&bytes[UDP_LENGTH_OFFSET..UDP_CHECKSUM_OFFSET], Endian::Big) as usize
+}
 
 #[pdu_type]
 pub struct Udp<'a> {}
@@ -22,11 +30,42 @@ impl<'a> Pdu<'a> for Udp<'a> {
     default_pdu_clone!(Udp);
 
     fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
-        // TODO: add dissection table logic
+        ParseError::check_len("udp", "header length", bytes, 0, UDP_HEADER_LEN)?;
+
+        // Trim to the length field so trailing link-layer padding is not
+        // dissected. A length of zero is used by IPv6 jumbograms.
+        let payload_end = match get_udp_length(bytes) {
+            0 => bytes.len(),
+            length if length < UDP_HEADER_LEN => {
+                return Err(ParseError::InvalidHeader {
+                    protocol: "udp",
+                    field: "length",
+                    offset: UDP_LENGTH_OFFSET,
+                    value: length as u64,
+                    reason: "is shorter than the header",
+                });
+            }
+            length => length.min(bytes.len()),
+        };
+        let payload = &bytes[UDP_HEADER_LEN..payload_end];
+
+        let child = if payload.is_empty() {
+            None
+        } else {
+            build_from_table_first(
+                &UDP_DISSECTION_TABLE,
+                [
+                    UdpType(get_udp_port(bytes, UDP_DPORT_OFFSET)),
+                    UdpType(get_udp_port(bytes, UDP_SPORT_OFFSET)),
+                ],
+                payload,
+            )
+        };
+
         Ok(Box::new(Self {
             header: Cow::Borrowed(&bytes[..UDP_HEADER_LEN]),
             parent: None,
-            child: None,
+            child,
         }))
     }
 
@@ -44,6 +83,14 @@ impl<'a> Pdu<'a> for Udp<'a> {
 }
 
 impl<'a> Udp<'a> {
+    pub fn new() -> Self {
+        Self {
+            header: Cow::Owned(vec![0; UDP_HEADER_LEN]),
+            parent: None,
+            child: None,
+        }
+    }
+
     pub fn src_port(&self) -> u16 {
         parse_bytes::<u16>(
             &self.header[UDP_SPORT_OFFSET..UDP_DPORT_OFFSET],
@@ -107,8 +154,6 @@ impl<'a> Udp<'a> {
         if let Some(chksum) = checksum {
             self.header.to_mut()[UDP_CHECKSUM_OFFSET..UDP_HEADER_LEN]
                 .copy_from_slice(&chksum.to_be_bytes());
-        } else {
-            ()
         }
     }
 
@@ -121,6 +166,73 @@ impl<'a> Udp<'a> {
 register_pdu!(Ipv4Type(0x11), Udp, IPV4_DISSECTION_TABLE);
 register_pdu!(Ipv6Type(0x11), Udp, IPV6_DISSECTION_TABLE);
 
+register_fields!(Udp, "udp", {
+    "udp.sport" => src_port { name: "Source Port", bits: (UDP_SPORT_OFFSET * 8, 16) },
+    "udp.dport" => dst_port { name: "Destination Port", bits: (UDP_DPORT_OFFSET * 8, 16) },
+    "udp.length" => length { name: "Length", bits: (UDP_LENGTH_OFFSET * 8, 16) },
+    "udp.checksum" => checksum { name: "Checksum", bits: (UDP_CHECKSUM_OFFSET * 8, 16), base: Hex },
+});
+
+/// A UDP port number. Payloads are dispatched on the destination port first,
+/// then on the source port, and fall back to `Raw` if neither is registered.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct UdpType(pub u16);
 
 pub static UDP_DISSECTION_TABLE: DissectionTable<UdpType> = create_table();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::Dns;
+    use crate::pdu::deserialize;
+
+    const UDP_DNS_QUERY_PADDED: [u8; 41] = [
+        // UDP header
+        0xd4, 0x31, // Src port = 54321
+        0x00, 0x35, // Dst port = 53
+        0x00, 0x25, // Length = 37
+        0x00, 0x00, // Checksum
+        // DNS query for example.com A
+        0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, b'e', b'x',
+        b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, 0x00, 0x01, 0x00, 0x01,
+        // Link-layer padding
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_dispatch_dst_port() {
+        let udp = Udp::from_bytes(&UDP_DNS_QUERY_PADDED).unwrap();
+        let dns = udp.find::<Dns>().unwrap();
+        assert!(dns.id() == 0x1234);
+        assert!(dns.to_bytes().len() == 29);
+    }
+
+    #[test]
+    fn test_dispatch_src_port() {
+        let mut bytes = UDP_DNS_QUERY_PADDED;
+        bytes[0..4].copy_from_slice(&[0x00, 0x35, 0xd4, 0x31]);
+        let udp = Udp::from_bytes(&bytes).unwrap();
+        assert!(udp.find::<Dns>().is_some());
+    }
+
+    #[test]
+    fn test_fallback_raw() {
+        let mut bytes = UDP_DNS_QUERY_PADDED;
+        bytes[0..4].copy_from_slice(&[0xd4, 0x31, 0xd4, 0x32]);
+        let udp = Udp::from_bytes(&bytes).unwrap();
+        let raw = udp.find::<Raw>().unwrap();
+        assert!(raw.to_bytes().len() == 29);
+    }
+
+    #[test]
+    fn test_bounds() {
+        assert!(Udp::from_bytes(&UDP_DNS_QUERY_PADDED[..6]).is_err());
+
+        let mut bytes = UDP_DNS_QUERY_PADDED;
+        bytes[4..6].copy_from_slice(&4u16.to_be_bytes());
+        assert!(Udp::from_bytes(&bytes).is_err());
+
+        let udp = deserialize::<Udp>(&UDP_DNS_QUERY_PADDED[..8]).unwrap();
+        assert!(udp.child_pdu().is_none());
+    }
+}
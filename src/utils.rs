@@ -2,6 +2,7 @@ use num_traits::{FromPrimitive, PrimInt, Unsigned};
 use std::mem;
 use std::ops::BitOrAssign;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Endian {
     Big,
     Little,
@@ -18,15 +19,14 @@ where
     match endian {
         Endian::Big => {
             for shift in (0..size).rev() {
-                result |= ((T::from_u8(bytes[shift as usize])).unwrap()
-                    << ((size - shift - 1) * BYTE_SIZE)) as T;
+                result |=
+                    ((T::from_u8(bytes[shift])).unwrap() << ((size - shift - 1) * BYTE_SIZE)) as T;
             }
             result
         }
         Endian::Little => {
-            for shift in 0..size {
-                result |= (((T::from_u8(bytes[shift as usize])).unwrap() as T)
-                    << (shift * BYTE_SIZE)) as T;
+            for (shift, byte) in bytes.iter().take(size).enumerate() {
+                result |= (((T::from_u8(*byte)).unwrap() as T) << (shift * BYTE_SIZE)) as T;
             }
             result
         }
@@ -46,12 +46,6 @@ pub fn printable_ascii(input: &[u8]) -> String {
         .collect()
 }
 
-pub fn get_header_ts(header: &pcap::PacketHeader) -> f64 {
-    let mut ts = header.ts.tv_sec as f64;
-    ts += header.ts.tv_usec as f64 / 1_000_000.0;
-    ts
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
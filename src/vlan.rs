@@ -0,0 +1,273 @@
+use crate::ethernet::{ETHER_DISSECTION_TABLE, ETHER_TYPE_NAMES, EtherType, Ethernet};
+use crate::prelude::*;
+
+const VLAN_TCI_OFFSET: usize = 0;
+const VLAN_TYPE_OFFSET: usize = 2;
+const VLAN_HEADER_LEN: usize = 4;
+
+const VLAN_PCP_SHIFT: u16 = 13;
+const VLAN_DEI_MASK: u16 = 0x1000;
+const VLAN_VID_MASK: u16 = 0x0FFF;
+
+/// 802.1Q customer tag.
+pub const TPID_8021Q: u16 = 0x8100;
+/// 802.1ad service tag, the outer tag of QinQ.
+pub const TPID_8021AD: u16 = 0x88A8;
+
+pub fn is_tpid(ether_type: u16) -> bool {
+    matches!(ether_type, TPID_8021Q | TPID_8021AD)
+}
+
+fn get_vlan_ether_type(bytes: &[u8]) -> u16 {
+    parse_bytes::<u16>(&bytes[VLAN_TYPE_OFFSET..VLAN_HEADER_LEN], Endian::Big)
+}
+
+/// An 802.1Q or 802.1ad tag. The TPID is the EtherType of the layer above,
+/// so a tag only holds the TCI and the EtherType of what it carries.
+/// Refer to <https://standards.ieee.org/ieee/802.1Q/10323/>
+#[pdu_type]
+pub struct Vlan<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Vlan<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(Vlan);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("vlan", "header length", bytes, 0, VLAN_HEADER_LEN)?;
+
+        // Nested tags come back through the same table.
+        let inner = build_from_table(
+            &ETHER_DISSECTION_TABLE,
+            EtherType(get_vlan_ether_type(bytes)),
+            &bytes[VLAN_HEADER_LEN..],
+        );
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..VLAN_HEADER_LEN]),
+            parent: None,
+            child: Some(inner),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "vlan": {
+                "vlan.priority": self.pcp(),
+                "vlan.dei": self.dei(),
+                "vlan.id": self.vid(),
+                "vlan.etype": self.ether_type(),
+                "vlan.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> Vlan<'a> {
+    pub fn new(vid: u16) -> Self {
+        let mut vlan = Self {
+            header: Cow::Owned(vec![0; VLAN_HEADER_LEN]),
+            parent: None,
+            child: None,
+        };
+        vlan.set_vid(vid);
+        vlan
+    }
+
+    pub fn tci(&self) -> u16 {
+        parse_bytes::<u16>(&self.header[VLAN_TCI_OFFSET..VLAN_TYPE_OFFSET], Endian::Big)
+    }
+
+    pub fn set_tci(&mut self, tci: u16) {
+        self.header.to_mut()[VLAN_TCI_OFFSET..VLAN_TYPE_OFFSET].copy_from_slice(&tci.to_be_bytes());
+    }
+
+    pub fn with_tci(&mut self, tci: u16) -> &mut Self {
+        self.set_tci(tci);
+        self
+    }
+
+    /// Priority code point.
+    pub fn pcp(&self) -> u8 {
+        (self.tci() >> VLAN_PCP_SHIFT) as u8
+    }
+
+    pub fn set_pcp(&mut self, pcp: u8) {
+        let tci = self.tci() & !(0x7 << VLAN_PCP_SHIFT);
+        self.set_tci(tci | (((pcp & 0x7) as u16) << VLAN_PCP_SHIFT));
+    }
+
+    pub fn with_pcp(&mut self, pcp: u8) -> &mut Self {
+        self.set_pcp(pcp);
+        self
+    }
+
+    /// Drop eligible indicator.
+    pub fn dei(&self) -> bool {
+        self.tci() & VLAN_DEI_MASK != 0
+    }
+
+    pub fn set_dei(&mut self, dei: bool) {
+        let tci = self.tci() & !VLAN_DEI_MASK;
+        self.set_tci(if dei { tci | VLAN_DEI_MASK } else { tci });
+    }
+
+    pub fn with_dei(&mut self, dei: bool) -> &mut Self {
+        self.set_dei(dei);
+        self
+    }
+
+    /// VLAN identifier.
+    pub fn vid(&self) -> u16 {
+        self.tci() & VLAN_VID_MASK
+    }
+
+    pub fn set_vid(&mut self, vid: u16) {
+        let tci = self.tci() & !VLAN_VID_MASK;
+        self.set_tci(tci | (vid & VLAN_VID_MASK));
+    }
+
+    pub fn with_vid(&mut self, vid: u16) -> &mut Self {
+        self.set_vid(vid);
+        self
+    }
+
+    /// EtherType of the payload, which is another TPID for stacked tags.
+    pub fn ether_type(&self) -> u16 {
+        get_vlan_ether_type(&self.header)
+    }
+
+    pub fn set_ether_type(&mut self, ether_type: u16) {
+        self.header.to_mut()[VLAN_TYPE_OFFSET..VLAN_HEADER_LEN]
+            .copy_from_slice(&ether_type.to_be_bytes());
+    }
+
+    pub fn with_ether_type(&mut self, ether_type: u16) -> &mut Self {
+        self.set_ether_type(ether_type);
+        self
+    }
+}
+
+impl<'a> Ethernet<'a> {
+    /// Tags, outermost first.
+    pub fn vlans(&self) -> Vec<&'a Vlan<'a>> {
+        let mut tags = Vec::new();
+        let mut next = self.child_pdu().as_deref();
+        while let Some(tag) = next.and_then(|pdu| pdu.downcast_ref::<Vlan>()) {
+            tags.push(tag);
+            next = tag.child_pdu().as_deref();
+        }
+        tags
+    }
+
+    /// Inserts `tag` as the outermost tag. The frame's EtherType moves into
+    /// the tag and is replaced by `tpid`.
+    pub fn push_vlan(&mut self, tpid: u16, mut tag: Vlan<'a>) {
+        tag.set_ether_type(self.ether_type());
+        *tag.child_pdu_mut() = self.child_pdu_mut().take();
+        self.set_ether_type(tpid);
+        *self.child_pdu_mut() = Some(Box::new(tag));
+    }
+
+    pub fn with_vlan(&mut self, tpid: u16, tag: Vlan<'a>) -> &mut Self {
+        self.push_vlan(tpid, tag);
+        self
+    }
+
+    /// Removes the outermost tag and returns it without its payload, which
+    /// moves up to the frame along with its EtherType.
+    pub fn pop_vlan(&mut self) -> Option<Box<Vlan<'a>>> {
+        self.child_pdu().as_ref()?.downcast_ref::<Vlan>()?;
+        let mut tag = self.child_pdu_mut().take()?.downcast::<Vlan>()?;
+        self.set_ether_type(tag.ether_type());
+        *self.child_pdu_mut() = tag.child_pdu_mut().take();
+        Some(tag)
+    }
+}
+
+register_pdu!(EtherType(TPID_8021Q), Vlan, ETHER_DISSECTION_TABLE, default);
+register_pdu!(EtherType(TPID_8021AD), Vlan, ETHER_DISSECTION_TABLE);
+
+register_fields!(Vlan, "vlan", {
+    "vlan.priority" => pcp { name: "Priority", bits: (VLAN_TCI_OFFSET * 8, 3) },
+    "vlan.dei" => dei { name: "Drop Eligible", bits: (VLAN_TCI_OFFSET * 8 + 3, 1) },
+    "vlan.id" => vid { name: "ID", bits: (VLAN_TCI_OFFSET * 8 + 4, 12) },
+    "vlan.etype" => ether_type {
+        name: "Type",
+        bits: (VLAN_TYPE_OFFSET * 8, 16),
+        base: Hex,
+        values: ETHER_TYPE_NAMES,
+    },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::Ip;
+
+    const QINQ_IPV4: [u8; 42] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x88,
+        0xa8, // Ethernet
+        0xb0, 0x64, 0x81, 0x00, // S-tag: PCP 5, DEI, VID 100
+        0x00, 0x0a, 0x08, 0x00, // C-tag: VID 10
+        0x45, 0x00, 0x00, 0x14, 0x00, 0x01, 0x00, 0x00, 0x40, 0xfd, 0x00, 0x00, 0xc0, 0x00, 0x02,
+        0x01, 0xc6, 0x33, 0x64, 0x02, // IPv4
+    ];
+
+    #[test]
+    fn test_qinq() {
+        let eth = Ethernet::from_bytes(&QINQ_IPV4).unwrap();
+        let tags = eth.downcast_ref::<Ethernet>().unwrap().vlans();
+        assert!(tags.len() == 2);
+        assert!(tags[0].pcp() == 5);
+        assert!(tags[0].dei());
+        assert!(tags[0].vid() == 100);
+        assert!(tags[0].ether_type() == TPID_8021Q);
+        assert!(tags[1].pcp() == 0);
+        assert!(!tags[1].dei());
+        assert!(tags[1].vid() == 10);
+        assert!(eth.find::<Ip>().is_some());
+        assert!(eth.chain_to_bytes() == QINQ_IPV4);
+    }
+
+    #[test]
+    fn test_set_tci_fields() {
+        let mut vlan = Vlan::new(0xFFFF);
+        assert!(vlan.vid() == 0x0FFF);
+        vlan.with_pcp(7).with_dei(true).with_vid(42);
+        assert!(vlan.tci() == 0xF02A);
+        vlan.set_dei(false);
+        assert!(vlan.tci() == 0xE02A);
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut untagged = QINQ_IPV4[..12].to_vec();
+        untagged.extend_from_slice(&[0x08, 0x00]);
+        untagged.extend_from_slice(&QINQ_IPV4[22..]);
+
+        let mut eth = deserialize_eth(&untagged);
+        eth.push_vlan(TPID_8021Q, Vlan::new(10));
+        let mut s_tag = Vlan::new(100);
+        s_tag.with_pcp(5).with_dei(true);
+        eth.push_vlan(TPID_8021AD, s_tag);
+        assert!(eth.chain_to_bytes() == QINQ_IPV4);
+
+        assert!(eth.pop_vlan().unwrap().vid() == 100);
+        assert!(eth.pop_vlan().unwrap().vid() == 10);
+        assert!(eth.pop_vlan().is_none());
+        assert!(eth.chain_to_bytes() == untagged);
+    }
+
+    fn deserialize_eth(bytes: &[u8]) -> Box<Ethernet<'_>> {
+        Ethernet::from_bytes(bytes)
+            .unwrap()
+            .downcast::<Ethernet>()
+            .unwrap()
+    }
+}
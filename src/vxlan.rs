@@ -0,0 +1,130 @@
+use crate::ethernet::Ethernet;
+use crate::prelude::*;
+use crate::udp::{UDP_DISSECTION_TABLE, UdpType};
+
+const VXLAN_FLAGS_OFFSET: usize = 0;
+const VXLAN_VNI_OFFSET: usize = 4;
+const VXLAN_HEADER_LEN: usize = 8;
+
+/// The VNI is valid.
+pub const VXLAN_FLAG_I: u8 = 0x08;
+
+pub const VXLAN_PORT: u16 = 4789;
+
+/// Virtual eXtensible LAN, which always carries an Ethernet frame.
+/// Refer to <https://www.rfc-editor.org/rfc/rfc7348>
+#[pdu_type]
+pub struct Vxlan<'a> {}
+
+#[pdu_impl]
+impl<'a> Pdu<'a> for Vxlan<'a> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&self.header);
+        res
+    }
+
+    default_pdu_clone!(Vxlan);
+
+    fn from_bytes(bytes: &'a [u8]) -> Result<Box<dyn Pdu<'a> + 'a>, ParseError> {
+        ParseError::check_len("vxlan", "header length", bytes, 0, VXLAN_HEADER_LEN)?;
+
+        let payload = &bytes[VXLAN_HEADER_LEN..];
+        let inner = Raw::or_malformed(Ethernet::from_bytes(payload), payload);
+
+        Ok(Box::new(Self {
+            header: Cow::Borrowed(&bytes[..VXLAN_HEADER_LEN]),
+            parent: None,
+            child: Some(inner),
+        }))
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(json!({
+            "vxlan": {
+                "vxlan.flags": self.flags(),
+                "vxlan.vni": self.vni(),
+                "vxlan.data": self.child_to_json(),
+            }
+        }))
+    }
+}
+
+impl<'a> Vxlan<'a> {
+    /// A header with the I flag set.
+    pub fn new(vni: u32) -> Self {
+        let mut vxlan = Self {
+            header: Cow::Owned(vec![0; VXLAN_HEADER_LEN]),
+            parent: None,
+            child: None,
+        };
+        vxlan.set_flags(VXLAN_FLAG_I);
+        vxlan.set_vni(vni);
+        vxlan
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.header[VXLAN_FLAGS_OFFSET]
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.header.to_mut()[VXLAN_FLAGS_OFFSET] = flags;
+    }
+
+    pub fn with_flags(&mut self, flags: u8) -> &mut Self {
+        self.set_flags(flags);
+        self
+    }
+
+    /// 24 bit VXLAN network identifier.
+    pub fn vni(&self) -> u32 {
+        parse_bytes::<u32>(
+            &self.header[VXLAN_VNI_OFFSET..VXLAN_HEADER_LEN],
+            Endian::Big,
+        ) >> 8
+    }
+
+    pub fn set_vni(&mut self, vni: u32) {
+        self.header.to_mut()[VXLAN_VNI_OFFSET..VXLAN_HEADER_LEN - 1]
+            .copy_from_slice(&vni.to_be_bytes()[1..]);
+    }
+
+    pub fn with_vni(&mut self, vni: u32) -> &mut Self {
+        self.set_vni(vni);
+        self
+    }
+}
+
+register_pdu!(UdpType(VXLAN_PORT), Vxlan, UDP_DISSECTION_TABLE);
+
+register_fields!(Vxlan, "vxlan", {
+    "vxlan.flags" => flags { name: "Flags", bits: (VXLAN_FLAGS_OFFSET * 8, 8), base: Hex },
+    "vxlan.vni" => vni { name: "VXLAN Network Identifier", bits: (VXLAN_VNI_OFFSET * 8, 24) },
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::Ip;
+    use crate::udp::Udp;
+
+    #[test]
+    fn test_vxlan() {
+        let mut bytes = vec![0xc0, 0x01, 0x12, 0xb5, 0x00, 0x32, 0x00, 0x00]; // UDP to 4789
+        bytes.extend_from_slice(&[0x08, 0x00, 0x00, 0x00, 0x00, 0x30, 0x39, 0x00]);
+        bytes.extend_from_slice(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        bytes.extend_from_slice(&[0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00]);
+        bytes.extend_from_slice(&[
+            0x45, 0x00, 0x00, 0x14, 0x00, 0x01, 0x00, 0x00, 0x40, 0xfd, 0x00, 0x00, 0x0a, 0x00,
+            0x00, 0x01, 0x0a, 0x00, 0x00, 0x02,
+        ]);
+
+        let udp = Udp::from_bytes(&bytes).unwrap();
+        let vxlan = udp.find::<Vxlan>().unwrap();
+        assert!(vxlan.flags() == VXLAN_FLAG_I);
+        assert!(vxlan.vni() == 12345);
+        assert!(udp.find::<Ethernet>().is_some());
+        assert!(udp.find::<Ip>().is_some());
+        assert!(Vxlan::new(12345).to_bytes() == bytes[8..16]);
+    }
+}